@@ -0,0 +1,133 @@
+//! Inputs for the benchmarks, generated from fixed seeds so every run measures the same
+//! thing. They're shaped after real game files rather than random noise, since how well
+//! data compresses or how many colors a texture has changes how much work there is.
+
+use cube_rs::{
+    bmg::{Bmg, BmgMessage, MessageId, TextEncoding},
+    bti::{BtiHeader, BtiImage, BtiQuality, TextureFormat},
+};
+use std::path::PathBuf;
+
+/// Small, fast, and good enough to make data that doesn't look uniform
+pub struct Lcg(u64);
+
+impl Lcg {
+    pub fn new(seed: u64) -> Self {
+        Lcg(seed)
+    }
+
+    pub fn next(&mut self) -> u32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) as u32
+    }
+
+    pub fn below(&mut self, max: u32) -> u32 {
+        self.next() % max
+    }
+}
+
+/// Binary data that compresses about as well as models and collision files do. Most of
+/// it repeats earlier data with small changes, and the rest is mostly small values.
+pub fn compressible_data(len: usize, seed: u64) -> Vec<u8> {
+    let mut rng = Lcg::new(seed);
+    let mut out = Vec::with_capacity(len);
+    while out.len() < len {
+        if out.len() > 64 && rng.below(10) < 6 {
+            let distance = 1 + rng.below(out.len().min(0x1000) as u32) as usize;
+            let length = 3 + rng.below(60) as usize;
+            let start = out.len() - distance;
+            for i in 0..length {
+                let byte = out[start + i];
+                out.push(if rng.below(16) == 0 { byte ^ 1 } else { byte });
+            }
+        } else {
+            let byte = match rng.below(4) {
+                0 => rng.next() as u8,
+                _ => rng.below(16) as u8,
+            };
+            out.push(byte);
+        }
+    }
+    out.truncate(len);
+    out
+}
+
+/// Files laid out like a stage archive: a few folders of models and textures of mixed
+/// sizes, about 1.5 MB in total
+pub fn archive_files() -> Vec<(PathBuf, Vec<u8>)> {
+    let mut rng = Lcg::new(1);
+    let folders = ["model", "texture", "collision", "param"];
+    (0..64)
+        .map(|i| {
+            let folder = folders[i % folders.len()];
+            let size = 1024 + rng.below(48 * 1024) as usize;
+            let path = PathBuf::from(folder).join(format!("file_{i:02}.bin"));
+            (path, compressible_data(size, i as u64))
+        })
+        .collect()
+}
+
+/// Pixels for a `size` by `size` texture: smooth gradients with some noise, like a
+/// painted terrain texture. With `max_colors`, the colors are reduced to at most that many.
+pub fn texture_pixels(size: usize, max_colors: Option<usize>) -> Vec<[u8; 4]> {
+    let mut rng = Lcg::new(2);
+    (0..size * size)
+        .map(|i| {
+            let (x, y) = (i % size, i / size);
+            let noise = rng.below(24) as usize;
+            let mut color = [
+                (x * 255 / size + noise) as u8,
+                ((x + y) * 127 / size + noise) as u8,
+                (y * 255 / size) as u8,
+                255,
+            ];
+            if let Some(max_colors) = max_colors {
+                // Keep only as many levels per channel as fit in the color budget
+                let levels = (max_colors as f32).cbrt().floor().max(2.) as u32;
+                let step = 256 / levels;
+                for channel in color.iter_mut().take(3) {
+                    *channel = ((*channel as u32 / step) * step) as u8;
+                }
+            }
+            color
+        })
+        .collect()
+}
+
+/// A complete BTI file in `format`, encoded from [`texture_pixels`]
+pub fn bti(format: TextureFormat, size: usize) -> Vec<u8> {
+    let max_colors = match format {
+        TextureFormat::C4 => Some(16),
+        TextureFormat::C8 => Some(256),
+        _ => None,
+    };
+    let pixels = texture_pixels(size, max_colors);
+    let header = BtiHeader::new(format, &pixels);
+    BtiImage::encode(size as u32, size as u32, &pixels, &header, BtiQuality::Fast).expect("Texture encodes")
+}
+
+/// A BMG with `count` messages of varying length, each with an ID and attributes, like
+/// a game's main text file
+pub fn bmg(count: usize, encoding: TextEncoding) -> Vec<u8> {
+    let mut rng = Lcg::new(3);
+    let words = [
+        "Olimar", "ship", "treasure", "Pikmin", "the", "found", "onion", "cave", "sunset", "day",
+    ];
+    let mut bmg = Bmg::new(encoding);
+    for i in 0..count {
+        let message = (0..4 + rng.below(30))
+            .map(|_| words[rng.below(words.len() as u32) as usize])
+            .collect::<Vec<_>>()
+            .join(" ");
+        bmg.add_message(BmgMessage {
+            message,
+            id: Some(MessageId::new(i as u32, 0).unwrap()),
+            attributes: format!("{:08X}", rng.next()),
+        })
+        .expect("Message is valid");
+    }
+    bmg.write()
+}
@@ -0,0 +1,141 @@
+//! Benchmarks for the work that dominates extracting and packing. Run with
+//! `cargo bench -p cube_rs`, or pass a name like `-- yaz0` to run one group.
+
+mod corpus;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use cube_rs::{
+    bmg::{Bmg, TextEncoding},
+    bti::{BtiHeader, BtiImage, BtiQuality, TextureFormat},
+    rarc::{ByteOrder, LoadLocations, Rarc, DEFAULT_ALIGNMENT},
+    szs::{decompress_szs, yaz0_compress, CompressionLevel},
+    yay0,
+};
+use std::{
+    fs::{create_dir_all, remove_dir_all, write},
+    hint::black_box,
+};
+
+fn rarc(c: &mut Criterion) {
+    let files = corpus::archive_files();
+    let total_size: usize = files.iter().map(|(_, data)| data.len()).sum();
+    let mut group = c.benchmark_group("rarc");
+    group.throughput(Throughput::Bytes(total_size as u64));
+
+    group.bench_function("build", |b| {
+        b.iter(|| Rarc::build("root", black_box(&files).iter().map(|(path, data)| (path, data))).unwrap());
+    });
+
+    // Packing a folder on disk, as `cube pack` does
+    let root = std::env::temp_dir().join(format!("cube-bench-rarc-{}", std::process::id()));
+    for (path, data) in files.iter() {
+        let path = root.join(path);
+        create_dir_all(path.parent().unwrap()).unwrap();
+        write(path, data).unwrap();
+    }
+    group.bench_function("pack_folder", |b| {
+        b.iter(|| {
+            let mut out = Vec::with_capacity(total_size * 2);
+            Rarc::encode_to(
+                &root,
+                &mut out,
+                DEFAULT_ALIGNMENT,
+                ByteOrder::Big,
+                &LoadLocations::default(),
+                None,
+            )
+            .unwrap();
+            out
+        });
+    });
+    remove_dir_all(&root).unwrap();
+
+    let archive = Rarc::build("root", files.iter().map(|(path, data)| (path, data))).unwrap();
+    group.bench_function("parse", |b| {
+        b.iter(|| {
+            let rarc = Rarc::parse(black_box(&archive)).unwrap();
+            rarc.files().count()
+        });
+    });
+    group.finish();
+}
+
+fn compression(c: &mut Criterion) {
+    let data = corpus::compressible_data(256 * 1024, 0);
+    let mut group = c.benchmark_group("compression");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.sample_size(10);
+
+    for level in [
+        CompressionLevel::Fast,
+        CompressionLevel::Lookahead(5),
+        CompressionLevel::default(),
+    ] {
+        group.bench_with_input(BenchmarkId::new("yaz0_compress", level), &level, |b, &level| {
+            b.iter(|| yaz0_compress(black_box(&data), level));
+        });
+    }
+    group.bench_function("yay0_compress", |b| {
+        b.iter(|| yay0::compress(black_box(&data), CompressionLevel::default()));
+    });
+
+    let yaz0 = yaz0_compress(&data, CompressionLevel::default());
+    group.bench_function("yaz0_decompress", |b| {
+        b.iter(|| decompress_szs(black_box(yaz0.clone())).unwrap());
+    });
+    let yay0 = yay0::compress(&data, CompressionLevel::default());
+    group.bench_function("yay0_decompress", |b| {
+        b.iter(|| yay0::decompress(black_box(&yay0)).unwrap());
+    });
+    group.finish();
+}
+
+fn bti(c: &mut Criterion) {
+    const SIZE: usize = 512;
+    let mut group = c.benchmark_group("bti");
+    group.throughput(Throughput::Elements((SIZE * SIZE) as u64));
+
+    for format in [
+        TextureFormat::CMPR,
+        TextureFormat::RGB5A3,
+        TextureFormat::RGBA32,
+        TextureFormat::C8,
+        TextureFormat::I4,
+    ] {
+        let bti = corpus::bti(format, SIZE);
+        group.bench_with_input(BenchmarkId::new("decode", format), &bti, |b, bti| {
+            b.iter(|| BtiImage::decode(black_box(bti)).unwrap());
+        });
+    }
+
+    let pixels = corpus::texture_pixels(SIZE, None);
+    let header = BtiHeader::new(TextureFormat::CMPR, &pixels);
+    for quality in [BtiQuality::Fast, BtiQuality::High] {
+        group.bench_with_input(BenchmarkId::new("encode_cmpr", quality), &quality, |b, &quality| {
+            b.iter(|| BtiImage::encode(SIZE as u32, SIZE as u32, black_box(&pixels), &header, quality).unwrap());
+        });
+    }
+    group.finish();
+}
+
+fn bmg(c: &mut Criterion) {
+    const MESSAGES: usize = 2000;
+    let data = corpus::bmg(MESSAGES, TextEncoding::ShiftJIS);
+    let mut group = c.benchmark_group("bmg");
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+
+    group.bench_function("parse", |b| {
+        b.iter(|| Bmg::read(black_box(&data)).unwrap());
+    });
+    group.bench_function("parse_messages", |b| {
+        b.iter(|| Bmg::read(black_box(&data)).unwrap().messages().count());
+    });
+    let bmg = Bmg::read(&data).unwrap();
+    group.bench_function("write", |b| {
+        b.iter(|| bmg.write());
+    });
+    group.finish();
+}
+
+criterion_group!(benches, rarc, compression, bti, bmg);
+criterion_main!(benches);
@@ -0,0 +1,14 @@
+#![no_main]
+
+use cube_rs::bti::BtiImage;
+use libfuzzer_sys::fuzz_target;
+
+// Any bytes, including headers with sizes and offsets near their limits, should either
+// decode or return a BtiError. The first byte picks where in the data the texture starts,
+// like it would inside a model or particle file.
+fuzz_target!(|data: &[u8]| {
+    let _ = BtiImage::decode(data);
+    if let Some((&base_offset, rest)) = data.split_first() {
+        let _ = BtiImage::decode_at(rest, base_offset as usize);
+    }
+});
@@ -0,0 +1,93 @@
+#ifndef CUBE_H
+#define CUBE_H
+
+/* Generated by cbindgen from src/ffi.rs. Do not edit by hand. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define CUBE_OK 0
+
+#define CUBE_ERROR 1
+
+// A byte buffer. When returned by the library, free it with `cube_buffer_free`.
+typedef struct CubeBuffer {
+  uint8_t *data;
+  uintptr_t len;
+} CubeBuffer;
+
+// A file with a null-terminated UTF-8 path relative to the archive root
+typedef struct CubeFile {
+  char *path;
+  struct CubeBuffer bytes;
+} CubeFile;
+
+// A list of files. When returned by the library, free it with `cube_file_list_free`.
+typedef struct CubeFileList {
+  struct CubeFile *files;
+  uintptr_t len;
+} CubeFileList;
+
+// Returns a description of why the last call on this thread that returns a status
+// failed, or null if it succeeded. The string is valid until the next call on the same thread.
+const char *cube_last_error(void);
+
+// Extracts an SZS archive, which may or may not be Yaz0 compressed.
+//
+// # Safety
+// `data` must point to `len` readable bytes and `out` must be a valid pointer.
+int32_t cube_extract_szs(const uint8_t *data, uintptr_t len, struct CubeFileList *out);
+
+// Lists the files in an uncompressed RARC archive.
+//
+// # Safety
+// `data` must point to `len` readable bytes and `out` must be a valid pointer.
+int32_t cube_rarc_decode(const uint8_t *data, uintptr_t len, struct CubeFileList *out);
+
+// Builds an uncompressed RARC archive from a list of files. Paths use `/` as the
+// separator, and intermediate directories are created automatically.
+//
+// # Safety
+// `root_name` must be a null-terminated string, `files` must point to `num_files`
+// valid files, and `out` must be a valid pointer.
+int32_t cube_rarc_encode(const char *root_name,
+                         const struct CubeFile *files,
+                         uintptr_t num_files,
+                         struct CubeBuffer *out);
+
+// Converts a BMG file to the same JSON representation the CLI uses.
+//
+// # Safety
+// `data` must point to `len` readable bytes and `out` must be a valid pointer.
+int32_t cube_bmg_to_json(const uint8_t *data, uintptr_t len, struct CubeBuffer *out);
+
+// Converts JSON in the format produced by `cube_bmg_to_json` back to a BMG file.
+//
+// # Safety
+// `json` must point to `len` readable bytes of UTF-8 and `out` must be a valid pointer.
+int32_t cube_bmg_from_json(const uint8_t *json, uintptr_t len, struct CubeBuffer *out);
+
+// Decodes a BTI texture to row-major RGBA8 pixels.
+//
+// # Safety
+// `data` must point to `len` readable bytes, and `width`, `height`, and `out` must be
+// valid pointers.
+int32_t cube_bti_decode(const uint8_t *data,
+                        uintptr_t len,
+                        uint32_t *width,
+                        uint32_t *height,
+                        struct CubeBuffer *out);
+
+// Frees a buffer returned by the library. Freeing an empty buffer is a no-op.
+//
+// # Safety
+// `buffer` must have been returned by the library and not already freed.
+void cube_buffer_free(struct CubeBuffer buffer);
+
+// Frees a file list returned by the library, including every file in it.
+//
+// # Safety
+// `list` must have been returned by the library and not already freed.
+void cube_file_list_free(struct CubeFileList list);
+
+#endif  /* CUBE_H */
@@ -0,0 +1,337 @@
+//! AFS, the archive format CRI's middleware uses, which many third-party GameCube games
+//! keep their sound, movies, and other data in. A table of each file's offset and size is
+//! followed by the files, each starting on a 0x800 byte boundary, and usually a table of
+//! their names and modification times. Unlike GameCube's own archives, AFS is always
+//! little-endian.
+//!
+//! Games look files up by their position in the table rather than by name, so names
+//! don't have to be unique and the order has to be kept. [`AfsIndex`] records both when an
+//! archive is extracted to a folder.
+
+use crate::{
+    rarc::ByteOrder,
+    util::{BinReader, OutOfBounds},
+    virtual_fs::VirtualFile,
+    CodecError, Decode, Encode, FromBytes,
+};
+use encoding_rs::SHIFT_JIS;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+pub const MAGIC: [u8; 4] = *b"AFS\0";
+const ALIGNMENT: usize = 0x800;
+const NAME_LEN: usize = 32;
+const NAME_ENTRY_SIZE: usize = 0x30;
+
+pub struct AfsArchive<'a> {
+    data: &'a [u8],
+    entries: Vec<AfsEntry>,
+    /// Whether the archive has a name table. Some leave it out, and are only ever read by
+    /// position.
+    pub has_names: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AfsEntry {
+    pub offset: u32,
+    pub size: u32,
+    /// Name from the name table, or empty if there isn't one
+    pub name: String,
+    pub modified: AfsTimestamp,
+}
+
+/// When a file was last changed, as the tool that built the archive recorded it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AfsTimestamp {
+    pub year: u16,
+    pub month: u16,
+    pub day: u16,
+    pub hour: u16,
+    pub minute: u16,
+    pub second: u16,
+}
+
+/// The order of an archive's files and what its name table said about them. This is
+/// everything needed to put an extracted archive back together.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AfsIndex {
+    pub has_names: bool,
+    pub files: Vec<AfsIndexEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AfsIndexEntry {
+    /// Where the file is extracted to, relative to the archive's folder. This is its name
+    /// when that's usable as a file name and no other file has it.
+    pub path: String,
+    /// Name in the name table, when it's different from `path`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "is_default_timestamp")]
+    pub modified: AfsTimestamp,
+}
+
+fn is_default_timestamp(timestamp: &AfsTimestamp) -> bool {
+    *timestamp == AfsTimestamp::default()
+}
+
+/// A file to build into an archive with [`AfsArchive::build`]
+#[derive(Debug, Clone)]
+pub struct AfsFile<'b> {
+    pub name: &'b str,
+    pub modified: AfsTimestamp,
+    pub data: &'b [u8],
+}
+
+impl<'a> FromBytes<'a> for AfsArchive<'a> {
+    fn from_bytes(data: &'a [u8]) -> Result<Self, CodecError> {
+        Ok(AfsArchive::parse(data)?)
+    }
+}
+
+/// Files are named by their [`AfsIndexEntry::path`]s
+impl<'a> Decode for AfsArchive<'a> {
+    type Out = Vec<VirtualFile>;
+    fn decode(&self) -> Result<Self::Out, CodecError> {
+        Ok(self
+            .index()
+            .files
+            .into_iter()
+            .zip(self.files())
+            .map(|(entry, (_, bytes))| VirtualFile {
+                path: entry.path.into(),
+                bytes: bytes.to_vec(),
+            })
+            .collect())
+    }
+}
+
+/// Rebuilds the archive with the same files, names, and order. The files are laid out
+/// again, so the padding between them may come out different.
+impl<'a> Encode for AfsArchive<'a> {
+    fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        let files: Vec<AfsFile> = self
+            .files()
+            .map(|(entry, data)| AfsFile {
+                name: &entry.name,
+                modified: entry.modified,
+                data,
+            })
+            .collect();
+        Ok(AfsArchive::build(&files, self.has_names)?)
+    }
+}
+
+impl<'a> AfsArchive<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, AfsError> {
+        if data.len() < 8 || data[..4] != MAGIC {
+            return Err(AfsError::Magic);
+        }
+        let mut reader = BinReader::at(data, 4).with_byte_order(ByteOrder::Little);
+        let count = reader.u32()? as usize;
+        let mut entries = Vec::with_capacity(count.min(data.len() / 8));
+        for index in 0..count {
+            let offset = reader.u32()?;
+            let size = reader.u32()?;
+            if offset as usize + size as usize > data.len() {
+                return Err(AfsError::FileOutOfBounds { index, offset, size });
+            }
+            entries.push(AfsEntry {
+                offset,
+                size,
+                name: String::new(),
+                modified: AfsTimestamp::default(),
+            });
+        }
+
+        // The name table is usually found right after the file table, but some archives
+        // keep its location just before the first file instead
+        let table_end = reader.position();
+        let first_file = entries
+            .iter()
+            .filter(|entry| entry.size > 0)
+            .map(|entry| entry.offset as usize)
+            .min();
+        let name_table = [Some(table_end), first_file.and_then(|offset| offset.checked_sub(8))]
+            .into_iter()
+            .flatten()
+            .filter(|&offset| offset >= table_end)
+            .find_map(|offset| {
+                let mut reader = BinReader::at(data, offset).with_byte_order(ByteOrder::Little);
+                let table_offset = reader.u32().ok()? as usize;
+                let table_size = reader.u32().ok()? as usize;
+                let valid = table_offset >= table_end
+                    && table_size >= count * NAME_ENTRY_SIZE
+                    && table_offset + count * NAME_ENTRY_SIZE <= data.len();
+                valid.then_some(table_offset)
+            });
+        if let Some(table_offset) = name_table {
+            let mut reader = BinReader::at(data, table_offset).with_byte_order(ByteOrder::Little);
+            for entry in entries.iter_mut() {
+                let name = reader.bytes(NAME_LEN)?;
+                let len = name.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+                entry.name = SHIFT_JIS.decode(&name[..len]).0.into_owned();
+                entry.modified = AfsTimestamp {
+                    year: reader.u16()?,
+                    month: reader.u16()?,
+                    day: reader.u16()?,
+                    hour: reader.u16()?,
+                    minute: reader.u16()?,
+                    second: reader.u16()?,
+                };
+                // Usually the file's size again
+                reader.skip(4);
+            }
+        }
+        Ok(AfsArchive {
+            data,
+            entries,
+            has_names: name_table.is_some(),
+        })
+    }
+
+    pub fn entries(&self) -> &[AfsEntry] {
+        &self.entries
+    }
+
+    /// Every file in the archive along with its entry, in order
+    pub fn files(&self) -> impl Iterator<Item = (&AfsEntry, &'a [u8])> + '_ {
+        let data = self.data;
+        self.entries.iter().map(move |entry| {
+            let start = entry.offset as usize;
+            (entry, &data[start..start + entry.size as usize])
+        })
+    }
+
+    /// Picks a file name for every file and notes what's needed to rebuild the archive.
+    /// Files without a name, with one that can't be used as a file name, or with the same
+    /// name as another file are named after their position instead, like `00012_name`.
+    pub fn index(&self) -> AfsIndex {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for entry in self.entries.iter() {
+            *counts.entry(entry.name.to_lowercase()).or_default() += 1;
+        }
+        let files = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                // Compared ignoring case, since that's how some file systems will see them
+                let unique = counts[&entry.name.to_lowercase()] == 1;
+                let path = match (is_usable_name(&entry.name), unique) {
+                    (true, true) => entry.name.clone(),
+                    (true, false) => format!("{index:05}_{}", entry.name),
+                    (false, _) => format!("{index:05}.bin"),
+                };
+                AfsIndexEntry {
+                    name: (self.has_names && path != entry.name).then(|| entry.name.clone()),
+                    path,
+                    modified: entry.modified,
+                }
+            })
+            .collect();
+        AfsIndex {
+            has_names: self.has_names,
+            files,
+        }
+    }
+
+    /// Builds an archive from its files in order, with a name table if `has_names`
+    pub fn build(files: &[AfsFile], has_names: bool) -> Result<Vec<u8>, AfsError> {
+        let table_end = 8 + files.len() * 8 + 8;
+        let mut out = Vec::with_capacity(table_end.next_multiple_of(ALIGNMENT));
+        out.extend(MAGIC);
+        out.extend((files.len() as u32).to_le_bytes());
+        out.resize(table_end.next_multiple_of(ALIGNMENT), 0);
+        for (index, file) in files.iter().enumerate() {
+            let offset = out.len();
+            out.extend(file.data);
+            out.resize(out.len().next_multiple_of(ALIGNMENT), 0);
+            let entry = 8 + index * 8;
+            out[entry..entry + 4].copy_from_slice(&(offset as u32).to_le_bytes());
+            out[entry + 4..entry + 8].copy_from_slice(&(file.data.len() as u32).to_le_bytes());
+        }
+        if !has_names {
+            return Ok(out);
+        }
+
+        let table_offset = out.len();
+        for file in files {
+            let (name, _, had_errors) = SHIFT_JIS.encode(file.name);
+            if had_errors || name.len() > NAME_LEN || name.contains(&0) {
+                return Err(AfsError::InvalidName(file.name.to_owned()));
+            }
+            out.extend(name.iter());
+            out.resize(out.len() + NAME_LEN - name.len(), 0);
+            let time = file.modified;
+            for value in [time.year, time.month, time.day, time.hour, time.minute, time.second] {
+                out.extend(value.to_le_bytes());
+            }
+            out.extend((file.data.len() as u32).to_le_bytes());
+        }
+        let table_size = out.len() - table_offset;
+        out.resize(out.len().next_multiple_of(ALIGNMENT), 0);
+        let pointer = table_end - 8;
+        out[pointer..pointer + 4].copy_from_slice(&(table_offset as u32).to_le_bytes());
+        out[pointer + 4..pointer + 8].copy_from_slice(&(table_size as u32).to_le_bytes());
+        Ok(out)
+    }
+}
+
+/// Whether a name from the name table can be used as a file name as it is, on Windows
+/// as well as everywhere else
+fn is_usable_name(name: &str) -> bool {
+    // Windows ignores extensions when looking for device names, so `CON.txt` is the console too
+    let (stem, _) = name.split_once('.').unwrap_or((name, ""));
+    let stem = stem.trim_end().to_ascii_uppercase();
+    let is_device = matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || (stem.len() == 4
+            && (stem.starts_with("COM") || stem.starts_with("LPT"))
+            && matches!(stem.as_bytes()[3], b'1'..=b'9'));
+    !name.is_empty()
+        && !is_device
+        && !name.contains(['/', '\\', ':', '<', '>', '"', '|', '?', '*'])
+        && !name.chars().any(char::is_control)
+        && name.trim() == name
+        && !name.ends_with('.')
+}
+
+impl AfsIndex {
+    /// Checks that every file has its own path, since they'd overwrite each other otherwise
+    pub fn check_paths(&self) -> Result<(), AfsError> {
+        let mut seen = HashSet::new();
+        for entry in self.files.iter() {
+            if !seen.insert(entry.path.to_lowercase()) {
+                return Err(AfsError::DuplicatePath(entry.path.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum AfsError {
+    #[error("Missing AFS magic number")]
+    Magic,
+
+    #[error("File {index} ({size:#X} bytes at {offset:#X}) is past the end of the archive")]
+    FileOutOfBounds { index: usize, offset: u32, size: u32 },
+
+    #[error("AFS archive ends before its tables do, at {0:#X}")]
+    Truncated(usize),
+
+    #[error("{0:?} can't be used as a file name in an AFS archive, which allows 32 bytes of Shift-JIS")]
+    InvalidName(String),
+
+    #[error("More than one file would be extracted to {0:?}")]
+    DuplicatePath(String),
+}
+
+impl From<OutOfBounds> for AfsError {
+    fn from(e: OutOfBounds) -> Self {
+        AfsError::Truncated(e.offset)
+    }
+}
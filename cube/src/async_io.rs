@@ -0,0 +1,61 @@
+//! Async versions of the functions that read files, for programs already running on a
+//! Tokio runtime, like a web service that previews archives. Only built with the `tokio`
+//! feature. Files are read with Tokio's own IO, and the decompressing and parsing that
+//! follows runs on its blocking thread pool so it doesn't hold up other tasks.
+
+use crate::{
+    iso::{self, IsoError},
+    tree::{self, ArchiveTree, TreeError},
+    virtual_fs::VirtualFile,
+};
+use std::{
+    io,
+    panic::resume_unwind,
+    path::{Path, PathBuf},
+};
+use tokio::task::spawn_blocking;
+
+impl VirtualFile {
+    /// Like [`VirtualFile::read`], without blocking the runtime
+    pub async fn read_async<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let path = path.as_ref();
+        let bytes = tokio::fs::read(path).await?;
+        Ok(VirtualFile {
+            path: path.to_owned(),
+            bytes,
+        })
+    }
+}
+
+/// Like [`iso::extract_iso`]. Disc images are parsed a piece at a time as they're read,
+/// so the whole extraction runs on the blocking thread pool.
+pub async fn extract_iso<P: AsRef<Path>>(iso_path: P) -> Result<Vec<VirtualFile>, IsoError> {
+    let iso_path = iso_path.as_ref().to_owned();
+    blocking(move || iso::extract_iso(iso_path)).await?
+}
+
+/// Like [`tree::extract_tree`]
+pub async fn extract_tree(data: Vec<u8>) -> Result<ArchiveTree, TreeError> {
+    blocking(move || tree::extract_tree(data)).await?
+}
+
+/// Reads an archive and extracts it with everything inside it, like [`tree::extract_tree`]
+pub async fn read_tree<P: AsRef<Path>>(path: P) -> Result<ArchiveTree, TreeError> {
+    let data = tokio::fs::read(path).await?;
+    extract_tree(data).await
+}
+
+/// Like [`tree::extract_disc_tree`]
+pub async fn extract_disc_tree<P: AsRef<Path>>(path: P) -> Result<ArchiveTree, TreeError> {
+    let path: PathBuf = path.as_ref().to_owned();
+    blocking(move || tree::extract_disc_tree(path)).await?
+}
+
+/// Runs `f` on the blocking thread pool, passing on any panic. Only fails if the runtime
+/// is shutting down.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> io::Result<T> {
+    spawn_blocking(f).await.map_err(|e| match e.try_into_panic() {
+        Ok(panic) => resume_unwind(panic),
+        Err(e) => io::Error::other(e),
+    })
+}
@@ -0,0 +1,310 @@
+//! BAA, the JAudio archive that bundles a game's sound data: its sound tables, sequence
+//! collections, instrument banks, and the wave systems that say where each sound's samples
+//! are in the separate .aw wave archives. The header is a list of chunks between `AA_<`
+//! and `>_AA`, each naming a kind of data and where in the file it is. The data itself
+//! is kept as it is, so each part can be swapped out without being decoded.
+
+use crate::util::read_u32;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const START_MAGIC: &[u8; 4] = b"AA_<";
+const END_MAGIC: &[u8; 4] = b">_AA";
+/// Largest alignment parts are detected as having. Parts are usually 32 byte aligned.
+const MAX_ALIGNMENT: u32 = 0x20;
+
+/// A whole audio archive. Every chunk points at one of `files`, which are laid out in
+/// order after the header, each starting on a multiple of `alignment`.
+#[derive(Debug, Clone)]
+pub struct Baa {
+    pub alignment: u32,
+    pub chunks: Vec<BaaChunk>,
+    pub files: Vec<BaaFile>,
+}
+
+/// One of the parts an archive is split into
+#[derive(Debug, Clone)]
+pub struct BaaFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// An entry in the archive's header, with the part it points to referred to by name.
+/// `id` is only stored for wave systems, banks, and sequences, and `flags` for wave systems.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BaaChunk {
+    pub kind: BaaChunkKind,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub id: u32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub flags: u32,
+    pub file: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BaaChunkKind {
+    /// Sound table
+    Bst,
+    /// Sound table names
+    Bstn,
+    /// Sequence collection
+    Bsc,
+    /// Wave system (WSYS)
+    Ws,
+    /// Instrument bank (IBNK)
+    Bnk,
+    /// Stream file table
+    Bsft,
+    /// Sound effect fade curves
+    Bfca,
+    /// A single sequence
+    Bms,
+}
+
+/// Everything needed to put an archive back together apart from the parts themselves,
+/// which are listed in the order they're laid out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaaIndex {
+    pub alignment: u32,
+    pub chunks: Vec<BaaChunk>,
+    pub files: Vec<String>,
+}
+
+impl BaaChunkKind {
+    fn from_tag(tag: &[u8]) -> Option<Self> {
+        Some(match tag {
+            b"bst " => BaaChunkKind::Bst,
+            b"bstn" => BaaChunkKind::Bstn,
+            b"bsc " => BaaChunkKind::Bsc,
+            b"ws  " => BaaChunkKind::Ws,
+            b"bnk " => BaaChunkKind::Bnk,
+            b"bsft" => BaaChunkKind::Bsft,
+            b"bfca" => BaaChunkKind::Bfca,
+            b"bms " => BaaChunkKind::Bms,
+            _ => return None,
+        })
+    }
+
+    fn tag(&self) -> &'static [u8; 4] {
+        match self {
+            BaaChunkKind::Bst => b"bst ",
+            BaaChunkKind::Bstn => b"bstn",
+            BaaChunkKind::Bsc => b"bsc ",
+            BaaChunkKind::Ws => b"ws  ",
+            BaaChunkKind::Bnk => b"bnk ",
+            BaaChunkKind::Bsft => b"bsft",
+            BaaChunkKind::Bfca => b"bfca",
+            BaaChunkKind::Bms => b"bms ",
+        }
+    }
+
+    /// File extension for this kind of part when it's extracted
+    pub fn extension(&self) -> &'static str {
+        match self {
+            BaaChunkKind::Bst => "bst",
+            BaaChunkKind::Bstn => "bstn",
+            BaaChunkKind::Bsc => "bsc",
+            BaaChunkKind::Ws => "wsys",
+            BaaChunkKind::Bnk => "bnk",
+            BaaChunkKind::Bsft => "bsft",
+            BaaChunkKind::Bfca => "bfca",
+            BaaChunkKind::Bms => "bms",
+        }
+    }
+
+    fn has_id(&self) -> bool {
+        matches!(self, BaaChunkKind::Ws | BaaChunkKind::Bnk | BaaChunkKind::Bms)
+    }
+
+    /// Whether the chunk gives where its part ends, rather than it having to be worked out
+    fn has_end(&self) -> bool {
+        matches!(
+            self,
+            BaaChunkKind::Bst | BaaChunkKind::Bstn | BaaChunkKind::Bsc | BaaChunkKind::Bms
+        )
+    }
+
+    /// Size of the chunk in the header, including its tag
+    fn size(&self) -> usize {
+        4 + 4 * (1 + self.has_id() as usize + self.has_end() as usize + (*self == BaaChunkKind::Ws) as usize)
+    }
+}
+
+impl Baa {
+    pub fn read(data: &[u8]) -> Result<Self, BaaError> {
+        if data.get(..4) != Some(START_MAGIC) {
+            return Err(BaaError::Magic);
+        }
+
+        // Chunks as (kind, id, flags, start, end), where the end isn't known for every kind
+        let mut entries = Vec::new();
+        let mut offset = 4;
+        loop {
+            let tag = data.get(offset..offset + 4).ok_or(BaaError::Truncated(offset))?;
+            if tag == END_MAGIC {
+                break;
+            }
+            let kind = BaaChunkKind::from_tag(tag).ok_or_else(|| BaaError::UnknownChunk {
+                tag: tag.escape_ascii().to_string(),
+                offset,
+            })?;
+            if offset + kind.size() > data.len() {
+                return Err(BaaError::Truncated(offset));
+            }
+            let mut fields = (offset + 4..offset + kind.size())
+                .step_by(4)
+                .map(|field| read_u32(data, field as u32));
+            let id = match kind.has_id() {
+                true => fields.next().unwrap_or_default(),
+                false => 0,
+            };
+            let start = fields.next().unwrap_or_default() as usize;
+            let end = kind.has_end().then(|| fields.next().unwrap_or_default() as usize);
+            let flags = fields.next().unwrap_or_default();
+            entries.push((kind, id, flags, start, end));
+            offset += kind.size();
+        }
+
+        // Parts are read in the order they're laid out, and chunks that point at the
+        // same place share one
+        let mut starts: Vec<usize> = entries.iter().map(|&(_, _, _, start, _)| start).collect();
+        starts.sort_unstable();
+        starts.dedup();
+        let header_end = offset + 4;
+        let alignment = starts.iter().fold(MAX_ALIGNMENT, |alignment, &start| {
+            alignment.min(1 << (start as u32).trailing_zeros().min(MAX_ALIGNMENT.trailing_zeros()))
+        });
+
+        let mut files = Vec::with_capacity(starts.len());
+        for (i, &start) in starts.iter().enumerate() {
+            let &(kind, _, _, _, end) = entries
+                .iter()
+                .find(|entry| entry.3 == start)
+                .expect("Every start is from a chunk");
+            let next = starts.get(i + 1).copied().unwrap_or(data.len());
+            let end = match end {
+                Some(end) => end,
+                None => own_size(kind, data, start).map_or(next, |size| start + size),
+            };
+            if start < header_end || start > end || end > data.len() {
+                return Err(BaaError::InvalidOffset { start, end });
+            }
+            files.push(BaaFile {
+                name: format!("{i:02}.{}", kind.extension()),
+                data: data[start..end].to_vec(),
+            });
+        }
+
+        let chunks = entries
+            .into_iter()
+            .map(|(kind, id, flags, start, _)| BaaChunk {
+                kind,
+                id,
+                flags,
+                file: files[starts.binary_search(&start).expect("Every start has a file")]
+                    .name
+                    .clone(),
+            })
+            .collect();
+        Ok(Baa {
+            alignment,
+            chunks,
+            files,
+        })
+    }
+
+    pub fn write(&self) -> Result<Vec<u8>, BaaError> {
+        if self.alignment == 0 {
+            return Err(BaaError::InvalidAlignment(self.alignment));
+        }
+        let header_size: usize = 4 + self.chunks.iter().map(|chunk| chunk.kind.size()).sum::<usize>() + 4;
+        let align = self.alignment as usize;
+        let mut starts = Vec::with_capacity(self.files.len());
+        let mut end = header_size;
+        for file in self.files.iter() {
+            let start = end.next_multiple_of(align);
+            starts.push(start);
+            end = start + file.data.len();
+        }
+        let offset = |value: usize| u32::try_from(value).map_err(|_| BaaError::TooLarge);
+
+        let mut out = Vec::with_capacity(end);
+        out.extend(START_MAGIC);
+        for chunk in self.chunks.iter() {
+            let index = self
+                .files
+                .iter()
+                .position(|file| file.name == chunk.file)
+                .ok_or_else(|| BaaError::MissingFile(chunk.file.clone()))?;
+            out.extend(chunk.kind.tag());
+            if chunk.kind.has_id() {
+                out.extend(chunk.id.to_be_bytes());
+            }
+            out.extend(offset(starts[index])?.to_be_bytes());
+            if chunk.kind.has_end() {
+                out.extend(offset(starts[index] + self.files[index].data.len())?.to_be_bytes());
+            }
+            if chunk.kind == BaaChunkKind::Ws {
+                out.extend(chunk.flags.to_be_bytes());
+            }
+        }
+        out.extend(END_MAGIC);
+        for (file, start) in self.files.iter().zip(starts) {
+            out.resize(start, 0);
+            out.extend(&file.data);
+        }
+        offset(out.len())?;
+        Ok(out)
+    }
+
+    /// Lists the archive's chunks and parts, without the parts' contents
+    pub fn index(&self) -> BaaIndex {
+        BaaIndex {
+            alignment: self.alignment,
+            chunks: self.chunks.clone(),
+            files: self.files.iter().map(|file| file.name.clone()).collect(),
+        }
+    }
+}
+
+/// Size of a part that records its own size in its header, like wave systems and banks do
+fn own_size(kind: BaaChunkKind, data: &[u8], start: usize) -> Option<usize> {
+    let magic: &[u8; 4] = match kind {
+        BaaChunkKind::Ws => b"WSYS",
+        BaaChunkKind::Bnk => b"IBNK",
+        _ => return None,
+    };
+    if data.get(start..start + 4)? != magic || start + 8 > data.len() {
+        return None;
+    }
+    Some(read_u32(data, start as u32 + 4) as usize)
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+#[derive(Debug, Error)]
+pub enum BaaError {
+    #[error("Not an audio archive (bad magic)")]
+    Magic,
+
+    #[error("Audio archive is truncated at {0:#X}")]
+    Truncated(usize),
+
+    #[error("Unknown chunk {tag:?} at {offset:#X} in audio archive header")]
+    UnknownChunk { tag: String, offset: usize },
+
+    #[error("Audio archive part from {start:#X} to {end:#X} is outside the file or overlaps its header")]
+    InvalidOffset { start: usize, end: usize },
+
+    #[error("Audio archive chunk points at {0:?}, which isn't one of its files")]
+    MissingFile(String),
+
+    #[error("Invalid alignment {0}. Parts have to start on a multiple of at least 1 byte")]
+    InvalidAlignment(u32),
+
+    #[error("Audio archive is too large, since offsets in it are 32 bits")]
+    TooLarge,
+}
@@ -0,0 +1,117 @@
+//! Headers Wii banners wrap around their U8 archives. opening.bnr starts with an IMET
+//! header holding the channel's title in every language, and the icon.bin, banner.bin,
+//! and sound.bin inside it each start with an IMD5 header. Both carry an MD5 hash that
+//! has to be recomputed whenever their contents change.
+
+use crate::{u8_archive::U8Archive, util::md5, util::read_u32};
+use thiserror::Error;
+
+const IMD5_SIZE: usize = 0x20;
+const IMET_SIZE: usize = 0x600;
+/// The IMET magic comes after this much padding at the start of the header
+const IMET_PADDING: usize = 0x40;
+/// Offset of the hash within the IMET header
+const IMET_HASH_OFFSET: usize = 0x5F0;
+/// Offset of the icon.bin, banner.bin, and sound.bin sizes within the IMET header
+const IMET_SIZES_OFFSET: usize = 0x4C;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BannerHeader {
+    /// The header of opening.bnr. Channel WADs have an extra 0x40 bytes before it, which
+    /// are kept here too.
+    Imet(Vec<u8>),
+    /// The header of the icon, banner, and sound files inside opening.bnr
+    Imd5([u8; IMD5_SIZE]),
+}
+
+impl BannerHeader {
+    /// Splits a wrapped file into its header and contents, usually a U8 archive. Returns
+    /// None if the file doesn't start with either header.
+    pub fn unwrap(data: &[u8]) -> Option<(BannerHeader, &[u8])> {
+        if data.len() >= IMD5_SIZE && &data[..4] == b"IMD5" {
+            let header = data[..IMD5_SIZE].try_into().unwrap();
+            return Some((BannerHeader::Imd5(header), &data[IMD5_SIZE..]));
+        }
+        // opening.bnr on discs has the magic at 0x40, and in channel WADs at 0x80
+        [0, 0x40].into_iter().find_map(|extra| {
+            let end = extra + IMET_SIZE;
+            let magic = data.get(extra + IMET_PADDING..extra + IMET_PADDING + 4)?;
+            (magic == b"IMET" && data.len() >= end).then(|| (BannerHeader::Imet(data[..end].to_vec()), &data[end..]))
+        })
+    }
+
+    /// Wraps new contents in this header, updating the sizes and hash it records
+    pub fn wrap(&self, contents: &[u8]) -> Result<Vec<u8>, BannerError> {
+        match self {
+            BannerHeader::Imd5(header) => {
+                let mut out = header.to_vec();
+                let size = u32::try_from(contents.len()).map_err(|_| BannerError::TooLarge)?;
+                out[4..8].copy_from_slice(&size.to_be_bytes());
+                out[0x10..0x20].copy_from_slice(&md5(contents));
+                out.extend(contents);
+                Ok(out)
+            }
+            BannerHeader::Imet(header) => {
+                let mut out = header.clone();
+                let start = header.len() - IMET_SIZE;
+                // The header records the sizes of the archive's meta files
+                if let Ok(archive) = U8Archive::parse(contents) {
+                    for (path, bytes) in archive.files() {
+                        let index = match path.file_name().and_then(|name| name.to_str()) {
+                            Some("icon.bin") => 0,
+                            Some("banner.bin") => 1,
+                            Some("sound.bin") => 2,
+                            _ => continue,
+                        };
+                        let offset = start + IMET_SIZES_OFFSET + index * 4;
+                        out[offset..offset + 4].copy_from_slice(&(bytes.len() as u32).to_be_bytes());
+                    }
+                }
+                // The hash covers the whole header with the hash itself zeroed out
+                let hash_offset = start + IMET_HASH_OFFSET;
+                out[hash_offset..hash_offset + 16].fill(0);
+                let hash = md5(&out[start..]);
+                out[hash_offset..hash_offset + 16].copy_from_slice(&hash);
+                out.extend(contents);
+                Ok(out)
+            }
+        }
+    }
+
+    /// The raw header bytes
+    pub fn bytes(&self) -> &[u8] {
+        match self {
+            BannerHeader::Imet(header) => header,
+            BannerHeader::Imd5(header) => header,
+        }
+    }
+
+    /// Reads a header previously written out with [`BannerHeader::bytes`]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BannerError> {
+        match BannerHeader::unwrap(bytes) {
+            Some((header, [])) => Ok(header),
+            _ => Err(BannerError::InvalidHeader),
+        }
+    }
+
+    /// Whether the hash in the header matches the contents
+    pub fn verify(&self, contents: &[u8]) -> bool {
+        match self {
+            BannerHeader::Imd5(header) => {
+                read_u32(header, 4) as usize == contents.len() && header[0x10..0x20] == md5(contents)
+            }
+            BannerHeader::Imet(header) => self
+                .wrap(contents)
+                .is_ok_and(|wrapped| wrapped[..header.len()] == header[..]),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BannerError {
+    #[error("Not an IMET or IMD5 header")]
+    InvalidHeader,
+
+    #[error("Contents are too large for the header to describe")]
+    TooLarge,
+}
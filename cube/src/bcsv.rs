@@ -0,0 +1,560 @@
+use crate::util::{read_u16, read_u32};
+use encoding_rs::SHIFT_JIS;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+/// BCSV (also known as JMap) files are binary tables used by Super Mario Galaxy,
+/// Luigi's Mansion, and other games built on JSystem. Column names are only stored
+/// as hashes, so a dictionary of known names is needed to recover them.
+/// Documentation on BCSVs:
+/// - Luma's Workshop: https://www.lumasworkshop.com/wiki/BCSV_(File_format)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bcsv {
+    /// Size in bytes of a single entry. Preserved so tables with padding or overlapping
+    /// bitfields can be written back identically.
+    pub entry_size: u32,
+    pub fields: Vec<BcsvField>,
+    pub entries: Vec<Vec<BcsvValue>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BcsvField {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<u32>,
+    #[serde(rename = "type")]
+    pub field_type: FieldType,
+    pub offset: u16,
+    #[serde(default = "BcsvField::default_mask")]
+    pub mask: u32,
+    #[serde(default)]
+    pub shift: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FieldType {
+    Long,
+    /// Fixed size 32 byte inline string
+    String,
+    Float,
+    Long2,
+    Short,
+    Char,
+    /// Offset into the string table that follows the entries
+    StringOffset,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum BcsvValue {
+    Int(i64),
+    Float(f32),
+    String(String),
+}
+
+impl Bcsv {
+    const HEADER_SIZE: usize = 0x10;
+    const FIELD_SIZE: usize = 0xC;
+
+    pub fn read(data: &[u8]) -> Result<Bcsv, BcsvError> {
+        if data.len() < Bcsv::HEADER_SIZE {
+            return Err(BcsvError::Truncated);
+        }
+        let num_entries = read_u32(data, 0x0) as usize;
+        let num_fields = read_u32(data, 0x4) as usize;
+        let data_offset = read_u32(data, 0x8) as usize;
+        let entry_size = read_u32(data, 0xC);
+
+        if entry_size == 0 && num_entries > 0 {
+            return Err(BcsvError::EmptyEntries(num_entries));
+        }
+        let string_table_offset = num_entries
+            .checked_mul(entry_size as usize)
+            .and_then(|size| size.checked_add(data_offset))
+            .filter(|&offset| offset <= data.len())
+            .ok_or(BcsvError::Truncated)?;
+        let fields_end = num_fields
+            .checked_mul(Bcsv::FIELD_SIZE)
+            .and_then(|size| size.checked_add(Bcsv::HEADER_SIZE));
+        if fields_end.is_none_or(|end| end > data.len()) {
+            return Err(BcsvError::Truncated);
+        }
+
+        let mut fields = Vec::with_capacity(num_fields);
+        for i in 0..num_fields {
+            let offset = (Bcsv::HEADER_SIZE + i * Bcsv::FIELD_SIZE) as u32;
+            let type_byte = data[offset as usize + 0xB];
+            let field = BcsvField {
+                name: None,
+                hash: Some(read_u32(data, offset)),
+                mask: read_u32(data, offset + 0x4),
+                offset: read_u16(data, offset + 0x8),
+                shift: data[offset as usize + 0xA],
+                field_type: FieldType::from_byte(type_byte).ok_or(BcsvError::InvalidFieldType(type_byte))?,
+            };
+            if field.offset as u32 + field.field_type.size() > entry_size {
+                return Err(BcsvError::FieldOutOfBounds(i));
+            }
+            fields.push(field);
+        }
+
+        let string_table = &data[string_table_offset..];
+        let mut entries = Vec::with_capacity(num_entries.min((data.len() - data_offset) / entry_size.max(1) as usize));
+        for i in 0..num_entries {
+            let entry = &data[data_offset + i * entry_size as usize..data_offset + (i + 1) * entry_size as usize];
+            entries.push(
+                fields
+                    .iter()
+                    .map(|field| field.read_value(entry, string_table))
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+
+        Ok(Bcsv {
+            entry_size,
+            fields,
+            entries,
+        })
+    }
+
+    pub fn write(&self) -> Result<Vec<u8>, BcsvError> {
+        let data_offset = Bcsv::HEADER_SIZE + self.fields.len() * Bcsv::FIELD_SIZE;
+        let mut out = Vec::with_capacity(data_offset + self.entries.len() * self.entry_size as usize);
+        out.extend((self.entries.len() as u32).to_be_bytes());
+        out.extend((self.fields.len() as u32).to_be_bytes());
+        out.extend((data_offset as u32).to_be_bytes());
+        out.extend(self.entry_size.to_be_bytes());
+
+        for (i, field) in self.fields.iter().enumerate() {
+            if field.offset as u32 + field.field_type.size() > self.entry_size {
+                return Err(BcsvError::FieldOutOfBounds(i));
+            }
+            out.extend(field.hash_value().ok_or(BcsvError::MissingFieldName(i))?.to_be_bytes());
+            out.extend(field.mask.to_be_bytes());
+            out.extend(field.offset.to_be_bytes());
+            out.push(field.shift);
+            out.push(field.field_type.to_byte());
+        }
+
+        let mut string_table = StringTable::default();
+        for (entry_idx, entry) in self.entries.iter().enumerate() {
+            if entry.len() != self.fields.len() {
+                return Err(BcsvError::WrongColumnCount {
+                    entry: entry_idx,
+                    expected: self.fields.len(),
+                    found: entry.len(),
+                });
+            }
+            let mut entry_data = vec![0u8; self.entry_size as usize];
+            for (field, value) in self.fields.iter().zip(entry) {
+                field.write_value(value, &mut entry_data, &mut string_table)?;
+            }
+            out.extend(entry_data);
+        }
+        out.extend(string_table.data);
+
+        // Nintendo's tools pad BCSVs to 32 bytes with '@'
+        while !out.len().is_multiple_of(32) {
+            out.push(b'@');
+        }
+        Ok(out)
+    }
+
+    /// Fills in the names of any fields whose hashes match one of the provided names.
+    pub fn resolve_names<'a>(&mut self, names: impl IntoIterator<Item = &'a str>) {
+        let names: HashMap<u32, &str> = names.into_iter().map(|name| (jmap_hash(name), name)).collect();
+        for field in self.fields.iter_mut().filter(|f| f.name.is_none()) {
+            if let Some(name) = field.hash.and_then(|hash| names.get(&hash)) {
+                field.name = Some(name.to_string());
+            }
+        }
+    }
+
+    /// Converts the table to CSV. Header cells are in the form `name:type:offset:mask:shift`
+    /// so the table can be rebuilt from the CSV alone. Unnamed fields use their hash in hex.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        let header: Vec<String> = self
+            .fields
+            .iter()
+            .map(|field| {
+                let name = field
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("0x{:08X}", field.hash.unwrap_or(0)));
+                format!(
+                    "{name}:{:?}:{}:0x{:08X}:{}",
+                    field.field_type, field.offset, field.mask, field.shift
+                )
+            })
+            .collect();
+        write_csv_row(&mut out, header.iter().map(String::as_str));
+
+        for entry in &self.entries {
+            let cells: Vec<String> = entry
+                .iter()
+                .map(|value| match value {
+                    BcsvValue::Int(i) => i.to_string(),
+                    BcsvValue::Float(f) => format!("{f:?}"),
+                    BcsvValue::String(s) => s.clone(),
+                })
+                .collect();
+            write_csv_row(&mut out, cells.iter().map(String::as_str));
+        }
+        out
+    }
+
+    pub fn from_csv(csv: &str) -> Result<Bcsv, BcsvError> {
+        let mut rows = parse_csv(csv).into_iter();
+        let header = rows.next().ok_or(BcsvError::InvalidCsvHeader(String::new()))?;
+        let fields = header
+            .iter()
+            .map(|cell| BcsvField::from_csv_header(cell))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let entries = rows
+            .enumerate()
+            .map(|(entry, row)| {
+                if row.len() != fields.len() {
+                    return Err(BcsvError::WrongColumnCount {
+                        entry,
+                        expected: fields.len(),
+                        found: row.len(),
+                    });
+                }
+                row.into_iter()
+                    .zip(&fields)
+                    .map(|(cell, field)| match field.field_type {
+                        FieldType::String | FieldType::StringOffset => Ok(BcsvValue::String(cell)),
+                        FieldType::Float => cell
+                            .parse()
+                            .map(BcsvValue::Float)
+                            .map_err(|_| BcsvError::InvalidValue(cell)),
+                        _ => cell
+                            .parse()
+                            .map(BcsvValue::Int)
+                            .map_err(|_| BcsvError::InvalidValue(cell)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let entry_size = fields
+            .iter()
+            .map(|f| f.offset as u32 + f.field_type.size())
+            .max()
+            .unwrap_or(0)
+            .next_multiple_of(4);
+        Ok(Bcsv {
+            entry_size,
+            fields,
+            entries,
+        })
+    }
+}
+
+impl BcsvField {
+    fn default_mask() -> u32 {
+        u32::MAX
+    }
+
+    /// The hash stored in the file. If only a name is known, the hash is derived from it.
+    pub fn hash_value(&self) -> Option<u32> {
+        self.hash.or_else(|| self.name.as_deref().map(jmap_hash))
+    }
+
+    fn read_value(&self, entry: &[u8], string_table: &[u8]) -> Result<BcsvValue, BcsvError> {
+        let offset = self.offset as u32;
+        let masked = |raw: u32| {
+            (raw & self.mask)
+                .checked_shr(self.shift as u32)
+                .ok_or(BcsvError::InvalidShift(self.shift))
+        };
+        Ok(match self.field_type {
+            FieldType::Long | FieldType::Long2 => BcsvValue::Int(masked(read_u32(entry, offset))? as i32 as i64),
+            FieldType::Short => BcsvValue::Int(masked(read_u16(entry, offset) as u32)? as i16 as i64),
+            FieldType::Char => BcsvValue::Int(masked(entry[offset as usize] as u32)? as i8 as i64),
+            FieldType::Float => BcsvValue::Float(f32::from_bits(read_u32(entry, offset))),
+            FieldType::String => BcsvValue::String(read_string(&entry[offset as usize..offset as usize + 0x20])),
+            FieldType::StringOffset => {
+                let string_offset = read_u32(entry, offset) as usize;
+                let string = string_table.get(string_offset..).ok_or(BcsvError::Truncated)?;
+                BcsvValue::String(read_string(string))
+            }
+        })
+    }
+
+    fn write_value(&self, value: &BcsvValue, entry: &mut [u8], strings: &mut StringTable) -> Result<(), BcsvError> {
+        let offset = self.offset as usize;
+        let size = self.field_type.size() as usize;
+        let slot = &mut entry[offset..offset + size];
+        match (self.field_type, value) {
+            (FieldType::Float, BcsvValue::Float(f)) => slot.copy_from_slice(&f.to_be_bytes()),
+            (FieldType::Float, BcsvValue::Int(i)) => slot.copy_from_slice(&(*i as f32).to_be_bytes()),
+            (FieldType::String, BcsvValue::String(s)) => {
+                let encoded = SHIFT_JIS.encode(s).0;
+                if encoded.len() >= size {
+                    return Err(BcsvError::StringTooLong(s.clone()));
+                }
+                slot[..encoded.len()].copy_from_slice(&encoded);
+            }
+            (FieldType::StringOffset, BcsvValue::String(s)) => {
+                slot.copy_from_slice(&strings.insert(s).to_be_bytes());
+            }
+            (FieldType::Long | FieldType::Long2 | FieldType::Short | FieldType::Char, BcsvValue::Int(i)) => {
+                // Multiple fields can share the same bytes via masks, so merge into what's already there
+                let mut raw = [0u8; 4];
+                raw[4 - size..].copy_from_slice(slot);
+                let existing = u32::from_be_bytes(raw);
+                let shifted = (*i as u32)
+                    .checked_shl(self.shift as u32)
+                    .ok_or(BcsvError::InvalidShift(self.shift))?;
+                let merged = (existing & !self.mask) | (shifted & self.mask);
+                slot.copy_from_slice(&merged.to_be_bytes()[4 - size..]);
+            }
+            _ => return Err(BcsvError::TypeMismatch(self.field_type)),
+        }
+        Ok(())
+    }
+
+    fn from_csv_header(cell: &str) -> Result<BcsvField, BcsvError> {
+        let invalid = || BcsvError::InvalidCsvHeader(cell.to_owned());
+        let parts: Vec<&str> = cell.split(':').collect();
+        let [name, field_type, offset, mask, shift] = parts[..] else {
+            return Err(invalid());
+        };
+        let (name, hash) = match name.strip_prefix("0x") {
+            Some(hex) => (None, Some(u32::from_str_radix(hex, 16).map_err(|_| invalid())?)),
+            None => (Some(name.to_owned()), None),
+        };
+        Ok(BcsvField {
+            name,
+            hash,
+            field_type: FieldType::from_name(field_type).ok_or_else(invalid)?,
+            offset: offset.parse().map_err(|_| invalid())?,
+            mask: u32::from_str_radix(mask.trim_start_matches("0x"), 16).map_err(|_| invalid())?,
+            shift: shift.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl FieldType {
+    pub fn from_byte(b: u8) -> Option<FieldType> {
+        match b {
+            0 => Some(FieldType::Long),
+            1 => Some(FieldType::String),
+            2 => Some(FieldType::Float),
+            3 => Some(FieldType::Long2),
+            4 => Some(FieldType::Short),
+            5 => Some(FieldType::Char),
+            6 => Some(FieldType::StringOffset),
+            _ => None,
+        }
+    }
+
+    pub fn to_byte(&self) -> u8 {
+        match self {
+            FieldType::Long => 0,
+            FieldType::String => 1,
+            FieldType::Float => 2,
+            FieldType::Long2 => 3,
+            FieldType::Short => 4,
+            FieldType::Char => 5,
+            FieldType::StringOffset => 6,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<FieldType> {
+        [
+            FieldType::Long,
+            FieldType::String,
+            FieldType::Float,
+            FieldType::Long2,
+            FieldType::Short,
+            FieldType::Char,
+            FieldType::StringOffset,
+        ]
+        .into_iter()
+        .find(|t| format!("{t:?}").eq_ignore_ascii_case(name))
+    }
+
+    /// Size in bytes of this field within an entry
+    pub fn size(&self) -> u32 {
+        match self {
+            FieldType::String => 0x20,
+            FieldType::Short => 2,
+            FieldType::Char => 1,
+            _ => 4,
+        }
+    }
+}
+
+/// The hash used for BCSV field names, computed over the name's Shift-JIS bytes.
+pub fn jmap_hash(name: &str) -> u32 {
+    SHIFT_JIS
+        .encode(name)
+        .0
+        .iter()
+        .fold(0u32, |hash, &c| hash.wrapping_mul(31).wrapping_add(c as i8 as u32))
+}
+
+/// Field names that show up in most Galaxy-family games, used when no dictionary is provided.
+pub const COMMON_FIELD_NAMES: &[&str] = &[
+    "name",
+    "Name",
+    "Type",
+    "Id",
+    "l_id",
+    "ObjectName",
+    "pos_x",
+    "pos_y",
+    "pos_z",
+    "dir_x",
+    "dir_y",
+    "dir_z",
+    "scale_x",
+    "scale_y",
+    "scale_z",
+    "Obj_arg0",
+    "Obj_arg1",
+    "Obj_arg2",
+    "Obj_arg3",
+    "Obj_arg4",
+    "Obj_arg5",
+    "Obj_arg6",
+    "Obj_arg7",
+    "SW_APPEAR",
+    "SW_DEAD",
+    "SW_A",
+    "SW_B",
+    "SW_SLEEP",
+    "CommonPath_ID",
+    "ClippingGroupId",
+    "GroupId",
+    "DemoGroupId",
+    "MapParts_ID",
+    "Obj_ID",
+    "CameraSetId",
+    "CastId",
+    "ViewGroupId",
+    "MessageId",
+    "ShapeModelNo",
+    "ParentID",
+    "ParamScale",
+    "ScenarioNo",
+    "ZoneName",
+    "FileName",
+    "no",
+    "id",
+];
+
+#[derive(Default)]
+struct StringTable {
+    data: Vec<u8>,
+    offsets: BTreeMap<String, u32>,
+}
+
+impl StringTable {
+    fn insert(&mut self, s: &str) -> u32 {
+        if let Some(offset) = self.offsets.get(s) {
+            return *offset;
+        }
+        let offset = self.data.len() as u32;
+        self.data.extend(SHIFT_JIS.encode(s).0.iter());
+        self.data.push(0);
+        self.offsets.insert(s.to_owned(), offset);
+        offset
+    }
+}
+
+fn read_string(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    SHIFT_JIS.decode(&data[..end]).0.into_owned()
+}
+
+pub(crate) fn write_csv_row<'a>(out: &mut String, cells: impl Iterator<Item = &'a str>) {
+    for (i, cell) in cells.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if cell.contains([',', '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&cell.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(cell);
+        }
+    }
+    out.push('\n');
+}
+
+fn parse_csv(csv: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut in_quotes = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            ('"', _) => in_quotes = !in_quotes,
+            (',', false) => row.push(std::mem::take(&mut cell)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            _ => cell.push(c),
+        }
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    rows
+}
+
+#[derive(Debug, Error)]
+pub enum BcsvError {
+    #[error("BCSV data is truncated or its header is inconsistent with its size")]
+    Truncated,
+
+    #[error("Unrecognized BCSV field type '{0}'")]
+    InvalidFieldType(u8),
+
+    #[error("BCSV has {0} entries, but its entries are 0 bytes long")]
+    EmptyEntries(usize),
+
+    #[error("Field {0} extends past the end of its entry")]
+    FieldOutOfBounds(usize),
+
+    #[error("Field {0} has neither a name nor a hash")]
+    MissingFieldName(usize),
+
+    #[error("Entry {entry} has {found} values, but there are {expected} fields")]
+    WrongColumnCount {
+        entry: usize,
+        expected: usize,
+        found: usize,
+    },
+
+    #[error("Value doesn't match field type {0:?}")]
+    TypeMismatch(FieldType),
+
+    #[error("String \"{0}\" is too long for an inline string field")]
+    StringTooLong(String),
+
+    #[error("Invalid CSV header cell \"{0}\". Expected name:type:offset:mask:shift")]
+    InvalidCsvHeader(String),
+
+    #[error("Invalid CSV value \"{0}\"")]
+    InvalidValue(String),
+
+    #[error("Field shift {0} is too large for a 32 bit value")]
+    InvalidShift(u8),
+}
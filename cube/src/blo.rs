@@ -0,0 +1,467 @@
+//! BLO, the J2D screen layout format that describes 2D menus and HUDs as a tree of panes.
+//! Only the parts needed to mod a layout's resources are understood: the textures its
+//! pictures show, the fonts and text of its text boxes, and the resource tables newer
+//! layouts keep textures in. Every other block is kept as hex, so a layout can be edited
+//! and written back without disturbing anything else in it.
+//!
+//! Panes that own children are followed by a BGN1 block, then the children, then an END1
+//! block, so the hierarchy is kept by writing blocks back in the same order.
+
+use crate::{
+    rarc::Rarc,
+    util::{from_hex_string, read_u16, read_u32, to_hex_string},
+};
+use encoding_rs::SHIFT_JIS;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use thiserror::Error;
+
+const MAGIC: &[u8; 4] = b"SCRN";
+const HEADER_SIZE: usize = 0x20;
+const BLOCK_HEADER_SIZE: usize = 0x8;
+/// Size of a pane's position, size, and tag, which every pane has
+const PANE_BASE_SIZE: usize = 0x10;
+/// The font colors, alignment, spacing, and size that come between a text box's font and
+/// its text
+const TEXT_STYLE_SIZE: usize = 0x12;
+
+/// A screen layout, as a flat list of its blocks in the order they're stored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Blo {
+    /// `blo1` in every known game
+    pub version: String,
+    /// The rest of the header after the block count, as hex. Usually padding.
+    pub header_extra: String,
+    pub blocks: Vec<BloBlock>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BloBlock {
+    /// A pane that shows a texture, such as PIC1
+    Picture {
+        magic: String,
+        /// The tag the game finds the pane by. Only for reference, since it's written from `pane`.
+        #[serde(default, skip_deserializing)]
+        tag: String,
+        /// The pane's position, size, and other settings, as hex
+        pane: String,
+        texture: ResourceReference,
+        palette: ResourceReference,
+        /// Everything after the palette, as hex
+        data: String,
+    },
+    /// A pane that shows text, such as TBX1
+    TextBox {
+        magic: String,
+        /// The tag the game finds the pane by. Only for reference, since it's written from `pane`.
+        #[serde(default, skip_deserializing)]
+        tag: String,
+        /// The pane's position, size, and other settings, as hex
+        pane: String,
+        font: ResourceReference,
+        /// Colors, alignment, spacing, and font size, as hex
+        style: String,
+        /// The text shown, with any control codes left in
+        text: String,
+        /// The text's bytes as hex, when they aren't valid Shift-JIS. Written in place of `text`.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        raw_text: Option<String>,
+        /// Everything after the text, as hex
+        data: String,
+    },
+    /// A table of resources that materials refer to by index, such as TEX1 for textures and
+    /// FNT1 for fonts
+    Resources {
+        magic: String,
+        resources: Vec<ResourceReference>,
+    },
+    /// Any other block, as hex
+    Other { magic: String, data: String },
+}
+
+/// A resource a layout loads by name, like `timg/icon.bti`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceReference {
+    /// Where the game looks for the resource. 0 means there isn't one, and 2 is the archive
+    /// the layout was loaded from.
+    pub source: u8,
+    pub name: String,
+}
+
+impl Blo {
+    pub fn read(data: &[u8]) -> Result<Blo, BloError> {
+        if data.len() < HEADER_SIZE {
+            return Err(BloError::Truncated(0));
+        }
+        if &data[..4] != MAGIC {
+            return Err(BloError::Magic);
+        }
+        let version = String::from_utf8_lossy(&data[0x4..0x8]).into_owned();
+        let num_blocks = read_u32(data, 0xC);
+
+        let mut blocks = Vec::new();
+        let mut offset = HEADER_SIZE;
+        for _ in 0..num_blocks {
+            if offset + BLOCK_HEADER_SIZE > data.len() {
+                return Err(BloError::Truncated(offset));
+            }
+            let magic = &data[offset..offset + 4];
+            let size = read_u32(data, offset as u32 + 4) as usize;
+            if size < BLOCK_HEADER_SIZE || offset + size > data.len() {
+                return Err(BloError::BlockOutOfBounds {
+                    magic: String::from_utf8_lossy(magic).into_owned(),
+                    offset,
+                });
+            }
+            blocks.push(read_block(&data[offset..offset + size], offset));
+            offset += size;
+        }
+
+        Ok(Blo {
+            version,
+            header_extra: to_hex_string(&data[0x10..HEADER_SIZE]),
+            blocks,
+        })
+    }
+
+    pub fn write(&self) -> Result<Vec<u8>, BloError> {
+        if self.version.len() != 4 {
+            return Err(BloError::MagicLength(self.version.clone()));
+        }
+        let header_extra = parse_hex(&self.header_extra)?;
+        if header_extra.len() != HEADER_SIZE - 0x10 {
+            return Err(BloError::HeaderLength(header_extra.len()));
+        }
+
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(self.version.as_bytes());
+        out.extend_from_slice(&[0; 4]); // File size, filled in below
+        out.extend_from_slice(&(self.blocks.len() as u32).to_be_bytes());
+        out.extend_from_slice(&header_extra);
+        for block in self.blocks.iter() {
+            let magic = block.magic();
+            if magic.len() != 4 {
+                return Err(BloError::MagicLength(magic.to_owned()));
+            }
+            let mut contents = block.write_contents()?;
+            // Panes align parts of themselves to 4 bytes from the start of the file
+            if !matches!(block, BloBlock::Other { .. }) {
+                contents.resize(
+                    (BLOCK_HEADER_SIZE + contents.len()).next_multiple_of(4) - BLOCK_HEADER_SIZE,
+                    0,
+                );
+            }
+            out.extend_from_slice(magic.as_bytes());
+            out.extend_from_slice(&((BLOCK_HEADER_SIZE + contents.len()) as u32).to_be_bytes());
+            out.extend(contents);
+        }
+        let size = out.len() as u32;
+        out[0x8..0xC].copy_from_slice(&size.to_be_bytes());
+        Ok(out)
+    }
+
+    /// Names of every texture the layout uses, each listed once, in the order they first appear
+    pub fn textures(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for block in self.blocks.iter() {
+            let references = match block {
+                BloBlock::Picture { texture, .. } => std::slice::from_ref(texture),
+                BloBlock::Resources { magic, resources } if magic == "TEX1" => resources.as_slice(),
+                _ => &[],
+            };
+            for reference in references.iter().filter(|r| r.source != 0 && !r.name.is_empty()) {
+                if !names.contains(&reference.name.as_str()) {
+                    names.push(&reference.name);
+                }
+            }
+        }
+        names
+    }
+
+    /// The text of every text box, in the order they're stored
+    pub fn texts(&self) -> impl Iterator<Item = &str> {
+        self.blocks.iter().filter_map(|block| match block {
+            BloBlock::TextBox { text, .. } => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Finds the textures this layout uses in the archive it was loaded from, the way the
+    /// game does: by file name, preferring files in a `timg` folder. Textures that aren't
+    /// in the archive are left out.
+    pub fn find_textures<'r>(&self, archive: &'r Rarc) -> Vec<(PathBuf, &'r [u8])> {
+        let files: Vec<(PathBuf, &[u8])> = archive.files().collect();
+        let in_timg = |path: &PathBuf| {
+            path.parent()
+                .and_then(|parent| parent.file_name())
+                .is_some_and(|dir| dir.eq_ignore_ascii_case("timg"))
+        };
+        self.textures()
+            .into_iter()
+            .filter_map(|name| {
+                // Names sometimes include the folder, which the lookup ignores
+                let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+                let matches = |path: &&(PathBuf, &[u8])| {
+                    path.0
+                        .file_name()
+                        .is_some_and(|file_name| file_name.eq_ignore_ascii_case(name))
+                };
+                files
+                    .iter()
+                    .filter(matches)
+                    .find(|(path, _)| in_timg(path))
+                    .or_else(|| files.iter().find(matches))
+                    .cloned()
+            })
+            .collect()
+    }
+}
+
+impl BloBlock {
+    pub fn magic(&self) -> &str {
+        match self {
+            BloBlock::Picture { magic, .. }
+            | BloBlock::TextBox { magic, .. }
+            | BloBlock::Resources { magic, .. }
+            | BloBlock::Other { magic, .. } => magic,
+        }
+    }
+
+    /// The block's contents after its magic and size
+    fn write_contents(&self) -> Result<Vec<u8>, BloError> {
+        let mut out = Vec::new();
+        match self {
+            BloBlock::Picture {
+                pane,
+                texture,
+                palette,
+                data,
+                ..
+            } => {
+                out.extend(parse_hex(pane)?);
+                texture.write(&mut out)?;
+                palette.write(&mut out)?;
+                out.extend(parse_hex(data)?);
+            }
+            BloBlock::TextBox {
+                pane,
+                font,
+                style,
+                text,
+                raw_text,
+                data,
+                ..
+            } => {
+                out.extend(parse_hex(pane)?);
+                font.write(&mut out)?;
+                out.extend(parse_hex(style)?);
+                let text = match raw_text {
+                    Some(raw_text) => parse_hex(raw_text)?,
+                    None => {
+                        let (encoded, _, unencodable) = SHIFT_JIS.encode(text);
+                        if unencodable {
+                            return Err(BloError::UnencodableText(text.clone()));
+                        }
+                        encoded.into_owned()
+                    }
+                };
+                let len = u16::try_from(text.len()).map_err(|_| BloError::TextTooLong(text.len()))?;
+                out.extend_from_slice(&len.to_be_bytes());
+                out.extend(text);
+                out.extend(parse_hex(data)?);
+            }
+            BloBlock::Resources { resources, .. } => {
+                let count = u16::try_from(resources.len()).map_err(|_| BloError::TooManyResources)?;
+                out.extend_from_slice(&count.to_be_bytes());
+                let mut entries = Vec::new();
+                let entries_start = 2 + resources.len() * 2;
+                for resource in resources.iter() {
+                    let offset =
+                        u16::try_from(entries_start + entries.len()).map_err(|_| BloError::TooManyResources)?;
+                    out.extend_from_slice(&offset.to_be_bytes());
+                    resource.write(&mut entries)?;
+                }
+                out.extend(entries);
+            }
+            BloBlock::Other { data, .. } => out.extend(parse_hex(data)?),
+        }
+        Ok(out)
+    }
+}
+
+impl ResourceReference {
+    /// Reads a reference at the start of `data`, returning it and its size
+    fn read(data: &[u8]) -> Option<(ResourceReference, usize)> {
+        let source = *data.first()?;
+        let len = *data.get(1)? as usize;
+        let name = data.get(2..2 + len)?;
+        let (name, _) = SHIFT_JIS.decode_without_bom_handling(name);
+        Some((
+            ResourceReference {
+                source,
+                name: name.into_owned(),
+            },
+            2 + len,
+        ))
+    }
+
+    fn write(&self, out: &mut Vec<u8>) -> Result<(), BloError> {
+        let (name, _, unencodable) = SHIFT_JIS.encode(&self.name);
+        if unencodable {
+            return Err(BloError::UnencodableText(self.name.clone()));
+        }
+        let len = u8::try_from(name.len()).map_err(|_| BloError::NameTooLong(self.name.clone()))?;
+        out.push(self.source);
+        out.push(len);
+        out.extend_from_slice(&name);
+        Ok(())
+    }
+}
+
+/// Reads a whole block, including its header. Blocks that don't parse as what their
+/// magic says they are are kept whole, so they're written back exactly as they were.
+fn read_block(block: &[u8], offset: usize) -> BloBlock {
+    let magic = String::from_utf8_lossy(&block[..4]).into_owned();
+    let parsed = match &block[..4] {
+        b"PIC1" => read_picture(block, offset, &magic),
+        b"TBX1" => read_text_box(block, offset, &magic),
+        b"TEX1" | b"FNT1" => read_resources(block, &magic),
+        _ => None,
+    };
+    parsed.unwrap_or_else(|| BloBlock::Other {
+        magic,
+        data: to_hex_string(&block[BLOCK_HEADER_SIZE..]),
+    })
+}
+
+/// Size of the settings every pane starts with, plus the count of the settings specific
+/// to its kind of pane that follows them
+fn pane_size(block: &[u8], offset: usize) -> Option<usize> {
+    let num_settings = *block.get(BLOCK_HEADER_SIZE)? as usize;
+    // Position, size, visibility, and tag always count as 6, then rotation, origin, alpha,
+    // and whether to inherit alpha are each there if the count says so
+    let optional_sizes = [2, 1, 1, 1];
+    let optional: usize = optional_sizes.iter().take(num_settings.saturating_sub(6)).sum();
+    let end = (offset + BLOCK_HEADER_SIZE + PANE_BASE_SIZE + optional).next_multiple_of(4) - offset;
+    // The kind of pane's own settings start with their count
+    (end < block.len()).then_some(end + 1 - BLOCK_HEADER_SIZE)
+}
+
+fn pane_tag(block: &[u8]) -> String {
+    let tag = &block[BLOCK_HEADER_SIZE + 0x4..BLOCK_HEADER_SIZE + 0x8];
+    if tag.iter().all(|b| b.is_ascii_graphic()) {
+        String::from_utf8_lossy(tag).into_owned()
+    } else {
+        format!("{:#X}", read_u32(tag, 0))
+    }
+}
+
+fn read_picture(block: &[u8], offset: usize, magic: &str) -> Option<BloBlock> {
+    let pane_size = pane_size(block, offset)?;
+    let mut pos = BLOCK_HEADER_SIZE + pane_size;
+    let (texture, size) = ResourceReference::read(&block[pos..])?;
+    pos += size;
+    let (palette, size) = ResourceReference::read(&block[pos..])?;
+    pos += size;
+    Some(BloBlock::Picture {
+        magic: magic.to_owned(),
+        tag: pane_tag(block),
+        pane: to_hex_string(&block[BLOCK_HEADER_SIZE..BLOCK_HEADER_SIZE + pane_size]),
+        texture,
+        palette,
+        data: to_hex_string(&block[pos..]),
+    })
+}
+
+fn read_text_box(block: &[u8], offset: usize, magic: &str) -> Option<BloBlock> {
+    let pane_size = pane_size(block, offset)?;
+    let mut pos = BLOCK_HEADER_SIZE + pane_size;
+    let (font, size) = ResourceReference::read(&block[pos..])?;
+    pos += size;
+    let style = block.get(pos..pos + TEXT_STYLE_SIZE)?;
+    pos += TEXT_STYLE_SIZE;
+    let len = read_u16(block.get(pos..pos + 2)?, 0) as usize;
+    pos += 2;
+    let text_bytes = block.get(pos..pos + len)?;
+    pos += len;
+    let (text, raw_text) = match SHIFT_JIS.decode_without_bom_handling_and_without_replacement(text_bytes) {
+        Some(text) => (text.into_owned(), None),
+        None => (
+            SHIFT_JIS.decode_without_bom_handling(text_bytes).0.into_owned(),
+            Some(to_hex_string(text_bytes)),
+        ),
+    };
+    Some(BloBlock::TextBox {
+        magic: magic.to_owned(),
+        tag: pane_tag(block),
+        pane: to_hex_string(&block[BLOCK_HEADER_SIZE..BLOCK_HEADER_SIZE + pane_size]),
+        font,
+        style: to_hex_string(style),
+        text,
+        raw_text,
+        data: to_hex_string(&block[pos..]),
+    })
+}
+
+/// Reads a table of resources: a count, then the offset of each resource from the start
+/// of the table
+fn read_resources(block: &[u8], magic: &str) -> Option<BloBlock> {
+    let table = &block[BLOCK_HEADER_SIZE..];
+    let count = read_u16(table.get(..2)?, 0) as usize;
+    let resources = (0..count)
+        .map(|i| {
+            let offset = read_u16(table.get(2 + i * 2..4 + i * 2)?, 0) as usize;
+            Some(ResourceReference::read(table.get(offset..)?)?.0)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let rebuilt = BloBlock::Resources {
+        magic: magic.to_owned(),
+        resources,
+    };
+    // Only understood if writing it back gives the same table, give or take padding
+    let written = rebuilt.write_contents().ok()?;
+    let padding = &table[written.len().min(table.len())..];
+    (table.starts_with(&written) && padding.iter().all(|&b| b == 0)).then_some(rebuilt)
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>, BloError> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(BloError::InvalidHex(hex.to_owned()));
+    }
+    from_hex_string(hex).map_err(|_| BloError::InvalidHex(hex.to_owned()))
+}
+
+#[derive(Debug, Error)]
+pub enum BloError {
+    #[error("Not a BLO screen layout (bad magic)")]
+    Magic,
+
+    #[error("BLO is truncated at {0:#X}")]
+    Truncated(usize),
+
+    #[error("{magic} block at {offset:#X} runs past the end of the file")]
+    BlockOutOfBounds { magic: String, offset: usize },
+
+    #[error("Magic {0:?} isn't four characters")]
+    MagicLength(String),
+
+    #[error("Header data is {0} bytes, but there's room for 16")]
+    HeaderLength(usize),
+
+    #[error("Invalid hex \"{0}\". Expected an even number of hex digits")]
+    InvalidHex(String),
+
+    #[error("Resource name {0:?} is longer than 255 bytes")]
+    NameTooLong(String),
+
+    #[error("Text is {0} bytes, more than a text box can hold")]
+    TextTooLong(usize),
+
+    #[error("{0:?} can't be written in Shift-JIS")]
+    UnencodableText(String),
+
+    #[error("Too many resources for one table")]
+    TooManyResources,
+}
@@ -0,0 +1,354 @@
+//! BMD and BDL, the J3D model formats. A model is a header followed by a list of sections,
+//! each starting with its magic and size: INF1 for the scene graph, VTX1 for vertices,
+//! MAT3 for materials, TEX1 for textures, and so on. BDLs are BMDs with an extra MDL3
+//! section of precompiled display lists.
+//!
+//! Only TEX1 is understood, which is enough to swap a model's textures. Every other
+//! section is kept exactly as it was.
+
+use crate::{
+    bti::{slice_at, BtiError, BtiHeader, TextureFormat},
+    util::{pad_to, read_u16, read_u32},
+};
+use encoding_rs::SHIFT_JIS;
+use serde::Serialize;
+use std::collections::HashMap;
+use thiserror::Error;
+
+const MAGIC: &[u8; 4] = b"J3D2";
+const HEADER_SIZE: usize = 0x20;
+const SECTION_HEADER_SIZE: usize = 0x8;
+/// TEX1's own header: its magic and size, the texture count, and where the texture
+/// headers and name table are
+const TEX1_HEADER_SIZE: usize = 0x14;
+
+/// A model, kept as the bytes it's stored as along with where each section is
+pub struct Bmd {
+    data: Vec<u8>,
+    sections: Vec<BmdSection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BmdSection {
+    pub magic: String,
+    /// From the start of the file, where the section's magic is
+    pub offset: usize,
+    /// Including the section's magic and size
+    pub size: usize,
+}
+
+/// A texture from a model's TEX1 section
+#[derive(Debug, Clone)]
+pub struct BmdTexture {
+    pub name: String,
+    /// The texture as a standalone BTI, header included
+    pub bti: Vec<u8>,
+}
+
+/// A texture header in TEX1 and the data it points to
+struct Tex1Entry {
+    header: BtiHeader,
+    image: Vec<u8>,
+    palette: Vec<u8>,
+}
+
+/// Everything in a TEX1 section, in a form it can be rebuilt from
+struct Tex1 {
+    entries: Vec<Tex1Entry>,
+    names: Vec<String>,
+    /// The name table as it's stored, since names never change
+    name_table: Vec<u8>,
+}
+
+impl Bmd {
+    pub fn read(data: &[u8]) -> Result<Self, BmdError> {
+        if data.len() < HEADER_SIZE {
+            return Err(BmdError::Truncated(0));
+        }
+        if &data[..4] != MAGIC {
+            return Err(BmdError::Magic);
+        }
+        let num_sections = read_u32(data, 0xC);
+
+        let mut sections = Vec::new();
+        let mut offset = HEADER_SIZE;
+        for _ in 0..num_sections {
+            if offset + SECTION_HEADER_SIZE > data.len() {
+                return Err(BmdError::Truncated(offset));
+            }
+            let magic = String::from_utf8_lossy(&data[offset..offset + 4]).into_owned();
+            let size = read_u32(data, offset as u32 + 4) as usize;
+            if size < SECTION_HEADER_SIZE || offset + size > data.len() {
+                return Err(BmdError::SectionOutOfBounds { magic, offset });
+            }
+            sections.push(BmdSection { magic, offset, size });
+            offset += size;
+        }
+
+        Ok(Bmd {
+            data: data.to_vec(),
+            sections,
+        })
+    }
+
+    /// `bmd3` or `bdl4` in most games, and `bmd2` in some early ones
+    pub fn version(&self) -> String {
+        String::from_utf8_lossy(&self.data[0x4..0x8]).into_owned()
+    }
+
+    /// The usual file extension for the model, `bdl` or `bmd`
+    pub fn extension(&self) -> &'static str {
+        match self.data[0x4..0x7] == *b"bdl" {
+            true => "bdl",
+            false => "bmd",
+        }
+    }
+
+    pub fn sections(&self) -> &[BmdSection] {
+        &self.sections
+    }
+
+    /// The first section with this magic, header included
+    pub fn section(&self, magic: &str) -> Option<&[u8]> {
+        let section = self.sections.iter().find(|section| section.magic == magic)?;
+        Some(&self.data[section.offset..section.offset + section.size])
+    }
+
+    /// The model as it's stored, with any replaced textures in it
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Every texture in TEX1, in the order materials refer to them. Models without a
+    /// TEX1 section have none. Textures that share image data each get their own copy.
+    pub fn textures(&self) -> Result<Vec<BmdTexture>, BmdError> {
+        let Some(tex1) = self.tex1()? else {
+            return Ok(Vec::new());
+        };
+        Ok(tex1
+            .entries
+            .into_iter()
+            .zip(tex1.names)
+            .map(|(entry, name)| BmdTexture {
+                name,
+                bti: entry.to_bti(),
+            })
+            .collect())
+    }
+
+    /// Replaces the texture at `index` in TEX1 with a BTI, which can be any size or
+    /// format. Its name stays the same, and materials go on using it by its index.
+    pub fn replace_texture(&mut self, index: usize, bti: &[u8]) -> Result<(), BmdError> {
+        let Some(mut tex1) = self.tex1()? else {
+            return Err(BmdError::NoTextures);
+        };
+        let count = tex1.entries.len();
+        let entry = tex1
+            .entries
+            .get_mut(index)
+            .ok_or(BmdError::TextureIndexOutOfRange { index, count })?;
+        *entry = Tex1Entry::from_bti(bti).map_err(|source| BmdError::Texture { index, source })?;
+        let section = tex1.write()?;
+        self.replace_section("TEX1", section)
+    }
+
+    fn tex1(&self) -> Result<Option<Tex1>, BmdError> {
+        self.section("TEX1").map(Tex1::read).transpose()
+    }
+
+    /// Swaps a section for a new one, moving everything after it to make room
+    fn replace_section(&mut self, magic: &str, contents: Vec<u8>) -> Result<(), BmdError> {
+        let idx = self
+            .sections
+            .iter()
+            .position(|section| section.magic == magic)
+            .expect("Section exists");
+        let BmdSection { offset, size, .. } = self.sections[idx];
+        let new_size = contents.len();
+        self.data.splice(offset..offset + size, contents);
+        let file_size = u32::try_from(self.data.len()).map_err(|_| BmdError::TooLarge)?;
+        self.data[0x8..0xC].copy_from_slice(&file_size.to_be_bytes());
+        self.sections[idx].size = new_size;
+        for section in self.sections[idx + 1..].iter_mut() {
+            section.offset = section.offset - size + new_size;
+        }
+        Ok(())
+    }
+}
+
+impl Tex1 {
+    /// Reads a whole TEX1 section, including its header. Texture headers give the
+    /// offsets of their image and palette from the start of the header itself.
+    fn read(section: &[u8]) -> Result<Self, BmdError> {
+        if section.len() < TEX1_HEADER_SIZE {
+            return Err(BmdError::InvalidTex1);
+        }
+        let count = read_u16(section, 0x8) as usize;
+        let headers_offset = read_u32(section, 0xC) as usize;
+        let names_offset = read_u32(section, 0x10) as usize;
+        if headers_offset + count * BtiHeader::SIZE > section.len() {
+            return Err(BmdError::InvalidTex1);
+        }
+        let entries = (0..count)
+            .map(|index| {
+                Tex1Entry::read(section, headers_offset + index * BtiHeader::SIZE)
+                    .map_err(|source| BmdError::Texture { index, source })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (names, name_table_size) = read_name_table(section, names_offset).ok_or(BmdError::InvalidTex1)?;
+        if names.len() != count {
+            return Err(BmdError::InvalidTex1);
+        }
+        Ok(Tex1 {
+            entries,
+            names,
+            name_table: section[names_offset..names_offset + name_table_size].to_vec(),
+        })
+    }
+
+    /// Lays the section out as the texture headers, then the name table, then each
+    /// image and palette aligned to 32 bytes. Textures with identical data share it.
+    fn write(&self) -> Result<Vec<u8>, BmdError> {
+        let count = u16::try_from(self.entries.len()).map_err(|_| BmdError::TooLarge)?;
+        let mut out = Vec::new();
+        out.extend(b"TEX1");
+        out.extend([0; 4]); // Size, filled in below
+        out.extend(count.to_be_bytes());
+        out.extend([0xFF; 2]);
+        out.extend((HEADER_SIZE as u32).to_be_bytes());
+        out.extend([0; 4]); // Name table offset, filled in below
+        out.resize(HEADER_SIZE, 0);
+        out.resize(HEADER_SIZE + self.entries.len() * BtiHeader::SIZE, 0);
+        let names_offset = out.len() as u32;
+        out[0x10..0x14].copy_from_slice(&names_offset.to_be_bytes());
+        out.extend(&self.name_table);
+        pad_to::<0x20>(&mut out);
+
+        let mut placed: HashMap<&[u8], usize> = HashMap::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            let header_offset = HEADER_SIZE + i * BtiHeader::SIZE;
+            let mut header = entry.header.clone();
+            let image_offset = place(&mut out, &mut placed, &entry.image);
+            header.image_data_offset = relative_offset(image_offset, header_offset)?;
+            header.palette_offset = match entry.palette.is_empty() {
+                true => 0,
+                false => relative_offset(place(&mut out, &mut placed, &entry.palette), header_offset)?,
+            };
+            out[header_offset..header_offset + BtiHeader::SIZE].copy_from_slice(&header.write());
+        }
+
+        let size = u32::try_from(out.len()).map_err(|_| BmdError::TooLarge)?;
+        out[0x4..0x8].copy_from_slice(&size.to_be_bytes());
+        Ok(out)
+    }
+}
+
+impl Tex1Entry {
+    /// Reads the texture header at `offset` in `data` and the image and palette it points to
+    fn read(data: &[u8], offset: usize) -> Result<Self, BtiError> {
+        let header = BtiHeader::read(&data[offset..])?;
+        let image_offset = offset as u64 + header.image_data_offset as u64;
+        let image_size = header.image_data_size()?;
+        let image = slice_at(data, image_offset, image_size).ok_or(BtiError::ImageDataOutOfBounds {
+            offset: image_offset,
+            size: image_size,
+            file_size: data.len(),
+        })?;
+        let uses_palette = TextureFormat::from_id(header.format).is_some_and(TextureFormat::uses_palette);
+        let palette = match uses_palette {
+            true => {
+                let palette_offset = offset as u64 + header.palette_offset as u64;
+                slice_at(data, palette_offset, header.num_colors as u64 * 2).ok_or(BtiError::PaletteOutOfBounds {
+                    offset: palette_offset,
+                    num_colors: header.num_colors,
+                    file_size: data.len(),
+                })?
+            }
+            false => &[],
+        };
+        Ok(Tex1Entry {
+            header,
+            image: image.to_vec(),
+            palette: palette.to_vec(),
+        })
+    }
+
+    fn from_bti(bti: &[u8]) -> Result<Self, BtiError> {
+        Self::read(bti, 0)
+    }
+
+    /// Lays the texture out the way standalone BTIs are: the header, then the image, then
+    /// the palette
+    fn to_bti(&self) -> Vec<u8> {
+        let mut header = self.header.clone();
+        header.image_data_offset = BtiHeader::SIZE as u32;
+        header.palette_offset = match self.palette.is_empty() {
+            true => 0,
+            false => (BtiHeader::SIZE + self.image.len()) as u32,
+        };
+        let mut out = header.write().to_vec();
+        out.extend(&self.image);
+        out.extend(&self.palette);
+        out
+    }
+}
+
+/// Reads a J3D name table: a count, then a hash and an offset from the start of the
+/// table for each name. Returns the names and how many bytes the table takes up.
+fn read_name_table(section: &[u8], offset: usize) -> Option<(Vec<String>, usize)> {
+    let table = section.get(offset..)?;
+    let count = read_u16(table.get(..2)?, 0) as usize;
+    let mut size = 4 + count * 4;
+    let names = (0..count)
+        .map(|i| {
+            let name_offset = read_u16(table.get(6 + i * 4..8 + i * 4)?, 0) as usize;
+            let name = table.get(name_offset..)?;
+            let len = name.iter().position(|&b| b == 0)?;
+            size = size.max(name_offset + len + 1);
+            Some(SHIFT_JIS.decode(&name[..len]).0.into_owned())
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some((names, size))
+}
+
+/// Appends data aligned to 32 bytes unless the same data is already in `out`, and
+/// returns where it is
+fn place<'a>(out: &mut Vec<u8>, placed: &mut HashMap<&'a [u8], usize>, data: &'a [u8]) -> usize {
+    *placed.entry(data).or_insert_with(|| {
+        let offset = out.len();
+        out.extend(data);
+        pad_to::<0x20>(out);
+        offset
+    })
+}
+
+fn relative_offset(offset: usize, header_offset: usize) -> Result<u32, BmdError> {
+    u32::try_from(offset - header_offset).map_err(|_| BmdError::TooLarge)
+}
+
+#[derive(Debug, Error)]
+pub enum BmdError {
+    #[error("Not a J3D model (bad magic)")]
+    Magic,
+
+    #[error("Model is truncated at {0:#X}")]
+    Truncated(usize),
+
+    #[error("{magic} section at {offset:#X} runs past the end of the file")]
+    SectionOutOfBounds { magic: String, offset: usize },
+
+    #[error("TEX1 section is corrupted")]
+    InvalidTex1,
+
+    #[error("Model has no TEX1 section")]
+    NoTextures,
+
+    #[error("Texture {index}: {source}")]
+    Texture { index: usize, source: BtiError },
+
+    #[error("Texture index {index} is out of range, since the model has {count} textures")]
+    TextureIndexOutOfRange { index: usize, count: usize },
+
+    #[error("Model is too large")]
+    TooLarge,
+}
@@ -1,10 +1,27 @@
-use crate::util::{from_hex_string, read_u16, read_u32, read_u64, to_hex_string};
+use crate::{
+    bcsv::write_csv_row,
+    util::{
+        from_base64, from_hex_string, read_u16, read_u32, to_base64, to_hex_string, BinReader, OutOfBounds, Warnings,
+    },
+    CodecError, Decode, Encode, FromBytes,
+};
 use encoding_rs::{SHIFT_JIS, UTF_16BE, UTF_8, WINDOWS_1252};
 use log::debug;
 use serde::{Deserialize, Serialize};
-use std::cmp::max;
+use std::{cmp::Reverse, collections::HashMap, fmt::Display, str::FromStr};
 use thiserror::Error;
 
+/// Sections in block aligned BMGs are padded to a multiple of this many bytes
+const BLOCK_SIZE: usize = 32;
+
+/// Version of the JSON layout BMGs are serialized to. Bumped whenever older versions of
+/// cube would pack the new layout wrong, so they can refuse to instead.
+pub const BMG_SCHEMA_VERSION: u32 = 2;
+
+/// Attributes given as this instead of hex are copied from the attributes most of the
+/// other messages have, for adding messages without knowing what their attributes mean
+pub const DEFAULT_ATTRIBUTES: &str = "default";
+
 /// BMGs are indexed text archives used in GameCube, Wii, and some WiiU games
 /// made by Nintendo.
 /// Documentation on BMGs:
@@ -17,7 +34,14 @@ pub struct Bmg {
     text_index_table: TextIndexTable,         // INF1
     string_pool: StringPool,                  // DAT1
     message_id_table: Option<MessageIdTable>, // MID1
+    flow_table: Option<FlowTable>,            // FLW1
+    flow_index: Option<FlowIndex>,            // FLI1
     unknown_sections: Vec<UnknownSection>,
+    pool_strings: bool,
+    /// Every section's size is padded to a multiple of this
+    block_alignment: u32,
+    /// Characters that were replaced while encoding added messages
+    unencodable_chars: Vec<UnencodableChar>,
 }
 
 impl Bmg {
@@ -25,48 +49,137 @@ impl Bmg {
         Bmg {
             header: BmgHeader::new(text_encoding),
             text_index_table: TextIndexTable::new(),
-            string_pool: StringPool::new(),
+            string_pool: StringPool::new(text_encoding),
             message_id_table: None,
+            flow_table: None,
+            flow_index: None,
             unknown_sections: Vec::with_capacity(0), // don't allocate for unknown sections
+            pool_strings: false,
+            block_alignment: text_encoding.default_block_alignment(),
+            unencodable_chars: Vec::new(),
         }
     }
 
     pub fn read(data: &[u8]) -> Result<Bmg, BmgError> {
+        Bmg::read_with(data, &mut Warnings::strict())
+    }
+
+    /// Like [`Bmg::read`], but salvages what it can from a damaged file instead of failing
+    /// outright. Sections that can't be read are skipped, and so are messages that don't
+    /// fit in INF1. Returns a description of each problem alongside the result.
+    pub fn read_permissive(data: &[u8]) -> Result<(Bmg, Vec<String>), BmgError> {
+        let mut warnings = Vec::new();
+        let bmg = Bmg::read_with(data, &mut Warnings::permissive(&mut warnings))?;
+        Ok((bmg, warnings))
+    }
+
+    fn read_with(data: &[u8], warnings: &mut Warnings) -> Result<Bmg, BmgError> {
+        let header = BmgHeader::read(data)?;
         let mut bmg = Bmg {
-            header: BmgHeader::read(data)?,
             text_index_table: TextIndexTable::new(),
-            string_pool: StringPool::new(),
+            string_pool: StringPool::new(header.encoding),
+            block_alignment: header.encoding.default_block_alignment(),
+            header,
             message_id_table: None,
+            flow_table: None,
+            flow_index: None,
             unknown_sections: Vec::with_capacity(0),
+            pool_strings: false,
+            unencodable_chars: Vec::new(),
         };
 
+        // Whether every section starts and ends on a block boundary, whatever the encoding
+        let mut aligned = true;
         let mut section_start = BmgHeader::SIZE;
-        for _ in 0..bmg.header.num_blocks {
-            // align if necessary
-            let alignment = bmg.block_padding();
-            while alignment != 0 && section_start % alignment != 0 {
-                section_start += 1;
+        for block in 0..bmg.header.num_blocks {
+            // Some files pad sections without counting the padding in their size
+            let padded = section_start.next_multiple_of(BLOCK_SIZE).min(data.len());
+            if data[section_start.min(padded)..padded].iter().all(|&b| b == 0) {
+                section_start = padded;
             }
+            aligned &= section_start.is_multiple_of(BLOCK_SIZE);
 
-            // read each section based on its magic value
-            match &data[section_start..section_start + 4] {
-                TextIndexTable::MAGIC => {
-                    bmg.text_index_table = TextIndexTable::read(&data[section_start..])?;
-                    section_start += bmg.text_index_table.section_size as usize;
-                }
-                StringPool::MAGIC => {
-                    bmg.string_pool = StringPool::read(&data[section_start..])?;
-                    section_start += bmg.string_pool.section_size as usize;
-                }
-                MessageIdTable::MAGIC => {
-                    bmg.message_id_table = Some(MessageIdTable::read(&data[section_start..])?);
-                    section_start += bmg.message_id_table.as_ref().unwrap().section_size as usize;
-                }
-                _ => {
-                    bmg.unknown_sections.push(UnknownSection::read(&data[section_start..])?);
-                    section_start += bmg.unknown_sections.last().unwrap().section_size as usize;
+            let mut reader = BinReader::at(data, section_start);
+            let (Ok(magic), Ok(section_size)) = (reader.bytes(4), reader.u32()) else {
+                let action = format!("Only {block} of {} sections could be read", bmg.header.num_blocks);
+                warnings.recover(BmgError::Truncated(section_start), &action)?;
+                break;
+            };
+            let mut section_size = section_size as usize;
+            let available = data.len() - section_start;
+            if section_size < 0x8 || section_size > available {
+                let error = BmgError::InvalidSectionSize {
+                    magic: String::from_utf8_lossy(magic).into_owned(),
+                    offset: section_start,
+                    section_size: section_size as u32,
+                };
+                match find_section(data, section_start + 0x8) {
+                    Some(next_section) => {
+                        let action = format!("Reading up to the next section at {next_section:#X}");
+                        warnings.recover(error, &action)?;
+                        section_size = next_section - section_start;
+                    }
+                    None => {
+                        warnings.recover(error, "Reading the rest of the file as that section")?;
+                        section_size = available;
+                    }
                 }
             }
+
+            // read each section based on its magic value
+            let section = &data[section_start..section_start + section_size];
+            let result = match magic {
+                TextIndexTable::MAGIC => TextIndexTable::read(section, warnings).map(|inf1| {
+                    bmg.text_index_table = inf1;
+                }),
+                StringPool::MAGIC => StringPool::read(section).map(|dat1| bmg.string_pool = dat1),
+                MessageIdTable::MAGIC => MessageIdTable::read(section).map(|mid1| {
+                    bmg.message_id_table = Some(mid1);
+                }),
+                FlowTable::MAGIC => FlowTable::read(section).map(|flw1| bmg.flow_table = Some(flw1)),
+                FlowIndex::MAGIC => FlowIndex::read(section).map(|fli1| bmg.flow_index = Some(fli1)),
+                _ => UnknownSection::read(section).map(|section| bmg.unknown_sections.push(section)),
+            };
+            if let Err(error) = result {
+                warnings.recover(error, "Skipping that section")?;
+            }
+            // Sizes on file include padding, so this lands on the next section
+            section_start += section_size;
+        }
+        if bmg.header.num_blocks > 0 {
+            aligned &= section_start.is_multiple_of(BLOCK_SIZE);
+            bmg.block_alignment = if aligned { BLOCK_SIZE as u32 } else { 1 };
+        }
+
+        // Keep pooling strings when re-encoding if the original shares any between messages
+        let mut offsets: Vec<u32> = bmg.text_index_table.messages.iter().map(|m| m.text_offset).collect();
+        offsets.sort_unstable();
+        bmg.pool_strings = offsets.windows(2).any(|pair| pair[0] == pair[1]);
+
+        // Every message needs its text in DAT1, and an ID if the file has IDs
+        let pool_size = bmg.string_pool.strings.len();
+        for (index, entry) in bmg.text_index_table.messages.iter_mut().enumerate() {
+            if entry.text_offset as usize > pool_size {
+                let error = BmgError::TextOffsetOutOfRange {
+                    index,
+                    offset: entry.text_offset,
+                };
+                warnings.recover(error, "Leaving that message empty")?;
+                entry.text_offset = pool_size as u32;
+            }
+        }
+        let num_ids = bmg
+            .message_id_table
+            .as_ref()
+            .filter(|mid1| mid1.raw_entries.is_none())
+            .map(|mid1| mid1.message_ids.len());
+        if let Some(num_ids) = num_ids.filter(|&ids| ids < bmg.text_index_table.messages.len()) {
+            let error = BmgError::MissingMessageIds {
+                messages: bmg.text_index_table.messages.len(),
+                ids: num_ids,
+            };
+            warnings.recover(error, "Skipping the messages without IDs")?;
+            bmg.text_index_table.truncate(num_ids);
         }
 
         Ok(bmg)
@@ -74,8 +187,8 @@ impl Bmg {
 
     pub fn write(&self) -> Vec<u8> {
         let mut out = Vec::with_capacity(self.header.file_size as usize);
-        let mut final_file_size = BmgHeader::SIZE as usize; // Header always this size
-        let align = self.block_padding() as u32;
+        let mut final_file_size = BmgHeader::SIZE; // Header always this size
+        let align = self.block_alignment;
 
         out.extend(self.header.write());
 
@@ -93,6 +206,18 @@ impl Bmg {
             out.extend(message_id_table);
         }
 
+        if let Some(flow_table) = self.flow_table.as_ref() {
+            let flow_table = flow_table.write(align);
+            final_file_size += flow_table.len();
+            out.extend(flow_table);
+        }
+
+        if let Some(flow_index) = self.flow_index.as_ref() {
+            let flow_index = flow_index.write(align);
+            final_file_size += flow_index.len();
+            out.extend(flow_index);
+        }
+
         for unk_section in self.unknown_sections.iter() {
             let unk_section = unk_section.write(align);
             final_file_size += unk_section.len();
@@ -104,13 +229,19 @@ impl Bmg {
         out
     }
 
-    fn block_padding(&self) -> usize {
-        match self.header.encoding {
-            TextEncoding::Undefined | TextEncoding::ShiftJIS => 32,
-            _ => 0,
+    /// What every section's size is padded to a multiple of: 32 for files whose sections
+    /// are block aligned, or 1 for ones that aren't padded at all. Files that are read keep
+    /// whatever their sections were padded to, regardless of their encoding.
+    pub fn block_alignment(&self) -> u32 {
+        self.block_alignment
+    }
+
+    pub fn set_block_alignment(&mut self, alignment: u32) -> Result<(), BmgError> {
+        if alignment == 0 {
+            return Err(BmgError::InvalidBlockAlignment(alignment));
         }
-        // ShiftJIS isn't said to be block aligned in the MKWii docs, but it appears
-        // to be based on Pikmin 2's main BMG file
+        self.block_alignment = alignment;
+        Ok(())
     }
 
     fn message_id_table_mut(&mut self) -> &mut MessageIdTable {
@@ -122,23 +253,163 @@ impl Bmg {
     }
 
     pub fn messages(&self) -> impl Iterator<Item = BmgMessage> + '_ {
+        (0..self.message_count()).map(|idx| self.decode_message(idx))
+    }
+
+    /// The messages as CSV, one row each with its ID, attributes, and text, for reviewing
+    /// or translating in a spreadsheet. The ID is blank if the BMG doesn't have IDs.
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        write_csv_row(&mut out, ["id", "attributes", "message"].into_iter());
+        for message in self.messages() {
+            let id = message.id.map(|id| id.to_string()).unwrap_or_default();
+            write_csv_row(
+                &mut out,
+                [id.as_str(), &message.attributes, &message.message].into_iter(),
+            );
+        }
+        out
+    }
+
+    /// Like [`Bmg::messages`], but takes the BMG along so the iterator can outlive it
+    pub fn into_messages(self) -> impl Iterator<Item = BmgMessage> {
+        (0..self.message_count()).map(move |idx| self.decode_message(idx))
+    }
+
+    /// Decodes the message at `index`
+    pub fn message(&self, index: usize) -> Option<BmgMessage> {
+        (index < self.message_count()).then(|| self.decode_message(index))
+    }
+
+    /// Decodes the first message with this ID. Always None without a MID1 section.
+    pub fn message_by_id(&self, id: MessageId) -> Option<BmgMessage> {
+        self.message(self.find_message(id)?)
+    }
+
+    /// Gives access to the message at `index` for editing it in place
+    pub fn message_mut(&mut self, index: usize) -> Option<BmgMessageMut<'_>> {
+        (index < self.message_count()).then_some(BmgMessageMut { bmg: self, index })
+    }
+
+    /// Gives access to the first message with this ID for editing it in place
+    pub fn message_by_id_mut(&mut self, id: MessageId) -> Option<BmgMessageMut<'_>> {
+        let index = self.find_message(id)?;
+        self.message_mut(index)
+    }
+
+    fn decode_message(&self, index: usize) -> BmgMessage {
+        let index_entry = &self.text_index_table.messages[index];
+        BmgMessage {
+            message: self.message_text(index),
+            id: self.message_id(index),
+            attributes: to_hex_string(&index_entry.attributes),
+        }
+    }
+
+    fn message_id(&self, index: usize) -> Option<MessageId> {
+        self.message_id_table
+            .as_ref()
+            .and_then(|mids| mids.message_ids.get(index).copied())
+    }
+
+    fn message_text(&self, index: usize) -> String {
+        let text_offset = self.text_index_table.messages[index].text_offset as usize;
+        let text = self.string_pool.strings.get(text_offset..).unwrap_or_default();
+        self.header.encoding.decode(text)
+    }
+
+    pub fn encoding(&self) -> TextEncoding {
+        self.header.encoding
+    }
+
+    pub fn file_id(&self) -> u16 {
+        self.text_index_table.bmg_file_id
+    }
+
+    pub fn default_color(&self) -> u8 {
+        self.text_index_table.default_color
+    }
+
+    /// Size of each INF1 entry, which is the 4 byte text offset plus the attributes
+    pub fn index_entry_size(&self) -> u16 {
+        self.text_index_table.entry_size
+    }
+
+    /// Every section in the order they're written, with the size each takes up
+    /// including padding
+    pub fn sections(&self) -> Vec<BmgSectionInfo> {
+        let align = self.block_alignment;
+        let known = |magic: &[u8], size: usize| BmgSectionInfo {
+            magic: String::from_utf8_lossy(magic).into_owned(),
+            size: size as u32,
+            known: true,
+        };
+        let mut sections = vec![
+            known(TextIndexTable::MAGIC, self.text_index_table.write(align).len()),
+            known(StringPool::MAGIC, self.string_pool.write(align).len()),
+        ];
+        if let Some(table) = &self.message_id_table {
+            sections.push(known(MessageIdTable::MAGIC, table.write(align).len()));
+        }
+        if let Some(table) = &self.flow_table {
+            sections.push(known(FlowTable::MAGIC, table.write(align).len()));
+        }
+        if let Some(index) = &self.flow_index {
+            sections.push(known(FlowIndex::MAGIC, index.write(align).len()));
+        }
+        sections.extend(self.unknown_sections.iter().map(|section| BmgSectionInfo {
+            magic: String::from_utf8_lossy(&section.magic).into_owned(),
+            size: section.write(align).len() as u32,
+            known: false,
+        }));
+        sections
+    }
+
+    /// How each message is stored in DAT1
+    pub fn message_layouts(&self) -> Vec<BmgMessageLayout> {
+        let codepoint_size = self.header.encoding.codepoint_size();
         self.text_index_table
             .messages
             .iter()
-            .enumerate()
-            .map(|(idx, index_entry)| {
-                let attributes = to_hex_string(&index_entry.attributes);
-                let message = self
-                    .header
-                    .encoding
-                    .decode(&self.string_pool.strings[index_entry.text_offset as usize..]);
-                let id = self.message_id_table.as_ref().map(|mids| mids.message_ids[idx]);
-                BmgMessage {
-                    message,
-                    id,
-                    attributes,
+            .map(|entry| {
+                let text = self
+                    .string_pool
+                    .strings
+                    .get(entry.text_offset as usize..)
+                    .unwrap_or_default();
+                let mut escape_sequences = Vec::new();
+                let mut offset = 0;
+                loop {
+                    // Text that isn't terminated, or has an escape sequence that doesn't fit, runs
+                    // to the end of DAT1 so none of it is lost
+                    let Some(codepoint) = text.get(offset..offset + codepoint_size) else {
+                        offset = text.len();
+                        break;
+                    };
+                    if codepoint.iter().all(|&b| b == 0) {
+                        offset += codepoint_size;
+                        break;
+                    } else if codepoint[codepoint_size - 1] == 0x1A
+                        && codepoint[..codepoint_size - 1].iter().all(|&b| b == 0)
+                    {
+                        let escape_len = text.get(offset + codepoint_size).copied().unwrap_or(0) as usize;
+                        if escape_len <= codepoint_size || offset + escape_len > text.len() {
+                            offset = text.len();
+                            break;
+                        }
+                        escape_sequences.push(text[offset + codepoint_size + 1..offset + escape_len].to_vec());
+                        offset += escape_len;
+                    } else {
+                        offset += codepoint_size;
+                    }
+                }
+                BmgMessageLayout {
+                    text_offset: entry.text_offset,
+                    size: offset,
+                    escape_sequences,
                 }
             })
+            .collect()
     }
 
     pub fn set_file_id(&mut self, id: u16) {
@@ -149,24 +420,392 @@ impl Bmg {
         self.text_index_table.default_color = color;
     }
 
-    pub fn set_message_id_format(&mut self, format: u8) {
-        self.message_id_table_mut().format = format;
+    /// Sets the format of MID1, which decides how big its entries are. Formats cube doesn't
+    /// know the entry size of can't be given IDs, so messages added after setting one
+    /// can't have IDs either. Messages already added keep their IDs, with the rest of
+    /// their entries padded with zeros or cut down. Fails if there are some and either
+    /// format's entry size isn't known, or if they were added without IDs.
+    pub fn set_message_id_format(&mut self, format: u8) -> Result<(), BmgError> {
+        if self.message_id_table.is_none() && self.message_count() > 0 {
+            return Err(BmgError::NoMessageIds);
+        }
+        self.message_id_table_mut().set_format(format)?;
+        self.update_file_size();
+        Ok(())
     }
 
     pub fn set_message_id_info(&mut self, info: u8) {
         self.message_id_table_mut().info = info;
     }
 
-    pub fn add_message(&mut self, message: BmgMessage) {
-        let encoded_message = self.header.encoding.encode(&message.message);
-        self.text_index_table.add_message(
-            self.string_pool.strings.len() as u32,
-            from_hex_string(&message.attributes).expect("Invalid hex string for message attributes"),
-        );
-        self.string_pool.add_message(&encoded_message);
+    /// When enabled, messages whose encoded text is identical to a previously added
+    /// message point at the existing string in DAT1 instead of storing another copy.
+    /// Works with or without a MID1 section since strings are matched by content.
+    /// Only affects messages added after this is set.
+    pub fn set_string_pooling(&mut self, enabled: bool) {
+        self.pool_strings = enabled;
+    }
+
+    pub fn string_pooling(&self) -> bool {
+        self.pool_strings
+    }
+
+    /// Characters in added messages that the text encoding can't represent. Each was
+    /// replaced with its substitute, or '?' if it doesn't have one.
+    pub fn unencodable_chars(&self) -> &[UnencodableChar] {
+        &self.unencodable_chars
+    }
+
+    /// Re-encodes every message in another text encoding, like going from CP1252 to UTF-16
+    /// for a translation that needs characters CP1252 doesn't have. DAT1 is rebuilt and
+    /// every message's offset updated to match. Fails without changing anything if a
+    /// message has a character the new encoding can't represent.
+    pub fn convert_encoding(&mut self, encoding: TextEncoding) -> Result<(), BmgError> {
+        let mut string_pool = StringPool::new(encoding);
+        // Messages that shared a string before still share one afterwards, including the
+        // empty string new pools start with if the old one had it too
+        let mut new_offsets = HashMap::new();
+        let empty = vec![0; self.header.encoding.codepoint_size()];
+        if self.string_pool.strings.starts_with(&empty) {
+            new_offsets.insert(0, 0);
+        }
+        let mut text_offsets = Vec::with_capacity(self.message_count());
+        for index in 0..self.message_count() {
+            let old_offset = self.text_index_table.messages[index].text_offset;
+            if let Some(&offset) = new_offsets.get(&old_offset) {
+                text_offsets.push(offset);
+                continue;
+            }
+            let text = self.message_text(index);
+            let (encoded_message, unencodable) = encoding.encode_checked(&text).map_err(|e| BmgError::InMessage {
+                index,
+                source: Box::new(e),
+            })?;
+            if let Some(&offset) = unencodable.first() {
+                return Err(BmgError::UnencodableInEncoding {
+                    index,
+                    character: text[offset..].chars().next().unwrap(),
+                    encoding,
+                });
+            }
+            let offset = string_pool.add_message(encoded_message, self.pool_strings);
+            new_offsets.insert(old_offset, offset);
+            text_offsets.push(offset);
+        }
+
+        for (entry, offset) in self.text_index_table.messages.iter_mut().zip(text_offsets) {
+            entry.text_offset = offset;
+        }
+        self.string_pool = string_pool;
+        self.header.encoding = encoding;
+        self.update_file_size();
+        Ok(())
+    }
+
+    /// The FLW1 section's flowcharts, if there is one
+    pub fn flow_table(&self) -> Option<&FlowTable> {
+        self.flow_table.as_ref()
+    }
+
+    pub fn flow_table_mut(&mut self) -> Option<&mut FlowTable> {
+        self.flow_table.as_mut()
+    }
+
+    /// Adds, replaces, or removes (with None) the FLW1 section
+    pub fn set_flow_table(&mut self, flow_table: Option<FlowTable>) {
+        self.header.num_blocks += flow_table.is_some() as u32;
+        self.header.num_blocks -= self.flow_table.is_some() as u32;
+        self.flow_table = flow_table;
+    }
+
+    /// The FLI1 section's flowchart entry points, if there is one
+    pub fn flow_index(&self) -> Option<&FlowIndex> {
+        self.flow_index.as_ref()
+    }
+
+    pub fn flow_index_mut(&mut self) -> Option<&mut FlowIndex> {
+        self.flow_index.as_mut()
+    }
+
+    /// Adds, replaces, or removes (with None) the FLI1 section
+    pub fn set_flow_index(&mut self, flow_index: Option<FlowIndex>) {
+        self.header.num_blocks += flow_index.is_some() as u32;
+        self.header.num_blocks -= self.flow_index.is_some() as u32;
+        self.flow_index = flow_index;
+    }
+
+    /// Adds a message to the end. Its attributes can be [`DEFAULT_ATTRIBUTES`] to copy
+    /// the ones most of the messages already in the BMG have.
+    pub fn add_message(&mut self, message: BmgMessage) -> Result<(), BmgError> {
+        let attributes = self.resolve_attributes(&message.attributes)?;
+        self.text_index_table.check_attributes(&attributes)?;
+        let (encoded_message, unencodable) = self.header.encoding.encode_checked(&message.message)?;
+        let message_index = self.text_index_table.messages.len();
+        self.unencodable_chars.extend(unencodable.into_iter().map(|offset| {
+            let character = message.message[offset..].chars().next().unwrap();
+            UnencodableChar {
+                message_index,
+                message_id: message.id,
+                offset,
+                character,
+                substitute: substitute_char(character),
+            }
+        }));
+        let text_offset = self.string_pool.add_message(encoded_message, self.pool_strings);
+        self.text_index_table.add_message(text_offset, attributes);
         if let Some(message_id) = message.id {
-            self.message_id_table_mut().add_message(message_id);
+            self.message_id_table_mut().add_message(message_id)?;
+        }
+        self.update_file_size();
+        Ok(())
+    }
+
+    pub fn message_count(&self) -> usize {
+        self.text_index_table.messages.len()
+    }
+
+    /// Index of the first message with this ID. Always None without a MID1 section.
+    pub fn find_message(&self, id: MessageId) -> Option<usize> {
+        self.message_id_table
+            .as_ref()?
+            .message_ids
+            .iter()
+            .position(|&mid| mid == id)
+    }
+
+    /// Replaces the text of the message at `index`, leaving every other message as it was.
+    /// The new text is added to the end of DAT1 rather than written over the old string,
+    /// since other messages may share it. Returns whether the text changed.
+    pub fn replace_message(&mut self, index: usize, text: &str) -> Result<bool, BmgError> {
+        let count = self.message_count();
+        let entry = self
+            .text_index_table
+            .messages
+            .get(index)
+            .ok_or(BmgError::MessageIndexOutOfRange { index, count })?;
+        let (encoded_message, unencodable) = self.header.encoding.encode_checked(text)?;
+        let message_id = self.message_id(index);
+        self.unencodable_chars.extend(unencodable.into_iter().map(|offset| {
+            let character = text[offset..].chars().next().unwrap();
+            UnencodableChar {
+                message_index: index,
+                message_id,
+                offset,
+                character,
+                substitute: substitute_char(character),
+            }
+        }));
+        // The encoded text includes its terminator, so this only matches the whole string
+        if self.string_pool.strings[entry.text_offset as usize..].starts_with(&encoded_message) {
+            return Ok(false);
+        }
+        let text_offset = self.string_pool.add_message(encoded_message, self.pool_strings);
+        self.text_index_table.messages[index].text_offset = text_offset;
+        self.update_file_size();
+        Ok(true)
+    }
+
+    /// Changes the ID of the message at `index`. Only BMGs with a MID1 section have IDs.
+    pub fn set_message_id(&mut self, index: usize, id: MessageId) -> Result<(), BmgError> {
+        let count = self.message_count();
+        let mids = self.message_id_table.as_mut().ok_or(BmgError::NoMessageIds)?;
+        if mids.raw_entries.is_some() {
+            return Err(BmgError::UnknownMessageIdFormat(mids.format));
+        }
+        let message_id = mids
+            .message_ids
+            .get_mut(index)
+            .ok_or(BmgError::MessageIndexOutOfRange { index, count })?;
+        *message_id = id;
+        Ok(())
+    }
+
+    /// Changes the attributes of the message at `index`, given as hex like in JSON or as
+    /// [`DEFAULT_ATTRIBUTES`]. Every message's attributes are the same length.
+    pub fn set_message_attributes(&mut self, index: usize, attributes: &str) -> Result<(), BmgError> {
+        let count = self.message_count();
+        let attributes = self.resolve_attributes(attributes)?;
+        self.text_index_table.check_attributes(&attributes)?;
+        let entry = self
+            .text_index_table
+            .messages
+            .get_mut(index)
+            .ok_or(BmgError::MessageIndexOutOfRange { index, count })?;
+        entry.attributes = attributes;
+        Ok(())
+    }
+
+    /// Replaces the text, attributes, and ID of the message at `index` at once. The text
+    /// is replaced like with [`Bmg::replace_message`], and the ID is left as it was if
+    /// `message` doesn't have one. Nothing is changed if any of them can't be.
+    pub fn set_message(&mut self, index: usize, message: BmgMessage) -> Result<(), BmgError> {
+        let count = self.message_count();
+        if index >= count {
+            return Err(BmgError::MessageIndexOutOfRange { index, count });
+        }
+        let attributes = self.resolve_attributes(&message.attributes)?;
+        self.text_index_table.check_attributes(&attributes)?;
+        if message.id.is_some() {
+            self.check_ids_editable()?;
+        }
+        self.replace_message(index, &message.message)?;
+        self.text_index_table.messages[index].attributes = attributes;
+        if let Some(id) = message.id {
+            self.set_message_id(index, id)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the message at `index` and returns it. The messages after it move up one,
+    /// taking their IDs with them, and flowchart nodes that show them are updated to
+    /// match. DAT1 is rebuilt without the removed message's text. Messages that a
+    /// flowchart shows can't be removed, since the node would be left without one.
+    pub fn remove_message(&mut self, index: usize) -> Result<BmgMessage, BmgError> {
+        let count = self.message_count();
+        let message = self
+            .message(index)
+            .ok_or(BmgError::MessageIndexOutOfRange { index, count })?;
+        self.check_ids_editable()?;
+        let shown_by = self.flow_table.as_ref().and_then(|flow_table| {
+            flow_table.nodes.iter().position(
+                |node| matches!(node, FlowNode::Message { message_index, .. } if *message_index as usize == index),
+            )
+        });
+        if let Some(node) = shown_by {
+            return Err(BmgError::MessageInFlowchart { index, node });
+        }
+        let order: Vec<usize> = (0..count).filter(|&i| i != index).collect();
+        self.rearrange_messages(&order);
+        Ok(message)
+    }
+
+    /// Moves the message at `from` so it ends up at `to`, shifting the ones in between.
+    /// See [`Bmg::reorder_messages`].
+    pub fn move_message(&mut self, from: usize, to: usize) -> Result<(), BmgError> {
+        let count = self.message_count();
+        if let Some(index) = [from, to].into_iter().find(|&index| index >= count) {
+            return Err(BmgError::MessageIndexOutOfRange { index, count });
+        }
+        let mut order: Vec<usize> = (0..count).collect();
+        order.remove(from);
+        order.insert(to, from);
+        self.reorder_messages(&order)
+    }
+
+    /// Puts the messages in a new order, where `order[i]` is the current index of the
+    /// message that should end up at `i`. IDs move along with their messages, flowchart
+    /// nodes are updated to show the same messages as before, and DAT1 is rebuilt with
+    /// the text in the new order.
+    pub fn reorder_messages(&mut self, order: &[usize]) -> Result<(), BmgError> {
+        let count = self.message_count();
+        let mut seen = vec![false; count];
+        let is_permutation = order.len() == count
+            && order
+                .iter()
+                .all(|&index| index < count && !std::mem::replace(&mut seen[index], true));
+        if !is_permutation {
+            return Err(BmgError::InvalidMessageOrder { count });
+        }
+        self.check_ids_editable()?;
+        self.rearrange_messages(order);
+        Ok(())
+    }
+
+    /// Messages can only be moved around if their IDs can be moved with them
+    fn check_ids_editable(&self) -> Result<(), BmgError> {
+        match &self.message_id_table {
+            Some(mids) if mids.raw_entries.is_some() => Err(BmgError::UnknownMessageIdFormat(mids.format)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Keeps only the messages at the indexes in `order`, in that order, and updates
+    /// everything that refers to messages by index or to their text by offset
+    fn rearrange_messages(&mut self, order: &[usize]) {
+        let mut new_indexes = vec![None; self.message_count()];
+        for (new_index, &index) in order.iter().enumerate() {
+            new_indexes[index] = Some(new_index);
+        }
+
+        // Strings are copied in the new order, and ones that messages shared stay shared
+        let layouts = self.message_layouts();
+        let codepoint_size = self.header.encoding.codepoint_size();
+        let mut string_pool = StringPool::new(self.header.encoding);
+        let mut offsets = HashMap::new();
+        if self.string_pool.strings.starts_with(&vec![0; codepoint_size]) {
+            offsets.insert(0, 0);
+        }
+        let mut entries: Vec<Option<TextIndexEntry>> = std::mem::take(&mut self.text_index_table.messages)
+            .into_iter()
+            .map(Some)
+            .collect();
+        for &index in order {
+            let mut entry = entries[index].take().unwrap();
+            let offset = entry.text_offset;
+            entry.text_offset = *offsets.entry(offset).or_insert_with(|| {
+                let text = &self.string_pool.strings[offset as usize..offset as usize + layouts[index].size];
+                string_pool.add_message(text.to_vec(), self.pool_strings)
+            });
+            self.text_index_table.messages.push(entry);
+        }
+        self.text_index_table.num_entries = order.len() as u16;
+        self.text_index_table.update_section_size();
+        self.string_pool = string_pool;
+
+        if let Some(mids) = self.message_id_table.as_mut() {
+            let extra_size = mids.extra.len() / mids.message_ids.len().max(1);
+            let message_ids = std::mem::take(&mut mids.message_ids);
+            let extra = std::mem::take(&mut mids.extra);
+            for &index in order.iter().filter(|&&index| index < message_ids.len()) {
+                mids.message_ids.push(message_ids[index]);
+                mids.extra.extend(&extra[index * extra_size..(index + 1) * extra_size]);
+            }
+            mids.num_messages = mids.message_ids.len() as u16;
+            mids.update_section_size();
+        }
+
+        if let Some(flow_table) = self.flow_table.as_mut() {
+            for node in flow_table.nodes.iter_mut() {
+                if let FlowNode::Message { message_index, .. } = node {
+                    if let Some(Some(new_index)) = new_indexes.get(*message_index as usize) {
+                        *message_index = *new_index as u16;
+                    }
+                }
+            }
+        }
+
+        self.unencodable_chars
+            .retain_mut(|unencodable| match new_indexes[unencodable.message_index] {
+                Some(new_index) => {
+                    unencodable.message_index = new_index;
+                    true
+                }
+                None => false,
+            });
+        self.update_file_size();
+    }
+
+    /// The attributes the most messages have, or the first of them if there's a tie.
+    /// None if there are no messages.
+    pub fn most_common_attributes(&self) -> Option<Vec<u8>> {
+        let entries = &self.text_index_table.messages;
+        most_common(entries.iter().map(|entry| entry.attributes.as_slice()))
+    }
+
+    fn resolve_attributes(&self, attributes: &str) -> Result<Vec<u8>, BmgError> {
+        match is_default_attributes(attributes) {
+            true => self.most_common_attributes().ok_or(BmgError::NoDefaultAttributes),
+            false => parse_attributes(attributes),
         }
+    }
+
+    fn add_unknown_section(&mut self, section: UnknownSection) {
+        self.unknown_sections.push(section);
+        self.header.num_blocks += 1;
+        self.update_file_size();
+    }
+
+    fn update_file_size(&mut self) {
         self.header.file_size = BmgHeader::SIZE as u32
             + self.text_index_table.section_size
             + self.string_pool.section_size
@@ -175,21 +814,232 @@ impl Bmg {
     }
 }
 
-impl From<BmgSerialize> for Bmg {
-    fn from(ser: BmgSerialize) -> Self {
+/// Finds the next section recognized by its magic at or after `start`, to pick up reading
+/// from after a section whose size can't be trusted
+fn find_section(data: &[u8], start: usize) -> Option<usize> {
+    const MAGICS: [&[u8]; 5] = [
+        TextIndexTable::MAGIC,
+        StringPool::MAGIC,
+        MessageIdTable::MAGIC,
+        FlowTable::MAGIC,
+        FlowIndex::MAGIC,
+    ];
+    let offset = data
+        .get(start..)?
+        .windows(4)
+        .position(|magic| MAGICS.contains(&magic))?;
+    Some(start + offset)
+}
+
+/// A section of a BMG file, as listed by [`Bmg::sections`]
+#[derive(Debug, Clone, Serialize)]
+pub struct BmgSectionInfo {
+    pub magic: String,
+    pub size: u32,
+    /// False for sections cube doesn't understand and only carries over unchanged
+    pub known: bool,
+}
+
+/// Where a message's text is stored in DAT1, as listed by [`Bmg::message_layouts`]
+#[derive(Debug, Clone, Serialize)]
+pub struct BmgMessageLayout {
+    pub text_offset: u32,
+    /// Encoded size of the text in bytes, including its null terminator
+    pub size: usize,
+    /// Contents of each escape sequence in the text, without the 0x1A and length prefix
+    pub escape_sequences: Vec<Vec<u8>>,
+}
+
+/// A character that couldn't be represented in a BMG's text encoding
+#[derive(Debug, Clone, Serialize)]
+pub struct UnencodableChar {
+    pub message_index: usize,
+    pub message_id: Option<MessageId>,
+    /// Offset of the character in the message text, in bytes
+    pub offset: usize,
+    pub character: char,
+    /// A similar looking replacement that's likely to be encodable
+    pub substitute: Option<&'static str>,
+}
+
+impl Display for UnencodableChar {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Message {}", self.message_index)?;
+        if let Some(id) = self.message_id {
+            write!(f, " (ID {id})")?;
+        }
+        write!(f, ": {:?} at byte {} can't be encoded", self.character, self.offset)?;
+        match self.substitute {
+            Some(substitute) => write!(f, ", try {substitute:?} instead"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A piece of a message's text, from [`text_segments`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextSegment<'a> {
+    Text(&'a str),
+    /// Contents of an escape sequence, without the 0x1A and length prefix
+    Escape(Vec<u8>),
+}
+
+/// Splits a message's text into plain text and the escape sequences between it, which
+/// are written the way [`TextEncoding::decode`] writes them. Anything from a malformed
+/// escape sequence onwards is treated as text.
+pub fn text_segments(text: &str) -> Vec<TextSegment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('\u{1A}') {
+        if start > 0 {
+            segments.push(TextSegment::Text(&rest[..start]));
+        }
+        rest = &rest[start..];
+        let Ok((tag, len)) = parse_escape_sequence(rest) else {
+            break;
+        };
+        segments.push(TextSegment::Escape(tag));
+        rest = &rest[len..];
+    }
+    if !rest.is_empty() {
+        segments.push(TextSegment::Text(rest));
+    }
+    segments
+}
+
+/// The tag bytes of the escape sequence at the start of `text`, and how long it is as
+/// text. Fails with the reason it's malformed. MSBT control tags are written the same way.
+pub(crate) fn parse_escape_sequence(text: &str) -> Result<(Vec<u8>, usize), &'static str> {
+    let tag_start = text.find("0x").ok_or("missing '0x' before tag bytes")?;
+    // this is in BYTES, not characters, so it's doubled for the hex digits
+    let tag_len: usize = text[1..tag_start].parse().map_err(|_| "tag length is not a number")?;
+    let tag_end = tag_len
+        .checked_mul(2)
+        .and_then(|digits| digits.checked_add(tag_start + 2))
+        .ok_or("tag is shorter than its declared length")?;
+    let tag = text
+        .get(tag_start + 2..tag_end)
+        .ok_or("tag is shorter than its declared length")?;
+    let tag_bytes = from_hex_string(tag)
+        .ok()
+        .filter(|_| tag.is_ascii())
+        .ok_or("tag contains invalid hex digits")?;
+    Ok((tag_bytes, tag_end))
+}
+
+/// Plain equivalents of characters that CP1252 and Shift-JIS are commonly missing
+fn substitute_char(c: char) -> Option<&'static str> {
+    Some(match c {
+        '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{2032}' => "'",
+        '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{2033}' => "\"",
+        '\u{2010}' | '\u{2011}' | '\u{2012}' | '\u{2013}' | '\u{2014}' | '\u{2015}' | '\u{2212}' => "-",
+        '\u{2026}' => "...",
+        '\u{00A0}' | '\u{2002}' | '\u{2003}' | '\u{2009}' | '\u{3000}' => " ",
+        '\u{301C}' | '\u{FF5E}' => "~",
+        'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' => "A",
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => "a",
+        'Ç' => "C",
+        'ç' => "c",
+        'È' | 'É' | 'Ê' | 'Ë' => "E",
+        'è' | 'é' | 'ê' | 'ë' => "e",
+        'Ì' | 'Í' | 'Î' | 'Ï' => "I",
+        'ì' | 'í' | 'î' | 'ï' => "i",
+        'Ñ' => "N",
+        'ñ' => "n",
+        'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' => "O",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' => "o",
+        'Ù' | 'Ú' | 'Û' | 'Ü' => "U",
+        'ù' | 'ú' | 'û' | 'ü' => "u",
+        'Ý' | 'Ÿ' => "Y",
+        'ý' | 'ÿ' => "y",
+        'ß' => "ss",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Æ' => "AE",
+        'æ' => "ae",
+        _ => return None,
+    })
+}
+
+/// Whether attributes are given as [`DEFAULT_ATTRIBUTES`], in any case
+pub fn is_default_attributes(attributes: &str) -> bool {
+    attributes.eq_ignore_ascii_case(DEFAULT_ATTRIBUTES)
+}
+
+fn most_common<'a>(attributes: impl Iterator<Item = &'a [u8]>) -> Option<Vec<u8>> {
+    // Counts along with where each was first seen, so ties go the same way every time
+    let mut counts: HashMap<&[u8], (usize, usize)> = HashMap::new();
+    for (index, attributes) in attributes.enumerate() {
+        counts.entry(attributes).or_insert((0, index)).0 += 1;
+    }
+    let (attributes, _) = counts
+        .into_iter()
+        .max_by_key(|(_, (count, first))| (*count, Reverse(*first)))?;
+    Some(attributes.to_vec())
+}
+
+/// Attributes are stored as a hex string in serialized messages, e.g. "0001FF00"
+fn parse_attributes(attributes: &str) -> Result<Vec<u8>, BmgError> {
+    if !attributes.len().is_multiple_of(2) || !attributes.is_ascii() {
+        return Err(BmgError::InvalidAttributeHex(attributes.to_owned()));
+    }
+    from_hex_string(attributes).map_err(|_| BmgError::InvalidAttributeHex(attributes.to_owned()))
+}
+
+impl TryFrom<BmgSerialize> for Bmg {
+    type Error = BmgError;
+    fn try_from(mut ser: BmgSerialize) -> Result<Self, Self::Error> {
+        if ser.schema_version > BMG_SCHEMA_VERSION {
+            return Err(BmgError::NewerSchema(ser.schema_version));
+        }
         let mut bmg = Bmg::new(ser.metadata.encoding);
+        bmg.set_string_pooling(ser.metadata.pool_strings);
         bmg.set_file_id(ser.metadata.bmg_file_id);
         bmg.set_default_color(ser.metadata.default_color);
+        if let Some(alignment) = ser.metadata.block_alignment {
+            bmg.set_block_alignment(alignment)?;
+        }
         if let Some(format) = ser.metadata.message_id_format {
-            bmg.set_message_id_format(format)
-        };
+            bmg.set_message_id_format(format)?;
+        }
         if let Some(info) = ser.metadata.message_id_info {
             bmg.set_message_id_info(info);
         }
-        for message in ser.messages {
-            bmg.add_message(message);
+        bmg.set_flow_table(ser.flow_table);
+        bmg.set_flow_index(ser.flow_index);
+
+        // Default attributes come from every message in the file, not just the ones
+        // before them. Invalid attributes are reported when their message is added.
+        let explicit: Vec<Vec<u8>> = ser
+            .messages
+            .iter()
+            .filter(|message| !is_default_attributes(&message.attributes))
+            .filter_map(|message| parse_attributes(&message.attributes).ok())
+            .collect();
+        if let Some(default) = most_common(explicit.iter().map(Vec::as_slice)) {
+            let default = to_hex_string(&default);
+            for message in ser.messages.iter_mut() {
+                if is_default_attributes(&message.attributes) {
+                    message.attributes.clone_from(&default);
+                }
+            }
+        }
+        for (index, message) in ser.messages.into_iter().enumerate() {
+            bmg.add_message(message).map_err(|e| BmgError::InMessage {
+                index,
+                source: Box::new(e),
+            })?;
+        }
+        if let Some(data) = ser.metadata.message_id_data {
+            let data = from_base64(&data).ok_or(BmgError::InvalidMessageIdData)?;
+            let num_messages = bmg.message_count() as u16;
+            bmg.message_id_table_mut().set_entry_data(data, num_messages);
+            bmg.update_file_size();
+        }
+        for section in ser.unknown_sections {
+            bmg.add_unknown_section(section.try_into()?);
         }
-        bmg
+        Ok(bmg)
     }
 }
 
@@ -199,6 +1049,7 @@ impl Serialize for Bmg {
         S: serde::Serializer,
     {
         BmgSerialize {
+            schema_version: BMG_SCHEMA_VERSION,
             messages: self.messages().collect(),
             metadata: BmgSerializeMetadata {
                 encoding: self.header.encoding,
@@ -206,18 +1057,50 @@ impl Serialize for Bmg {
                 default_color: self.text_index_table.default_color,
                 message_id_format: self.message_id_table.as_ref().map(|t| t.format),
                 message_id_info: self.message_id_table.as_ref().map(|t| t.info),
+                message_id_data: self
+                    .message_id_table
+                    .as_ref()
+                    .map(MessageIdTable::entry_data)
+                    .filter(|data| !data.is_empty())
+                    .map(to_base64),
+                pool_strings: self.pool_strings,
+                block_alignment: Some(self.block_alignment),
             },
+            flow_table: self.flow_table.clone(),
+            flow_index: self.flow_index.clone(),
+            unknown_sections: self.unknown_sections.iter().map(Into::into).collect(),
         }
         .serialize(serializer)
     }
 }
 
+impl FromBytes<'_> for Bmg {
+    fn from_bytes(data: &[u8]) -> Result<Self, CodecError> {
+        Ok(Bmg::read(data)?)
+    }
+}
+
+impl Decode for Bmg {
+    type Out = Vec<BmgMessage>;
+    fn decode(&self) -> Result<Self::Out, CodecError> {
+        Ok(self.messages().collect())
+    }
+}
+
+impl Encode for Bmg {
+    fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        Ok(self.write())
+    }
+}
+
 impl<'de> Deserialize<'de> for Bmg {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        BmgSerialize::deserialize(deserializer).map(Into::into)
+        BmgSerialize::deserialize(deserializer)?
+            .try_into()
+            .map_err(serde::de::Error::custom)
     }
 }
 
@@ -230,6 +1113,55 @@ pub struct BmgMessage {
     pub attributes: String,
 }
 
+/// A message in a BMG that can be edited in place, from [`Bmg::message_mut`]. Changes
+/// are made to the BMG straight away.
+pub struct BmgMessageMut<'a> {
+    bmg: &'a mut Bmg,
+    index: usize,
+}
+
+impl BmgMessageMut<'_> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Decodes the message as it is now
+    pub fn get(&self) -> BmgMessage {
+        self.bmg.decode_message(self.index)
+    }
+
+    pub fn text(&self) -> String {
+        self.bmg.message_text(self.index)
+    }
+
+    /// Replaces the message's text. See [`Bmg::replace_message`].
+    pub fn set_text(&mut self, text: &str) -> Result<bool, BmgError> {
+        self.bmg.replace_message(self.index, text)
+    }
+
+    pub fn id(&self) -> Option<MessageId> {
+        self.bmg.message_id(self.index)
+    }
+
+    pub fn set_id(&mut self, id: MessageId) -> Result<(), BmgError> {
+        self.bmg.set_message_id(self.index, id)
+    }
+
+    pub fn attributes(&self) -> &[u8] {
+        &self.bmg.text_index_table.messages[self.index].attributes
+    }
+
+    /// Replaces the message's attributes, given as hex like in JSON
+    pub fn set_attributes(&mut self, attributes: &str) -> Result<(), BmgError> {
+        self.bmg.set_message_attributes(self.index, attributes)
+    }
+
+    /// Removes the message from the BMG. See [`Bmg::remove_message`].
+    pub fn remove(self) -> Result<BmgMessage, BmgError> {
+        self.bmg.remove_message(self.index)
+    }
+}
+
 /// The minimum set of metadata needed to perfectly reconstruct the BMG from a serialized format,
 /// such as JSON. Serializing the raw BMG file format structs is not very human friendly.
 #[derive(Debug, Serialize, Deserialize)]
@@ -239,12 +1171,63 @@ struct BmgSerializeMetadata {
     default_color: u8,
     message_id_format: Option<u8>,
     message_id_info: Option<u8>,
+    /// As base64, what follows each ID in MID1 entries wider than 4 bytes, or the whole
+    /// table if the format's entry size isn't known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message_id_data: Option<String>,
+    /// Whether identical messages share a single string in DAT1
+    #[serde(default)]
+    pool_strings: bool,
+    /// What sections are padded to a multiple of. Defaults to the usual one for the
+    /// encoding when missing, as it is in files extracted by older versions.
+    #[serde(default)]
+    block_alignment: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct BmgSerialize {
+    /// Missing from files extracted before the layout was versioned, which are version 0
+    #[serde(default)]
+    schema_version: u32,
     metadata: BmgSerializeMetadata,
     messages: Vec<BmgMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    flow_table: Option<FlowTable>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    flow_index: Option<FlowIndex>,
+    /// Sections cube doesn't understand, kept so they're packed back as they were
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    unknown_sections: Vec<UnknownSectionSerialize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UnknownSectionSerialize {
+    magic: String,
+    /// Everything after the section's header, as base64
+    data: String,
+}
+
+impl From<&UnknownSection> for UnknownSectionSerialize {
+    fn from(section: &UnknownSection) -> Self {
+        UnknownSectionSerialize {
+            magic: String::from_utf8_lossy(&section.magic).into_owned(),
+            data: to_base64(&section.data),
+        }
+    }
+}
+
+impl TryFrom<UnknownSectionSerialize> for UnknownSection {
+    type Error = BmgError;
+    fn try_from(ser: UnknownSectionSerialize) -> Result<Self, Self::Error> {
+        let invalid = || BmgError::InvalidUnknownSection(ser.magic.clone());
+        let magic = ser.magic.as_bytes().try_into().map_err(|_| invalid())?;
+        let data = from_base64(&ser.data).ok_or_else(invalid)?;
+        Ok(UnknownSection {
+            magic,
+            section_size: data.len() as u32 + 0x8,
+            data,
+        })
+    }
 }
 
 #[derive(Debug)]
@@ -295,18 +1278,24 @@ impl BmgHeader {
 
     /// Assumes the first 0x20 bytes of the provided slice are a valid BMG header.
     pub fn read(data: &[u8]) -> Result<BmgHeader, BmgError> {
-        if &data[..0x8] != BmgHeader::MAGIC {
+        if data.starts_with(b"MsgStdBn") {
+            return Err(BmgError::IsMsbt);
+        } else if data.get(..0x8) != Some(BmgHeader::MAGIC) {
             return Err(BmgError::InvalidHeaderMagic);
         }
+        if data.len() < BmgHeader::SIZE {
+            return Err(BmgError::Truncated(data.len()));
+        }
 
-        let file_size = read_u32(data, 0x8);
-        let num_blocks = read_u32(data, 0xC);
-        let encoding_byte = data[0x10];
+        let mut reader = BinReader::at(data, BmgHeader::MAGIC.len());
+        let file_size = reader.u32()?;
+        let num_blocks = reader.u32()?;
+        let encoding_byte = reader.u8()?;
         let encoding = TextEncoding::from_byte(encoding_byte).ok_or(BmgError::InvalidTextEncoding(encoding_byte))?;
-        let _unk0 = data[0x11];
-        let _unk1 = read_u16(data, 0x12);
-        let _unk2 = read_u64(data, 0x14);
-        let _unk3 = read_u32(data, 0x1C);
+        let _unk0 = reader.u8()?;
+        let _unk1 = reader.u16()?;
+        let _unk2 = reader.u64()?;
+        let _unk3 = reader.u32()?;
 
         let header = BmgHeader {
             file_size,
@@ -354,6 +1343,17 @@ impl TextEncoding {
         }
     }
 
+    /// Block alignment that files in this encoding usually have, for new files and ones
+    /// extracted before the alignment was recorded
+    fn default_block_alignment(&self) -> u32 {
+        match self {
+            TextEncoding::Undefined | TextEncoding::ShiftJIS => BLOCK_SIZE as u32,
+            _ => 1,
+        }
+        // ShiftJIS isn't said to be block aligned in the MKWii docs, but it appears
+        // to be based on Pikmin 2's main BMG file
+    }
+
     fn codepoint_size(&self) -> usize {
         match self {
             TextEncoding::UTF16 => 2,
@@ -362,7 +1362,7 @@ impl TextEncoding {
     }
 
     /// Decodes raw null-terminated bytes into a string using this format
-    pub fn decode<'a>(&self, data: &'a [u8]) -> String {
+    pub fn decode(&self, data: &[u8]) -> String {
         fn read_codepoint(data: &[u8], offset: usize, codepoint_size: usize) -> u16 {
             if codepoint_size == 2 {
                 read_u16(data, offset as u32)
@@ -376,10 +1376,15 @@ impl TextEncoding {
         let mut offset = 0;
         let mut cur_block_len = 0;
         loop {
-            let codepoint = read_codepoint(data, offset, codepoint_size);
+            // Text that runs off the end of the data ends there, as if it were terminated
+            let codepoint = match offset + codepoint_size <= data.len() {
+                true => read_codepoint(data, offset, codepoint_size),
+                false => 0,
+            };
             // null terminator
             if codepoint == 0 {
-                blocks.push(TextDecoderBlock::Text(&data[offset - cur_block_len..offset]));
+                let end = offset.min(data.len());
+                blocks.push(TextDecoderBlock::Text(&data[end - cur_block_len..end]));
                 break;
             }
             // escape sequences
@@ -387,11 +1392,15 @@ impl TextEncoding {
                 blocks.push(TextDecoderBlock::Text(&data[offset - cur_block_len..offset]));
                 cur_block_len = 0;
 
-                let tag_len = u8::from_be(data[offset + codepoint_size]);
+                // A tag too short to hold its own header, or too long for the data, ends the text
+                let tag_len = data.get(offset + codepoint_size).copied().unwrap_or(0) as usize;
+                if tag_len <= codepoint_size || offset + tag_len > data.len() {
+                    break;
+                }
                 blocks.push(TextDecoderBlock::EscapeSequence(
-                    &data[offset + codepoint_size + 1..offset + tag_len as usize],
+                    &data[offset + codepoint_size + 1..offset + tag_len],
                 ));
-                offset += tag_len as usize;
+                offset += tag_len;
             }
             // normal characters
             else {
@@ -423,37 +1432,75 @@ impl TextEncoding {
         text
     }
 
-    pub fn encode(&self, text: &str) -> Vec<u8> {
+    /// Encodes a string into null-terminated bytes using this format. Escape sequences
+    /// must be in the form produced by [`TextEncoding::decode`]. Characters this format
+    /// can't represent are replaced, see [`TextEncoding::encode_checked`].
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, BmgError> {
+        Ok(self.encode_checked(text)?.0)
+    }
+
+    /// Like [`TextEncoding::encode`], but also returns the byte offset of each character
+    /// that couldn't be represented. They're replaced with a similar looking substitute,
+    /// or '?' if there isn't one.
+    pub fn encode_checked(&self, text: &str) -> Result<(Vec<u8>, Vec<usize>), BmgError> {
         let encoder = match self {
             TextEncoding::Undefined | TextEncoding::CP1252 => WINDOWS_1252,
             TextEncoding::UTF8 => UTF_8,
             TextEncoding::UTF16 => UTF_16BE,
             TextEncoding::ShiftJIS => SHIFT_JIS,
         };
+        let escape_error = |offset, reason| BmgError::InvalidEscapeSequence { offset, reason };
         let mut out = Vec::new();
+        let mut unencodable = Vec::new();
         let mut offset = 0;
         while offset < text.len() {
             if text[offset..].starts_with('\u{1A}') {
-                let tag_start = text[offset..].find("0x").unwrap();
-                // this is in BYTES, not characters, so sometimes we multiply by two when dealing with characters
-                let tag_len: usize = text[offset + 1..offset + tag_start]
-                    .parse()
-                    .expect("Invalid tag length in BMG string");
-                let tag_str = &text[offset + tag_start + 2..offset + tag_start + 2 + (tag_len * 2)];
-                let tag_bytes = u64::from_str_radix(tag_str, 16).expect("Invalid digits in BMG text tag");
-                out.push(0x1A);
-                out.push((tag_len + 1 + self.codepoint_size()) as u8);
-                out.extend(&tag_bytes.to_be_bytes()[8 - tag_len..]);
-                offset += (tag_len * 2) + tag_start + 2;
+                let (tag_bytes, len) =
+                    parse_escape_sequence(&text[offset..]).map_err(|reason| escape_error(offset, reason))?;
+                let escape_len = u8::try_from(tag_bytes.len() + 1 + self.codepoint_size())
+                    .map_err(|_| escape_error(offset, "tag is too long"))?;
+                out.extend(self.control_code(0x1A));
+                out.push(escape_len);
+                out.extend(tag_bytes);
+                offset += len;
             } else {
                 let next_sub_index = text[offset..].find('\u{1A}').unwrap_or(text[offset..].len());
-                out.extend(encoder.encode(&text[offset..offset + next_sub_index]).0.iter());
+                let segment = &text[offset..offset + next_sub_index];
+                if *self == TextEncoding::UTF16 {
+                    // encoding_rs can only decode UTF-16, and encodes it as UTF-8 instead
+                    out.extend(segment.encode_utf16().flat_map(u16::to_be_bytes));
+                    offset += next_sub_index;
+                    continue;
+                }
+                let (encoded, _, had_errors) = encoder.encode(segment);
+                if had_errors {
+                    // encoding_rs would write unmappable characters as HTML entities
+                    for (index, c) in segment.char_indices() {
+                        let mut buf = [0; 4];
+                        let (encoded, _, unmappable) = encoder.encode(c.encode_utf8(&mut buf));
+                        if unmappable {
+                            unencodable.push(offset + index);
+                            out.extend(substitute_char(c).unwrap_or("?").bytes());
+                        } else {
+                            out.extend(encoded.iter());
+                        }
+                    }
+                } else {
+                    out.extend(encoded.iter());
+                }
                 offset += next_sub_index;
             }
         }
-        out.push(b'\0');
+        out.extend(self.control_code(0));
 
-        out
+        Ok((out, unencodable))
+    }
+
+    /// A control code like the null terminator, as wide as this encoding's characters
+    fn control_code(&self, code: u8) -> Vec<u8> {
+        let mut bytes = vec![0; self.codepoint_size()];
+        bytes[self.codepoint_size() - 1] = code;
+        bytes
     }
 }
 
@@ -490,14 +1537,31 @@ impl TextIndexTable {
         }
     }
 
+    /// Checks that a message's attributes fit the table. Every entry is the same size, so
+    /// they have to be as long as every other message's, and the first message sets it.
+    pub fn check_attributes(&self, attributes: &[u8]) -> Result<(), BmgError> {
+        if self.messages.is_empty() {
+            u16::try_from(attributes.len() + 4).map_err(|_| BmgError::AttributesTooLong(attributes.len()))?;
+        } else if attributes.len() + 4 != self.entry_size as usize {
+            return Err(BmgError::InconsistentAttributeLength {
+                expected: self.entry_size as usize - 4,
+                found: attributes.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Adds an entry whose attributes have been through [`TextIndexTable::check_attributes`]
     pub fn add_message(&mut self, offset: u32, attributes: Vec<u8>) {
-        self.num_entries += 1;
-        self.entry_size = max(self.entry_size, attributes.len() as u16 + 4);
-        self.section_size += self.entry_size as u32;
+        if self.messages.is_empty() {
+            self.entry_size = attributes.len() as u16 + 4;
+        }
         self.messages.push(TextIndexEntry {
             text_offset: offset,
             attributes,
-        })
+        });
+        self.num_entries = self.messages.len() as u16;
+        self.update_section_size();
     }
 
     pub fn write(&self, align: u32) -> Vec<u8> {
@@ -512,29 +1576,49 @@ impl TextIndexTable {
         out.extend(self.bmg_file_id.to_be_bytes());
         out.push(self.default_color);
         out.push(self._unk1);
-        out.extend(self.messages.iter().map(|entry| entry.write()).flatten());
+        let entry_size = self.entry_size as usize;
+        out.extend(self.messages.iter().flat_map(|entry| entry.write(entry_size)));
         out.extend(vec![0; padding as usize]);
 
         out
     }
 
-    /// Assumes a TextIndexTable (INF1) section begins at index 0 of the given slice
-    pub fn read(data: &[u8]) -> Result<TextIndexTable, BmgError> {
-        if &data[..0x4] != TextIndexTable::MAGIC {
+    /// Reads the TextIndexTable (INF1) section that makes up the given slice
+    pub fn read(data: &[u8], warnings: &mut Warnings) -> Result<TextIndexTable, BmgError> {
+        let mut reader = BinReader::new(data);
+        if reader.bytes(4)? != TextIndexTable::MAGIC {
             return Err(BmgError::InvalidSectionMagic);
         }
+        if data.len() < TextIndexTable::DRY_SIZE {
+            return Err(BmgError::InvalidSectionSize {
+                magic: "INF1".into(),
+                offset: 0,
+                section_size: data.len() as u32,
+            });
+        }
 
-        let section_length = read_u32(data, 0x4);
-        let num_entries = read_u16(data, 0x8);
-        let entry_size = read_u16(data, 0xA);
-        let bmg_file_id = read_u16(data, 0xC);
-        let default_color = data[0xE];
-        let unk1 = data[0xF];
-        let messages: Vec<TextIndexEntry> = data[0x10..section_length as usize]
-            .chunks_exact(entry_size as usize)
-            .take(num_entries as usize)
-            .map(|chunk| TextIndexEntry::read(chunk, entry_size as usize))
-            .collect();
+        let section_length = data.len() as u32;
+        reader.skip(4); // Section size
+        let num_entries = reader.u16()?;
+        let entry_size = reader.u16()?;
+        let bmg_file_id = reader.u16()?;
+        let default_color = reader.u8()?;
+        let unk1 = reader.u8()?;
+        let error = BmgError::InvalidEntrySize {
+            entry_size,
+            num_entries,
+            section_size: section_length,
+        };
+        if entry_size < 4 {
+            return Err(error);
+        }
+        let fits = (data.len() - TextIndexTable::DRY_SIZE) / entry_size as usize;
+        if num_entries as usize > fits {
+            warnings.recover(error, &format!("Reading the {fits} messages that fit"))?;
+        }
+        let messages = (0..(num_entries as usize).min(fits))
+            .map(|_| TextIndexEntry::read(&mut reader, entry_size as usize))
+            .collect::<Result<Vec<_>, _>>()?;
 
         debug!(
             "Read TextIndexTable of size {} bytes and {} messages",
@@ -543,8 +1627,9 @@ impl TextIndexTable {
         );
 
         Ok(TextIndexTable {
-            section_size: section_length,
-            num_entries,
+            // Padding is added back when writing
+            section_size: (TextIndexTable::DRY_SIZE + messages.len() * entry_size as usize) as u32,
+            num_entries: messages.len() as u16,
             entry_size,
             bmg_file_id,
             default_color,
@@ -552,6 +1637,17 @@ impl TextIndexTable {
             messages,
         })
     }
+
+    /// Drops every message after the first `len`
+    fn truncate(&mut self, len: usize) {
+        self.messages.truncate(len);
+        self.num_entries = self.messages.len() as u16;
+        self.update_section_size();
+    }
+
+    fn update_section_size(&mut self) {
+        self.section_size = (TextIndexTable::DRY_SIZE + self.messages.len() * self.entry_size as usize) as u32;
+    }
 }
 
 #[derive(Debug)]
@@ -562,18 +1658,21 @@ struct TextIndexEntry {
 }
 
 impl TextIndexEntry {
-    pub fn write(&self) -> Vec<u8> {
-        let mut out = Vec::with_capacity(4 + self.attributes.len());
+    /// Every entry in the table takes up exactly `entry_size` bytes, whatever the length
+    /// of its own attributes
+    pub fn write(&self, entry_size: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(entry_size);
         out.extend((self.text_offset).to_be_bytes());
         out.extend(&self.attributes);
+        out.resize(entry_size, 0);
         out
     }
 
-    pub fn read(data: &[u8], len: usize) -> TextIndexEntry {
-        TextIndexEntry {
-            text_offset: read_u32(data, 0x0),
-            attributes: Vec::from(&data[0x4..len]),
-        }
+    fn read(reader: &mut BinReader, entry_size: usize) -> Result<TextIndexEntry, OutOfBounds> {
+        Ok(TextIndexEntry {
+            text_offset: reader.u32()?,
+            attributes: reader.bytes(entry_size - 4)?.to_vec(),
+        })
     }
 }
 
@@ -583,21 +1682,33 @@ struct StringPool {
     /// Blob of null-terminated strings. Each character is either one or two bytes,
     /// determined by the text encoding in the header.
     strings: Vec<u8>,
+    /// Offset of the first copy of each string added since creation, for string pooling
+    offsets: HashMap<Vec<u8>, u32>,
 }
 
 impl StringPool {
     const MAGIC: &'static [u8] = b"DAT1";
 
-    pub fn new() -> StringPool {
+    pub fn new(encoding: TextEncoding) -> StringPool {
+        // Always starts with an empty string
+        let empty = vec![0; encoding.codepoint_size()];
         StringPool {
-            section_size: 9,
-            strings: vec![0], // Always starts with an empty string
+            section_size: 8 + empty.len() as u32,
+            strings: empty.clone(),
+            offsets: HashMap::from([(empty, 0)]),
         }
     }
 
-    pub fn add_message(&mut self, string: &[u8]) {
+    /// Adds an encoded, null-terminated string and returns its offset in the pool
+    pub fn add_message(&mut self, string: Vec<u8>, pool: bool) -> u32 {
+        if let Some(offset) = self.offsets.get(&string).filter(|_| pool) {
+            return *offset;
+        }
+        let offset = self.strings.len() as u32;
         self.section_size += string.len() as u32;
-        self.strings.extend_from_slice(string);
+        self.strings.extend_from_slice(&string);
+        self.offsets.entry(string).or_insert(offset);
+        offset
     }
 
     pub fn write(&self, align: u32) -> Vec<u8> {
@@ -613,16 +1724,22 @@ impl StringPool {
     }
 
     pub fn read(data: &[u8]) -> Result<StringPool, BmgError> {
-        if &data[..0x4] != StringPool::MAGIC {
+        let mut reader = BinReader::new(data);
+        if reader.bytes(4)? != StringPool::MAGIC {
             return Err(BmgError::InvalidSectionMagic);
         }
 
-        let section_size = read_u32(data, 0x4);
-        let strings = data[0x8..section_size as usize].to_vec();
+        let section_size = data.len() as u32;
+        reader.skip(4); // Section size
+        let strings = reader.bytes(data.len().saturating_sub(reader.position()))?.to_vec();
 
         debug!("Read StringPool of size {section_size} bytes");
 
-        Ok(StringPool { section_size, strings })
+        Ok(StringPool {
+            section_size,
+            strings,
+            offsets: HashMap::new(),
+        })
     }
 }
 
@@ -633,6 +1750,12 @@ struct MessageIdTable {
     format: u8,
     info: u8,
     message_ids: Vec<MessageId>,
+    /// The rest of each entry after its ID, for formats with entries wider than 4 bytes.
+    /// Nothing's known to use it, so it's only kept to write back.
+    extra: Vec<u8>,
+    /// Every entry as it was stored, for formats whose entry size isn't known. Messages
+    /// don't have IDs then, and the table is written back exactly as it was.
+    raw_entries: Option<Vec<u8>>,
 }
 
 impl MessageIdTable {
@@ -646,13 +1769,94 @@ impl MessageIdTable {
             format: 0,
             info: 0,
             message_ids: Vec::new(),
+            extra: Vec::new(),
+            raw_entries: None,
+        }
+    }
+
+    /// Bytes per entry in a format. Most games use 0x10, and cube wrote 0 before it read
+    /// the format, both with 4-byte IDs. Entries are 8 bytes in 0x20, with the ID first.
+    fn entry_size(format: u8) -> Option<usize> {
+        match format {
+            0x00 | 0x10 => Some(4),
+            0x20 => Some(8),
+            _ => None,
         }
     }
 
-    pub fn add_message(&mut self, message_id: MessageId) {
-        self.section_size += 4;
+    /// Changes the format, keeping the table raw if its entry size isn't known. Entries
+    /// already in the table are cut down or padded with zeros to the new entry size, which
+    /// can't be done if either format's entry size isn't known.
+    pub fn set_format(&mut self, format: u8) -> Result<(), BmgError> {
+        let sizes = (
+            MessageIdTable::entry_size(self.format),
+            MessageIdTable::entry_size(format),
+        );
+        match sizes {
+            (Some(old_size), Some(new_size)) => {
+                let (old_extra, new_extra) = (old_size - 4, new_size - 4);
+                let mut extra = Vec::with_capacity(self.message_ids.len() * new_extra);
+                for i in 0..self.message_ids.len() {
+                    let entry = &self.extra[i * old_extra..(i + 1) * old_extra];
+                    extra.extend(entry.iter().copied().chain(std::iter::repeat(0)).take(new_extra));
+                }
+                self.extra = extra;
+                self.raw_entries = None;
+            }
+            _ if self.num_messages > 0 && format != self.format => {
+                return Err(BmgError::MessageIdFormatChange {
+                    from: self.format,
+                    to: format,
+                });
+            }
+            (_, Some(_)) => self.raw_entries = None,
+            (_, None) => self.raw_entries = Some(self.raw_entries.take().unwrap_or_default()),
+        }
+        self.format = format;
+        self.update_section_size();
+        Ok(())
+    }
+
+    pub fn add_message(&mut self, message_id: MessageId) -> Result<(), BmgError> {
+        let Some(entry_size) = MessageIdTable::entry_size(self.format) else {
+            return Err(BmgError::UnknownMessageIdFormat(self.format));
+        };
         self.num_messages += 1;
         self.message_ids.push(message_id);
+        self.extra.resize(self.message_ids.len() * (entry_size - 4), 0);
+        self.update_section_size();
+        Ok(())
+    }
+
+    /// What follows the IDs in each entry, or all of each entry if the format isn't known.
+    /// Empty for the usual 4-byte entries.
+    pub fn entry_data(&self) -> &[u8] {
+        self.raw_entries.as_deref().unwrap_or(&self.extra)
+    }
+
+    /// Restores what [`MessageIdTable::entry_data`] returned once messages have been
+    /// added. The rest of any entries it doesn't cover is zero.
+    pub fn set_entry_data(&mut self, data: Vec<u8>, num_messages: u16) {
+        match &mut self.raw_entries {
+            Some(raw_entries) => {
+                *raw_entries = data;
+                self.num_messages = num_messages;
+            }
+            None => {
+                let len = self.extra.len();
+                self.extra = data;
+                self.extra.resize(len, 0);
+            }
+        }
+        self.update_section_size();
+    }
+
+    fn update_section_size(&mut self) {
+        let entries_size = match &self.raw_entries {
+            Some(raw_entries) => raw_entries.len(),
+            None => self.message_ids.len() * 4 + self.extra.len(),
+        };
+        self.section_size = (MessageIdTable::DRY_SIZE + entries_size) as u32;
     }
 
     pub fn write(&self, align: u32) -> Vec<u8> {
@@ -666,59 +1870,419 @@ impl MessageIdTable {
         out.push(self.format);
         out.push(self.info);
         out.extend(0u32.to_be_bytes()); // Padding
-        out.extend(self.message_ids.iter().flat_map(|id| id.write()));
+        match &self.raw_entries {
+            Some(raw_entries) => out.extend(raw_entries),
+            None if self.extra.is_empty() => out.extend(self.message_ids.iter().flat_map(|id| id.write())),
+            None => {
+                let extra_size = self.extra.len() / self.message_ids.len();
+                for (id, extra) in self.message_ids.iter().zip(self.extra.chunks(extra_size)) {
+                    out.extend(id.write());
+                    out.extend(extra);
+                }
+            }
+        }
         out.extend(vec![0; padding as usize]);
         out
     }
 
     pub fn read(data: &[u8]) -> Result<MessageIdTable, BmgError> {
-        if &data[..0x4] != MessageIdTable::MAGIC {
+        let mut reader = BinReader::new(data);
+        if reader.bytes(4)? != MessageIdTable::MAGIC {
             return Err(BmgError::InvalidSectionMagic);
         }
 
-        let section_size = read_u32(data, 0x4);
-        let num_messages = read_u16(data, 0x8);
-        let format = data[0xA];
-        let info = data[0xB];
-        let message_ids: Vec<MessageId> = data[0x10..section_size as usize]
-            .chunks_exact(4)
-            .map(|chunk| MessageId::read(&chunk))
-            .collect();
+        if data.len() < MessageIdTable::DRY_SIZE {
+            return Err(BmgError::InvalidSectionSize {
+                magic: "MID1".into(),
+                offset: 0,
+                section_size: data.len() as u32,
+            });
+        }
 
-        debug!(
-            "Read MessageIdTable of size {} bytes and {} messages",
-            section_size,
-            message_ids.len()
-        );
+        let section_size = data.len() as u32;
+        reader.skip(4); // Section size
+        let num_messages = reader.u16()?;
+        let format = reader.u8()?;
+        let info = reader.u8()?;
+        reader.skip(4); // Padding
 
-        Ok(MessageIdTable {
+        let mut table = MessageIdTable {
             section_size,
             num_messages,
             format,
             info,
-            message_ids,
-        })
+            message_ids: Vec::new(),
+            extra: Vec::new(),
+            raw_entries: None,
+        };
+        let Some(entry_size) = MessageIdTable::entry_size(format) else {
+            // Without knowing how big entries are, there's no telling where the padding
+            // starts, so all of it is kept
+            debug!("Keeping MessageIdTable with unknown format {format:#04X} as it is");
+            table.raw_entries = Some(data[MessageIdTable::DRY_SIZE..].to_vec());
+            table.update_section_size();
+            return Ok(table);
+        };
+
+        // Tables cut short by their section's size keep the IDs that fit
+        let fits = (data.len() - MessageIdTable::DRY_SIZE) / entry_size;
+        for _ in 0..(num_messages as usize).min(fits) {
+            table.message_ids.push(MessageId::from_u32(reader.u32()?));
+            table.extra.extend(reader.bytes(entry_size - 4)?);
+        }
+
+        debug!(
+            "Read MessageIdTable of size {} bytes and {} messages",
+            section_size,
+            table.message_ids.len()
+        );
+
+        // Padding is added back when writing
+        table.update_section_size();
+        Ok(table)
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// A message's ID in MID1. In JSON this can be written either as `{"id": 1234, "sub_id": 0}`
+/// or as the string "1234:0".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "MessageIdRepr")]
 pub struct MessageId {
     id: u32,
     sub_id: u8,
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MessageIdRepr {
+    Struct { id: u32, sub_id: u8 },
+    String(String),
+}
+
+impl TryFrom<MessageIdRepr> for MessageId {
+    type Error = BmgError;
+    fn try_from(repr: MessageIdRepr) -> Result<Self, Self::Error> {
+        match repr {
+            MessageIdRepr::Struct { id, sub_id } => MessageId::new(id, sub_id),
+            MessageIdRepr::String(string) => string.parse(),
+        }
+    }
+}
+
+impl Display for MessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.id, self.sub_id)
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = BmgError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BmgError::InvalidMessageId(s.to_owned());
+        let (id, sub_id) = s.split_once(':').ok_or_else(invalid)?;
+        MessageId::new(
+            id.trim().parse().map_err(|_| invalid())?,
+            sub_id.trim().parse().map_err(|_| invalid())?,
+        )
+    }
+}
+
 impl MessageId {
-    pub fn write(&self) -> [u8; 4] {
-        (self.id << 8 | self.sub_id as u32).to_be_bytes()
+    /// IDs are stored in 24 bits, so `id` must be less than 0x1000000
+    pub fn new(id: u32, sub_id: u8) -> Result<MessageId, BmgError> {
+        if id > 0xFFFFFF {
+            return Err(BmgError::MessageIdOutOfRange(id));
+        }
+        Ok(MessageId { id, sub_id })
     }
 
-    pub fn read(data: &[u8]) -> MessageId {
-        let value = read_u32(data, 0);
+    /// Splits a value as stored in MID1, with the ID in the upper 24 bits and the sub ID
+    /// in the lowest 8
+    pub fn from_u32(value: u32) -> MessageId {
         MessageId {
             id: value >> 8,
             sub_id: (value & 0xFF) as u8,
         }
     }
+
+    /// The value stored in MID1
+    pub fn to_u32(&self) -> u32 {
+        self.id << 8 | self.sub_id as u32
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn sub_id(&self) -> u8 {
+        self.sub_id
+    }
+
+    pub fn write(&self) -> [u8; 4] {
+        self.to_u32().to_be_bytes()
+    }
+
+    pub fn read(data: &[u8]) -> MessageId {
+        MessageId::from_u32(read_u32(data, 0))
+    }
+}
+
+/// FLW1: flowcharts that decide which message comes next, e.g. based on the player's
+/// choices or game state. Used by Mario Kart Wii, Twilight Princess, and others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowTable {
+    pub nodes: Vec<FlowNode>,
+    /// Node indexes that branch and event nodes continue to. A branch with n outcomes
+    /// uses n consecutive entries starting at its `branch_index`.
+    pub branch_targets: Vec<u16>,
+    /// Bytes after the branch targets, including any padding. Their meaning isn't known.
+    #[serde(default)]
+    pub trailing: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FlowNode {
+    /// Shows a message, then continues to `next_node`
+    Message {
+        unk0: u8,
+        /// Index of the message in INF1
+        message_index: u16,
+        next_node: u16,
+        unk1: u16,
+    },
+    /// Continues to one of several nodes depending on a condition defined by the game
+    Branch {
+        unk0: u8,
+        condition: u16,
+        parameter: u16,
+        branch_index: u16,
+    },
+    /// Triggers an event defined by the game, then continues via the branch targets
+    Event {
+        event: u8,
+        branch_index: u16,
+        parameter: u32,
+    },
+    /// A node type cube doesn't know about, including its type byte
+    Other { bytes: [u8; 8] },
+}
+
+impl FlowTable {
+    const MAGIC: &'static [u8] = b"FLW1";
+    const DRY_SIZE: usize = 0x10;
+
+    pub fn write(&self, align: u32) -> Vec<u8> {
+        let section_size =
+            (FlowTable::DRY_SIZE + self.nodes.len() * 8 + self.branch_targets.len() * 2 + self.trailing.len()) as u32;
+        let padding = (align - (section_size % align)) % align;
+        let final_section_size = section_size + padding;
+
+        let mut out = Vec::with_capacity(final_section_size as usize);
+        out.extend(FlowTable::MAGIC);
+        out.extend(final_section_size.to_be_bytes());
+        out.extend((self.nodes.len() as u16).to_be_bytes());
+        out.extend((self.branch_targets.len() as u16).to_be_bytes());
+        out.extend(0u32.to_be_bytes()); // Padding
+        out.extend(self.nodes.iter().flat_map(FlowNode::write));
+        out.extend(self.branch_targets.iter().flat_map(|target| target.to_be_bytes()));
+        out.extend(&self.trailing);
+        out.extend(vec![0; padding as usize]);
+        out
+    }
+
+    pub fn read(data: &[u8]) -> Result<FlowTable, BmgError> {
+        let mut reader = BinReader::new(data);
+        if reader.bytes(4)? != FlowTable::MAGIC {
+            return Err(BmgError::InvalidSectionMagic);
+        }
+
+        let section_size = data.len();
+        if section_size < FlowTable::DRY_SIZE {
+            return Err(BmgError::InvalidFlowSection {
+                magic: "FLW1",
+                section_size: section_size as u32,
+            });
+        }
+        reader.skip(4); // Section size
+        let num_nodes = reader.u16()? as usize;
+        let num_branch_targets = reader.u16()? as usize;
+        let nodes_end = FlowTable::DRY_SIZE + num_nodes * 8;
+        let branch_targets_end = nodes_end + num_branch_targets * 2;
+        if branch_targets_end > section_size {
+            return Err(BmgError::InvalidFlowSection {
+                magic: "FLW1",
+                section_size: section_size as u32,
+            });
+        }
+
+        reader.seek(FlowTable::DRY_SIZE);
+        let nodes: Vec<FlowNode> = (0..num_nodes)
+            .map(|_| Ok(FlowNode::read(reader.bytes(8)?)))
+            .collect::<Result<_, OutOfBounds>>()?;
+        let branch_targets = (0..num_branch_targets)
+            .map(|_| reader.u16())
+            .collect::<Result<_, _>>()?;
+
+        debug!("Read FlowTable of size {section_size} bytes and {} nodes", nodes.len());
+
+        Ok(FlowTable {
+            nodes,
+            branch_targets,
+            trailing: data[branch_targets_end..section_size].to_vec(),
+        })
+    }
+}
+
+impl FlowNode {
+    pub fn write(&self) -> [u8; 8] {
+        let mut out = [0u8; 8];
+        match *self {
+            FlowNode::Message {
+                unk0,
+                message_index,
+                next_node,
+                unk1,
+            } => {
+                out[..2].copy_from_slice(&[1, unk0]);
+                out[2..4].copy_from_slice(&message_index.to_be_bytes());
+                out[4..6].copy_from_slice(&next_node.to_be_bytes());
+                out[6..].copy_from_slice(&unk1.to_be_bytes());
+            }
+            FlowNode::Branch {
+                unk0,
+                condition,
+                parameter,
+                branch_index,
+            } => {
+                out[..2].copy_from_slice(&[2, unk0]);
+                out[2..4].copy_from_slice(&condition.to_be_bytes());
+                out[4..6].copy_from_slice(&parameter.to_be_bytes());
+                out[6..].copy_from_slice(&branch_index.to_be_bytes());
+            }
+            FlowNode::Event {
+                event,
+                branch_index,
+                parameter,
+            } => {
+                out[..2].copy_from_slice(&[3, event]);
+                out[2..4].copy_from_slice(&branch_index.to_be_bytes());
+                out[4..].copy_from_slice(&parameter.to_be_bytes());
+            }
+            FlowNode::Other { bytes } => out = bytes,
+        }
+        out
+    }
+
+    /// Reads the node in the first 8 bytes of `data`, which must be at least that long
+    pub fn read(data: &[u8]) -> FlowNode {
+        let bytes: [u8; 8] = data[..8].try_into().unwrap();
+        let mut reader = BinReader::new(&bytes);
+        let (kind, unk0) = (reader.u8(), reader.u8());
+        // The reads can't fail, since there are exactly 8 bytes
+        let mut u16 = || reader.u16().unwrap();
+        match (kind.unwrap(), unk0.unwrap()) {
+            (1, unk0) => FlowNode::Message {
+                unk0,
+                message_index: u16(),
+                next_node: u16(),
+                unk1: u16(),
+            },
+            (2, unk0) => FlowNode::Branch {
+                unk0,
+                condition: u16(),
+                parameter: u16(),
+                branch_index: u16(),
+            },
+            (3, event) => FlowNode::Event {
+                event,
+                branch_index: u16(),
+                parameter: u32::from_be_bytes(bytes[4..].try_into().unwrap()),
+            },
+            _ => FlowNode::Other { bytes },
+        }
+    }
+}
+
+/// FLI1: the entry point of each flowchart in FLW1
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowIndex {
+    pub entries: Vec<FlowIndexEntry>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct FlowIndexEntry {
+    /// The ID the game uses to start this flowchart
+    pub flow_id: u32,
+    /// Index of the first node in FLW1
+    pub start_node: u16,
+    pub unk0: u16,
+}
+
+impl FlowIndex {
+    const MAGIC: &'static [u8] = b"FLI1";
+    const DRY_SIZE: usize = 0x10;
+    const ENTRY_SIZE: u16 = 8;
+
+    pub fn write(&self, align: u32) -> Vec<u8> {
+        let section_size = (FlowIndex::DRY_SIZE + self.entries.len() * FlowIndex::ENTRY_SIZE as usize) as u32;
+        let padding = (align - (section_size % align)) % align;
+        let final_section_size = section_size + padding;
+
+        let mut out = Vec::with_capacity(final_section_size as usize);
+        out.extend(FlowIndex::MAGIC);
+        out.extend(final_section_size.to_be_bytes());
+        out.extend((self.entries.len() as u16).to_be_bytes());
+        out.extend(FlowIndex::ENTRY_SIZE.to_be_bytes());
+        out.extend(0u32.to_be_bytes()); // Padding
+        for entry in &self.entries {
+            out.extend(entry.flow_id.to_be_bytes());
+            out.extend(entry.start_node.to_be_bytes());
+            out.extend(entry.unk0.to_be_bytes());
+        }
+        out.extend(vec![0; padding as usize]);
+        out
+    }
+
+    pub fn read(data: &[u8]) -> Result<FlowIndex, BmgError> {
+        let mut reader = BinReader::new(data);
+        if reader.bytes(4)? != FlowIndex::MAGIC {
+            return Err(BmgError::InvalidSectionMagic);
+        }
+
+        let section_size = data.len() as u32;
+        if data.len() < FlowIndex::DRY_SIZE {
+            return Err(BmgError::InvalidFlowSection {
+                magic: "FLI1",
+                section_size,
+            });
+        }
+        reader.skip(4); // Section size
+        let num_entries = reader.u16()?;
+        let entry_size = reader.u16()?;
+        let entries_end = FlowIndex::DRY_SIZE + num_entries as usize * FlowIndex::ENTRY_SIZE as usize;
+        if entry_size != FlowIndex::ENTRY_SIZE || entries_end > data.len() {
+            return Err(BmgError::InvalidFlowSection {
+                magic: "FLI1",
+                section_size,
+            });
+        }
+
+        reader.seek(FlowIndex::DRY_SIZE);
+        let entries = (0..num_entries)
+            .map(|_| {
+                Ok(FlowIndexEntry {
+                    flow_id: reader.u32()?,
+                    start_node: reader.u16()?,
+                    unk0: reader.u16()?,
+                })
+            })
+            .collect::<Result<_, OutOfBounds>>()?;
+
+        debug!("Read FlowIndex of size {section_size} bytes and {num_entries} entries");
+
+        Ok(FlowIndex { entries })
+    }
 }
 
 #[derive(Debug)]
@@ -743,16 +2307,16 @@ impl UnknownSection {
 
     pub fn read(data: &[u8]) -> Result<UnknownSection, BmgError> {
         let magic: [u8; 4] = data[..0x4].try_into().map_err(|_| BmgError::InvalidSectionMagic)?;
-        let section_size = read_u32(data, 0x4);
+        let section_size = data.len() as u32;
         debug!(
             "Reading unknown section type with magic {} and size {} bytes",
-            std::str::from_utf8(&magic).unwrap(),
+            String::from_utf8_lossy(&magic),
             section_size
         );
         Ok(UnknownSection {
             magic,
             section_size,
-            data: data[0x8..section_size as usize].to_vec(),
+            data: data[0x8..].to_vec(),
         })
     }
 }
@@ -762,9 +2326,109 @@ pub enum BmgError {
     #[error("Invalid magic byte sequence in BMG header. Expected \"{}\"", std::str::from_utf8(BmgHeader::MAGIC).unwrap())]
     InvalidHeaderMagic,
 
+    #[error("This is an MSBT file, not a BMG. Read it with the msbt module, or name it .msbt to extract it")]
+    IsMsbt,
+
     #[error("Invalid magic byte sequence in BMG section")]
     InvalidSectionMagic,
 
+    #[error("BMG JSON is schema version {0}, but this version of cube only understands up to version {BMG_SCHEMA_VERSION}. Update cube to pack it")]
+    NewerSchema(u32),
+
+    #[error("Unknown section \"{0}\" in BMG JSON needs a 4 character magic and base64 data")]
+    InvalidUnknownSection(String),
+
     #[error("Unrecognized BMG text encoding byte '{0}'")]
     InvalidTextEncoding(u8),
+
+    #[error("Invalid message attributes \"{0}\". Expected an even number of hex digits")]
+    InvalidAttributeHex(String),
+
+    #[error("Message attributes are {found} bytes long, but previous messages have {expected} bytes")]
+    InconsistentAttributeLength { expected: usize, found: usize },
+
+    #[error("Message attributes are {0} bytes long, which is more than a BMG can hold")]
+    AttributesTooLong(usize),
+
+    #[error("Message {index} has {character:?}, which can't be encoded in {encoding:?}")]
+    UnencodableInEncoding {
+        index: usize,
+        character: char,
+        encoding: TextEncoding,
+    },
+
+    #[error("Invalid escape sequence at byte {offset}: {reason}")]
+    InvalidEscapeSequence { offset: usize, reason: &'static str },
+
+    #[error("INF1 entry size {entry_size} is invalid for {num_entries} entries in a section of {section_size} bytes")]
+    InvalidEntrySize {
+        entry_size: u16,
+        num_entries: u16,
+        section_size: u32,
+    },
+
+    #[error("BMG ends unexpectedly at {0:#X}")]
+    Truncated(usize),
+
+    #[error("{magic} section at {offset:#X} has an invalid size of {section_size} bytes")]
+    InvalidSectionSize {
+        magic: String,
+        offset: usize,
+        section_size: u32,
+    },
+
+    #[error("Message {index} starts at {offset:#X}, past the end of DAT1")]
+    TextOffsetOutOfRange { index: usize, offset: u32 },
+
+    #[error("INF1 has {messages} messages but MID1 only has IDs for {ids}")]
+    MissingMessageIds { messages: usize, ids: usize },
+
+    #[error("{magic} section of {section_size} bytes is too small for its contents")]
+    InvalidFlowSection { magic: &'static str, section_size: u32 },
+
+    #[error("Invalid message ID \"{0}\". Expected an ID and sub ID like \"1234:0\"")]
+    InvalidMessageId(String),
+
+    #[error("Message ID {0} is too large. IDs can be at most 16777215")]
+    MessageIdOutOfRange(u32),
+
+    #[error("There's no message {index} in a BMG of {count} messages")]
+    MessageIndexOutOfRange { index: usize, count: usize },
+
+    #[error("A new order for a BMG's messages has to list each one once, and it has {count}")]
+    InvalidMessageOrder { count: usize },
+
+    #[error("Message {index} is shown by flowchart node {node}, so it can't be removed")]
+    MessageInFlowchart { index: usize, node: usize },
+
+    #[error("Attributes are \"default\", but there are no messages with attributes to copy")]
+    NoDefaultAttributes,
+
+    #[error("This BMG has no MID1 section, so its messages don't have IDs")]
+    NoMessageIds,
+
+    #[error("MID1 has format {0:#04X}, whose entries cube can't read, so its messages can't have IDs")]
+    UnknownMessageIdFormat(u8),
+
+    #[error("MID1 can't change from format {from:#04X} to {to:#04X} once messages have been added, since cube can't read the entries of one of them")]
+    MessageIdFormatChange { from: u8, to: u8 },
+
+    #[error("MID1 data isn't valid base64")]
+    InvalidMessageIdData,
+
+    #[error("Invalid block alignment {0}. Sections have to be padded to a multiple of at least 1 byte")]
+    InvalidBlockAlignment(u32),
+
+    #[error("Message {index}: {source}")]
+    InMessage {
+        index: usize,
+        #[source]
+        source: Box<BmgError>,
+    },
+}
+
+impl From<OutOfBounds> for BmgError {
+    fn from(e: OutOfBounds) -> Self {
+        BmgError::Truncated(e.offset)
+    }
 }
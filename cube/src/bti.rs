@@ -1,188 +1,634 @@
-use super::util::{read_u16, read_u32};
+use super::util::{read_u16, read_u32, BinReader, OutOfBounds};
+use crate::{CodecError, Decode, Encode, FromBytes};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+};
+use thiserror::Error;
 
-type Color = [u8; 4];
+pub(crate) type Color = [u8; 4];
 
 pub struct BtiImage {
     pub width: u32,
     pub height: u32,
+    pub header: BtiHeader,
     data: Vec<Color>,
 }
 
-impl BtiImage {
-    pub fn decode(data: &[u8]) -> Self {
-        let format = format_to_index(data[0x0]);
-        let _alpha_setting = data[0x1];
-        let width = read_u16(data, 0x2) as u32;
-        let height = read_u16(data, 0x4) as u32;
-
-        // 0: clamp to edge
-        // 1: repeat
-        // 2: mirror
-        let _wrap_s = data[0x5];
-        let _wrap_t = data[0x6];
-
-        let _palettes_enabled = data[0x8] > 0;
-        let palette_format = data[0x9];
-        let num_colors = read_u16(data, 0xA);
-        let palette_data_offset = read_u32(data, 0xC);
-        let _min_filter = data[0x14];
-        let _mag_filter = data[0x15];
-        let _min_lod = data[0x16];
-        let _max_lod = data[0x17];
-        let mut mipmap_count = data[0x18];
-        let _lod_bias = read_u16(data, 0x1A);
-        let img_data_offset = read_u32(data, 0x1C);
-
-        let block_width = BLOCK_WIDTHS[format as usize] as u32;
-        let block_height = BLOCK_HEIGHTS[format as usize] as u32;
-        let block_data_size = BLOCK_DATA_SIZE[format as usize] as u32;
-
-        if mipmap_count == 0 {
-            mipmap_count = 1;
+/// Every field of a BTI header. Decoding only needs a few of them, but keeping the rest
+/// means a texture can be re-encoded with exactly the header it was extracted with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BtiHeader {
+    pub format: u8,
+    pub alpha_setting: u8,
+    pub width: u16,
+    pub height: u16,
+    /// 0: clamp to edge, 1: repeat, 2: mirror
+    pub wrap_s: u8,
+    pub wrap_t: u8,
+    pub palettes_enabled: u8,
+    pub palette_format: u8,
+    pub num_colors: u16,
+    pub palette_offset: u32,
+    pub mipmaps_enabled: u8,
+    pub edge_lod: u8,
+    pub bias_clamp: u8,
+    pub max_anisotropy: u8,
+    pub min_filter: u8,
+    pub mag_filter: u8,
+    pub min_lod: i8,
+    pub max_lod: i8,
+    pub mipmap_count: u8,
+    pub unknown: u8,
+    pub lod_bias: i16,
+    pub image_data_offset: u32,
+}
+
+impl BtiHeader {
+    pub const SIZE: usize = 0x20;
+
+    pub fn read(data: &[u8]) -> Result<Self, BtiError> {
+        let mut reader = BinReader::new(data);
+        Ok(BtiHeader {
+            format: reader.u8()?,
+            alpha_setting: reader.u8()?,
+            width: reader.u16()?,
+            height: reader.u16()?,
+            wrap_s: reader.u8()?,
+            wrap_t: reader.u8()?,
+            palettes_enabled: reader.u8()?,
+            palette_format: reader.u8()?,
+            num_colors: reader.u16()?,
+            palette_offset: reader.u32()?,
+            mipmaps_enabled: reader.u8()?,
+            edge_lod: reader.u8()?,
+            bias_clamp: reader.u8()?,
+            max_anisotropy: reader.u8()?,
+            min_filter: reader.u8()?,
+            mag_filter: reader.u8()?,
+            min_lod: reader.i8()?,
+            max_lod: reader.i8()?,
+            mipmap_count: reader.u8()?,
+            unknown: reader.u8()?,
+            lod_bias: reader.i16()?,
+            image_data_offset: reader.u32()?,
+        })
+    }
+
+    pub fn write(&self) -> [u8; Self::SIZE] {
+        let mut out = [0u8; Self::SIZE];
+        out[0x0] = self.format;
+        out[0x1] = self.alpha_setting;
+        out[0x2..0x4].copy_from_slice(&self.width.to_be_bytes());
+        out[0x4..0x6].copy_from_slice(&self.height.to_be_bytes());
+        out[0x6] = self.wrap_s;
+        out[0x7] = self.wrap_t;
+        out[0x8] = self.palettes_enabled;
+        out[0x9] = self.palette_format;
+        out[0xA..0xC].copy_from_slice(&self.num_colors.to_be_bytes());
+        out[0xC..0x10].copy_from_slice(&self.palette_offset.to_be_bytes());
+        out[0x10] = self.mipmaps_enabled;
+        out[0x11] = self.edge_lod;
+        out[0x12] = self.bias_clamp;
+        out[0x13] = self.max_anisotropy;
+        out[0x14] = self.min_filter;
+        out[0x15] = self.mag_filter;
+        out[0x16] = self.min_lod as u8;
+        out[0x17] = self.max_lod as u8;
+        out[0x18] = self.mipmap_count;
+        out[0x19] = self.unknown;
+        out[0x1A..0x1C].copy_from_slice(&self.lod_bias.to_be_bytes());
+        out[0x1C..].copy_from_slice(&self.image_data_offset.to_be_bytes());
+        out
+    }
+
+    /// Header for a new texture in `format`, with the usual settings for a texture
+    /// that isn't mipmapped: clamped edges and linear filtering
+    pub fn new(format: TextureFormat, pixels: &[[u8; 4]]) -> Self {
+        let mut header = BtiHeader {
+            format: 0,
+            alpha_setting: 0,
+            width: 0,
+            height: 0,
+            wrap_s: 0,
+            wrap_t: 0,
+            palettes_enabled: 0,
+            palette_format: 0,
+            num_colors: 0,
+            palette_offset: 0,
+            mipmaps_enabled: 0,
+            edge_lod: 0,
+            bias_clamp: 0,
+            max_anisotropy: 0,
+            min_filter: 1,
+            mag_filter: 1,
+            min_lod: 0,
+            max_lod: 0,
+            mipmap_count: 1,
+            unknown: 0,
+            lod_bias: 0,
+            image_data_offset: 0,
+        };
+        header.use_format(format, pixels);
+        header
+    }
+
+    /// Size of the image data the header describes, every mipmap included
+    pub fn image_data_size(&self) -> Result<u64, BtiError> {
+        let format = format_to_index(self.format).ok_or(BtiError::UnknownFormat(self.format))?;
+        // The size of every mipmap is where the one after the last would start
+        Ok(get_mipmap_offset(
+            self.mipmap_count.max(1),
+            self.width as u64,
+            self.height as u64,
+            BLOCK_WIDTHS[format] as u64,
+            BLOCK_HEIGHTS[format] as u64,
+            BLOCK_DATA_SIZE[format] as u64,
+        ))
+    }
+
+    /// Switches to a different image format. The alpha setting and palette format are
+    /// picked to suit `pixels` if the format actually changes.
+    pub fn set_format(&mut self, format: TextureFormat, pixels: &[[u8; 4]]) {
+        if self.format != format.id() {
+            self.use_format(format, pixels);
         }
+    }
 
-        // Size of all image data is equal to the size of the next mipmap starting index after the last one
-        let img_data_size = get_mipmap_offset(
-            mipmap_count,
-            width,
-            height,
-            block_width,
-            block_height,
-            block_data_size,
-        );
-
-        let img_data_end = img_data_offset as usize + img_data_size as usize;
-        let img_data = &data[img_data_offset as usize..img_data_end];
-
-        let palette_data_end = palette_data_offset as usize + (num_colors * 2) as usize;
-        let palette_data = &data[palette_data_offset as usize..palette_data_end];
-
-        let mut decoded_data = vec![[0, 0, 0, 0]; (width * height) as usize];
-        let colors = decode_palettes(palette_data, palette_format, num_colors, format);
-
-        let mut offset = 0;
-        let mut block_x = 0;
-        let mut block_y = 0;
-        let block_size = BLOCK_DATA_SIZE[format as usize] as usize;
-        while block_y < height as usize {
-            let decoded_pixels = match format {
-                0 => decode_i4_block(img_data, offset, block_size),
-                1 => decode_i8_block(img_data, offset, block_size),
-                2 => decode_ia4_block(img_data, offset, block_size),
-                3 => decode_ia8_block(img_data, offset, block_size),
-                4 => decode_rgb565_block(img_data, offset, block_size),
-                5 => decode_rgb5a3_block(img_data, offset, block_size),
-                6 => decode_rgba32_block(img_data, offset),
-                7 => decode_c4_block(img_data, offset, block_size, &colors),
-                8 => decode_c8_block(img_data, offset, block_size, &colors),
-                9 => decode_c14x2_block(img_data, offset, block_size, &colors),
-                10 => decode_cmpr_block(img_data, offset),
-                _ => panic!("Unknown image format {format}"),
-            };
+    fn use_format(&mut self, format: TextureFormat, pixels: &[[u8; 4]]) {
+        let opaque = pixels.iter().all(|c| c[3] == 255);
+        self.format = format.id();
+        self.alpha_setting = !opaque as u8;
+        self.palettes_enabled = format.uses_palette() as u8;
+        // RGB565 if nothing needs alpha, otherwise RGB5A3
+        self.palette_format = if !format.uses_palette() {
+            0
+        } else if opaque {
+            1
+        } else {
+            2
+        };
+    }
+}
 
-            for (i, pixel) in decoded_pixels.iter().enumerate() {
-                let x_in_block = i % BLOCK_WIDTHS[format as usize] as usize;
-                let y_in_block = i / BLOCK_WIDTHS[format as usize] as usize;
-                let x = block_x + x_in_block;
-                let y = block_y + y_in_block;
-                if x >= width as usize || y >= height as usize {
-                    continue;
-                }
-                decoded_data[x + y * width as usize] = *pixel;
+/// GX texture formats. These are stored in BTI headers by their [`TextureFormat::id`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFormat {
+    I4,
+    I8,
+    IA4,
+    IA8,
+    RGB565,
+    RGB5A3,
+    RGBA32,
+    C4,
+    C8,
+    C14X2,
+    CMPR,
+}
+
+impl TextureFormat {
+    const ALL: [TextureFormat; 11] = [
+        TextureFormat::I4,
+        TextureFormat::I8,
+        TextureFormat::IA4,
+        TextureFormat::IA8,
+        TextureFormat::RGB565,
+        TextureFormat::RGB5A3,
+        TextureFormat::RGBA32,
+        TextureFormat::C4,
+        TextureFormat::C8,
+        TextureFormat::C14X2,
+        TextureFormat::CMPR,
+    ];
+
+    pub fn id(self) -> u8 {
+        match self {
+            TextureFormat::I4 => 0x0,
+            TextureFormat::I8 => 0x1,
+            TextureFormat::IA4 => 0x2,
+            TextureFormat::IA8 => 0x3,
+            TextureFormat::RGB565 => 0x4,
+            TextureFormat::RGB5A3 => 0x5,
+            TextureFormat::RGBA32 => 0x6,
+            TextureFormat::C4 => 0x8,
+            TextureFormat::C8 => 0x9,
+            TextureFormat::C14X2 => 0xA,
+            TextureFormat::CMPR => 0xE,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|format| format.id() == id)
+    }
+
+    pub fn uses_palette(self) -> bool {
+        matches!(self, TextureFormat::C4 | TextureFormat::C8 | TextureFormat::C14X2)
+    }
+
+    /// Most colors a palette can hold in this format
+    fn max_colors(self) -> usize {
+        match self {
+            TextureFormat::C4 => 16,
+            TextureFormat::C8 => 256,
+            _ => 0x4000,
+        }
+    }
+
+    /// Picks the smallest format that can hold the image without visibly losing anything.
+    /// Grayscale images use the intensity formats, images with few colors use a palette,
+    /// and everything else is CMPR unless it has partial transparency, which CMPR can't do.
+    pub fn choose(pixels: &[[u8; 4]]) -> TextureFormat {
+        let opaque = pixels.iter().all(|c| c[3] == 255);
+        let fits_4_bits = |value: u8| value >> 4 == value & 0xF;
+        if pixels.iter().all(|c| c[0] == c[1] && c[1] == c[2]) {
+            // Intensity formats use the intensity as alpha too
+            if opaque || pixels.iter().all(|c| c[3] == c[0]) {
+                return match pixels.iter().all(|c| fits_4_bits(c[0])) {
+                    true => TextureFormat::I4,
+                    false => TextureFormat::I8,
+                };
             }
+            return match pixels.iter().all(|c| fits_4_bits(c[0]) && fits_4_bits(c[3])) {
+                true => TextureFormat::IA4,
+                false => TextureFormat::IA8,
+            };
+        }
 
-            offset += block_size;
-            block_x += BLOCK_WIDTHS[format as usize] as usize;
-            if block_x >= width as usize {
-                block_x = 0;
-                block_y += BLOCK_HEIGHTS[format as usize] as usize;
+        let to_palette_color: fn(Color) -> u16 = if opaque { color_to_rgb565 } else { color_to_rgb5a3 };
+        let mut colors = HashSet::new();
+        for &pixel in pixels {
+            colors.insert(to_palette_color(pixel));
+            if colors.len() > 256 {
+                break;
             }
         }
+        match colors.len() {
+            0..=16 => TextureFormat::C4,
+            17..=256 => TextureFormat::C8,
+            _ if pixels.iter().all(|c| c[3] == 0 || c[3] == 255) => TextureFormat::CMPR,
+            _ => TextureFormat::RGB5A3,
+        }
+    }
+}
+
+impl Display for TextureFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl FromStr for TextureFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("Unknown texture format '{s}'"))
+    }
+}
+
+/// How hard to try when compressing CMPR textures. Other formats are lossless or have
+/// only one way to encode each pixel, so they're unaffected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BtiQuality {
+    /// Each block's endpoints are its two most different colors
+    #[default]
+    Fast,
+    /// Also tries every way of splitting each block's colors between its palette and
+    /// keeps whichever fits best. Several times slower, with less banding in gradients.
+    High,
+}
+
+impl Display for BtiQuality {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BtiQuality::Fast => write!(f, "fast"),
+            BtiQuality::High => write!(f, "high"),
+        }
+    }
+}
 
-        BtiImage {
+impl FromStr for BtiQuality {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fast" => Ok(BtiQuality::Fast),
+            "high" => Ok(BtiQuality::High),
+            _ => Err(format!("Invalid texture quality '{s}'. Expected 'fast' or 'high'")),
+        }
+    }
+}
+
+impl BtiImage {
+    pub fn decode(data: &[u8]) -> Result<Self, BtiError> {
+        Self::decode_inner(data, 0, None)
+    }
+
+    /// Decodes a BTI header found `base_offset` bytes into `data`, along with the image
+    /// and palette it points to. Formats like BMD/BDL and BLO embed texture headers like
+    /// this, with offsets relative to the start of each header rather than the file, and
+    /// the data may be anywhere after it. Errors give offsets from the start of `data`.
+    pub fn decode_at(data: &[u8], base_offset: usize) -> Result<Self, BtiError> {
+        Self::decode_inner(data, base_offset, None)
+    }
+
+    /// Decodes using raw palette data from somewhere other than the BTI itself, such as
+    /// a sibling file. Some BTIs in the wild have no palette of their own, or a palette
+    /// offset pointing outside the file. The palette must be in the format given by the
+    /// BTI header.
+    pub fn decode_with_palette(data: &[u8], palette_data: &[u8]) -> Result<Self, BtiError> {
+        Self::decode_inner(data, 0, Some(palette_data))
+    }
+
+    fn decode_inner(data: &[u8], base_offset: usize, external_palette: Option<&[u8]>) -> Result<Self, BtiError> {
+        let header = BtiHeader::read(data.get(base_offset..).unwrap_or_default())?;
+        let format = format_to_index(header.format).ok_or(BtiError::UnknownFormat(header.format))?;
+        let width = header.width as u32;
+        let height = header.height as u32;
+        let palette_format = header.palette_format;
+        let num_colors = header.num_colors;
+        // Offsets are worked out in 64 bits so that huge ones can't wrap around into the file
+        let palette_data_offset = base_offset as u64 + header.palette_offset as u64;
+        let img_data_offset = base_offset as u64 + header.image_data_offset as u64;
+        let img_data_size = header.image_data_size()?;
+
+        let img_data = slice_at(data, img_data_offset, img_data_size).ok_or(BtiError::ImageDataOutOfBounds {
+            offset: img_data_offset,
+            size: img_data_size,
+            file_size: data.len(),
+        })?;
+
+        // Only these 3 formats use palettes
+        let colors = if [7, 8, 9].contains(&format) {
+            let palette_data = match external_palette {
+                Some(palette_data) => palette_data,
+                None if num_colors == 0 => return Err(BtiError::MissingPalette),
+                None => {
+                    slice_at(data, palette_data_offset, num_colors as u64 * 2).ok_or(BtiError::PaletteOutOfBounds {
+                        offset: palette_data_offset,
+                        num_colors,
+                        file_size: data.len(),
+                    })?
+                }
+            };
+            decode_palette(palette_data, palette_format)?
+        } else {
+            Vec::new()
+        };
+
+        Ok(BtiImage {
             width,
             height,
-            data: decoded_data,
-        }
+            header,
+            data: decode_blocks(img_data, format, width as usize, height as usize, &colors),
+        })
     }
 
     pub fn pixels(&self) -> impl Iterator<Item = &[u8; 4]> {
         self.data.iter()
     }
+
+    /// Encodes an image into a complete BTI file. The format and settings like wrap modes
+    /// and filtering come from `header`, and its size, offsets, and palette size are filled
+    /// in. If the header asks for mipmaps, they're generated by averaging each 2x2 square of
+    /// the level above. `quality` only matters for CMPR.
+    pub fn encode(
+        width: u32,
+        height: u32,
+        pixels: &[[u8; 4]],
+        header: &BtiHeader,
+        quality: BtiQuality,
+    ) -> Result<Vec<u8>, BtiError> {
+        if pixels.len() != width as usize * height as usize {
+            return Err(BtiError::WrongImageSize {
+                width,
+                height,
+                pixels: pixels.len(),
+            });
+        }
+        let format = TextureFormat::from_id(header.format).ok_or(BtiError::UnknownFormat(header.format))?;
+        let mut header = header.clone();
+        header.width = u16::try_from(width).map_err(|_| BtiError::TooLarge(width, height))?;
+        header.height = u16::try_from(height).map_err(|_| BtiError::TooLarge(width, height))?;
+        header.mipmap_count = header.mipmap_count.max(1);
+
+        // Every mipmap level shares one palette, so it's built from the full size image
+        let palette = if format.uses_palette() {
+            let palette = Palette::build(pixels, header.palette_format)?;
+            if palette.colors.len() > format.max_colors() {
+                return Err(BtiError::TooManyColors {
+                    format,
+                    colors: palette.colors.len(),
+                });
+            }
+            Some(palette)
+        } else {
+            None
+        };
+
+        let mut img_data = Vec::new();
+        let mut level = Cow::Borrowed(pixels);
+        let (mut level_width, mut level_height) = (width as usize, height as usize);
+        for _ in 0..header.mipmap_count {
+            img_data.extend(encode_blocks(
+                &level,
+                format,
+                level_width,
+                level_height,
+                palette.as_ref(),
+                quality,
+            ));
+            level = Cow::Owned(downsample(&level, level_width, level_height));
+            level_width /= 2;
+            level_height /= 2;
+        }
+
+        // Image data comes straight after the header, followed by the palette
+        header.image_data_offset = BtiHeader::SIZE as u32;
+        header.palette_offset = 0;
+        header.num_colors = 0;
+        let mut out = Vec::with_capacity(BtiHeader::SIZE + img_data.len());
+        out.extend(header.write());
+        out.extend(img_data);
+        if let Some(palette) = palette {
+            header.palette_offset = out.len() as u32;
+            header.num_colors = palette.colors.len() as u16;
+            out.extend(palette.colors.iter().flat_map(|c| c.to_be_bytes()));
+            out[..BtiHeader::SIZE].copy_from_slice(&header.write());
+        }
+        Ok(out)
+    }
+}
+
+/// Same as [`BtiImage::decode`]
+impl FromBytes<'_> for BtiImage {
+    fn from_bytes(data: &[u8]) -> Result<Self, CodecError> {
+        Ok(BtiImage::decode(data)?)
+    }
+}
+
+/// Gives the image's pixels as 8-bit RGBA, row by row
+impl Decode for BtiImage {
+    type Out = Vec<u8>;
+    fn decode(&self) -> Result<Self::Out, CodecError> {
+        Ok(self.data.concat())
+    }
+}
+
+/// Re-encodes the image with the header it was decoded with, at the default quality
+impl Encode for BtiImage {
+    fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        Ok(BtiImage::encode(
+            self.width,
+            self.height,
+            &self.data,
+            &self.header,
+            BtiQuality::default(),
+        )?)
+    }
+}
+
+/// Decodes the first image in `img_data`, which must be large enough to hold it.
+/// `format` is a GX texture format ID as found in BTI headers.
+pub(crate) fn decode_image(img_data: &[u8], format: u8, width: usize, height: usize, palette: &[Color]) -> Vec<Color> {
+    let format = format_to_index(format).expect("Known image format");
+    decode_blocks(img_data, format, width, height, palette)
+}
+
+fn decode_blocks(img_data: &[u8], format: usize, width: usize, height: usize, colors: &[Color]) -> Vec<Color> {
+    let mut decoded_data = vec![[0, 0, 0, 0]; width * height];
+
+    // Blocks are decoded into this buffer and then copied into place row by row,
+    // skipping any part of the block that hangs over the edge of the image
+    let mut block = [[0u8; 4]; MAX_BLOCK_PIXELS];
+    let block_width = BLOCK_WIDTHS[format] as usize;
+    let block_height = BLOCK_HEIGHTS[format] as usize;
+    let block_size = BLOCK_DATA_SIZE[format] as usize;
+    let mut offset = 0;
+    for block_y in (0..height).step_by(block_height) {
+        for block_x in (0..width).step_by(block_width) {
+            match format {
+                0 => decode_i4_block(img_data, offset, block_size, &mut block),
+                1 => decode_i8_block(img_data, offset, block_size, &mut block),
+                2 => decode_ia4_block(img_data, offset, block_size, &mut block),
+                3 => decode_ia8_block(img_data, offset, block_size, &mut block),
+                4 => decode_rgb565_block(img_data, offset, block_size, &mut block),
+                5 => decode_rgb5a3_block(img_data, offset, block_size, &mut block),
+                6 => decode_rgba32_block(img_data, offset, &mut block),
+                7 => decode_c4_block(img_data, offset, block_size, colors, &mut block),
+                8 => decode_c8_block(img_data, offset, block_size, colors, &mut block),
+                9 => decode_c14x2_block(img_data, offset, block_size, colors, &mut block),
+                10 => decode_cmpr_block(img_data, offset, &mut block),
+                _ => unreachable!("Unknown image format {format}"),
+            }
+
+            let visible_width = block_width.min(width - block_x);
+            for y_in_block in 0..block_height.min(height - block_y) {
+                let src = &block[y_in_block * block_width..][..visible_width];
+                let dest_start = block_x + (block_y + y_in_block) * width;
+                decoded_data[dest_start..dest_start + visible_width].copy_from_slice(src);
+            }
+            offset += block_size;
+        }
+    }
+    decoded_data
+}
+
+/// Reorders pixels from rows into the tiled block layout GX textures use. Parts of
+/// blocks past the edge of the image are filled with the default value.
+pub(crate) fn to_blocks<T: Copy + Default>(
+    pixels: &[T],
+    width: usize,
+    height: usize,
+    block_width: usize,
+    block_height: usize,
+) -> Vec<T> {
+    let mut out = Vec::with_capacity(pixels.len());
+    for block_y in (0..height).step_by(block_height) {
+        for block_x in (0..width).step_by(block_width) {
+            for y in block_y..block_y + block_height {
+                for x in block_x..block_x + block_width {
+                    let inside = x < width && y < height;
+                    out.push(if inside { pixels[y * width + x] } else { T::default() });
+                }
+            }
+        }
+    }
+    out
 }
 
 const BLOCK_WIDTHS: [u16; 11] = [8, 8, 8, 4, 4, 4, 4, 8, 8, 4, 8];
 const BLOCK_HEIGHTS: [u16; 11] = [8, 4, 4, 4, 4, 4, 4, 8, 4, 4, 8];
 const BLOCK_DATA_SIZE: [u16; 11] = [32, 32, 32, 32, 32, 32, 64, 32, 32, 32, 32];
+const MAX_BLOCK_PIXELS: usize = 64;
 
-fn format_to_index(format: u8) -> usize {
+fn format_to_index(format: u8) -> Option<usize> {
     match format {
-        0x8 => 7,
-        0x9 => 8,
-        0xA => 9,
-        0xE => 10,
-        _ => format as usize,
+        0x0..=0x6 => Some(format as usize),
+        0x8 => Some(7),
+        0x9 => Some(8),
+        0xA => Some(9),
+        0xE => Some(10),
+        _ => None,
     }
 }
 
+/// In 64 bits, since the largest images with every mipmap level take more than 4 GiB
 fn get_mipmap_offset(
     mut mipmap_index: u8,
-    mut width: u32,
-    mut height: u32,
-    block_width: u32,
-    block_height: u32,
-    block_data_size: u32,
-) -> usize {
+    mut width: u64,
+    mut height: u64,
+    block_width: u64,
+    block_height: u64,
+    block_data_size: u64,
+) -> u64 {
     let mut offset = 0;
-    let mut blocks_wide = (width + block_width - 1) / block_width;
-    let mut blocks_tall = (height + block_height - 1) / block_height;
+    let mut blocks_wide = width.div_ceil(block_width);
+    let mut blocks_tall = height.div_ceil(block_height);
     let mut curr_mipmap_size = blocks_wide * blocks_tall * block_data_size;
     while mipmap_index > 0 {
         offset += curr_mipmap_size;
         width /= 2;
         height /= 2;
-        blocks_wide = (width + block_width - 1) / block_width;
-        blocks_tall = (height + block_height - 1) / block_height;
+        blocks_wide = width.div_ceil(block_width);
+        blocks_tall = height.div_ceil(block_height);
         curr_mipmap_size = blocks_wide * blocks_tall * block_data_size;
         mipmap_index -= 1;
     }
-    return offset as usize;
+    offset
 }
 
-fn decode_palettes(
-    palette_data: &[u8],
-    palette_format: u8,
-    num_colors: u16,
-    img_format: usize,
-) -> Vec<Color> {
-    // Only these 3 formats use palettes
-    if ![7, 8, 9].contains(&img_format) {
-        return vec![];
-    }
-
-    let mut colors = Vec::with_capacity(num_colors as usize);
-    for o in 0..num_colors {
-        let raw_color = read_u16(palette_data, (o * 2) as u32);
-        let color = match palette_format {
-            0 => ia8_to_color(raw_color),
-            1 => rgb565_to_color(raw_color),
-            2 => rgb5a3_to_color(raw_color),
-            _ => panic!("Invalid palette format: {palette_format}"),
-        };
-        colors.push(color);
-    }
+/// The `size` bytes at `offset` in `data`, or None if any of them are past the end
+pub(crate) fn slice_at(data: &[u8], offset: u64, size: u64) -> Option<&[u8]> {
+    let start = usize::try_from(offset).ok()?;
+    let end = usize::try_from(offset.checked_add(size)?).ok()?;
+    data.get(start..end)
+}
 
-    colors
+/// Decodes every color in `palette_data`. Palettes shorter than the image's color
+/// indexes expect are fine; out of range indexes decode as transparent.
+pub(crate) fn decode_palette(palette_data: &[u8], palette_format: u8) -> Result<Vec<Color>, BtiError> {
+    let to_color = match palette_format {
+        0 => ia8_to_color,
+        1 => rgb565_to_color,
+        2 => rgb5a3_to_color,
+        _ => return Err(BtiError::InvalidPaletteFormat(palette_format)),
+    };
+    Ok(palette_data
+        .chunks_exact(2)
+        .map(|raw_color| to_color(u16::from_be_bytes([raw_color[0], raw_color[1]])))
+        .collect())
 }
 
-fn decode_i4_block(img_data: &[u8], offset: usize, block_data_size: usize) -> Vec<Color> {
-    let mut pixels = Vec::with_capacity(block_data_size * 2);
-    for i in 0..block_data_size {
-        let b = img_data[offset + i];
-        pixels.push(i4_to_color((b >> 4) & 0xF));
-        pixels.push(i4_to_color(b & 0xF));
+fn decode_i4_block(img_data: &[u8], offset: usize, block_data_size: usize, out: &mut [Color]) {
+    for (i, &b) in img_data[offset..offset + block_data_size].iter().enumerate() {
+        out[i * 2] = i4_to_color((b >> 4) & 0xF);
+        out[i * 2 + 1] = i4_to_color(b & 0xF);
     }
-    pixels
 }
 
 const fn i4_to_color(c: u8) -> Color {
@@ -194,24 +640,20 @@ const fn i4_to_color(c: u8) -> Color {
     ]
 }
 
-fn decode_i8_block(img_data: &[u8], offset: usize, block_data_size: usize) -> Vec<Color> {
-    let mut pixels = Vec::with_capacity(block_data_size);
-    for i in 0..block_data_size {
-        pixels.push(i8_to_color(img_data[offset + i]));
+fn decode_i8_block(img_data: &[u8], offset: usize, block_data_size: usize, out: &mut [Color]) {
+    for (pixel, &b) in out.iter_mut().zip(&img_data[offset..offset + block_data_size]) {
+        *pixel = i8_to_color(b);
     }
-    pixels
 }
 
 const fn i8_to_color(c: u8) -> Color {
     [c, c, c, c]
 }
 
-fn decode_ia4_block(img_data: &[u8], offset: usize, block_data_size: usize) -> Vec<Color> {
-    let mut pixels = Vec::with_capacity(block_data_size);
-    for i in 0..block_data_size {
-        pixels.push(ia4_to_color(img_data[offset + i]));
+fn decode_ia4_block(img_data: &[u8], offset: usize, block_data_size: usize, out: &mut [Color]) {
+    for (pixel, &b) in out.iter_mut().zip(&img_data[offset..offset + block_data_size]) {
+        *pixel = ia4_to_color(b);
     }
-    pixels
 }
 
 const fn ia4_to_color(c: u8) -> Color {
@@ -223,12 +665,8 @@ const fn ia4_to_color(c: u8) -> Color {
     ]
 }
 
-fn decode_ia8_block(img_data: &[u8], offset: usize, block_data_size: usize) -> Vec<Color> {
-    let mut pixels = Vec::with_capacity(block_data_size / 2);
-    for i in 0..block_data_size / 2 {
-        pixels.push(ia8_to_color(read_u16(img_data, (offset + i * 2) as u32)));
-    }
-    pixels
+fn decode_ia8_block(img_data: &[u8], offset: usize, block_data_size: usize, out: &mut [Color]) {
+    decode_u16_block(img_data, offset, block_data_size, out, ia8_to_color);
 }
 
 const fn ia8_to_color(c: u16) -> Color {
@@ -240,12 +678,8 @@ const fn ia8_to_color(c: u16) -> Color {
     ]
 }
 
-fn decode_rgb565_block(img_data: &[u8], offset: usize, block_data_size: usize) -> Vec<Color> {
-    let mut pixels = Vec::with_capacity(block_data_size / 2);
-    for i in 0..block_data_size / 2 {
-        pixels.push(rgb565_to_color(read_u16(img_data, (offset + i * 2) as u32)));
-    }
-    pixels
+fn decode_rgb565_block(img_data: &[u8], offset: usize, block_data_size: usize, out: &mut [Color]) {
+    decode_u16_block(img_data, offset, block_data_size, out, rgb565_to_color);
 }
 
 const fn rgb565_to_color(c: u16) -> Color {
@@ -257,12 +691,8 @@ const fn rgb565_to_color(c: u16) -> Color {
     ]
 }
 
-fn decode_rgb5a3_block(img_data: &[u8], offset: usize, block_data_size: usize) -> Vec<Color> {
-    let mut pixels = Vec::with_capacity(block_data_size / 2);
-    for i in 0..block_data_size / 2 {
-        pixels.push(rgb5a3_to_color(read_u16(img_data, (offset + i * 2) as u32)));
-    }
-    pixels
+fn decode_rgb5a3_block(img_data: &[u8], offset: usize, block_data_size: usize, out: &mut [Color]) {
+    decode_u16_block(img_data, offset, block_data_size, out, rgb5a3_to_color);
 }
 
 const fn rgb5a3_to_color(c: u16) -> Color {
@@ -283,78 +713,64 @@ const fn rgb5a3_to_color(c: u16) -> Color {
     }
 }
 
-fn decode_rgba32_block(img_data: &[u8], offset: usize) -> Vec<Color> {
-    let mut colors = Vec::with_capacity(16);
-    for i in 0..16 {
-        let color = [
-            img_data[offset + i * 2],
-            img_data[offset + (i * 2) + 1],
-            img_data[offset + (i * 2) + 32],
-            img_data[offset + (i * 2) + 33],
-        ];
-        colors.push(color);
+/// Fully opaque colors use the 5 bits per channel form, everything else gives up a bit
+/// per channel for 3 bits of alpha
+pub(crate) const fn color_to_rgb5a3(c: Color) -> u16 {
+    let [r, g, b, a] = c;
+    if a == 255 {
+        0x8000 | ((r as u16 >> 3) << 10) | ((g as u16 >> 3) << 5) | (b as u16 >> 3)
+    } else {
+        ((a as u16 >> 5) << 12) | ((r as u16 >> 4) << 8) | ((g as u16 >> 4) << 4) | (b as u16 >> 4)
     }
-    colors
 }
 
-fn decode_c4_block(
+/// Decodes a block of 16-bit big endian values, one per pixel
+fn decode_u16_block(
     img_data: &[u8],
     offset: usize,
     block_data_size: usize,
-    palette: &Vec<Color>,
-) -> Vec<Color> {
-    let mut colors = Vec::with_capacity(block_data_size * 2);
-    for i in 0..block_data_size {
-        for nibble in 0..2 {
-            let color_index = (img_data[offset + i] >> ((1 - nibble) * 4)) & 0xF;
-            if color_index as usize > palette.len() {
-                colors.push([0, 0, 0, 0]); // Past the edge of the image
-            } else {
-                colors.push(palette[color_index as usize]);
-            }
-        }
+    out: &mut [Color],
+    to_color: impl Fn(u16) -> Color,
+) {
+    let values = img_data[offset..offset + block_data_size].chunks_exact(2);
+    for (pixel, value) in out.iter_mut().zip(values) {
+        *pixel = to_color(u16::from_be_bytes([value[0], value[1]]));
     }
-    colors
 }
 
-fn decode_c8_block(
-    img_data: &[u8],
-    offset: usize,
-    block_data_size: usize,
-    palette: &Vec<Color>,
-) -> Vec<Color> {
-    let mut colors = Vec::with_capacity(block_data_size);
-    for i in 0..block_data_size {
-        let color_index = img_data[offset + i];
-        if color_index as usize > palette.len() {
-            colors.push([0, 0, 0, 0]); // Past the edge of the image
-        } else {
-            colors.push(palette[color_index as usize]);
-        }
+fn decode_rgba32_block(img_data: &[u8], offset: usize, out: &mut [Color]) {
+    // Alpha and red for all 16 pixels come first, then green and blue
+    let (ar, gb) = img_data[offset..offset + 64].split_at(32);
+    for (i, pixel) in out[..16].iter_mut().enumerate() {
+        *pixel = [ar[i * 2 + 1], gb[i * 2], gb[i * 2 + 1], ar[i * 2]];
     }
-    colors
 }
 
-fn decode_c14x2_block(
-    img_data: &[u8],
-    offset: usize,
-    block_data_size: usize,
-    palette: &Vec<Color>,
-) -> Vec<Color> {
-    let mut colors = Vec::with_capacity(block_data_size / 2);
-    for i in 0..block_data_size / 2 {
-        let color_index = read_u16(img_data, (offset + i) as u32) & 0x3FFF;
-        if color_index as usize > palette.len() {
-            colors.push([0, 0, 0, 0]); // Past the edge of the image
-        } else {
-            colors.push(palette[color_index as usize]);
-        }
+fn decode_c4_block(img_data: &[u8], offset: usize, block_data_size: usize, palette: &[Color], out: &mut [Color]) {
+    for (i, &b) in img_data[offset..offset + block_data_size].iter().enumerate() {
+        out[i * 2] = palette_color(palette, (b >> 4) as usize);
+        out[i * 2 + 1] = palette_color(palette, (b & 0xF) as usize);
+    }
+}
+
+fn decode_c8_block(img_data: &[u8], offset: usize, block_data_size: usize, palette: &[Color], out: &mut [Color]) {
+    for (pixel, &b) in out.iter_mut().zip(&img_data[offset..offset + block_data_size]) {
+        *pixel = palette_color(palette, b as usize);
     }
-    colors
 }
 
-fn decode_cmpr_block(img_data: &[u8], offset: usize) -> Vec<Color> {
-    let mut colors = vec![[0, 0, 0, 0]; 64];
+fn decode_c14x2_block(img_data: &[u8], offset: usize, block_data_size: usize, palette: &[Color], out: &mut [Color]) {
+    decode_u16_block(img_data, offset, block_data_size, out, |c| {
+        palette_color(palette, (c & 0x3FFF) as usize)
+    });
+}
+
+/// Indexes past the end of the palette decode as transparent
+fn palette_color(palette: &[Color], index: usize) -> Color {
+    palette.get(index).copied().unwrap_or([0, 0, 0, 0])
+}
+
+fn decode_cmpr_block(img_data: &[u8], offset: usize, out: &mut [Color]) {
     let mut sub_block_offset = offset;
     for sub_block in 0..4 {
         let x = (sub_block % 2) * 4;
@@ -366,51 +782,364 @@ fn decode_cmpr_block(img_data: &[u8], offset: usize) -> Vec<Color> {
         let color_indexes = read_u32(img_data, sub_block_offset as u32 + 4);
         for i in 0..16 {
             let color_index = (color_indexes >> ((15 - i) * 2)) & 3;
-            let color = palette[color_index as usize];
             let sub_x = i % 4;
             let sub_y = i / 4;
-            let pixel_index = x + (y * 8) + sub_x + (sub_y * 8);
-            colors[pixel_index] = color;
+            out[x + (y * 8) + sub_x + (sub_y * 8)] = palette[color_index as usize];
         }
 
         sub_block_offset += 8;
     }
-    colors
 }
 
+/// Colors a CMPR sub block can use. The in-between colors are worked out in 16 bits so
+/// bright endpoints don't overflow.
 const fn get_interpolated_cmpr_colors(c1b: u16, c2b: u16) -> [Color; 4] {
     let c1 = rgb565_to_color(c1b);
     let c2 = rgb565_to_color(c2b);
     if c1b > c2b {
-        [
-            c1,
-            c2,
-            [
-                (2u8.saturating_mul(c1[0]).saturating_add(c2[0])) / 3,
-                (2u8.saturating_mul(c1[1]).saturating_add(c2[1])) / 3,
-                (2u8.saturating_mul(c1[2]).saturating_add(c2[2])) / 3,
-                255,
-            ],
-            [
-                (c1[0].saturating_add(2u8.saturating_mul(c2[0]))) / 3,
-                (c1[1].saturating_add(2u8.saturating_mul(c2[1]))) / 3,
-                (c1[2].saturating_add(2u8.saturating_mul(c2[2]))) / 3,
-                255,
-            ],
-        ]
+        [c1, c2, mix_colors(c1, c2, 2, 1), mix_colors(c1, c2, 1, 2)]
     } else {
-        [
-            c1,
-            c2,
-            [
-                c1[0] / 2 + c2[0] / 2,
-                c1[1] / 2 + c2[1] / 2,
-                c1[2] / 2 + c2[2] / 2,
-                255,
-            ],
-            [0, 0, 0, 0],
-        ]
+        [c1, c2, mix_colors(c1, c2, 1, 1), [0, 0, 0, 0]]
+    }
+}
+
+/// Weighted average of two opaque colors
+const fn mix_colors(a: Color, b: Color, weight_a: u16, weight_b: u16) -> Color {
+    let total = weight_a + weight_b;
+    [
+        ((a[0] as u16 * weight_a + b[0] as u16 * weight_b) / total) as u8,
+        ((a[1] as u16 * weight_a + b[1] as u16 * weight_b) / total) as u8,
+        ((a[2] as u16 * weight_a + b[2] as u16 * weight_b) / total) as u8,
+        255,
+    ]
+}
+
+/// Colors an image uses, converted to a palette format, in the order they first appear
+struct Palette {
+    colors: Vec<u16>,
+    indexes: HashMap<u16, u16>,
+    to_palette_color: fn(Color) -> u16,
+    to_color: fn(u16) -> Color,
+}
+
+impl Palette {
+    fn new(to_palette_color: fn(Color) -> u16, to_color: fn(u16) -> Color) -> Self {
+        Palette {
+            colors: Vec::new(),
+            indexes: HashMap::new(),
+            to_palette_color,
+            to_color,
+        }
+    }
+
+    fn build(pixels: &[Color], palette_format: u8) -> Result<Self, BtiError> {
+        let mut palette = match palette_format {
+            0 => Palette::new(color_to_ia8, ia8_to_color),
+            1 => Palette::new(color_to_rgb565, rgb565_to_color),
+            2 => Palette::new(color_to_rgb5a3, rgb5a3_to_color),
+            _ => return Err(BtiError::InvalidPaletteFormat(palette_format)),
+        };
+        for &pixel in pixels {
+            let color = (palette.to_palette_color)(pixel);
+            palette.indexes.entry(color).or_insert_with(|| {
+                palette.colors.push(color);
+                (palette.colors.len() - 1) as u16
+            });
+        }
+        Ok(palette)
+    }
+
+    /// Mipmaps can have colors the full size image doesn't, which get the closest one
+    /// in the palette
+    fn index(&self, pixel: Color) -> u16 {
+        let color = (self.to_palette_color)(pixel);
+        self.indexes.get(&color).copied().unwrap_or_else(|| {
+            (0..self.colors.len())
+                .min_by_key(|&i| color_distance((self.to_color)(self.colors[i]), pixel))
+                .unwrap_or_default() as u16
+        })
+    }
+}
+
+/// Encodes one image into blocks, the reverse of [`decode_blocks`]
+fn encode_blocks(
+    pixels: &[Color],
+    format: TextureFormat,
+    width: usize,
+    height: usize,
+    palette: Option<&Palette>,
+    quality: BtiQuality,
+) -> Vec<u8> {
+    let index = format_to_index(format.id()).expect("Known image format");
+    let block_width = BLOCK_WIDTHS[index] as usize;
+    let block_height = BLOCK_HEIGHTS[index] as usize;
+    let blocks = |values: Vec<u8>| to_blocks(&values, width, height, block_width, block_height);
+    let u16_blocks = |values: Vec<u16>| {
+        to_blocks(&values, width, height, block_width, block_height)
+            .into_iter()
+            .flat_map(u16::to_be_bytes)
+            .collect()
+    };
+    let map = |to_value: fn(Color) -> u8| pixels.iter().map(|&c| to_value(c)).collect();
+    let map_u16 = |to_value: fn(Color) -> u16| pixels.iter().map(|&c| to_value(c)).collect();
+    let palette_indexes = || {
+        let palette = palette.expect("Palette formats have a palette");
+        pixels.iter().map(|&c| palette.index(c))
+    };
+    match format {
+        TextureFormat::I4 => pack_nibbles(blocks(map(|c| intensity(c) >> 4))),
+        TextureFormat::I8 => blocks(map(intensity)),
+        TextureFormat::IA4 => blocks(map(|c| (c[3] & 0xF0) | intensity(c) >> 4)),
+        TextureFormat::IA8 => u16_blocks(map_u16(color_to_ia8)),
+        TextureFormat::RGB565 => u16_blocks(map_u16(color_to_rgb565)),
+        TextureFormat::RGB5A3 => u16_blocks(map_u16(color_to_rgb5a3)),
+        TextureFormat::RGBA32 => to_blocks(pixels, width, height, 4, 4)
+            .chunks_exact(16)
+            .flat_map(|block| {
+                // Alpha and red for all 16 pixels come first, then green and blue
+                let ar = block.iter().flat_map(|c| [c[3], c[0]]);
+                let gb = block.iter().flat_map(|c| [c[1], c[2]]);
+                ar.chain(gb).collect::<Vec<_>>()
+            })
+            .collect(),
+        TextureFormat::C4 => pack_nibbles(blocks(palette_indexes().map(|i| i as u8).collect())),
+        TextureFormat::C8 => blocks(palette_indexes().map(|i| i as u8).collect()),
+        TextureFormat::C14X2 => u16_blocks(palette_indexes().collect()),
+        TextureFormat::CMPR => {
+            // Pixels past the edge of the image are left out so they don't affect the colors picked
+            let pixels: Vec<Option<Color>> = pixels.iter().copied().map(Some).collect();
+            to_blocks(&pixels, width, height, 8, 8)
+                .chunks_exact(64)
+                .flat_map(|block| {
+                    [(0, 0), (4, 0), (0, 4), (4, 4)].into_iter().flat_map(move |(x, y)| {
+                        let sub_block: [Option<Color>; 16] =
+                            std::array::from_fn(|i| block[x + i % 4 + (y + i / 4) * 8]);
+                        encode_cmpr_sub_block(&sub_block, quality)
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+/// Packs pairs of 4 bit values into bytes, high nibble first
+fn pack_nibbles(values: Vec<u8>) -> Vec<u8> {
+    values.chunks_exact(2).map(|pair| pair[0] << 4 | pair[1]).collect()
+}
+
+/// Halves an image's size by averaging each 2x2 square of pixels
+fn downsample(pixels: &[Color], width: usize, height: usize) -> Vec<Color> {
+    let (new_width, new_height) = (width / 2, height / 2);
+    let mut out = Vec::with_capacity(new_width * new_height);
+    for y in 0..new_height {
+        for x in 0..new_width {
+            let square = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(dx, dy)| pixels[(y * 2 + dy) * width + x * 2 + dx]);
+            out.push(std::array::from_fn(|channel| {
+                (square.iter().map(|c| c[channel] as u32).sum::<u32>() / 4) as u8
+            }));
+        }
     }
+    out
+}
+
+/// Encodes a 4x4 CMPR sub block. Blocks with any transparent pixels use the mode with
+/// one transparent color and a single interpolated one.
+fn encode_cmpr_sub_block(pixels: &[Option<Color>; 16], quality: BtiQuality) -> [u8; 8] {
+    let opaque: Vec<Color> = pixels.iter().flatten().copied().filter(|c| c[3] >= 0x80).collect();
+    let has_transparency = pixels.iter().flatten().any(|c| c[3] < 0x80);
+    if opaque.is_empty() {
+        return [0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF];
+    }
+
+    let axis = principal_axis(&opaque);
+    let (start, end) = cmpr_range_fit(&opaque, axis);
+    let mut best = CmprFit::new(pixels, start, end, has_transparency);
+    if quality == BtiQuality::High {
+        if let Some((start, end)) = cmpr_cluster_fit(&opaque, axis, has_transparency) {
+            let fit = CmprFit::new(pixels, start, end, has_transparency);
+            if fit.error < best.error {
+                best = fit;
+            }
+        }
+    }
+
+    let mut out = [0u8; 8];
+    out[..2].copy_from_slice(&best.color0.to_be_bytes());
+    out[2..4].copy_from_slice(&best.color1.to_be_bytes());
+    out[4..].copy_from_slice(&best.indexes.to_be_bytes());
+    out
+}
+
+/// A CMPR sub block's endpoints and the palette color each pixel uses
+struct CmprFit {
+    color0: u16,
+    color1: u16,
+    indexes: u32,
+    /// Total squared distance of each pixel from its palette color
+    error: u32,
+}
+
+impl CmprFit {
+    fn new(pixels: &[Option<Color>; 16], start: [f32; 3], end: [f32; 3], has_transparency: bool) -> Self {
+        let (mut color0, mut color1) = (quantize_rgb565(start), quantize_rgb565(end));
+        // The order of the endpoints decides the mode
+        if has_transparency == (color0 > color1) {
+            std::mem::swap(&mut color0, &mut color1);
+        }
+        let palette = get_interpolated_cmpr_colors(color0, color1);
+        let candidates = if color0 > color1 { 4 } else { 3 };
+
+        let mut indexes = 0u32;
+        let mut error = 0;
+        for (i, pixel) in pixels.iter().enumerate() {
+            let index = match *pixel {
+                Some(c) if c[3] < 0x80 => 3,
+                Some(c) => {
+                    let index = (0..candidates).min_by_key(|&i| color_distance(palette[i], c)).unwrap();
+                    error += color_distance(palette[index], c);
+                    index
+                }
+                None => 0,
+            };
+            indexes |= (index as u32) << ((15 - i) * 2);
+        }
+        CmprFit {
+            color0,
+            color1,
+            indexes,
+            error,
+        }
+    }
+}
+
+/// The direction `colors` vary most in
+fn principal_axis(colors: &[Color]) -> [f32; 3] {
+    let count = colors.len() as f32;
+    let mean: [f32; 3] = std::array::from_fn(|channel| colors.iter().map(|c| c[channel] as f32).sum::<f32>() / count);
+    let mut covariance = [[0f32; 3]; 3];
+    for c in colors {
+        let d: [f32; 3] = std::array::from_fn(|channel| c[channel] as f32 - mean[channel]);
+        for (row, &d_row) in covariance.iter_mut().zip(&d) {
+            for (value, &d_col) in row.iter_mut().zip(&d) {
+                *value += d_row * d_col;
+            }
+        }
+    }
+    // Power iteration converges on the direction of greatest variance
+    let mut axis = [1f32, 1., 1.];
+    for _ in 0..8 {
+        let next: [f32; 3] = std::array::from_fn(|row| (0..3).map(|col| covariance[row][col] * axis[col]).sum());
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length == 0.0 {
+            break;
+        }
+        axis = next.map(|v| v / length);
+    }
+    axis
+}
+
+fn project(c: [f32; 3], axis: [f32; 3]) -> f32 {
+    c[0] * axis[0] + c[1] * axis[1] + c[2] * axis[2]
+}
+
+fn to_rgb_f32(c: Color) -> [f32; 3] {
+    [c[0] as f32, c[1] as f32, c[2] as f32]
+}
+
+/// The two colors at either end of `colors` along `axis`
+fn cmpr_range_fit(colors: &[Color], axis: [f32; 3]) -> ([f32; 3], [f32; 3]) {
+    let by_projection =
+        |a: &&Color, b: &&Color| project(to_rgb_f32(**a), axis).total_cmp(&project(to_rgb_f32(**b), axis));
+    let min = colors.iter().min_by(by_projection).unwrap();
+    let max = colors.iter().max_by(by_projection).unwrap();
+    (to_rgb_f32(*max), to_rgb_f32(*min))
+}
+
+/// Sorts `colors` along `axis` and tries every way of splitting them into consecutive
+/// runs, one for each color in the block's palette. For each split, the endpoints that
+/// best fit the runs by least squares are worked out, and the pair with the least error
+/// wins. None if every split puts all the colors on one endpoint.
+fn cmpr_cluster_fit(colors: &[Color], axis: [f32; 3], has_transparency: bool) -> Option<([f32; 3], [f32; 3])> {
+    let mut sorted: Vec<[f32; 3]> = colors.iter().map(|&c| to_rgb_f32(c)).collect();
+    sorted.sort_by(|&a, &b| project(a, axis).total_cmp(&project(b, axis)));
+    // How much of the start endpoint each palette color is made of, in order from the
+    // start to the end. With only one interpolated color, the two middle runs share it.
+    let weights: [f32; 4] = match has_transparency {
+        true => [1., 0.5, 0.5, 0.],
+        false => [1., 2. / 3., 1. / 3., 0.],
+    };
+
+    let mut prefix_sums = vec![[0f32; 3]; sorted.len() + 1];
+    for (i, c) in sorted.iter().enumerate() {
+        prefix_sums[i + 1] = std::array::from_fn(|channel| prefix_sums[i][channel] + c[channel]);
+    }
+    let n = sorted.len();
+    let mut best: Option<(f32, [f32; 3], [f32; 3])> = None;
+    for first in 0..=n {
+        for second in first..=n {
+            for third in second..=n {
+                let bounds = [0, first, second, third, n];
+                let (mut aa, mut bb, mut ab) = (0f32, 0f32, 0f32);
+                let (mut ax, mut bx) = ([0f32; 3], [0f32; 3]);
+                for (run, &alpha) in weights.iter().enumerate() {
+                    let (from, to) = (bounds[run], bounds[run + 1]);
+                    let count = (to - from) as f32;
+                    let beta = 1. - alpha;
+                    aa += count * alpha * alpha;
+                    bb += count * beta * beta;
+                    ab += count * alpha * beta;
+                    for channel in 0..3 {
+                        let sum = prefix_sums[to][channel] - prefix_sums[from][channel];
+                        ax[channel] += alpha * sum;
+                        bx[channel] += beta * sum;
+                    }
+                }
+                let determinant = aa * bb - ab * ab;
+                if determinant.abs() < f32::EPSILON {
+                    continue;
+                }
+                let start: [f32; 3] = std::array::from_fn(|c| (ax[c] * bb - bx[c] * ab) / determinant);
+                let end: [f32; 3] = std::array::from_fn(|c| (bx[c] * aa - ax[c] * ab) / determinant);
+                // Squared error, leaving out the sum of the squared colors which is the
+                // same for every split
+                let error: f32 = (0..3)
+                    .map(|c| {
+                        aa * start[c] * start[c] + bb * end[c] * end[c] + 2. * ab * start[c] * end[c]
+                            - 2. * start[c] * ax[c]
+                            - 2. * end[c] * bx[c]
+                    })
+                    .sum();
+                if best.is_none_or(|(best_error, _, _)| error < best_error) {
+                    best = Some((error, start, end));
+                }
+            }
+        }
+    }
+    best.map(|(_, start, end)| (start, end))
+}
+
+/// Converts a color to RGB565, rounding to the nearest value rather than truncating
+fn quantize_rgb565(c: [f32; 3]) -> u16 {
+    let quantize = |value: f32, max: f32| (value.clamp(0., 255.) * max / 255.).round() as u16;
+    quantize(c[0], 31.) << 11 | quantize(c[1], 63.) << 5 | quantize(c[2], 31.)
+}
+
+fn color_distance(a: Color, b: Color) -> u32 {
+    (0..4).map(|i| (a[i] as i32 - b[i] as i32).pow(2) as u32).sum()
+}
+
+/// Perceived brightness, used by the intensity formats
+const fn intensity(c: Color) -> u8 {
+    ((c[0] as u32 * 299 + c[1] as u32 * 587 + c[2] as u32 * 114 + 500) / 1000) as u8
+}
+
+const fn color_to_ia8(c: Color) -> u16 {
+    (c[3] as u16) << 8 | intensity(c) as u16
+}
+
+const fn color_to_rgb565(c: Color) -> u16 {
+    let [r, g, b, _] = c;
+    ((r as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (b as u16 >> 3)
 }
 
 const fn swizzle_3_to_8(b: u8) -> u8 {
@@ -428,3 +1157,45 @@ const fn swizzle_5_to_8(b: u8) -> u8 {
 const fn swizzle_6_to_8(b: u8) -> u8 {
     (b << 2) | (b >> 4)
 }
+
+#[derive(Debug, Error)]
+pub enum BtiError {
+    #[error("BTI header is truncated")]
+    Truncated,
+
+    #[error("Unknown image format {0:#X}")]
+    UnknownFormat(u8),
+
+    #[error("Invalid palette format {0}")]
+    InvalidPaletteFormat(u8),
+
+    #[error("Image data at {offset:#X} (size {size:#X}) is past the end of the file ({file_size:#X} bytes)")]
+    ImageDataOutOfBounds { offset: u64, size: u64, file_size: usize },
+
+    /// The palette is likely stored in a separate file
+    #[error("Palette at {offset:#X} ({num_colors} colors) is past the end of the file ({file_size:#X} bytes)")]
+    PaletteOutOfBounds {
+        offset: u64,
+        num_colors: u16,
+        file_size: usize,
+    },
+
+    /// The palette is likely stored in a separate file
+    #[error("Image format uses a palette, but the BTI doesn't contain one")]
+    MissingPalette,
+
+    #[error("Image is {width}x{height} but has {pixels} pixels")]
+    WrongImageSize { width: u32, height: u32, pixels: usize },
+
+    #[error("Image is {0}x{1}, larger than a BTI can hold")]
+    TooLarge(u32, u32),
+
+    #[error("Image has {colors} colors, more than {format} can hold")]
+    TooManyColors { format: TextureFormat, colors: usize },
+}
+
+impl From<OutOfBounds> for BtiError {
+    fn from(_: OutOfBounds) -> Self {
+        BtiError::Truncated
+    }
+}
@@ -0,0 +1,569 @@
+use crate::{
+    afs,
+    bmg::TextEncoding,
+    gcz::{is_gcz, GczReader},
+    iso::IsoMetadata,
+    msbt::MsbtEncoding,
+    rarc::ByteOrder,
+    szs::{Yaz0Info, Yaz0Magic},
+    util::{read_u16, read_u32},
+};
+use std::{
+    io::{Cursor, Read},
+    path::Path,
+};
+
+/// Every file format cube knows how to identify, whether or not it can extract it yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileFormat {
+    Gcm,
+    Yaz0,
+    Yay0,
+    Rarc,
+    U8,
+    Afs,
+    Imet,
+    Imd5,
+    Bmg,
+    Msbt,
+    Jpc,
+    Blo,
+    Bmd,
+    Baa,
+    Bti,
+    Bcsv,
+    Rel,
+    Gci,
+}
+
+/// Identifies a format from the start of a file's contents
+pub type Sniffer = fn(&[u8]) -> Option<Detection>;
+
+/// Static information about a format and how to recognize it
+pub struct FormatInfo {
+    pub format: FileFormat,
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Lowercase file extensions this format commonly uses, without the dot
+    pub extensions: &'static [&'static str],
+    /// Identifies the format from file contents. None for formats without a magic
+    /// number or a reliable heuristic, which can only be identified by extension.
+    pub sniff: Option<Sniffer>,
+}
+
+/// The result of identifying a file from its contents
+#[derive(Debug, Clone)]
+pub struct Detection {
+    pub format: FileFormat,
+    pub version: Option<String>,
+    /// Key header values, in the order they appear in the file
+    pub metadata: Vec<(&'static str, String)>,
+}
+
+/// All known formats. Sniffers run in this order, so formats with stronger magic
+/// numbers come before heuristic ones.
+pub static FORMATS: &[FormatInfo] = &[
+    FormatInfo {
+        format: FileFormat::Gcm,
+        name: "GCM",
+        description: "GameCube disc image",
+        extensions: &["iso", "gcm", "gcz", "rvz", "wia"],
+        sniff: Some(sniff_gcm),
+    },
+    FormatInfo {
+        format: FileFormat::Yaz0,
+        name: "Yaz0",
+        description: "Yaz0 compressed data",
+        extensions: &["szs"],
+        sniff: Some(sniff_yaz0),
+    },
+    FormatInfo {
+        format: FileFormat::Yay0,
+        name: "Yay0",
+        description: "Yay0 compressed data",
+        extensions: &["szp"],
+        sniff: Some(sniff_yay0),
+    },
+    FormatInfo {
+        format: FileFormat::Rarc,
+        name: "RARC",
+        description: "Archive",
+        extensions: &["arc"],
+        sniff: Some(sniff_rarc),
+    },
+    FormatInfo {
+        format: FileFormat::U8,
+        name: "U8",
+        description: "Archive",
+        extensions: &["u8"],
+        sniff: Some(sniff_u8),
+    },
+    FormatInfo {
+        format: FileFormat::Afs,
+        name: "AFS",
+        description: "CRI archive",
+        extensions: &["afs"],
+        sniff: Some(sniff_afs),
+    },
+    FormatInfo {
+        format: FileFormat::Imet,
+        name: "IMET",
+        description: "Wii banner",
+        extensions: &["bnr"],
+        sniff: Some(sniff_imet),
+    },
+    FormatInfo {
+        format: FileFormat::Imd5,
+        name: "IMD5",
+        description: "Hashed Wii banner part",
+        extensions: &[],
+        sniff: Some(sniff_imd5),
+    },
+    FormatInfo {
+        format: FileFormat::Bmg,
+        name: "BMG",
+        description: "Text dictionary",
+        extensions: &["bmg"],
+        sniff: Some(sniff_bmg),
+    },
+    FormatInfo {
+        format: FileFormat::Msbt,
+        name: "MSBT",
+        description: "Text dictionary from later consoles",
+        extensions: &["msbt"],
+        sniff: Some(sniff_msbt),
+    },
+    FormatInfo {
+        format: FileFormat::Jpc,
+        name: "JPC",
+        description: "Particle effects",
+        extensions: &["jpc"],
+        sniff: Some(sniff_jpc),
+    },
+    FormatInfo {
+        format: FileFormat::Blo,
+        name: "BLO",
+        description: "Screen layout",
+        extensions: &["blo"],
+        sniff: Some(sniff_blo),
+    },
+    FormatInfo {
+        format: FileFormat::Bmd,
+        name: "BMD",
+        description: "3D model",
+        extensions: &["bmd", "bdl"],
+        sniff: Some(sniff_bmd),
+    },
+    FormatInfo {
+        format: FileFormat::Baa,
+        name: "BAA",
+        description: "Audio archive",
+        extensions: &["baa"],
+        sniff: Some(sniff_baa),
+    },
+    FormatInfo {
+        format: FileFormat::Gci,
+        name: "GCI",
+        description: "Memory card save file",
+        extensions: &["gci"],
+        sniff: Some(sniff_gci),
+    },
+    FormatInfo {
+        format: FileFormat::Bti,
+        name: "BTI",
+        description: "Texture",
+        extensions: &["bti"],
+        sniff: Some(sniff_bti),
+    },
+    FormatInfo {
+        format: FileFormat::Bcsv,
+        name: "BCSV",
+        description: "Data table",
+        extensions: &["bcsv", "jmp"],
+        sniff: None,
+    },
+    FormatInfo {
+        format: FileFormat::Rel,
+        name: "REL",
+        description: "Relocatable code module",
+        extensions: &["rel"],
+        sniff: None,
+    },
+];
+
+impl FileFormat {
+    pub fn info(&self) -> &'static FormatInfo {
+        FORMATS
+            .iter()
+            .find(|info| info.format == *self)
+            .expect("Every format is registered")
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.info().name
+    }
+
+    pub fn from_extension(extension: &str) -> Option<FileFormat> {
+        let extension = extension.to_ascii_lowercase();
+        FORMATS
+            .iter()
+            .find(|info| info.extensions.contains(&extension.as_str()))
+            .map(|info| info.format)
+    }
+
+    pub fn from_path(path: &Path) -> Option<FileFormat> {
+        FileFormat::from_extension(&path.extension()?.to_string_lossy())
+    }
+}
+
+/// Sniffers never look further than this many bytes into a file, so callers working
+/// with large files such as disc images only need to read this much.
+pub const SNIFF_LEN: usize = 0x10000;
+
+/// Identifies a file from its contents alone
+pub fn detect(data: &[u8]) -> Option<Detection> {
+    FORMATS
+        .iter()
+        .filter_map(|info| info.sniff)
+        .find_map(|sniff| sniff(data))
+}
+
+/// Identifies a file by its extension, except where Wii files reuse extensions like
+/// .arc and .bin for archives and banners, which are checked by their contents
+pub fn identify(path: &Path, data: &[u8]) -> Option<FileFormat> {
+    let format = FileFormat::from_path(path);
+    if matches!(format, None | Some(FileFormat::Rarc)) {
+        let detected = detect(data).map(|detection| detection.format);
+        if let Some(detected @ (FileFormat::U8 | FileFormat::Imet | FileFormat::Imd5)) = detected {
+            return Some(detected);
+        }
+    }
+    format
+}
+
+/// Compressed images are recognized too. The start of a GCZ is usually decompressed
+/// enough to read its header, but RVZ and WIA images are only identified as such.
+fn sniff_gcm(data: &[u8]) -> Option<Detection> {
+    let container = match data.get(..4)? {
+        magic if is_gcz(magic) => "GCZ",
+        b"RVZ\x01" => "RVZ",
+        b"WIA\x01" => "WIA",
+        _ => return sniff_boot_bin(data),
+    };
+    let mut boot_bin = vec![0u8; IsoMetadata::HEADER_LEN];
+    let decompressed = container == "GCZ"
+        && GczReader::new(Cursor::new(data)).is_ok_and(|mut gcz| gcz.read_exact(&mut boot_bin).is_ok());
+    let mut detection = decompressed
+        .then(|| sniff_boot_bin(&boot_bin))
+        .flatten()
+        .unwrap_or(Detection {
+            format: FileFormat::Gcm,
+            version: None,
+            metadata: Vec::new(),
+        });
+    detection.metadata.push(("container", container.to_owned()));
+    Some(detection)
+}
+
+fn sniff_boot_bin(data: &[u8]) -> Option<Detection> {
+    let metadata = IsoMetadata::from_boot_bin(data)?;
+    Some(Detection {
+        format: FileFormat::Gcm,
+        version: Some(metadata.revision.to_string()),
+        metadata: vec![
+            ("game_id", metadata.game_id),
+            ("region", metadata.region.to_string()),
+            ("disc", (metadata.disc_number as u16 + 1).to_string()),
+            ("title", metadata.internal_name),
+        ],
+    })
+}
+
+fn sniff_yaz0(data: &[u8]) -> Option<Detection> {
+    let info = Yaz0Info::read(data)?;
+    let mut metadata = vec![("decompressed_size", read_u32(data, 0x4).to_string())];
+    if info.reserved != [0; 2] {
+        metadata.push((
+            "reserved",
+            format!("{:#010X},{:#010X}", info.reserved[0], info.reserved[1]),
+        ));
+    }
+    Some(Detection {
+        format: FileFormat::Yaz0,
+        // Yaz1 is the same format under another name
+        version: (info.magic == Yaz0Magic::Yaz1).then(|| info.magic.to_string()),
+        metadata,
+    })
+}
+
+fn sniff_yay0(data: &[u8]) -> Option<Detection> {
+    if data.len() < 0x10 || &data[..4] != b"Yay0" {
+        return None;
+    }
+    Some(Detection {
+        format: FileFormat::Yay0,
+        version: None,
+        metadata: vec![("decompressed_size", read_u32(data, 0x4).to_string())],
+    })
+}
+
+fn sniff_rarc(data: &[u8]) -> Option<Detection> {
+    if data.len() < 0x40 {
+        return None;
+    }
+    // Byte-swapped archives have their magic reversed too
+    let little_endian = match &data[..4] {
+        b"RARC" => false,
+        b"CRAR" => true,
+        _ => return None,
+    };
+    let read_u32 = |offset| match little_endian {
+        true => read_u32(data, offset).swap_bytes(),
+        false => read_u32(data, offset),
+    };
+    let read_u16 = |offset| match little_endian {
+        true => read_u16(data, offset).swap_bytes(),
+        false => read_u16(data, offset),
+    };
+    Some(Detection {
+        format: FileFormat::Rarc,
+        version: None,
+        metadata: vec![
+            ("size", read_u32(0x4).to_string()),
+            ("nodes", read_u32(0x20).to_string()),
+            ("files", read_u16(0x38).to_string()),
+            ("byte_order", if little_endian { "little" } else { "big" }.to_owned()),
+        ],
+    })
+}
+
+fn sniff_u8(data: &[u8]) -> Option<Detection> {
+    const MAGIC: u32 = 0x55AA382D;
+    if data.len() < 0x20 || read_u32(data, 0x0) != MAGIC {
+        return None;
+    }
+    // The root node's size field holds the total number of nodes
+    let root_offset = read_u32(data, 0x4);
+    let num_nodes = (root_offset as usize + 0xC <= data.len()).then(|| read_u32(data, root_offset + 0x8));
+    Some(Detection {
+        format: FileFormat::U8,
+        version: None,
+        metadata: num_nodes
+            .map(|num_nodes| ("nodes", num_nodes.to_string()))
+            .into_iter()
+            .collect(),
+    })
+}
+
+/// AFS headers are little-endian, even on GameCube
+fn sniff_afs(data: &[u8]) -> Option<Detection> {
+    if data.len() < 0x8 || data[..4] != afs::MAGIC {
+        return None;
+    }
+    Some(Detection {
+        format: FileFormat::Afs,
+        version: None,
+        metadata: vec![("files", read_u32(data, 0x4).swap_bytes().to_string())],
+    })
+}
+
+/// The IMET magic is at 0x40 in opening.bnr, or 0x80 when it's inside a channel WAD
+fn sniff_imet(data: &[u8]) -> Option<Detection> {
+    let magic_offset = [0x40, 0x80]
+        .into_iter()
+        .find(|&offset| data.get(offset..offset + 4) == Some(b"IMET"))?;
+    if data.len() < magic_offset + 0x20 {
+        return None;
+    }
+    let sizes = magic_offset as u32 + 0xC;
+    Some(Detection {
+        format: FileFormat::Imet,
+        version: None,
+        metadata: vec![
+            ("icon_size", read_u32(data, sizes).to_string()),
+            ("banner_size", read_u32(data, sizes + 0x4).to_string()),
+            ("sound_size", read_u32(data, sizes + 0x8).to_string()),
+        ],
+    })
+}
+
+fn sniff_imd5(data: &[u8]) -> Option<Detection> {
+    if data.len() < 0x20 || &data[..4] != b"IMD5" {
+        return None;
+    }
+    Some(Detection {
+        format: FileFormat::Imd5,
+        version: None,
+        metadata: vec![("size", read_u32(data, 0x4).to_string())],
+    })
+}
+
+fn sniff_bmg(data: &[u8]) -> Option<Detection> {
+    if data.len() < 0x20 || &data[..8] != b"MESGbmg1" {
+        return None;
+    }
+    let encoding = TextEncoding::from_byte(data[0x10])
+        .map(|encoding| format!("{encoding:?}"))
+        .unwrap_or_else(|| format!("unknown ({})", data[0x10]));
+    Some(Detection {
+        format: FileFormat::Bmg,
+        version: Some(String::from("bmg1")),
+        metadata: vec![
+            ("size", read_u32(data, 0x8).to_string()),
+            ("sections", read_u32(data, 0xC).to_string()),
+            ("encoding", encoding),
+        ],
+    })
+}
+
+fn sniff_msbt(data: &[u8]) -> Option<Detection> {
+    if data.len() < 0x20 || &data[..8] != b"MsgStdBn" {
+        return None;
+    }
+    let byte_order = match [data[0x8], data[0x9]] {
+        [0xFE, 0xFF] => ByteOrder::Big,
+        [0xFF, 0xFE] => ByteOrder::Little,
+        _ => return None,
+    };
+    let encoding = MsbtEncoding::from_byte(data[0xC])
+        .map(|encoding| format!("{encoding:?}"))
+        .unwrap_or_else(|| format!("unknown ({})", data[0xC]));
+    Some(Detection {
+        format: FileFormat::Msbt,
+        version: Some(data[0xD].to_string()),
+        metadata: vec![
+            ("byte_order", byte_order.to_string()),
+            ("encoding", encoding),
+            ("sections", byte_order.read_u16(data, 0xE).to_string()),
+        ],
+    })
+}
+
+/// GCIs have no magic number, but their size always matches the block count in the
+/// header exactly
+fn sniff_gci(data: &[u8]) -> Option<Detection> {
+    const HEADER_SIZE: usize = 0x40;
+    const BLOCK_SIZE: usize = 0x2000;
+    if data.len() <= HEADER_SIZE || !data[..6].iter().all(u8::is_ascii_alphanumeric) {
+        return None;
+    }
+    let block_count = read_u16(data, 0x38);
+    let size = HEADER_SIZE + block_count as usize * BLOCK_SIZE;
+    // Callers may only pass in the first SNIFF_LEN bytes of larger saves
+    let size_matches = data.len() == size || (data.len() == SNIFF_LEN && size > SNIFF_LEN);
+    if block_count == 0 || !size_matches {
+        return None;
+    }
+    let file_name = &data[0x8..0x28];
+    let file_name_len = file_name.iter().position(|&b| b == 0).unwrap_or(file_name.len());
+    Some(Detection {
+        format: FileFormat::Gci,
+        version: None,
+        metadata: vec![
+            ("game_code", String::from_utf8_lossy(&data[..4]).into_owned()),
+            (
+                "file_name",
+                String::from_utf8_lossy(&file_name[..file_name_len]).into_owned(),
+            ),
+            ("blocks", block_count.to_string()),
+        ],
+    })
+}
+
+fn sniff_jpc(data: &[u8]) -> Option<Detection> {
+    if data.len() < 0x10 || &data[..4] != b"JPAC" {
+        return None;
+    }
+    Some(Detection {
+        format: FileFormat::Jpc,
+        version: Some(String::from_utf8_lossy(&data[4..8]).into_owned()),
+        metadata: vec![
+            ("particles", read_u16(data, 0x8).to_string()),
+            ("textures", read_u16(data, 0xA).to_string()),
+        ],
+    })
+}
+
+fn sniff_blo(data: &[u8]) -> Option<Detection> {
+    if data.len() < 0x20 || &data[..4] != b"SCRN" || &data[4..7] != b"blo" {
+        return None;
+    }
+    Some(Detection {
+        format: FileFormat::Blo,
+        version: Some(String::from_utf8_lossy(&data[4..8]).into_owned()),
+        metadata: vec![("blocks", read_u32(data, 0xC).to_string())],
+    })
+}
+
+fn sniff_bmd(data: &[u8]) -> Option<Detection> {
+    if data.len() < 0x20 || &data[..4] != b"J3D2" || !matches!(&data[4..7], b"bmd" | b"bdl") {
+        return None;
+    }
+    Some(Detection {
+        format: FileFormat::Bmd,
+        version: Some(String::from_utf8_lossy(&data[4..8]).into_owned()),
+        metadata: vec![
+            ("size", read_u32(data, 0x8).to_string()),
+            ("sections", read_u32(data, 0xC).to_string()),
+        ],
+    })
+}
+
+fn sniff_baa(data: &[u8]) -> Option<Detection> {
+    if data.len() < 0x8 || &data[..4] != b"AA_<" {
+        return None;
+    }
+    Some(Detection {
+        format: FileFormat::Baa,
+        version: None,
+        metadata: Vec::new(),
+    })
+}
+
+/// BTIs have no magic number, so this checks that every header field is in range
+/// and the image data starts inside the file.
+fn sniff_bti(data: &[u8]) -> Option<Detection> {
+    if data.len() < 0x20 {
+        return None;
+    }
+    let format = match data[0x0] {
+        0x0 => "I4",
+        0x1 => "I8",
+        0x2 => "IA4",
+        0x3 => "IA8",
+        0x4 => "RGB565",
+        0x5 => "RGB5A3",
+        0x6 => "RGBA32",
+        0x8 => "C4",
+        0x9 => "C8",
+        0xA => "C14X2",
+        0xE => "CMPR",
+        _ => return None,
+    };
+    let width = read_u16(data, 0x2);
+    let height = read_u16(data, 0x4);
+    let wrap_s = data[0x6];
+    let wrap_t = data[0x7];
+    let palette_format = data[0x9];
+    let image_offset = read_u32(data, 0x1C);
+    let plausible = (1..=1024).contains(&width)
+        && (1..=1024).contains(&height)
+        && wrap_s <= 2
+        && wrap_t <= 2
+        && palette_format <= 2
+        && image_offset >= 0x20
+        && (image_offset as usize) < data.len();
+    if !plausible {
+        return None;
+    }
+    Some(Detection {
+        format: FileFormat::Bti,
+        version: None,
+        metadata: vec![
+            ("image_format", format.to_owned()),
+            ("width", width.to_string()),
+            ("height", height.to_string()),
+            ("mipmaps", data[0x18].to_string()),
+        ],
+    })
+}
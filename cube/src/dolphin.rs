@@ -0,0 +1,75 @@
+//! Names the Dolphin emulator gives textures when dumping them, which are also the names
+//! it looks for in texture packs. Dolphin hashes a texture's data as the game loads it, so
+//! a BTI's name can be worked out without running the game:
+//! `tex1_{width}x{height}[_m]_{texture hash}[_{palette hash}]_{format}`.
+
+use crate::bti::{slice_at, BtiError, BtiHeader, TextureFormat};
+use xxhash_rust::xxh64::xxh64;
+
+const PREFIX: &str = "tex1_";
+
+/// The name Dolphin dumps a BTI's texture under and loads its replacement from, without
+/// the `.png` extension. `palette` is for BTIs whose palette is stored somewhere else,
+/// like [`BtiImage::decode_with_palette`](crate::bti::BtiImage::decode_with_palette) takes.
+pub fn texture_name(bti: &[u8], palette: Option<&[u8]>) -> Result<String, BtiError> {
+    let header = BtiHeader::read(bti)?;
+    let format = TextureFormat::from_id(header.format).ok_or(BtiError::UnknownFormat(header.format))?;
+    // Only the largest mipmap is hashed
+    let size = BtiHeader {
+        mipmap_count: 1,
+        ..header.clone()
+    }
+    .image_data_size()?;
+    let offset = header.image_data_offset as u64;
+    let data = slice_at(bti, offset, size).ok_or(BtiError::ImageDataOutOfBounds {
+        offset,
+        size,
+        file_size: bti.len(),
+    })?;
+
+    // Dolphin only counts a texture as mipmapped if it's sampled with a mipmap filter
+    let mipmapped = (2..=5).contains(&header.min_filter);
+    let mut name = format!("{PREFIX}{}x{}", header.width, header.height);
+    if mipmapped {
+        name.push_str("_m");
+    }
+    name.push_str(&format!("_{:016x}", xxh64(data, 0)));
+    if format.uses_palette() {
+        let palette = match palette {
+            Some(palette) => palette,
+            None => {
+                let offset = header.palette_offset as u64;
+                slice_at(bti, offset, header.num_colors as u64 * 2).ok_or(BtiError::PaletteOutOfBounds {
+                    offset,
+                    num_colors: header.num_colors,
+                    file_size: bti.len(),
+                })?
+            }
+        };
+        name.push_str(&format!("_{:016x}", palette_hash(data, palette, format)));
+    }
+    name.push_str(&format!("_{}", header.format));
+    Ok(name)
+}
+
+/// Hashes the part of the palette from the lowest color index the texture uses to the
+/// highest, the same way Dolphin does. Colors past the end of the palette count as zero.
+fn palette_hash(data: &[u8], palette: &[u8], format: TextureFormat) -> u64 {
+    let indexes: Box<dyn Iterator<Item = usize>> = match format {
+        TextureFormat::C4 => Box::new(
+            data.iter()
+                .flat_map(|byte| [(byte & 0xF) as usize, (byte >> 4) as usize]),
+        ),
+        TextureFormat::C8 => Box::new(data.iter().map(|&index| index as usize)),
+        // Dolphin byte-swaps just the first byte of each index here, so only the top six
+        // bits of each index count
+        _ => Box::new(data.iter().step_by(2).map(|&high| (high as usize & 0x3F) << 8)),
+    };
+    let (min, max) = indexes.fold((usize::MAX, 0), |(min, max), index| (min.min(index), max.max(index)));
+    if min > max {
+        return xxh64(&[], 0);
+    }
+    let mut used = palette.get(min * 2..).unwrap_or_default().to_vec();
+    used.resize((max + 1 - min) * 2, 0);
+    xxh64(&used, 0)
+}
@@ -0,0 +1,261 @@
+//! One error type for anything that goes wrong in cube, for callers that need to tell
+//! errors apart or show where they happened without knowing every format's own error.
+//! [`CubeError`] wraps any of them with a stable [`ErrorCode`] and the chain of containers
+//! the file was found in, like `game.iso > files/stage.szs > timg/sky.bti`.
+
+use crate::{
+    afs::AfsError, baa::BaaError, banner::BannerError, bcsv::BcsvError, blo::BloError, bmd::BmdError, bmg::BmgError,
+    bti::BtiError, gci::GciError, gcz::GczError, iso::IsoError, jpc::JpcError, msbt::MsbtError, rarc::RarcError,
+    rel::RelError, szs::SzsError, tree::TreeError, u8_archive::U8Error, yay0::Yay0Error,
+};
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// What kind of problem an error is. Codes are never reused or renumbered, so scripts
+/// can rely on them across versions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    /// Anything not covered by another code
+    Other,
+    /// Reading or writing a file failed
+    Io,
+    /// A JSON file made by extracting couldn't be read back
+    Json,
+    Yaz0,
+    Yay0,
+    Rarc,
+    U8,
+    Afs,
+    Banner,
+    Iso,
+    Gcz,
+    Bmg,
+    Msbt,
+    Bti,
+    Png,
+    Bmd,
+    Blo,
+    Jpc,
+    Baa,
+    Bcsv,
+    Rel,
+    Gci,
+}
+
+impl ErrorCode {
+    /// The code as it's shown, like `E0110`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Other => "E0000",
+            ErrorCode::Io => "E0001",
+            ErrorCode::Json => "E0002",
+            ErrorCode::Yaz0 => "E0100",
+            ErrorCode::Yay0 => "E0101",
+            ErrorCode::Rarc => "E0110",
+            ErrorCode::U8 => "E0111",
+            ErrorCode::Afs => "E0112",
+            ErrorCode::Banner => "E0113",
+            ErrorCode::Iso => "E0120",
+            ErrorCode::Gcz => "E0121",
+            ErrorCode::Bmg => "E0200",
+            ErrorCode::Msbt => "E0201",
+            ErrorCode::Bti => "E0300",
+            ErrorCode::Png => "E0301",
+            ErrorCode::Bmd => "E0302",
+            ErrorCode::Blo => "E0303",
+            ErrorCode::Jpc => "E0304",
+            ErrorCode::Baa => "E0400",
+            ErrorCode::Bcsv => "E0500",
+            ErrorCode::Rel => "E0501",
+            ErrorCode::Gci => "E0502",
+        }
+    }
+
+    /// Works out the code for an error from its type. Failures to read or write a file
+    /// are [`ErrorCode::Io`] whichever format was being worked on, and errors from opening
+    /// nested archives get the code of the archive that couldn't be opened.
+    pub fn of(error: &(dyn Error + 'static)) -> ErrorCode {
+        if let Some(error) = error.downcast_ref::<CubeError>() {
+            return error.code;
+        }
+        let mut source = Some(error);
+        while let Some(error) = source {
+            // Decompression reads from memory, so its IO errors mean the data ran out
+            if error.is::<yaz0::Error>() {
+                break;
+            }
+            if error.is::<io::Error>()
+                || matches!(error.downcast_ref(), Some(RarcError::IOError(_)))
+                || error.downcast_ref::<IsoError>().is_some_and(IsoError::is_io)
+            {
+                return ErrorCode::Io;
+            }
+            source = error.source();
+        }
+        match error.downcast_ref::<TreeError>() {
+            Some(TreeError::Szs(SzsError::Rarc(_)) | TreeError::Rarc(_)) => return ErrorCode::Rarc,
+            Some(TreeError::Szs(SzsError::Yay0(_))) => return ErrorCode::Yay0,
+            Some(TreeError::Szs(_)) => return ErrorCode::Yaz0,
+            Some(TreeError::U8(_)) => return ErrorCode::U8,
+            Some(TreeError::Afs(_)) => return ErrorCode::Afs,
+            Some(TreeError::Iso(_)) => return ErrorCode::Iso,
+            Some(TreeError::Io(_)) => return ErrorCode::Io,
+            Some(TreeError::NotAnArchive) | None => {}
+        }
+        match error.downcast_ref::<SzsError>() {
+            Some(SzsError::Rarc(_)) => return ErrorCode::Rarc,
+            Some(SzsError::Yay0(_)) => return ErrorCode::Yay0,
+            Some(SzsError::Yaz0(_)) => return ErrorCode::Yaz0,
+            None => {}
+        }
+        let codes: &[(IsType, ErrorCode)] = &[
+            (|e| e.is::<yaz0::Error>(), ErrorCode::Yaz0),
+            (|e| e.is::<Yay0Error>(), ErrorCode::Yay0),
+            (|e| e.is::<RarcError>(), ErrorCode::Rarc),
+            (|e| e.is::<U8Error>(), ErrorCode::U8),
+            (|e| e.is::<AfsError>(), ErrorCode::Afs),
+            (|e| e.is::<BannerError>(), ErrorCode::Banner),
+            (|e| e.is::<IsoError>(), ErrorCode::Iso),
+            (|e| e.is::<GczError>(), ErrorCode::Gcz),
+            (|e| e.is::<BmgError>(), ErrorCode::Bmg),
+            (|e| e.is::<MsbtError>(), ErrorCode::Msbt),
+            (|e| e.is::<BtiError>(), ErrorCode::Bti),
+            #[cfg(feature = "png")]
+            (|e| e.is::<crate::png::PngError>(), ErrorCode::Png),
+            (|e| e.is::<BmdError>(), ErrorCode::Bmd),
+            (|e| e.is::<BloError>(), ErrorCode::Blo),
+            (|e| e.is::<JpcError>(), ErrorCode::Jpc),
+            (|e| e.is::<BaaError>(), ErrorCode::Baa),
+            (|e| e.is::<BcsvError>(), ErrorCode::Bcsv),
+            (|e| e.is::<RelError>(), ErrorCode::Rel),
+            (|e| e.is::<GciError>(), ErrorCode::Gci),
+        ];
+        codes
+            .iter()
+            .find(|(is, _)| is(error))
+            .map_or(ErrorCode::Other, |&(_, code)| code)
+    }
+}
+
+/// Whether an error is of a particular type
+type IsType = fn(&(dyn Error + 'static)) -> bool;
+
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Any error, along with its [`ErrorCode`] and where it happened
+pub struct CubeError {
+    code: ErrorCode,
+    path: Vec<PathBuf>,
+    source: Box<dyn Error>,
+}
+
+impl CubeError {
+    /// Wraps an error, working out its code with [`ErrorCode::of`]. A `CubeError` is
+    /// returned as it is, keeping what's known about where it happened.
+    pub fn new(source: impl Into<Box<dyn Error>>) -> Self {
+        let source = source.into();
+        match source.downcast::<CubeError>() {
+            Ok(error) => *error,
+            Err(source) => CubeError {
+                code: ErrorCode::of(source.as_ref()),
+                path: Vec::new(),
+                source,
+            },
+        }
+    }
+
+    /// Wraps an error that's known to be of a particular kind
+    pub fn with_code(code: ErrorCode, source: impl Into<Box<dyn Error>>) -> Self {
+        CubeError {
+            code,
+            ..CubeError::new(source)
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    /// The file the error happened in, after the containers it was found in. Each path is
+    /// relative to the container before it, and the first is as it was given.
+    pub fn path(&self) -> &[PathBuf] {
+        &self.path
+    }
+
+    /// Notes that whatever the error happened in was inside `container`. Callers add each
+    /// container around it in turn, from the innermost out.
+    pub fn in_container(mut self, container: impl AsRef<Path>) -> Self {
+        self.path.insert(0, container.as_ref().to_owned());
+        self
+    }
+}
+
+impl Display for CubeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] ", self.code)?;
+        for (i, path) in self.path.iter().enumerate() {
+            let separator = if i + 1 == self.path.len() { ": " } else { " > " };
+            write!(f, "{}{separator}", path.to_string_lossy())?;
+        }
+        write!(f, "{}", self.source)
+    }
+}
+
+/// The same as [`Display`], since this is what `main` prints when it returns an error
+impl Debug for CubeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Error for CubeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+macro_rules! impl_from {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for CubeError {
+                fn from(error: $error) -> Self {
+                    CubeError::new(error)
+                }
+            }
+        )*
+    };
+}
+
+impl_from!(
+    io::Error,
+    SzsError,
+    Yay0Error,
+    RarcError,
+    U8Error,
+    AfsError,
+    BannerError,
+    IsoError,
+    GczError,
+    TreeError,
+    BmgError,
+    MsbtError,
+    BtiError,
+    BmdError,
+    BloError,
+    JpcError,
+    BaaError,
+    BcsvError,
+    RelError,
+    GciError,
+);
+
+#[cfg(feature = "png")]
+impl_from!(crate::png::PngError);
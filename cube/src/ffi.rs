@@ -0,0 +1,241 @@
+//! C-compatible bindings, enabled with the `capi` feature. The matching header is
+//! include/cube.h, generated with `cbindgen --config cbindgen.toml --output include/cube.h src/ffi.rs`.
+//!
+//! Every function returns [`CUBE_OK`] on success or [`CUBE_ERROR`] on failure, in which
+//! case [`cube_last_error`] describes what went wrong. Buffers and file lists handed out
+//! by the library belong to the caller and must be released with [`cube_buffer_free`]
+//! and [`cube_file_list_free`] respectively.
+
+use crate::{bmg::Bmg, bti::BtiImage, rarc::Rarc, szs::extract_szs, virtual_fs::VirtualFile, Decode};
+use std::{
+    cell::RefCell,
+    error::Error,
+    ffi::{c_char, CStr, CString},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr, slice,
+};
+
+pub const CUBE_OK: i32 = 0;
+pub const CUBE_ERROR: i32 = 1;
+
+/// A byte buffer. When returned by the library, free it with `cube_buffer_free`.
+#[repr(C)]
+pub struct CubeBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+/// A file with a null-terminated UTF-8 path relative to the archive root
+#[repr(C)]
+pub struct CubeFile {
+    pub path: *mut c_char,
+    pub bytes: CubeBuffer,
+}
+
+/// A list of files. When returned by the library, free it with `cube_file_list_free`.
+#[repr(C)]
+pub struct CubeFileList {
+    pub files: *mut CubeFile,
+    pub len: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Returns a description of why the last call on this thread that returns a status
+/// failed, or null if it succeeded. The string is valid until the next call on the same thread.
+#[no_mangle]
+pub extern "C" fn cube_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Extracts an SZS archive, which may or may not be Yaz0 compressed.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn cube_extract_szs(data: *const u8, len: usize, out: *mut CubeFileList) -> i32 {
+    guard(|| {
+        let files = extract_szs(input(data, len)?.to_vec())?;
+        write_out(out, file_list(files)?)
+    })
+}
+
+/// Lists the files in an uncompressed RARC archive.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn cube_rarc_decode(data: *const u8, len: usize, out: *mut CubeFileList) -> i32 {
+    guard(|| {
+        let rarc = Rarc::parse(input(data, len)?)?;
+        write_out(out, file_list(rarc.decode()?)?)
+    })
+}
+
+/// Builds an uncompressed RARC archive from a list of files. Paths use `/` as the
+/// separator, and intermediate directories are created automatically.
+///
+/// # Safety
+/// `root_name` must be a null-terminated string, `files` must point to `num_files`
+/// valid files, and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn cube_rarc_encode(
+    root_name: *const c_char,
+    files: *const CubeFile,
+    num_files: usize,
+    out: *mut CubeBuffer,
+) -> i32 {
+    guard(|| {
+        let root_name = input_str(root_name)?;
+        let files = if num_files == 0 {
+            &[]
+        } else if files.is_null() {
+            return Err("files is null".into());
+        } else {
+            slice::from_raw_parts(files, num_files)
+        };
+        let files = files
+            .iter()
+            .map(|file| Ok((input_str(file.path)?, input(file.bytes.data, file.bytes.len)?)))
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        write_out(out, buffer(Rarc::build(root_name, files)?))
+    })
+}
+
+/// Converts a BMG file to the same JSON representation the CLI uses.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn cube_bmg_to_json(data: *const u8, len: usize, out: *mut CubeBuffer) -> i32 {
+    guard(|| {
+        let bmg = Bmg::read(input(data, len)?)?;
+        write_out(out, buffer(serde_json::to_vec_pretty(&bmg)?))
+    })
+}
+
+/// Converts JSON in the format produced by `cube_bmg_to_json` back to a BMG file.
+///
+/// # Safety
+/// `json` must point to `len` readable bytes of UTF-8 and `out` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn cube_bmg_from_json(json: *const u8, len: usize, out: *mut CubeBuffer) -> i32 {
+    guard(|| {
+        let bmg: Bmg = serde_json::from_slice(input(json, len)?)?;
+        write_out(out, buffer(bmg.write()))
+    })
+}
+
+/// Decodes a BTI texture to row-major RGBA8 pixels.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes, and `width`, `height`, and `out` must be
+/// valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn cube_bti_decode(
+    data: *const u8,
+    len: usize,
+    width: *mut u32,
+    height: *mut u32,
+    out: *mut CubeBuffer,
+) -> i32 {
+    guard(|| {
+        let bti = BtiImage::decode(input(data, len)?)?;
+        write_out(width, bti.width)?;
+        write_out(height, bti.height)?;
+        write_out(out, buffer(bti.pixels().flatten().copied().collect()))
+    })
+}
+
+/// Frees a buffer returned by the library. Freeing an empty buffer is a no-op.
+///
+/// # Safety
+/// `buffer` must have been returned by the library and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn cube_buffer_free(buffer: CubeBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer.data, buffer.len)));
+    }
+}
+
+/// Frees a file list returned by the library, including every file in it.
+///
+/// # Safety
+/// `list` must have been returned by the library and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn cube_file_list_free(list: CubeFileList) {
+    if list.files.is_null() {
+        return;
+    }
+    let files = Box::from_raw(ptr::slice_from_raw_parts_mut(list.files, list.len));
+    for file in files.into_vec() {
+        drop(CString::from_raw(file.path));
+        cube_buffer_free(file.bytes);
+    }
+}
+
+/// Runs `f`, recording any error or panic so it doesn't cross the FFI boundary, or
+/// clearing the last one if it succeeds
+fn guard(f: impl FnOnce() -> Result<(), Box<dyn Error>>) -> i32 {
+    let message = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => {
+            LAST_ERROR.with(|e| *e.borrow_mut() = None);
+            return CUBE_OK;
+        }
+        Ok(Err(e)) => e.to_string(),
+        Err(panic) => panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| String::from("Unknown internal error")),
+    };
+    LAST_ERROR.with(|e| *e.borrow_mut() = CString::new(message.replace('\0', "")).ok());
+    CUBE_ERROR
+}
+
+unsafe fn input<'a>(data: *const u8, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+    match (data.is_null(), len) {
+        (true, 0) => Ok(&[]),
+        (true, _) => Err("Input data is null".into()),
+        (false, _) => Ok(slice::from_raw_parts(data, len)),
+    }
+}
+
+unsafe fn input_str<'a>(s: *const c_char) -> Result<&'a str, Box<dyn Error>> {
+    if s.is_null() {
+        return Err("String argument is null".into());
+    }
+    Ok(CStr::from_ptr(s).to_str()?)
+}
+
+unsafe fn write_out<T>(out: *mut T, value: T) -> Result<(), Box<dyn Error>> {
+    if out.is_null() {
+        return Err("Output pointer is null".into());
+    }
+    out.write(value);
+    Ok(())
+}
+
+fn buffer(bytes: Vec<u8>) -> CubeBuffer {
+    let len = bytes.len();
+    let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+    CubeBuffer { data, len }
+}
+
+fn file_list(files: Vec<VirtualFile>) -> Result<CubeFileList, Box<dyn Error>> {
+    let files = files
+        .into_iter()
+        .map(|file| {
+            let path = file.path.to_string_lossy().replace('\\', "/");
+            Ok(CubeFile {
+                path: CString::new(path)?.into_raw(),
+                bytes: buffer(file.bytes),
+            })
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let len = files.len();
+    let files = Box::into_raw(files.into_boxed_slice()) as *mut CubeFile;
+    Ok(CubeFileList { files, len })
+}
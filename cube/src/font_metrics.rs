@@ -0,0 +1,75 @@
+//! Working out how wide BMG text comes out on screen, for catching lines that won't fit
+//! in a game's text box before they're seen in game. Fonts draw each character at its
+//! own width, so counting characters only goes so far with proportional fonts.
+
+use crate::bmg::{text_segments, TextSegment};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How wide each character in a game's font is drawn, read from JSON like
+/// `{"default_width": 12, "widths": {"i": 4, "W": 18}}`. Widths are in whatever units
+/// the text box is measured in, usually pixels.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FontMetrics {
+    /// Width of every character that isn't in `widths`
+    pub default_width: u32,
+    #[serde(default)]
+    pub widths: HashMap<char, u32>,
+}
+
+impl FontMetrics {
+    pub fn char_width(&self, c: char) -> u32 {
+        self.widths.get(&c).copied().unwrap_or(self.default_width)
+    }
+
+    /// Width of a single line of text. Escape sequences aren't drawn, so they don't count.
+    pub fn line_width(&self, line: &str) -> u32 {
+        text_segments(line)
+            .into_iter()
+            .map(|segment| match segment {
+                TextSegment::Text(text) => text.chars().map(|c| self.char_width(c)).sum(),
+                TextSegment::Escape(_) => 0,
+            })
+            .sum()
+    }
+
+    /// Measures every line of a message, flagging the ones wider than `max_width`
+    pub fn message_stats(&self, message: &str, max_width: Option<u32>) -> MessageStats {
+        let mut characters = 0;
+        let mut text = String::new();
+        for segment in text_segments(message) {
+            if let TextSegment::Text(segment) = segment {
+                characters += segment.chars().filter(|&c| c != '\n').count();
+                text.push_str(segment);
+            }
+        }
+        let line_widths: Vec<u32> = text.split('\n').map(|line| self.line_width(line)).collect();
+        let overflowing_lines = line_widths
+            .iter()
+            .enumerate()
+            .filter(|(_, &width)| max_width.is_some_and(|max_width| width > max_width))
+            .map(|(i, _)| i + 1)
+            .collect();
+        MessageStats {
+            characters,
+            longest_line: line_widths.iter().copied().max().unwrap_or(0),
+            line_widths,
+            overflowing_lines,
+        }
+    }
+}
+
+/// What [`FontMetrics::message_stats`] found out about a message
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageStats {
+    /// Characters drawn, not counting escape sequences or line breaks
+    pub characters: usize,
+    /// Width of the widest line
+    pub longest_line: u32,
+    /// Width of each line, in order
+    pub line_widths: Vec<u32>,
+    /// Line numbers, counting from 1, of the lines that are too wide
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub overflowing_lines: Vec<usize>,
+}
@@ -0,0 +1,472 @@
+//! GCI, the format memory card save files are exported in by emulators and homebrew
+//! save managers. A 0x40 byte directory entry describing the save is followed by the
+//! save's blocks, which include its banner, icon animation, and comments.
+
+use crate::{
+    bti::{color_to_rgb5a3, decode_image, decode_palette, to_blocks, Color},
+    util::{read_u16, read_u32},
+};
+use encoding_rs::{Encoding, SHIFT_JIS, WINDOWS_1252};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+const HEADER_SIZE: usize = 0x40;
+const BLOCK_SIZE: usize = 0x2000;
+const COMMENT_SIZE: usize = 32;
+const MAX_ICONS: usize = 8;
+const PALETTE_SIZE: usize = 256 * 2;
+
+const GX_RGB5A3: u8 = 0x5;
+const GX_C8: u8 = 0x9;
+/// Palette entries are always RGB5A3
+const PALETTE_FORMAT: u8 = 2;
+
+pub const BANNER_WIDTH: usize = 96;
+pub const BANNER_HEIGHT: usize = 32;
+pub const ICON_SIZE: usize = 32;
+
+pub struct Gci {
+    pub header: GciHeader,
+    /// The save's blocks, including the banner, icons, and comments
+    pub data: Vec<u8>,
+}
+
+/// The directory entry at the start of a GCI
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GciHeader {
+    pub game_code: String,
+    pub maker_code: String,
+    /// Banner format in the low 2 bits (0: none, 1: CI8, 2: RGB5A3), and whether the
+    /// icon animation ping-pongs in bit 2
+    pub banner_flags: u8,
+    pub file_name: String,
+    /// Seconds since January 1st 2000
+    pub modified_time: u32,
+    /// Offset of the banner and icons from the start of the save data
+    pub image_offset: u32,
+    /// 2 bits per icon frame (0: none, 1: CI8 with a shared palette, 2: RGB5A3,
+    /// 3: CI8 with its own palette)
+    pub icon_formats: u16,
+    /// 2 bits per icon frame. The first frame with a speed of 0 ends the animation.
+    pub animation_speeds: u16,
+    pub permissions: u8,
+    pub copy_count: u8,
+    pub first_block: u16,
+    pub block_count: u16,
+    /// Offset of the two comment strings from the start of the save data
+    pub comment_offset: u32,
+}
+
+/// The two lines of text shown next to a save in the memory card manager
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GciComments {
+    pub title: String,
+    pub description: String,
+}
+
+/// A save's header and comments, which is everything about it that can be edited as text
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GciMetadata {
+    #[serde(flatten)]
+    pub header: GciHeader,
+    pub comments: GciComments,
+}
+
+/// A decoded banner or icon frame
+#[derive(Debug, Clone)]
+pub struct GciImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+/// Where one image lives in the save data and how it's stored
+#[derive(Debug, Clone, Copy)]
+struct ImageSlot {
+    format: SlotFormat,
+    width: usize,
+    height: usize,
+    offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SlotFormat {
+    Rgb5a3,
+    /// CI8 with a 256 color palette at this offset, shared by every icon frame using it
+    /// or owned by a single image
+    Ci8 {
+        palette_offset: usize,
+        shared: bool,
+    },
+}
+
+impl Gci {
+    pub fn read(data: &[u8]) -> Result<Self, GciError> {
+        if data.len() < HEADER_SIZE {
+            return Err(GciError::Truncated);
+        }
+        let encoding = text_encoding(&data[..4]);
+        let header = GciHeader {
+            game_code: String::from_utf8_lossy(&data[..4]).into_owned(),
+            maker_code: String::from_utf8_lossy(&data[4..6]).into_owned(),
+            banner_flags: data[0x7],
+            file_name: decode_str(encoding, &data[0x8..0x28]),
+            modified_time: read_u32(data, 0x28),
+            image_offset: read_u32(data, 0x2C),
+            icon_formats: read_u16(data, 0x30),
+            animation_speeds: read_u16(data, 0x32),
+            permissions: data[0x34],
+            copy_count: data[0x35],
+            first_block: read_u16(data, 0x36),
+            block_count: read_u16(data, 0x38),
+            comment_offset: read_u32(data, 0x3C),
+        };
+
+        let data_size = header.block_count as usize * BLOCK_SIZE;
+        if data.len() < HEADER_SIZE + data_size {
+            return Err(GciError::Truncated);
+        }
+        Ok(Gci {
+            header,
+            data: data[HEADER_SIZE..HEADER_SIZE + data_size].to_vec(),
+        })
+    }
+
+    pub fn write(&self) -> Vec<u8> {
+        let header = &self.header;
+        let encoding = text_encoding(header.game_code.as_bytes());
+        let mut out = vec![0u8; HEADER_SIZE];
+        write_str(&mut out[..4], header.game_code.as_bytes());
+        write_str(&mut out[4..6], header.maker_code.as_bytes());
+        out[0x6] = 0xFF;
+        out[0x7] = header.banner_flags;
+        write_str(&mut out[0x8..0x28], &encoding.encode(&header.file_name).0);
+        out[0x28..0x2C].copy_from_slice(&header.modified_time.to_be_bytes());
+        out[0x2C..0x30].copy_from_slice(&header.image_offset.to_be_bytes());
+        out[0x30..0x32].copy_from_slice(&header.icon_formats.to_be_bytes());
+        out[0x32..0x34].copy_from_slice(&header.animation_speeds.to_be_bytes());
+        out[0x34] = header.permissions;
+        out[0x35] = header.copy_count;
+        out[0x36..0x38].copy_from_slice(&header.first_block.to_be_bytes());
+        out[0x38..0x3A].copy_from_slice(&header.block_count.to_be_bytes());
+        out[0x3A..0x3C].copy_from_slice(&[0xFF, 0xFF]);
+        out[0x3C..0x40].copy_from_slice(&header.comment_offset.to_be_bytes());
+        out.extend(&self.data);
+        out
+    }
+
+    pub fn metadata(&self) -> Result<GciMetadata, GciError> {
+        Ok(GciMetadata {
+            header: self.header.clone(),
+            comments: self.comments()?,
+        })
+    }
+
+    pub fn comments(&self) -> Result<GciComments, GciError> {
+        let offset = self.header.comment_offset as usize;
+        let comments = self
+            .data
+            .get(offset..offset + COMMENT_SIZE * 2)
+            .ok_or(GciError::CommentsOutOfBounds(self.header.comment_offset))?;
+        let encoding = text_encoding(self.header.game_code.as_bytes());
+        Ok(GciComments {
+            title: decode_str(encoding, &comments[..COMMENT_SIZE]),
+            description: decode_str(encoding, &comments[COMMENT_SIZE..]),
+        })
+    }
+
+    pub fn set_comments(&mut self, comments: &GciComments) -> Result<(), GciError> {
+        // Leave unchanged comments alone, including anything after their terminators
+        if self.comments()? == *comments {
+            return Ok(());
+        }
+        let offset = self.header.comment_offset as usize;
+        let encoding = text_encoding(self.header.game_code.as_bytes());
+        let dest = self
+            .data
+            .get_mut(offset..offset + COMMENT_SIZE * 2)
+            .ok_or(GciError::CommentsOutOfBounds(self.header.comment_offset))?;
+        for (line, dest) in [&comments.title, &comments.description]
+            .into_iter()
+            .zip(dest.chunks_exact_mut(COMMENT_SIZE))
+        {
+            let (encoded, _, had_errors) = encoding.encode(line);
+            if had_errors || encoded.len() > COMMENT_SIZE {
+                return Err(GciError::InvalidComment(line.clone()));
+            }
+            dest.fill(0);
+            dest[..encoded.len()].copy_from_slice(&encoded);
+        }
+        Ok(())
+    }
+
+    pub fn banner(&self) -> Result<Option<GciImage>, GciError> {
+        self.banner_slot().map(|slot| self.decode_slot(slot)).transpose()
+    }
+
+    /// One entry per frame of the icon animation. Frames without an image of their
+    /// own are None.
+    pub fn icons(&self) -> Result<Vec<Option<GciImage>>, GciError> {
+        self.icon_slots()
+            .into_iter()
+            .map(|slot| slot.map(|slot| self.decode_slot(slot)).transpose())
+            .collect()
+    }
+
+    /// Replaces the banner, keeping its current format. CI8 banners can have up to 256
+    /// distinct colors after conversion to RGB5A3.
+    pub fn set_banner(&mut self, pixels: &[Color]) -> Result<(), GciError> {
+        let slot = self.banner_slot().ok_or(GciError::MissingImage)?;
+        self.encode_slot(slot, pixels)
+    }
+
+    /// Replaces an icon frame, keeping its current format. Frames sharing a palette
+    /// can only use colors already in it.
+    pub fn set_icon(&mut self, frame: usize, pixels: &[Color]) -> Result<(), GciError> {
+        let slot = self
+            .icon_slots()
+            .get(frame)
+            .copied()
+            .flatten()
+            .ok_or(GciError::MissingImage)?;
+        self.encode_slot(slot, pixels)
+    }
+
+    fn banner_slot(&self) -> Option<ImageSlot> {
+        if self.header.image_offset == u32::MAX {
+            return None;
+        }
+        let offset = self.header.image_offset as usize;
+        let format = match self.header.banner_flags & 3 {
+            1 => SlotFormat::Ci8 {
+                palette_offset: offset + BANNER_WIDTH * BANNER_HEIGHT,
+                shared: false,
+            },
+            2 => SlotFormat::Rgb5a3,
+            _ => return None,
+        };
+        Some(ImageSlot {
+            format,
+            width: BANNER_WIDTH,
+            height: BANNER_HEIGHT,
+            offset,
+        })
+    }
+
+    fn icon_slots(&self) -> Vec<Option<ImageSlot>> {
+        if self.header.image_offset == u32::MAX {
+            return Vec::new();
+        }
+        // Icons follow the banner, then the shared palette follows the icons
+        let mut offset = self.header.image_offset as usize;
+        offset += self
+            .banner_slot()
+            .map(|slot| slot.end() - slot.offset)
+            .unwrap_or_default();
+
+        let mut slots = Vec::new();
+        for frame in 0..MAX_ICONS {
+            if (self.header.animation_speeds >> (frame * 2)) & 3 == 0 {
+                break;
+            }
+            let format = match (self.header.icon_formats >> (frame * 2)) & 3 {
+                1 => SlotFormat::Ci8 {
+                    palette_offset: 0, // Filled in below once the end of the icons is known
+                    shared: true,
+                },
+                2 => SlotFormat::Rgb5a3,
+                3 => SlotFormat::Ci8 {
+                    palette_offset: offset + ICON_SIZE * ICON_SIZE,
+                    shared: false,
+                },
+                _ => {
+                    slots.push(None);
+                    continue;
+                }
+            };
+            let slot = ImageSlot {
+                format,
+                width: ICON_SIZE,
+                height: ICON_SIZE,
+                offset,
+            };
+            offset = slot.end();
+            slots.push(Some(slot));
+        }
+
+        for slot in slots.iter_mut().flatten() {
+            if let SlotFormat::Ci8 { shared: true, .. } = slot.format {
+                slot.format = SlotFormat::Ci8 {
+                    palette_offset: offset,
+                    shared: true,
+                };
+            }
+        }
+        slots
+    }
+
+    fn decode_slot(&self, slot: ImageSlot) -> Result<GciImage, GciError> {
+        let image_data = self.slot_data(slot)?;
+        let pixels = match slot.format {
+            SlotFormat::Rgb5a3 => decode_image(image_data, GX_RGB5A3, slot.width, slot.height, &[]),
+            SlotFormat::Ci8 { palette_offset, .. } => {
+                let palette_data = self.palette_data(palette_offset)?;
+                let palette = decode_palette(palette_data, PALETTE_FORMAT).expect("Valid palette format");
+                decode_image(image_data, GX_C8, slot.width, slot.height, &palette)
+            }
+        };
+        Ok(GciImage {
+            width: slot.width,
+            height: slot.height,
+            pixels,
+        })
+    }
+
+    fn encode_slot(&mut self, slot: ImageSlot, pixels: &[Color]) -> Result<(), GciError> {
+        if pixels.len() != slot.width * slot.height {
+            return Err(GciError::WrongImageSize {
+                expected: (slot.width, slot.height),
+                pixels: pixels.len(),
+            });
+        }
+        // Converting to RGB5A3 and back can change colors slightly, so leave images
+        // that haven't been edited exactly as they were
+        if self.decode_slot(slot)?.pixels == pixels {
+            return Ok(());
+        }
+
+        let colors: Vec<u16> = pixels.iter().map(|&c| color_to_rgb5a3(c)).collect();
+        match slot.format {
+            SlotFormat::Rgb5a3 => {
+                let encoded: Vec<u8> = to_blocks(&colors, slot.width, slot.height, 4, 4)
+                    .into_iter()
+                    .flat_map(u16::to_be_bytes)
+                    .collect();
+                self.slot_data_mut(slot)?.copy_from_slice(&encoded);
+            }
+            SlotFormat::Ci8 { palette_offset, shared } => {
+                let mut palette: Vec<u16> = if shared {
+                    self.palette_data(palette_offset)?
+                        .chunks_exact(2)
+                        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                let mut palette_indexes: HashMap<u16, u8> = HashMap::new();
+                for (index, &color) in palette.iter().enumerate() {
+                    palette_indexes.entry(color).or_insert(index as u8);
+                }
+
+                let mut indexes = Vec::with_capacity(colors.len());
+                for color in colors {
+                    let index = match palette_indexes.get(&color) {
+                        Some(&index) => index,
+                        None if shared => return Err(GciError::ColorNotInPalette(color)),
+                        None if palette.len() == 256 => return Err(GciError::TooManyColors),
+                        None => {
+                            palette.push(color);
+                            let index = (palette.len() - 1) as u8;
+                            palette_indexes.insert(color, index);
+                            index
+                        }
+                    };
+                    indexes.push(index);
+                }
+
+                let encoded = to_blocks(&indexes, slot.width, slot.height, 8, 4);
+                self.slot_data_mut(slot)?.copy_from_slice(&encoded);
+                if !shared {
+                    palette.resize(256, 0);
+                    let palette: Vec<u8> = palette.into_iter().flat_map(u16::to_be_bytes).collect();
+                    self.data[palette_offset..palette_offset + PALETTE_SIZE].copy_from_slice(&palette);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn slot_data(&self, slot: ImageSlot) -> Result<&[u8], GciError> {
+        self.data
+            .get(slot.offset..slot.offset + slot.pixel_data_size())
+            .ok_or(GciError::ImageOutOfBounds(slot.offset))
+    }
+
+    fn slot_data_mut(&mut self, slot: ImageSlot) -> Result<&mut [u8], GciError> {
+        let pixel_data_end = slot.offset + slot.pixel_data_size();
+        self.data
+            .get_mut(slot.offset..pixel_data_end)
+            .ok_or(GciError::ImageOutOfBounds(slot.offset))
+    }
+
+    fn palette_data(&self, offset: usize) -> Result<&[u8], GciError> {
+        self.data
+            .get(offset..offset + PALETTE_SIZE)
+            .ok_or(GciError::ImageOutOfBounds(offset))
+    }
+}
+
+impl ImageSlot {
+    fn pixel_data_size(&self) -> usize {
+        match self.format {
+            SlotFormat::Rgb5a3 => self.width * self.height * 2,
+            SlotFormat::Ci8 { .. } => self.width * self.height,
+        }
+    }
+
+    /// End of this image's pixel data and, if it has its own, its palette
+    fn end(&self) -> usize {
+        match self.format {
+            SlotFormat::Ci8 { shared: false, .. } => self.offset + self.pixel_data_size() + PALETTE_SIZE,
+            _ => self.offset + self.pixel_data_size(),
+        }
+    }
+}
+
+/// Japanese saves use Shift-JIS for their text, everything else uses Windows-1252
+fn text_encoding(game_code: &[u8]) -> &'static Encoding {
+    if game_code.get(3) == Some(&b'J') {
+        SHIFT_JIS
+    } else {
+        WINDOWS_1252
+    }
+}
+
+fn decode_str(encoding: &'static Encoding, bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    encoding.decode_without_bom_handling(&bytes[..len]).0.into_owned()
+}
+
+/// Copies as much of `src` as fits, leaving the rest of `dest` zeroed
+fn write_str(dest: &mut [u8], src: &[u8]) {
+    let len = src.len().min(dest.len());
+    dest[..len].copy_from_slice(&src[..len]);
+}
+
+#[derive(Debug, Error)]
+pub enum GciError {
+    #[error("GCI file is truncated")]
+    Truncated,
+
+    #[error("Image data at {0:#X} is past the end of the save data")]
+    ImageOutOfBounds(usize),
+
+    #[error("Comments at {0:#X} are past the end of the save data")]
+    CommentsOutOfBounds(u32),
+
+    #[error("Comment {0:?} is too long or can't be encoded")]
+    InvalidComment(String),
+
+    #[error("The save doesn't have that image")]
+    MissingImage,
+
+    #[error("Image should be {}x{} but has {pixels} pixels", expected.0, expected.1)]
+    WrongImageSize { expected: (usize, usize), pixels: usize },
+
+    #[error("Image has more than 256 colors, which is the most a CI8 image can use")]
+    TooManyColors,
+
+    #[error("Color {0:#06X} isn't in the icon animation's shared palette")]
+    ColorNotInPalette(u16),
+}
@@ -0,0 +1,188 @@
+//! GCZ, Dolphin's compressed disc image format. The disc is split into equal sized blocks
+//! that are each zlib compressed, or stored as they are if that doesn't make them smaller,
+//! with a table of where every block starts so any part of the disc can be read without
+//! decompressing what comes before it.
+
+use std::io::{self, Read, Seek, SeekFrom};
+use thiserror::Error;
+
+/// Little endian, like the rest of the header
+pub const GCZ_MAGIC: u32 = 0xB10BC001;
+const HEADER_LEN: u64 = 0x20;
+/// Set in a block's offset when it's stored uncompressed
+const UNCOMPRESSED_FLAG: u64 = 1 << 63;
+/// Anything bigger is a corrupted header rather than a real block size
+const MAX_BLOCK_SIZE: u32 = 64 << 20;
+
+/// Reads a GCZ image as if it were the uncompressed disc. Blocks are decompressed as
+/// they're read, and the most recent one is kept for the reads after it.
+pub struct GczReader<R> {
+    inner: R,
+    block_size: u32,
+    data_size: u64,
+    /// Where each block's data starts, relative to the end of the tables
+    block_offsets: Vec<u64>,
+    compressed_data_size: u64,
+    data_start: u64,
+    position: u64,
+    /// The block in `block`, if one has been read yet
+    cached: Option<usize>,
+    block: Vec<u8>,
+}
+
+/// Whether `data` starts with a GCZ header
+pub fn is_gcz(data: &[u8]) -> bool {
+    data.get(..4)
+        .is_some_and(|magic| u32::from_le_bytes(magic.try_into().unwrap()) == GCZ_MAGIC)
+}
+
+impl<R: Read + Seek> GczReader<R> {
+    /// Reads the header and block table from the start of `inner`
+    pub fn new(mut inner: R) -> Result<Self, GczError> {
+        let mut header = [0u8; HEADER_LEN as usize];
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_exact(&mut header).map_err(|_| GczError::Truncated)?;
+        if !is_gcz(&header) {
+            return Err(GczError::Magic);
+        }
+        let u32_at = |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+        let u64_at = |offset: usize| u64::from_le_bytes(header[offset..offset + 8].try_into().unwrap());
+        let compressed_data_size = u64_at(0x8);
+        let data_size = u64_at(0x10);
+        let block_size = u32_at(0x18);
+        let num_blocks = u32_at(0x1C);
+        let valid_block_size = block_size > 0 && block_size <= MAX_BLOCK_SIZE;
+        if !valid_block_size || num_blocks as u64 != data_size.div_ceil(block_size as u64) {
+            return Err(GczError::InvalidHeader);
+        }
+        // The block table is sized from the header, so it has to fit in the file before it's read
+        let data_start = HEADER_LEN + num_blocks as u64 * 12;
+        let stream_len = inner.seek(SeekFrom::End(0))?;
+        if data_start > stream_len {
+            return Err(GczError::Truncated);
+        }
+
+        // Offsets are followed by a checksum of each block, which zlib already covers
+        inner.seek(SeekFrom::Start(HEADER_LEN))?;
+        let mut table = vec![0u8; num_blocks as usize * 8];
+        inner.read_exact(&mut table).map_err(|_| GczError::Truncated)?;
+        let block_offsets = table
+            .chunks_exact(8)
+            .map(|offset| u64::from_le_bytes(offset.try_into().unwrap()))
+            .collect();
+        Ok(GczReader {
+            inner,
+            block_size,
+            data_size,
+            block_offsets,
+            compressed_data_size,
+            data_start,
+            position: 0,
+            cached: None,
+            block: Vec::new(),
+        })
+    }
+
+    /// Size of the uncompressed disc
+    pub fn len(&self) -> u64 {
+        self.data_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data_size == 0
+    }
+
+    fn load_block(&mut self, idx: usize) -> Result<(), GczError> {
+        if self.cached == Some(idx) {
+            return Ok(());
+        }
+        let offset = self.block_offsets[idx];
+        let start = offset & !UNCOMPRESSED_FLAG;
+        let end = match self.block_offsets.get(idx + 1) {
+            Some(next) => next & !UNCOMPRESSED_FLAG,
+            None => self.compressed_data_size,
+        };
+        let len = end.checked_sub(start).filter(|&len| len <= MAX_BLOCK_SIZE as u64 * 2);
+        let len = len.ok_or(GczError::InvalidBlock(idx))?;
+        let mut stored = vec![0u8; len as usize];
+        self.inner.seek(SeekFrom::Start(self.data_start + start))?;
+        self.inner.read_exact(&mut stored).map_err(|_| GczError::Truncated)?;
+
+        self.cached = None;
+        self.block = match offset & UNCOMPRESSED_FLAG {
+            0 => miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(&stored, self.block_size as usize)
+                .map_err(|e| GczError::Decompress(idx, e.to_string()))?,
+            _ => stored,
+        };
+        self.cached = Some(idx);
+        Ok(())
+    }
+}
+
+/// Reads fill as much of the buffer as they can, even across blocks, since some readers
+/// take a short read to mean the end of the data
+impl<R: Read + Seek> Read for GczReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() && self.position < self.data_size {
+            let idx = (self.position / self.block_size as u64) as usize;
+            self.load_block(idx)?;
+            let in_block = (self.position % self.block_size as u64) as usize;
+            let available = self
+                .block
+                .len()
+                .saturating_sub(in_block)
+                .min((self.data_size - self.position) as usize);
+            if available == 0 {
+                return Err(GczError::InvalidBlock(idx).into());
+            }
+            let len = available.min(buf.len() - filled);
+            buf[filled..filled + len].copy_from_slice(&self.block[in_block..in_block + len]);
+            filled += len;
+            self.position += len as u64;
+        }
+        Ok(filled)
+    }
+}
+
+impl<R: Read + Seek> Seek for GczReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.data_size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Seek before the start"))?;
+        Ok(self.position)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum GczError {
+    #[error("Not a GCZ image (bad magic)")]
+    Magic,
+
+    #[error("GCZ image is truncated")]
+    Truncated,
+
+    #[error("GCZ header has an invalid block size or count")]
+    InvalidHeader,
+
+    #[error("GCZ block {0} is corrupted")]
+    InvalidBlock(usize),
+
+    #[error("Couldn't decompress GCZ block {0}: {1}")]
+    Decompress(usize, String),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl From<GczError> for io::Error {
+    fn from(value: GczError) -> Self {
+        match value {
+            GczError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
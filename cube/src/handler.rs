@@ -0,0 +1,148 @@
+//! Extracting and packing looked up by format, so a program built on cube can handle
+//! every format the same way instead of knowing about each one. Formats are added by
+//! implementing [`FormatHandler`] and calling [`Registry::register`], which works the
+//! same for formats cube doesn't know about as for the ones in [`FORMATS`](crate::detect::FORMATS).
+
+use crate::{
+    detect::{identify, FileFormat},
+    virtual_fs::VirtualFile,
+};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+/// The program doing the extracting and packing. Its own options are handed to
+/// handlers as they are, so they can be whatever it needs.
+pub trait Tool: 'static {
+    /// What extraction knows about where a file is and how it's being extracted
+    type ExtractContext<'a>;
+    /// Options for packing
+    type PackOptions;
+    /// A file produced by extraction, along with anything else the program tracks about it
+    type Extracted: From<VirtualFile>;
+}
+
+/// Everything needed to extract and pack one file format
+pub trait FormatHandler<T: Tool>: Send + Sync {
+    /// Short lowercase name, like `rarc`
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// The formats in [`FORMATS`](crate::detect::FORMATS) this handler is for, which decide its extensions and
+    /// what it detects unless it says otherwise
+    fn formats(&self) -> &'static [FileFormat] {
+        &[]
+    }
+
+    /// Lowercase file extensions this format commonly uses, without the dot
+    fn extensions(&self) -> Vec<&'static str> {
+        self.formats()
+            .iter()
+            .flat_map(|format| format.info().extensions.iter().copied())
+            .collect()
+    }
+
+    /// Whether a file is in this format, judging by its path and contents
+    fn detect(&self, vfile: &VirtualFile) -> bool {
+        identify(&vfile.path, &vfile.bytes).is_some_and(|format| self.formats().contains(&format))
+    }
+
+    /// Discs and archives, whose contents are extracted in turn. Output from these is
+    /// credited to the formats of the files inside them.
+    fn is_container(&self) -> bool {
+        false
+    }
+
+    /// Whether a file in this format is extracted with the current options. Files that
+    /// aren't are kept as they are.
+    fn extracts(&self, _vfile: &VirtualFile, _cx: &T::ExtractContext<'_>) -> bool {
+        false
+    }
+
+    fn extract(&self, vfile: VirtualFile, _cx: &T::ExtractContext<'_>) -> Result<Vec<T::Extracted>, Box<dyn Error>> {
+        Ok(vec![vfile.into()])
+    }
+
+    /// Formats this handler can pack into, named after their usual file extension
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Which of this handler's formats an extracted file or folder should be packed back
+    /// into, if it looks like one of them
+    fn guess_pack_format(&self, _path: &Path) -> Option<&'static str> {
+        None
+    }
+
+    /// Packs a file or folder into `format`, one of [`FormatHandler::pack_formats`].
+    /// Returns None if it can't be packed into that format.
+    fn pack(
+        &self,
+        _path: &Path,
+        _format: &str,
+        _options: &T::PackOptions,
+    ) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        Ok(None)
+    }
+
+    /// Where a file or folder packed into `format` goes by default, if it can be written
+    /// out with [`FormatHandler::pack_to`] as it's packed rather than built in memory by
+    /// [`FormatHandler::pack`]
+    fn streamed_output(&self, _path: &Path, _format: &str, _options: &T::PackOptions) -> Option<PathBuf> {
+        None
+    }
+
+    /// Packs a file or folder into `format`, writing it to the file at `out` as it goes.
+    /// Only called when there's a [`FormatHandler::streamed_output`].
+    fn pack_to(
+        &self,
+        path: &Path,
+        _format: &str,
+        _options: &T::PackOptions,
+        _out: &Path,
+    ) -> Result<(), Box<dyn Error>> {
+        Err(format!("{path:?} can't be written out as it's packed").into())
+    }
+}
+
+/// Every format that can be extracted or packed, in the order they're tried
+pub struct Registry<T: Tool> {
+    handlers: Vec<Box<dyn FormatHandler<T>>>,
+}
+
+impl<T: Tool> Default for Registry<T> {
+    fn default() -> Self {
+        Registry { handlers: Vec::new() }
+    }
+}
+
+impl<T: Tool> Registry<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a handler, which is tried after the ones already registered
+    pub fn register(&mut self, handler: impl FormatHandler<T> + 'static) {
+        self.handlers.push(Box::new(handler));
+    }
+
+    pub fn handlers(&self) -> impl Iterator<Item = &dyn FormatHandler<T>> {
+        self.handlers.iter().map(AsRef::as_ref)
+    }
+
+    /// The handler for a file's format, if any
+    pub fn find(&self, vfile: &VirtualFile) -> Option<&dyn FormatHandler<T>> {
+        self.handlers().find(|handler| handler.detect(vfile))
+    }
+
+    /// The handler that packs into `format`, if any
+    pub fn packer(&self, format: &str) -> Option<&dyn FormatHandler<T>> {
+        self.handlers().find(|handler| handler.pack_formats().contains(&format))
+    }
+
+    pub fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        self.handlers().find_map(|handler| handler.guess_pack_format(path))
+    }
+}
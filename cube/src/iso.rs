@@ -1,24 +1,809 @@
-use crate::virtual_fs::VirtualFile;
-use gc_gcm::{DirEntry, GcmError, GcmFile};
+use crate::{
+    gcz::{is_gcz, GczReader},
+    util::read_u32,
+    virtual_fs::VirtualFile,
+};
+use encoding_rs::SHIFT_JIS;
+use gc_gcm::{DirEntry, FsNode, GcmError, GcmFile};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::{HashMap, HashSet},
     error::Error,
     fmt::Display,
-    fs::File,
-    io::{BufReader, Read, Seek, SeekFrom},
+    fs::{metadata, read, read_dir, File},
+    io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
 };
 
+/// Where boot.bin says main.dol and fst.bin are, how big fst.bin is, and how big it can
+/// be on any of the game's discs
+const DOL_OFFSET: u32 = 0x420;
+const FST_OFFSET: u32 = 0x424;
+const FST_SIZE: u32 = 0x428;
+const FST_MAX_SIZE: u32 = 0x42C;
+const BI2_LEN: usize = 0x2000;
+/// The apploader always comes right after boot.bin and bi2.bin
+const APPLOADER_OFFSET: u32 = 0x2440;
+/// Largest alignment kept by files that move, the size of the blocks discs are read in
+const MAX_ALIGNMENT: u32 = 0x8000;
+
+/// Identifying information from a disc's boot.bin header
+#[derive(Debug, Clone, Serialize)]
+pub struct IsoMetadata {
+    /// Six character ID, e.g. GPVE01. The first four characters identify the game and
+    /// region, and the last two the publisher.
+    pub game_id: String,
+    pub region: Region,
+    /// Zero-based, so the second disc of a two disc game is 1
+    pub disc_number: u8,
+    pub revision: u8,
+    pub internal_name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Region {
+    Japan,
+    NorthAmerica,
+    Europe,
+    Korea,
+    Unknown,
+}
+
+impl IsoMetadata {
+    /// Size of the boot.bin header at the start of every disc
+    pub const HEADER_LEN: usize = 0x440;
+    const MAGIC: u32 = 0xC2339F3D;
+
+    /// Reads the metadata from the start of a disc image without reading the rest of it
+    pub fn open<P: AsRef<Path>>(iso_path: P) -> Result<Self, IsoError> {
+        let mut boot_bin = vec![0u8; Self::HEADER_LEN];
+        open_disc(iso_path.as_ref())?.read_exact(&mut boot_bin)?;
+        Self::from_boot_bin(&boot_bin)
+            .ok_or_else(|| std::io::Error::new(ErrorKind::InvalidData, "Not a GameCube disc image").into())
+    }
+
+    /// Parses a boot.bin header. Returns None if it doesn't have the GameCube disc magic number.
+    pub fn from_boot_bin(boot_bin: &[u8]) -> Option<Self> {
+        if boot_bin.len() < Self::HEADER_LEN || read_u32(boot_bin, 0x1C) != Self::MAGIC {
+            return None;
+        }
+        let game_id = String::from_utf8_lossy(&boot_bin[..6]).into_owned();
+        let region = Region::from_code(boot_bin[3]);
+        let name = boot_bin[0x20..0x400].split(|b| *b == 0).next().unwrap_or_default();
+        // Japanese discs store their name in Shift-JIS, which is a superset of ASCII
+        let internal_name = match region {
+            Region::Japan => SHIFT_JIS.decode(name).0.into_owned(),
+            _ => String::from_utf8_lossy(name).into_owned(),
+        };
+        Some(IsoMetadata {
+            game_id,
+            region,
+            disc_number: boot_bin[0x6],
+            revision: boot_bin[0x7],
+            internal_name: internal_name.trim().to_owned(),
+        })
+    }
+
+    /// A folder name for the disc's contents like "GPVE01 - Pikmin 2", with any characters
+    /// that aren't allowed in file names replaced
+    pub fn folder_name(&self) -> String {
+        let mut name = format!("{} - {}", self.game_id, self.internal_name);
+        if self.disc_number > 0 {
+            name.push_str(&format!(" (Disc {})", u16::from(self.disc_number) + 1));
+        }
+        name.chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect::<String>()
+            .trim_end_matches(['.', ' '])
+            .to_owned()
+    }
+}
+
+impl Region {
+    /// Identifies the region from the fourth character of the game ID
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            b'J' => Region::Japan,
+            b'E' => Region::NorthAmerica,
+            b'P' | b'D' | b'F' | b'H' | b'I' | b'S' | b'U' | b'X' | b'Y' => Region::Europe,
+            b'K' => Region::Korea,
+            _ => Region::Unknown,
+        }
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Region::Japan => write!(f, "Japan"),
+            Region::NorthAmerica => write!(f, "North America"),
+            Region::Europe => write!(f, "Europe"),
+            Region::Korea => write!(f, "Korea"),
+            Region::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+/// An opened disc image. The small system files at the start of the disc are read up
+/// front, but the files in its file system are only read by [`extract_iso`].
+pub struct Iso {
+    gcm: GcmFile,
+}
+
+impl Iso {
+    /// Folder the system files are put in by [`Iso::system_files`], following Dolphin
+    pub const SYSTEM_FOLDER: &'static str = "sys";
+    /// How much a GameCube disc holds. Larger images only work in emulators.
+    pub const DISC_SIZE: u64 = 0x57058000;
+
+    pub fn open<P: AsRef<Path>>(iso_path: P) -> Result<Self, IsoError> {
+        Ok(Iso {
+            gcm: GcmFile::from_reader(&mut open_disc(iso_path.as_ref())?)?,
+        })
+    }
+
+    /// The game's main executable
+    pub fn main_dol(&self) -> &[u8] {
+        &self.gcm.dol.raw_data
+    }
+
+    /// Offset of main.dol on the disc, e.g. for tools that patch it in place
+    pub fn main_dol_offset(&self) -> u32 {
+        self.gcm.dol_offset
+    }
+
+    /// The apploader, including its header and trailer
+    pub fn apploader(&self) -> &[u8] {
+        &self.gcm.apploader
+    }
+
+    /// The disc header, boot.bin
+    pub fn boot_bin(&self) -> &[u8] {
+        &self.gcm.boot_bin
+    }
+
+    /// The extra disc header, bi2.bin
+    pub fn bi2_bin(&self) -> &[u8] {
+        &self.gcm.bi2_bin
+    }
+
+    /// The raw file system table, fst.bin
+    pub fn fst_bin(&self) -> &[u8] {
+        &self.gcm.fst_bytes
+    }
+
+    /// boot.bin, bi2.bin, apploader.img, main.dol, and fst.bin, with paths inside
+    /// [`Iso::SYSTEM_FOLDER`]. None of them are part of the disc's file system.
+    pub fn system_files(&self) -> Vec<VirtualFile> {
+        [
+            ("boot.bin", self.boot_bin()),
+            ("bi2.bin", self.bi2_bin()),
+            ("apploader.img", self.apploader()),
+            ("main.dol", self.main_dol()),
+            ("fst.bin", self.fst_bin()),
+        ]
+        .into_iter()
+        .map(|(name, bytes)| VirtualFile {
+            path: Path::new(Self::SYSTEM_FOLDER).join(name),
+            bytes: bytes.to_vec(),
+        })
+        .collect()
+    }
+}
+
+/// Anything a disc image can be read from
+trait DiscReader: Read + Seek {}
+
+impl<R: Read + Seek> DiscReader for R {}
+
+/// Opens a disc image for reading, decompressing it as it's read if it's a GCZ
+fn open_disc(path: &Path) -> Result<Box<dyn DiscReader>, IsoError> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 4];
+    let read = file.read(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+    match &magic[..read] {
+        magic if is_gcz(magic) => Ok(Box::new(GczReader::new(file).map_err(io::Error::from)?)),
+        // Out of scope: they need LZMA, bzip2, and Zstandard, and rebuild the data Dolphin
+        // strips from discs rather than just decompressing blocks
+        b"RVZ\x01" | b"WIA\x01" => Err(invalid_data(
+            "RVZ and WIA images aren't supported. Convert it to ISO or GCZ first, e.g. with \
+             `dolphin-tool convert -f iso`",
+        )),
+        _ => Ok(Box::new(file)),
+    }
+}
+
+/// A disc image kept open to read parts of it on demand, like the files listed in its
+/// [`Fst`], for programs that only need some of them
+pub struct IsoReader {
+    reader: Box<dyn DiscReader>,
+}
+
+impl IsoReader {
+    pub fn open<P: AsRef<Path>>(iso_path: P) -> Result<Self, IsoError> {
+        Ok(IsoReader {
+            reader: open_disc(iso_path.as_ref())?,
+        })
+    }
+
+    /// Fills `buf` with the data at `offset`. Offsets are into the uncompressed disc, so
+    /// the ones in the [`Fst`] work for GCZ images too.
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Result<(), IsoError> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        self.reader.read_exact(buf)?;
+        Ok(())
+    }
+}
+
+/// A file on a disc and where it is, from [`list_iso`]
+#[derive(Debug, Clone)]
+pub struct DiscFile {
+    pub path: PathBuf,
+    /// Offset into the uncompressed disc, for [`IsoReader::read_at`]
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Every file on a disc, in the order [`extract_iso`] reads them, without reading any
+pub fn list_iso<P: AsRef<Path>>(iso_path: P) -> Result<Vec<DiscFile>, IsoError> {
+    let mut iso_reader = open_disc(iso_path.as_ref())?;
+    let iso = GcmFile::from_reader(&mut iso_reader)?;
+    Ok(traverse_filesystem(&iso)
+        .into_iter()
+        .map(|vgf| {
+            let location = vgf.entry.as_file().unwrap();
+            DiscFile {
+                path: vgf.path,
+                offset: location.offset as u64,
+                size: location.size as u64,
+            }
+        })
+        .collect())
+}
+
 pub fn extract_iso<P: AsRef<Path>>(iso_path: P) -> Result<Vec<VirtualFile>, IsoError> {
     let iso_path = iso_path.as_ref();
-    let iso = GcmFile::open(iso_path)?;
+    let mut iso_reader = open_disc(iso_path)?;
+    let iso = GcmFile::from_reader(&mut iso_reader)?;
     let all_files = traverse_filesystem(&iso);
-    let mut iso_reader = BufReader::new(File::open(iso_path)?);
+    debug!("Reading {} files from {iso_path:?}", all_files.len());
     all_files
         .into_iter()
         .map(|vgf| vgf.read(&mut iso_reader).map_err(Into::into))
         .collect()
 }
 
+/// A disc's file system table, i.e. the name, location, and size of every file on the disc.
+/// Entries are listed in the order they appear in the table, and every directory comes
+/// before its contents.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fst {
+    pub entries: Vec<FstEntry>,
+}
+
+/// Paths are relative to the disc root and always use `/` as the separator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FstEntry {
+    File { path: String, offset: u32, size: u32 },
+    Dir { path: String },
+}
+
+impl Fst {
+    pub fn open<P: AsRef<Path>>(iso_path: P) -> Result<Self, IsoError> {
+        let iso = GcmFile::from_reader(&mut open_disc(iso_path.as_ref())?)?;
+        let mut entries = Vec::with_capacity(iso.filesystem.files.len());
+        // Directories still open, as the node index they end at and their path
+        let mut dirs: Vec<(u32, String)> = Vec::new();
+        for (idx, node) in iso.filesystem.files.iter().enumerate() {
+            // Node 0 is the root, which isn't included in `files`
+            let idx = idx as u32 + 1;
+            while dirs.last().is_some_and(|(end, _)| *end <= idx) {
+                dirs.pop();
+            }
+            let (FsNode::File { name, .. } | FsNode::Directory { name, .. }) = node;
+            let path = match dirs.last() {
+                Some((_, parent)) => format!("{parent}/{name}"),
+                None => name.clone(),
+            };
+            match node {
+                FsNode::File { offset, size, .. } => entries.push(FstEntry::File {
+                    path,
+                    offset: *offset,
+                    size: *size,
+                }),
+                FsNode::Directory { end_index, .. } => {
+                    dirs.push((*end_index, path.clone()));
+                    entries.push(FstEntry::Dir { path });
+                }
+            }
+        }
+        Ok(Fst { entries })
+    }
+
+    /// Serializes the table in the format used on disc and by fst.bin. Intermediate
+    /// directories are created for any file whose parent isn't listed.
+    pub fn write(&self) -> Vec<u8> {
+        let mut root = FstDir::default();
+        for entry in &self.entries {
+            match entry {
+                FstEntry::File { path, offset, size } => {
+                    let (dir, name) = root.parent_of(path);
+                    dir.children.push(FstNode::File {
+                        name: name.to_owned(),
+                        offset: *offset,
+                        size: *size,
+                    });
+                }
+                FstEntry::Dir { path } => {
+                    let (dir, name) = root.parent_of(path);
+                    dir.subdir(name);
+                }
+            }
+        }
+
+        // Each node is 12 bytes: type and name offset, then either the file's offset and
+        // size, or the directory's parent index and the index after its last descendant
+        let mut nodes = vec![[0u32; 3]];
+        let mut string_table = Vec::new();
+        root.flatten(0, &mut nodes, &mut string_table);
+        nodes[0] = [1 << 24, 0, nodes.len() as u32];
+
+        let mut out = Vec::with_capacity(nodes.len() * 0xC + string_table.len());
+        for node in nodes {
+            out.extend(node.iter().flat_map(|n| n.to_be_bytes()));
+        }
+        out.extend(string_table);
+        out
+    }
+
+    /// Reads a table in the format used on disc and by fst.bin, as written by [`Fst::write`]
+    pub fn parse(fst_bin: &[u8]) -> Result<Self, IsoError> {
+        let node = |idx: u32| {
+            let start = idx as usize * 0xC;
+            let node = fst_bin.get(start..start + 0xC)?;
+            Some([read_u32(node, 0), read_u32(node, 4), read_u32(node, 8)])
+        };
+        let truncated = || invalid_data("File system table is truncated");
+        let [_, _, count] = node(0).ok_or_else(truncated)?;
+        let string_table = fst_bin.get(count as usize * 0xC..).ok_or_else(truncated)?;
+
+        let mut entries = Vec::with_capacity(count.saturating_sub(1) as usize);
+        // Directories still open, as the node index they end at and their path
+        let mut dirs: Vec<(u32, String)> = Vec::new();
+        for idx in 1..count {
+            while dirs.last().is_some_and(|(end, _)| *end <= idx) {
+                dirs.pop();
+            }
+            let [kind_and_name, offset_or_parent, size_or_end] = node(idx).ok_or_else(truncated)?;
+            let name = string_table
+                .get((kind_and_name & 0xFFFFFF) as usize..)
+                .and_then(|names| names.split(|b| *b == 0).next())
+                .ok_or_else(|| invalid_data(format!("File system entry {idx} has no name")))?;
+            let name = String::from_utf8_lossy(name);
+            let path = match dirs.last() {
+                Some((_, parent)) => format!("{parent}/{name}"),
+                None => name.into_owned(),
+            };
+            if kind_and_name >> 24 == 0 {
+                entries.push(FstEntry::File {
+                    path,
+                    offset: offset_or_parent,
+                    size: size_or_end,
+                });
+            } else {
+                dirs.push((size_or_end, path.clone()));
+                entries.push(FstEntry::Dir { path });
+            }
+        }
+        Ok(Fst { entries })
+    }
+}
+
+/// Part of a rebuilt disc that had to move to make room for a file before it that grew.
+/// Files next to each other that moved by the same amount are grouped together.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShiftedRegion {
+    pub old_offset: u32,
+    pub new_offset: u32,
+    pub size: u32,
+    /// Disc paths of the files in the region, with main.dol and fst.bin in
+    /// [`Iso::SYSTEM_FOLDER`]
+    pub files: Vec<String>,
+}
+
+/// Something on a disc being rebuilt that can be moved: main.dol, fst.bin, or a file
+struct DiscRegion {
+    path: String,
+    /// Index in the file system table, for files
+    entry: Option<usize>,
+    old_offset: u32,
+    new_offset: u64,
+    size: u32,
+}
+
+/// Rebuilds a disc image from a folder extracted with its system files, writing it to `out`.
+///
+/// Files are read from the folder at the paths listed in its fst.bin, which decides what's on
+/// the disc, so files are added or removed by editing the table. Everything stays where it was
+/// on the original disc unless something before it grew, in which case it and everything
+/// after it moves up just enough to fit, keeping its alignment. main.dol and fst.bin move
+/// the same way, and boot.bin is updated to point to them. Returns what moved.
+///
+/// Files the table doesn't list are left off with a warning, unless they look like they
+/// were extracted from one it does, like `foo.png` or anything in `foo/` for `foo.bti`.
+///
+/// The image ends after its last file rather than being padded out to
+/// [`Iso::DISC_SIZE`], which Dolphin and disc burning tools don't need.
+pub fn write_iso<W: Write>(root: impl AsRef<Path>, out: &mut W) -> Result<Vec<ShiftedRegion>, IsoError> {
+    let mut source = FolderSource(root.as_ref());
+    let fst = Fst::parse(&source.system_file("fst.bin")?)?;
+    for path in unlisted_files(root.as_ref(), &fst)? {
+        warn!("{path:?} isn't listed in fst.bin, so it's left off the disc");
+    }
+    write_disc(&mut source, out)
+}
+
+/// Files in an extracted disc's folder that aren't on the disc and weren't extracted from
+/// anything that is. Hidden files and the system folder are skipped.
+fn unlisted_files(root: &Path, fst: &Fst) -> io::Result<Vec<String>> {
+    let listed: HashSet<&str> = fst
+        .entries
+        .iter()
+        .filter_map(|entry| match entry {
+            FstEntry::File { path, .. } => Some(path.as_str()),
+            FstEntry::Dir { .. } => None,
+        })
+        .collect();
+    // What's extracted from a file is named after it with its extension dropped
+    let stems: HashSet<&str> = listed
+        .iter()
+        .map(|path| match path.rfind('.').filter(|&dot| !path[dot..].contains('/')) {
+            Some(dot) => &path[..dot],
+            None => path,
+        })
+        .collect();
+
+    let mut unlisted = Vec::new();
+    let mut dirs = vec![String::new()];
+    while let Some(dir) = dirs.pop() {
+        for entry in read_dir(root.join(&dir))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') || (dir.is_empty() && name == Iso::SYSTEM_FOLDER) {
+                continue;
+            }
+            let path = match dir.is_empty() {
+                true => name,
+                false => format!("{dir}/{name}"),
+            };
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let extracted = path
+                .match_indices(['.', '/'])
+                .any(|(idx, _)| stems.contains(&path[..idx]));
+            if !listed.contains(path.as_str()) && !extracted {
+                unlisted.push(path);
+            }
+        }
+    }
+    unlisted.sort();
+    Ok(unlisted)
+}
+
+/// Rebuilds a disc image with some of its files replaced, writing it to `out`. `files`
+/// holds the new contents of files by their path in the [`Fst`], and everything else is
+/// copied from the original image. Files move to make room like they do in [`write_iso`].
+pub fn patch_iso<W: Write>(
+    iso_path: impl AsRef<Path>,
+    files: &HashMap<String, Vec<u8>>,
+    out: &mut W,
+) -> Result<Vec<ShiftedRegion>, IsoError> {
+    let iso_path = iso_path.as_ref();
+    let locations = Fst::open(iso_path)?
+        .entries
+        .into_iter()
+        .filter_map(|entry| match entry {
+            FstEntry::File { path, offset, size } => Some((path, (offset, size))),
+            FstEntry::Dir { .. } => None,
+        })
+        .collect();
+    let mut source = ImageSource {
+        iso: Iso::open(iso_path)?,
+        reader: open_disc(iso_path)?,
+        locations,
+        files,
+    };
+    write_disc(&mut source, out)
+}
+
+/// Where the files for a disc being rebuilt come from
+trait DiscSource {
+    /// Reads a system file by its name in [`Iso::SYSTEM_FOLDER`], like `boot.bin`
+    fn system_file(&mut self, name: &str) -> io::Result<Vec<u8>>;
+    /// Size of a file listed in the file system table
+    fn file_size(&mut self, path: &str) -> io::Result<u64>;
+    /// Copies a file listed in the file system table to `out`, returning how much was copied
+    fn copy_file(&mut self, path: &str, out: &mut dyn Write) -> io::Result<u64>;
+}
+
+/// A folder extracted with its system files
+struct FolderSource<'a>(&'a Path);
+
+impl DiscSource for FolderSource<'_> {
+    fn system_file(&mut self, name: &str) -> io::Result<Vec<u8>> {
+        let path = self.0.join(Iso::SYSTEM_FOLDER).join(name);
+        read(&path).map_err(|e| io::Error::new(e.kind(), format!("Couldn't read {path:?}: {e}")))
+    }
+
+    fn file_size(&mut self, path: &str) -> io::Result<u64> {
+        metadata(self.0.join(path))
+            .map_err(|e| io::Error::new(e.kind(), format!("Couldn't read {path:?}: {e}")))
+            .map(|metadata| metadata.len())
+    }
+
+    fn copy_file(&mut self, path: &str, out: &mut dyn Write) -> io::Result<u64> {
+        io::copy(&mut File::open(self.0.join(path))?, out)
+    }
+}
+
+/// An existing disc image, with some of its files replaced
+struct ImageSource<'a> {
+    iso: Iso,
+    reader: Box<dyn DiscReader>,
+    /// Offset and size of each file on the original disc, by path
+    locations: HashMap<String, (u32, u32)>,
+    files: &'a HashMap<String, Vec<u8>>,
+}
+
+impl ImageSource<'_> {
+    fn location(&self, path: &str) -> io::Result<(u32, u32)> {
+        self.locations
+            .get(path)
+            .copied()
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("{path:?} isn't on the disc")))
+    }
+}
+
+impl DiscSource for ImageSource<'_> {
+    fn system_file(&mut self, name: &str) -> io::Result<Vec<u8>> {
+        let file = match name {
+            "boot.bin" => self.iso.boot_bin(),
+            "bi2.bin" => self.iso.bi2_bin(),
+            "apploader.img" => self.iso.apploader(),
+            "main.dol" => self.iso.main_dol(),
+            "fst.bin" => self.iso.fst_bin(),
+            _ => unreachable!("Only the usual system files are read"),
+        };
+        Ok(file.to_vec())
+    }
+
+    fn file_size(&mut self, path: &str) -> io::Result<u64> {
+        match self.files.get(path) {
+            Some(bytes) => Ok(bytes.len() as u64),
+            None => Ok(self.location(path)?.1 as u64),
+        }
+    }
+
+    fn copy_file(&mut self, path: &str, out: &mut dyn Write) -> io::Result<u64> {
+        if let Some(bytes) = self.files.get(path) {
+            out.write_all(bytes)?;
+            return Ok(bytes.len() as u64);
+        }
+        let (offset, size) = self.location(path)?;
+        self.reader.seek(SeekFrom::Start(offset as u64))?;
+        io::copy(&mut (&mut self.reader).take(size as u64), out)
+    }
+}
+
+fn write_disc<W: Write>(source: &mut impl DiscSource, out: &mut W) -> Result<Vec<ShiftedRegion>, IsoError> {
+    let mut boot_bin = source.system_file("boot.bin")?;
+    let bi2_bin = source.system_file("bi2.bin")?;
+    let apploader = source.system_file("apploader.img")?;
+    let main_dol = source.system_file("main.dol")?;
+    let old_fst_bin = source.system_file("fst.bin")?;
+    let mut fst = Fst::parse(&old_fst_bin)?;
+    if boot_bin.len() != IsoMetadata::HEADER_LEN || bi2_bin.len() != BI2_LEN {
+        return Err(invalid_data("boot.bin or bi2.bin is the wrong size"));
+    }
+    // Only the names decide how big the table is, so it can be sized before anything moves.
+    // It keeps any padding it had, so an unchanged disc comes out the same.
+    let fst_len = fst.write().len().max(old_fst_bin.len()) as u32;
+
+    let dol_path = format!("{}/main.dol", Iso::SYSTEM_FOLDER);
+    let fst_path = format!("{}/fst.bin", Iso::SYSTEM_FOLDER);
+    let mut regions = vec![
+        DiscRegion {
+            path: dol_path.clone(),
+            entry: None,
+            old_offset: read_u32(&boot_bin, DOL_OFFSET),
+            new_offset: 0,
+            size: disc_size(main_dol.len() as u64)?,
+        },
+        DiscRegion {
+            path: fst_path.clone(),
+            entry: None,
+            old_offset: read_u32(&boot_bin, FST_OFFSET),
+            new_offset: 0,
+            size: fst_len,
+        },
+    ];
+    for (idx, entry) in fst.entries.iter().enumerate() {
+        if let FstEntry::File { path, offset, .. } = entry {
+            let size = source.file_size(path)?;
+            regions.push(DiscRegion {
+                path: path.clone(),
+                entry: Some(idx),
+                old_offset: *offset,
+                new_offset: 0,
+                size: disc_size(size)?,
+            });
+        }
+    }
+
+    // Anything that claims to be inside the system area is new, and goes after everything else
+    let system_end = APPLOADER_OFFSET as u64 + apploader.len() as u64;
+    let (mut layout, added): (Vec<_>, Vec<_>) = regions
+        .into_iter()
+        .partition(|region| region.old_offset as u64 >= system_end);
+    layout.sort_by_key(|region| region.old_offset);
+    let moved_from = layout.len();
+    layout.extend(added);
+    let mut end = system_end;
+    for region in layout.iter_mut() {
+        let alignment = 1 << region.old_offset.trailing_zeros().min(MAX_ALIGNMENT.trailing_zeros());
+        region.new_offset = (region.old_offset as u64).max(end.next_multiple_of(alignment));
+        end = region.new_offset + region.size as u64;
+    }
+    disc_size(end)?;
+
+    let mut shifted: Vec<ShiftedRegion> = Vec::new();
+    for region in layout[..moved_from].iter() {
+        if region.new_offset == region.old_offset as u64 {
+            continue;
+        }
+        let new_offset = region.new_offset as u32;
+        match shifted.last_mut() {
+            Some(last) if last.new_offset - last.old_offset == new_offset - region.old_offset => {
+                last.size = region.old_offset + region.size - last.old_offset;
+                last.files.push(region.path.clone());
+            }
+            _ => shifted.push(ShiftedRegion {
+                old_offset: region.old_offset,
+                new_offset,
+                size: region.size,
+                files: vec![region.path.clone()],
+            }),
+        }
+    }
+
+    for region in layout.iter() {
+        if let Some(FstEntry::File { offset, size, .. }) = region.entry.map(|idx| &mut fst.entries[idx]) {
+            *offset = region.new_offset as u32;
+            *size = region.size;
+        }
+    }
+    let mut fst_bin = fst.write();
+    fst_bin.resize(fst_len as usize, 0);
+    let region_offset = |path: &str| {
+        let region = layout
+            .iter()
+            .find(|region| region.entry.is_none() && region.path == path);
+        region.expect("main.dol and fst.bin are always laid out").new_offset as u32
+    };
+    let max_fst_len = read_u32(&boot_bin, FST_MAX_SIZE).max(fst_len);
+    for (offset, value) in [
+        (DOL_OFFSET, region_offset(&dol_path)),
+        (FST_OFFSET, region_offset(&fst_path)),
+        (FST_SIZE, fst_len),
+        (FST_MAX_SIZE, max_fst_len),
+    ] {
+        let offset = offset as usize;
+        boot_bin[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    out.write_all(&boot_bin)?;
+    out.write_all(&bi2_bin)?;
+    out.write_all(&apploader)?;
+    let mut written = system_end;
+    layout.sort_by_key(|region| region.new_offset);
+    for region in layout.iter() {
+        io::copy(&mut io::repeat(0).take(region.new_offset - written), out)?;
+        let copied = match region.entry {
+            Some(_) => source.copy_file(&region.path, out)?,
+            None if region.path == dol_path => io::copy(&mut &main_dol[..], out)?,
+            None => io::copy(&mut &fst_bin[..], out)?,
+        };
+        if copied != region.size as u64 {
+            return Err(invalid_data(format!(
+                "{:?} changed while the disc was being built",
+                region.path
+            )));
+        }
+        written = region.new_offset + copied;
+    }
+    Ok(shifted)
+}
+
+/// Checks that a size or offset fits on a disc, where they're 32 bits
+fn disc_size(size: u64) -> Result<u32, IsoError> {
+    u32::try_from(size).map_err(|_| invalid_data("Disc image would be larger than 4 GiB"))
+}
+
+fn invalid_data(message: impl Into<String>) -> IsoError {
+    io::Error::new(ErrorKind::InvalidData, message.into()).into()
+}
+
+#[derive(Default)]
+struct FstDir {
+    name: String,
+    children: Vec<FstNode>,
+}
+
+enum FstNode {
+    File { name: String, offset: u32, size: u32 },
+    Dir(FstDir),
+}
+
+impl FstDir {
+    /// Finds or creates the directory a path belongs in, returning it and the path's last component
+    fn parent_of<'p>(&mut self, path: &'p str) -> (&mut FstDir, &'p str) {
+        let mut components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        let name = components.pop().unwrap_or_default();
+        let dir = components
+            .into_iter()
+            .fold(self, |dir, component| dir.subdir(component));
+        (dir, name)
+    }
+
+    fn subdir(&mut self, name: &str) -> &mut FstDir {
+        let idx = self
+            .children
+            .iter()
+            .position(|child| matches!(child, FstNode::Dir(dir) if dir.name == name))
+            .unwrap_or_else(|| {
+                self.children.push(FstNode::Dir(FstDir {
+                    name: name.to_owned(),
+                    children: Vec::new(),
+                }));
+                self.children.len() - 1
+            });
+        let FstNode::Dir(dir) = &mut self.children[idx] else {
+            unreachable!()
+        };
+        dir
+    }
+
+    fn flatten(&self, own_idx: u32, nodes: &mut Vec<[u32; 3]>, string_table: &mut Vec<u8>) {
+        for child in &self.children {
+            let name = match child {
+                FstNode::File { name, .. } => name,
+                FstNode::Dir(dir) => &dir.name,
+            };
+            let name_offset = string_table.len() as u32;
+            string_table.extend(name.as_bytes());
+            string_table.push(0);
+            match child {
+                FstNode::File { offset, size, .. } => nodes.push([name_offset, *offset, *size]),
+                FstNode::Dir(dir) => {
+                    let idx = nodes.len();
+                    nodes.push([1 << 24 | name_offset, own_idx, 0]);
+                    dir.flatten(idx as u32, nodes, string_table);
+                    nodes[idx][2] = nodes.len() as u32;
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 struct VirtualGcmFile<'a> {
     pub path: PathBuf,
@@ -30,7 +815,7 @@ impl<'a> VirtualGcmFile<'a> {
         Self { path, entry }
     }
 
-    fn read(self, iso_reader: &mut BufReader<File>) -> std::io::Result<VirtualFile> {
+    fn read(self, iso_reader: &mut dyn DiscReader) -> std::io::Result<VirtualFile> {
         let file_location = self.entry.as_file().unwrap();
         let mut data = vec![0u8; file_location.size as usize];
         iso_reader.seek(SeekFrom::Start(file_location.offset as u64))?;
@@ -52,11 +837,8 @@ fn traverse_filesystem(iso: &GcmFile) -> Vec<VirtualGcmFile<'_>> {
 }
 
 fn traverse_fs_recursive(entries: Vec<VirtualGcmFile<'_>>) -> Vec<VirtualGcmFile<'_>> {
-    let (mut files, directories): (Vec<_>, Vec<_>) =
-        entries.into_iter().partition(|e| e.entry.is_file());
-    files
-        .iter_mut()
-        .for_each(|f| f.path.push(f.entry.entry_name()));
+    let (mut files, directories): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| e.entry.is_file());
+    files.iter_mut().for_each(|f| f.path.push(f.entry.entry_name()));
     files.extend(directories.into_iter().flat_map(|mut d| {
         d.path.push(d.entry.entry_name());
         traverse_fs_recursive(
@@ -84,6 +866,12 @@ impl Display for IsoError {
     }
 }
 
+impl IsoError {
+    pub(crate) fn is_io(&self) -> bool {
+        matches!(self.0, GcmError::IoError(_))
+    }
+}
+
 impl From<GcmError> for IsoError {
     fn from(value: GcmError) -> Self {
         IsoError(value)
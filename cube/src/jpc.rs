@@ -0,0 +1,307 @@
+//! JPC, the JParticle container that holds every particle effect in a scene along with
+//! the textures they use. Each effect (a JPA resource) is a short header followed by
+//! tagged blocks, and refers to textures by their index in the container's texture table
+//! through its TDB1 block.
+
+use crate::util::{pad_to, read_u16, read_u32};
+use encoding_rs::SHIFT_JIS;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const HEADER_SIZE: usize = 0x10;
+const RESOURCE_HEADER_SIZE: usize = 0x8;
+const BLOCK_HEADER_SIZE: usize = 0x8;
+const TEXTURE_HEADER_SIZE: usize = 0x20;
+const TEXTURE_NAME_SIZE: usize = 0x14;
+
+/// The container versions with the layout read here. Earlier games use JPAC1-00,
+/// whose resources are laid out differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JpcVersion {
+    #[serde(rename = "JPAC2-10")]
+    Jpac2_10,
+    #[serde(rename = "JPAC2-11")]
+    Jpac2_11,
+}
+
+impl JpcVersion {
+    pub fn magic(&self) -> &'static [u8; 8] {
+        match self {
+            JpcVersion::Jpac2_10 => b"JPAC2-10",
+            JpcVersion::Jpac2_11 => b"JPAC2-11",
+        }
+    }
+}
+
+pub struct Jpc {
+    pub version: JpcVersion,
+    pub particles: Vec<JpaResource>,
+    pub textures: Vec<JpcTexture>,
+}
+
+/// A single particle effect, kept as the bytes it's stored as
+#[derive(Debug, Clone)]
+pub struct JpaResource {
+    data: Vec<u8>,
+}
+
+/// A texture shared by the container's particles
+#[derive(Debug, Clone)]
+pub struct JpcTexture {
+    pub name: String,
+    /// The texture as a BTI, header included
+    pub bti: Vec<u8>,
+}
+
+/// The order of a container's particles and textures, and which textures each particle
+/// uses by name. This is everything needed to put an extracted container back together.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JpcIndex {
+    pub version: JpcVersion,
+    pub particles: Vec<JpcIndexParticle>,
+    pub textures: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JpcIndexParticle {
+    pub id: u16,
+    pub textures: Vec<String>,
+}
+
+impl Jpc {
+    pub fn read(data: &[u8]) -> Result<Self, JpcError> {
+        if data.len() < HEADER_SIZE {
+            return Err(JpcError::Truncated(0));
+        }
+        let version = match &data[..8] {
+            b"JPAC2-10" => JpcVersion::Jpac2_10,
+            b"JPAC2-11" => JpcVersion::Jpac2_11,
+            magic if magic.starts_with(b"JPAC") => {
+                return Err(JpcError::UnsupportedVersion(
+                    String::from_utf8_lossy(magic).into_owned(),
+                ))
+            }
+            _ => return Err(JpcError::Magic),
+        };
+        let num_particles = read_u16(data, 0x8) as usize;
+        let num_textures = read_u16(data, 0xA) as usize;
+        let texture_table_offset = read_u32(data, 0xC) as usize;
+
+        let mut particles = Vec::with_capacity(num_particles);
+        let mut offset = HEADER_SIZE;
+        for _ in 0..num_particles {
+            let size = resource_size(data, offset)?;
+            particles.push(JpaResource {
+                data: data[offset..offset + size].to_vec(),
+            });
+            offset += size;
+        }
+
+        let mut textures = Vec::with_capacity(num_textures);
+        let mut offset = texture_table_offset;
+        for _ in 0..num_textures {
+            if offset + TEXTURE_HEADER_SIZE > data.len() || &data[offset..offset + 4] != b"TEX1" {
+                return Err(JpcError::Truncated(offset));
+            }
+            let size = read_u32(data, offset as u32 + 0x4) as usize;
+            if size < TEXTURE_HEADER_SIZE || offset + size > data.len() {
+                return Err(JpcError::Truncated(offset));
+            }
+            let name = &data[offset + 0xC..offset + 0xC + TEXTURE_NAME_SIZE];
+            let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+            textures.push(JpcTexture {
+                name: SHIFT_JIS.decode(name).0.into_owned(),
+                bti: data[offset + TEXTURE_HEADER_SIZE..offset + size].to_vec(),
+            });
+            offset += size;
+        }
+
+        Ok(Jpc {
+            version,
+            particles,
+            textures,
+        })
+    }
+
+    pub fn write(&self) -> Result<Vec<u8>, JpcError> {
+        let mut out = Vec::new();
+        out.extend(self.version.magic());
+        out.extend(count_u16(self.particles.len(), "particles")?.to_be_bytes());
+        out.extend(count_u16(self.textures.len(), "textures")?.to_be_bytes());
+        out.extend([0; 4]); // Texture table offset, filled in below
+        for particle in self.particles.iter() {
+            out.extend(&particle.data);
+        }
+
+        pad_to::<0x20>(&mut out);
+        let texture_table_offset = out.len() as u32;
+        out[0xC..0x10].copy_from_slice(&texture_table_offset.to_be_bytes());
+        for texture in self.textures.iter() {
+            let (name, _, unencodable) = SHIFT_JIS.encode(&texture.name);
+            if unencodable || name.len() > TEXTURE_NAME_SIZE {
+                return Err(JpcError::InvalidTextureName(texture.name.clone()));
+            }
+            let size = (TEXTURE_HEADER_SIZE + texture.bti.len()).next_multiple_of(0x20);
+            let start = out.len();
+            out.extend(b"TEX1");
+            out.extend((size as u32).to_be_bytes());
+            out.extend([0; 4]);
+            out.extend(name.iter());
+            out.resize(start + TEXTURE_HEADER_SIZE, 0);
+            out.extend(&texture.bti);
+            out.resize(start + size, 0);
+        }
+        Ok(out)
+    }
+
+    /// Lists the container's contents, with each particle's textures referred to by name
+    pub fn index(&self) -> Result<JpcIndex, JpcError> {
+        let particles = self
+            .particles
+            .iter()
+            .map(|particle| {
+                let textures = particle
+                    .texture_indices()
+                    .into_iter()
+                    .map(|index| match self.textures.get(index as usize) {
+                        Some(texture) => Ok(texture.name.clone()),
+                        None => Err(JpcError::TextureIndexOutOfRange {
+                            particle: particle.id(),
+                            index,
+                        }),
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok(JpcIndexParticle {
+                    id: particle.id(),
+                    textures,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(JpcIndex {
+            version: self.version,
+            particles,
+            textures: self.textures.iter().map(|texture| texture.name.clone()).collect(),
+        })
+    }
+}
+
+impl JpaResource {
+    /// Checks that `data` holds exactly one resource
+    pub fn new(data: Vec<u8>) -> Result<Self, JpcError> {
+        if resource_size(&data, 0)? != data.len() {
+            return Err(JpcError::TrailingData);
+        }
+        Ok(JpaResource { data })
+    }
+
+    pub fn id(&self) -> u16 {
+        read_u16(&self.data, 0x0)
+    }
+
+    pub fn set_id(&mut self, id: u16) {
+        self.data[..2].copy_from_slice(&id.to_be_bytes());
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Indices into the container's texture table of every texture this particle uses
+    pub fn texture_indices(&self) -> Vec<u16> {
+        let Some(offset) = self.tdb1_offset() else {
+            return Vec::new();
+        };
+        (0..self.texture_count())
+            .map(|i| read_u16(&self.data, (offset + BLOCK_HEADER_SIZE + i * 2) as u32))
+            .collect()
+    }
+
+    /// Points this particle at different textures. It has to use as many as it did before.
+    pub fn set_texture_indices(&mut self, indices: &[u16]) -> Result<(), JpcError> {
+        let count = self.texture_count();
+        let offset = match self.tdb1_offset() {
+            Some(offset) if indices.len() == count => offset + BLOCK_HEADER_SIZE,
+            None if indices.is_empty() => return Ok(()),
+            _ => {
+                return Err(JpcError::WrongTextureCount {
+                    particle: self.id(),
+                    expected: count,
+                    got: indices.len(),
+                })
+            }
+        };
+        for (i, index) in indices.iter().enumerate() {
+            self.data[offset + i * 2..offset + i * 2 + 2].copy_from_slice(&index.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    fn texture_count(&self) -> usize {
+        self.data[0x6] as usize
+    }
+
+    fn tdb1_offset(&self) -> Option<usize> {
+        let mut offset = RESOURCE_HEADER_SIZE;
+        for _ in 0..read_u16(&self.data, 0x2) {
+            if &self.data[offset..offset + 4] == b"TDB1" {
+                // Resources are validated when they're read, but the count comes from
+                // the header and could still claim more entries than the block holds
+                let size = read_u32(&self.data, offset as u32 + 0x4) as usize;
+                return (BLOCK_HEADER_SIZE + self.texture_count() * 2 <= size).then_some(offset);
+            }
+            offset += read_u32(&self.data, offset as u32 + 0x4) as usize;
+        }
+        None
+    }
+}
+
+/// Size of the resource at `offset`, including its header and all of its blocks
+fn resource_size(data: &[u8], offset: usize) -> Result<usize, JpcError> {
+    if offset + RESOURCE_HEADER_SIZE > data.len() {
+        return Err(JpcError::Truncated(offset));
+    }
+    let num_blocks = read_u16(data, offset as u32 + 0x2);
+    let mut end = offset + RESOURCE_HEADER_SIZE;
+    for _ in 0..num_blocks {
+        if end + BLOCK_HEADER_SIZE > data.len() {
+            return Err(JpcError::Truncated(end));
+        }
+        let size = read_u32(data, end as u32 + 0x4) as usize;
+        if size < BLOCK_HEADER_SIZE || end + size > data.len() {
+            return Err(JpcError::Truncated(end));
+        }
+        end += size;
+    }
+    Ok(end - offset)
+}
+
+fn count_u16(count: usize, what: &'static str) -> Result<u16, JpcError> {
+    u16::try_from(count).map_err(|_| JpcError::TooMany(what))
+}
+
+#[derive(Debug, Error)]
+pub enum JpcError {
+    #[error("Not a particle container (bad magic)")]
+    Magic,
+
+    #[error("Unsupported particle container version {0}")]
+    UnsupportedVersion(String),
+
+    #[error("Particle container is truncated at {0:#X}")]
+    Truncated(usize),
+
+    #[error("Particle data has bytes left over after its last block")]
+    TrailingData,
+
+    #[error("Too many {0} for a particle container")]
+    TooMany(&'static str),
+
+    #[error("Texture name {0:?} is too long or can't be encoded")]
+    InvalidTextureName(String),
+
+    #[error("Particle {particle} uses texture {index}, which isn't in the container")]
+    TextureIndexOutOfRange { particle: u16, index: u16 },
+
+    #[error("Particle {particle} uses {expected} textures but was given {got}")]
+    WrongTextureCount { particle: u16, expected: usize, got: usize },
+}
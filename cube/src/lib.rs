@@ -1,10 +1,38 @@
+pub mod afs;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod baa;
+pub mod banner;
+pub mod bcsv;
+pub mod blo;
+pub mod bmd;
 pub mod bmg;
 pub mod bti;
+pub mod detect;
+pub mod dolphin;
+pub mod error;
+#[cfg(feature = "capi")]
+pub mod ffi;
+pub mod font_metrics;
+pub mod gci;
+pub mod gcz;
+pub mod handler;
 pub mod iso;
+pub mod jpc;
+pub mod msbt;
+#[cfg(feature = "png")]
+pub mod png;
+pub mod preview;
 pub mod rarc;
+pub mod rel;
 pub mod szs;
 pub mod traits;
+pub mod tree;
+pub mod u8_archive;
 mod util;
 pub mod virtual_fs;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod yay0;
 
 pub use traits::*;
@@ -0,0 +1,581 @@
+//! MSBT, the LibMessageStudio text format that later Nintendo games use in place of BMG.
+//! Messages are found by a text label instead of a numeric ID, and their text is UTF-8,
+//! UTF-16, or UTF-32 in either byte order. The JSON form matches a BMG's, with control
+//! tags kept as escape sequences in the text.
+
+use crate::{
+    bmg::parse_escape_sequence,
+    rarc::ByteOrder,
+    util::{from_hex_string, to_hex_string},
+};
+use encoding_rs::{UTF_16BE, UTF_16LE};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+const MAGIC: &[u8; 8] = b"MsgStdBn";
+const HEADER_SIZE: usize = 0x20;
+const SECTION_HEADER_SIZE: usize = 0x10;
+/// Sections start on 16 byte boundaries, padded with this byte
+const SECTION_PADDING: u8 = 0xAB;
+/// Hash buckets in the label table of every known MSBT
+const DEFAULT_LABEL_GROUPS: u32 = 101;
+
+/// Starts a tag with parameters
+const TAG_START: u32 = 0x0E;
+/// Ends a tag that wraps text, in version 3 files
+const TAG_END: u32 = 0x0F;
+
+/// An MSBT with its messages decoded. Serializes to the same layout as a BMG's JSON, with
+/// labels in place of message IDs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Msbt {
+    pub metadata: MsbtMetadata,
+    pub messages: Vec<MsbtMessage>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MsbtMetadata {
+    pub byte_order: ByteOrder,
+    pub encoding: MsbtEncoding,
+    pub version: u8,
+    /// Number of hash buckets in the label table
+    #[serde(default = "default_label_groups")]
+    pub label_groups: u32,
+    /// Size of each message's attributes, or None if the file has no ATR1 section
+    #[serde(default)]
+    pub attribute_size: Option<u32>,
+    /// Anything in ATR1 after the attributes themselves, such as strings they point to, as hex
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub attribute_data: String,
+    /// Every section in the order they're written. Ones this module doesn't understand
+    /// keep their contents as hex.
+    #[serde(default = "default_sections")]
+    pub sections: Vec<MsbtSection>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MsbtSection {
+    pub magic: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+}
+
+/// A message's text, the label it's looked up by, and its attributes as hex
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MsbtMessage {
+    pub message: String,
+    pub label: Option<String>,
+    #[serde(default)]
+    pub attributes: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MsbtEncoding {
+    UTF8,
+    UTF16,
+    UTF32,
+}
+
+impl MsbtEncoding {
+    pub fn from_byte(b: u8) -> Option<MsbtEncoding> {
+        match b {
+            0 => Some(MsbtEncoding::UTF8),
+            1 => Some(MsbtEncoding::UTF16),
+            2 => Some(MsbtEncoding::UTF32),
+            _ => None,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            MsbtEncoding::UTF8 => 0,
+            MsbtEncoding::UTF16 => 1,
+            MsbtEncoding::UTF32 => 2,
+        }
+    }
+
+    fn unit_size(self) -> usize {
+        match self {
+            MsbtEncoding::UTF8 => 1,
+            MsbtEncoding::UTF16 => 2,
+            MsbtEncoding::UTF32 => 4,
+        }
+    }
+}
+
+fn default_label_groups() -> u32 {
+    DEFAULT_LABEL_GROUPS
+}
+
+fn default_sections() -> Vec<MsbtSection> {
+    [b"LBL1", b"ATR1", b"TXT2"]
+        .into_iter()
+        .map(|magic| MsbtSection {
+            magic: String::from_utf8_lossy(magic).into_owned(),
+            data: None,
+        })
+        .collect()
+}
+
+impl Msbt {
+    pub fn read(data: &[u8]) -> Result<Msbt, MsbtError> {
+        if data.len() < HEADER_SIZE {
+            return Err(MsbtError::Truncated(0));
+        }
+        if &data[..8] != MAGIC {
+            return Err(MsbtError::Magic);
+        }
+        let byte_order = match [data[0x8], data[0x9]] {
+            [0xFE, 0xFF] => ByteOrder::Big,
+            [0xFF, 0xFE] => ByteOrder::Little,
+            bom => return Err(MsbtError::InvalidByteOrderMark(bom)),
+        };
+        let encoding = MsbtEncoding::from_byte(data[0xC]).ok_or(MsbtError::UnknownEncoding(data[0xC]))?;
+        let version = data[0xD];
+        let num_sections = byte_order.read_u16(data, 0xE);
+
+        let mut metadata = MsbtMetadata {
+            byte_order,
+            encoding,
+            version,
+            label_groups: DEFAULT_LABEL_GROUPS,
+            attribute_size: None,
+            attribute_data: String::new(),
+            sections: Vec::with_capacity(num_sections as usize),
+        };
+        let mut labels: Vec<(String, u32)> = Vec::new();
+        let mut attribute_section = None;
+        let mut texts: Vec<String> = Vec::new();
+
+        let mut offset = HEADER_SIZE;
+        for _ in 0..num_sections {
+            if offset + SECTION_HEADER_SIZE > data.len() {
+                return Err(MsbtError::Truncated(offset));
+            }
+            let magic = &data[offset..offset + 4];
+            let size = byte_order.read_u32(data, offset as u32 + 0x4) as usize;
+            let start = offset + SECTION_HEADER_SIZE;
+            let section = data.get(start..start + size).ok_or(MsbtError::InvalidSectionSize {
+                magic: String::from_utf8_lossy(magic).into_owned(),
+                offset,
+            })?;
+            let known = match magic {
+                b"LBL1" => {
+                    let (groups, section_labels) = read_labels(section, byte_order)?;
+                    metadata.label_groups = groups;
+                    labels = section_labels;
+                    true
+                }
+                b"ATR1" => {
+                    attribute_section = Some(section);
+                    true
+                }
+                b"TXT2" => {
+                    texts = read_texts(section, byte_order, encoding)?;
+                    true
+                }
+                _ => false,
+            };
+            metadata.sections.push(MsbtSection {
+                magic: String::from_utf8_lossy(magic).into_owned(),
+                data: (!known).then(|| to_hex_string(section)),
+            });
+            offset = (start + size).next_multiple_of(0x10);
+        }
+        // Read once the messages are, since attributes past the last message are never used
+        let mut attributes: Vec<&[u8]> = Vec::new();
+        if let Some(section) = attribute_section {
+            let (size, entries, rest) = read_attributes(section, byte_order, texts.len())?;
+            metadata.attribute_size = Some(size);
+            metadata.attribute_data = to_hex_string(rest);
+            attributes = entries;
+        }
+
+        let mut messages: Vec<MsbtMessage> = texts
+            .into_iter()
+            .enumerate()
+            .map(|(index, message)| MsbtMessage {
+                message,
+                label: None,
+                attributes: attributes.get(index).map(|a| to_hex_string(a)).unwrap_or_default(),
+            })
+            .collect();
+        for (label, index) in labels {
+            let message = messages
+                .get_mut(index as usize)
+                .ok_or_else(|| MsbtError::LabelIndexOutOfRange {
+                    label: label.clone(),
+                    index,
+                })?;
+            // A message with more than one label keeps the first
+            message.label.get_or_insert(label);
+        }
+        Ok(Msbt { metadata, messages })
+    }
+
+    pub fn write(&self) -> Result<Vec<u8>, MsbtError> {
+        let byte_order = self.metadata.byte_order;
+        let mut out = Vec::new();
+        out.extend(MAGIC);
+        out.extend(match byte_order {
+            ByteOrder::Big => [0xFE, 0xFF],
+            ByteOrder::Little => [0xFF, 0xFE],
+        });
+        out.extend([0; 2]);
+        out.push(self.metadata.encoding.to_byte());
+        out.push(self.metadata.version);
+        let num_sections = u16::try_from(self.metadata.sections.len()).map_err(|_| MsbtError::TooManySections)?;
+        out.extend(byte_order.u16_bytes(num_sections));
+        out.extend([0; 2]);
+        out.extend([0; 4]); // File size, filled in below
+        out.resize(HEADER_SIZE, 0);
+
+        for section in self.metadata.sections.iter() {
+            let magic: [u8; 4] = section
+                .magic
+                .as_bytes()
+                .try_into()
+                .map_err(|_| MsbtError::InvalidSectionMagic(section.magic.clone()))?;
+            let data = match (&magic, &section.data) {
+                (_, Some(hex)) => parse_hex(hex)?,
+                (b"LBL1", None) => self.write_labels()?,
+                (b"ATR1", None) => self.write_attributes()?,
+                (b"TXT2", None) => self.write_texts()?,
+                (_, None) => return Err(MsbtError::MissingSectionData(section.magic.clone())),
+            };
+            out.extend(magic);
+            out.extend(byte_order.u32_bytes(data.len() as u32));
+            out.extend([0; 8]);
+            out.extend(data);
+            out.resize(out.len().next_multiple_of(0x10), SECTION_PADDING);
+        }
+
+        let file_size = byte_order.u32_bytes(out.len() as u32);
+        out[0x12..0x16].copy_from_slice(&file_size);
+        Ok(out)
+    }
+
+    fn write_labels(&self) -> Result<Vec<u8>, MsbtError> {
+        let byte_order = self.metadata.byte_order;
+        let groups = self.metadata.label_groups.max(1);
+        let mut buckets: Vec<Vec<(&str, u32)>> = vec![Vec::new(); groups as usize];
+        for (index, message) in self.messages.iter().enumerate() {
+            if let Some(label) = &message.label {
+                if label.len() > u8::MAX as usize {
+                    return Err(MsbtError::LabelTooLong(label.clone()));
+                }
+                buckets[(label_hash(label) % groups) as usize].push((label, index as u32));
+            }
+        }
+
+        let mut out = Vec::new();
+        out.extend(byte_order.u32_bytes(groups));
+        let mut label_offset = 4 + groups as usize * 8;
+        for bucket in buckets.iter() {
+            out.extend(byte_order.u32_bytes(bucket.len() as u32));
+            out.extend(byte_order.u32_bytes(label_offset as u32));
+            label_offset += bucket.iter().map(|(label, _)| 1 + label.len() + 4).sum::<usize>();
+        }
+        for (label, index) in buckets.into_iter().flatten() {
+            out.push(label.len() as u8);
+            out.extend(label.as_bytes());
+            out.extend(byte_order.u32_bytes(index));
+        }
+        Ok(out)
+    }
+
+    fn write_attributes(&self) -> Result<Vec<u8>, MsbtError> {
+        let byte_order = self.metadata.byte_order;
+        let size = self.metadata.attribute_size.unwrap_or(0);
+        let mut out = Vec::new();
+        out.extend(byte_order.u32_bytes(self.messages.len() as u32));
+        out.extend(byte_order.u32_bytes(size));
+        for message in self.messages.iter() {
+            let attributes = parse_hex(&message.attributes)?;
+            if attributes.len() != size as usize {
+                return Err(MsbtError::InconsistentAttributeLength {
+                    expected: size as usize,
+                    found: attributes.len(),
+                });
+            }
+            out.extend(attributes);
+        }
+        out.extend(parse_hex(&self.metadata.attribute_data)?);
+        Ok(out)
+    }
+
+    fn write_texts(&self) -> Result<Vec<u8>, MsbtError> {
+        let byte_order = self.metadata.byte_order;
+        let mut offsets = Vec::with_capacity(self.messages.len());
+        let mut strings = Vec::new();
+        let table_size = 4 + self.messages.len() * 4;
+        for (index, message) in self.messages.iter().enumerate() {
+            offsets.push((table_size + strings.len()) as u32);
+            let text = encode_text(&message.message, self.metadata.encoding, byte_order).map_err(|e| {
+                MsbtError::InMessage {
+                    index,
+                    source: Box::new(e),
+                }
+            })?;
+            strings.extend(text);
+        }
+        let mut out = Vec::with_capacity(table_size + strings.len());
+        out.extend(byte_order.u32_bytes(self.messages.len() as u32));
+        out.extend(offsets.into_iter().flat_map(|offset| byte_order.u32_bytes(offset)));
+        out.extend(strings);
+        Ok(out)
+    }
+}
+
+/// Reads LBL1, returning its number of hash buckets and every label with the index of
+/// the message it names
+fn read_labels(section: &[u8], byte_order: ByteOrder) -> Result<(u32, Vec<(String, u32)>), MsbtError> {
+    let truncated = |offset| MsbtError::InvalidSection { magic: "LBL1", offset };
+    if section.len() < 4 {
+        return Err(truncated(0));
+    }
+    let groups = byte_order.read_u32(section, 0);
+    if 4 + groups as usize * 8 > section.len() {
+        return Err(truncated(4));
+    }
+    let mut labels = Vec::new();
+    for group in 0..groups {
+        let entry = 4 + group * 8;
+        let num_labels = byte_order.read_u32(section, entry);
+        let mut offset = byte_order.read_u32(section, entry + 4) as usize;
+        for _ in 0..num_labels {
+            let len = *section.get(offset).ok_or(truncated(offset))? as usize;
+            let name = section.get(offset + 1..offset + 1 + len).ok_or(truncated(offset))?;
+            if offset + 1 + len + 4 > section.len() {
+                return Err(truncated(offset));
+            }
+            let index = byte_order.read_u32(section, (offset + 1 + len) as u32);
+            labels.push((String::from_utf8_lossy(name).into_owned(), index));
+            offset += 1 + len + 4;
+        }
+    }
+    // Buckets are in hash order, so put labels back in message order
+    labels.sort_by_key(|(_, index)| *index);
+    Ok((groups, labels))
+}
+
+/// The size of each message's attributes, the attributes, and any data after them
+type AttributeSection<'a> = (u32, Vec<&'a [u8]>, &'a [u8]);
+
+/// Reads the attributes of at most `max_entries` messages
+fn read_attributes(
+    section: &[u8],
+    byte_order: ByteOrder,
+    max_entries: usize,
+) -> Result<AttributeSection<'_>, MsbtError> {
+    if section.len() < 8 {
+        return Err(MsbtError::InvalidSection {
+            magic: "ATR1",
+            offset: 0,
+        });
+    }
+    let num_entries = byte_order.read_u32(section, 0) as usize;
+    let size = byte_order.read_u32(section, 4);
+    let end = num_entries
+        .checked_mul(size as usize)
+        .and_then(|len| len.checked_add(8))
+        .filter(|&end| end <= section.len())
+        .ok_or(MsbtError::InvalidSection {
+            magic: "ATR1",
+            offset: 8,
+        })?;
+    let entries = match size {
+        0 => vec![&section[8..8]; num_entries.min(max_entries)],
+        size => section[8..end].chunks_exact(size as usize).collect(),
+    };
+    Ok((size, entries, &section[end..]))
+}
+
+fn read_texts(section: &[u8], byte_order: ByteOrder, encoding: MsbtEncoding) -> Result<Vec<String>, MsbtError> {
+    let invalid = |offset| MsbtError::InvalidSection { magic: "TXT2", offset };
+    if section.len() < 4 {
+        return Err(invalid(0));
+    }
+    let num_entries = byte_order.read_u32(section, 0) as usize;
+    if 4 + num_entries * 4 > section.len() {
+        return Err(invalid(4));
+    }
+    let offsets: Vec<usize> = (0..num_entries)
+        .map(|idx| byte_order.read_u32(section, 4 + idx as u32 * 4) as usize)
+        .collect();
+    offsets
+        .iter()
+        .enumerate()
+        .map(|(idx, &start)| {
+            let end = offsets.get(idx + 1).copied().unwrap_or(section.len());
+            let text = section.get(start..end.max(start)).ok_or(invalid(start))?;
+            Ok(decode_text(text, encoding, byte_order))
+        })
+        .collect()
+}
+
+/// Decodes a message's text up to its terminator. Tags are written like BMG escape
+/// sequences, as the tag's start character, its length in bytes, and `0x` followed by
+/// its bytes: the group, type, and parameters, in the file's byte order.
+pub fn decode_text(data: &[u8], encoding: MsbtEncoding, byte_order: ByteOrder) -> String {
+    let unit_size = encoding.unit_size();
+    let read_unit = |offset: usize| -> Option<u32> {
+        let bytes = data.get(offset..offset + unit_size)?;
+        Some(match unit_size {
+            1 => bytes[0] as u32,
+            2 => byte_order.read_u16(bytes, 0) as u32,
+            _ => byte_order.read_u32(bytes, 0),
+        })
+    };
+
+    let mut text = String::new();
+    let mut run_start = 0;
+    let mut offset = 0;
+    loop {
+        let unit = read_unit(offset).unwrap_or(0);
+        if unit != 0 && unit != TAG_START && unit != TAG_END {
+            offset += unit_size;
+            continue;
+        }
+        text.push_str(&decode_run(&data[run_start..offset], encoding, byte_order));
+
+        // Group and type, then for tags that start the size of the parameters after them.
+        // Tags that run past the end of the text end it.
+        let tag_start = offset + unit_size;
+        let (header_len, params_len) = match unit {
+            TAG_START if tag_start + 6 <= data.len() => (6, byte_order.read_u16(data, tag_start as u32 + 4) as usize),
+            TAG_END => (4, 0),
+            _ => break,
+        };
+        let tag_end = tag_start + header_len + params_len;
+        if tag_end > data.len() {
+            break;
+        }
+        let mut tag = data[tag_start..tag_start + 4].to_vec();
+        tag.extend(&data[tag_start + header_len..tag_end]);
+        text.push(char::from_u32(unit).expect("Tag characters are valid"));
+        text.push_str(&format!("{}0x{}", tag.len(), to_hex_string(&tag)));
+        offset = tag_end;
+        run_start = offset;
+    }
+    text
+}
+
+fn decode_run(bytes: &[u8], encoding: MsbtEncoding, byte_order: ByteOrder) -> String {
+    match (encoding, byte_order) {
+        (MsbtEncoding::UTF8, _) => String::from_utf8_lossy(bytes).into_owned(),
+        (MsbtEncoding::UTF16, ByteOrder::Big) => UTF_16BE.decode_without_bom_handling(bytes).0.into_owned(),
+        (MsbtEncoding::UTF16, ByteOrder::Little) => UTF_16LE.decode_without_bom_handling(bytes).0.into_owned(),
+        (MsbtEncoding::UTF32, _) => bytes
+            .chunks_exact(4)
+            .map(|unit| char::from_u32(byte_order.read_u32(unit, 0)).unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect(),
+    }
+}
+
+/// Encodes text with tags in the form produced by [`decode_text`], adding a terminator
+pub fn encode_text(text: &str, encoding: MsbtEncoding, byte_order: ByteOrder) -> Result<Vec<u8>, MsbtError> {
+    let push_unit = |out: &mut Vec<u8>, unit: u32| match encoding {
+        MsbtEncoding::UTF8 => out.push(unit as u8),
+        MsbtEncoding::UTF16 => out.extend(byte_order.u16_bytes(unit as u16)),
+        MsbtEncoding::UTF32 => out.extend(byte_order.u32_bytes(unit)),
+    };
+    let escape_error = |offset, reason| MsbtError::InvalidEscapeSequence { offset, reason };
+
+    let mut out = Vec::new();
+    let mut offset = 0;
+    while offset < text.len() {
+        let c = text[offset..].chars().next().expect("Offset is inside the text");
+        let unit = c as u32;
+        if unit != TAG_START && unit != TAG_END {
+            match encoding {
+                MsbtEncoding::UTF8 => out.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                MsbtEncoding::UTF16 => {
+                    for unit in c.encode_utf16(&mut [0; 2]) {
+                        out.extend(byte_order.u16_bytes(*unit));
+                    }
+                }
+                MsbtEncoding::UTF32 => out.extend(byte_order.u32_bytes(unit)),
+            }
+            offset += c.len_utf8();
+            continue;
+        }
+
+        let (tag, len) = parse_escape_sequence(&text[offset..]).map_err(|reason| escape_error(offset, reason))?;
+        if tag.len() < 4 || (unit == TAG_END && tag.len() != 4) {
+            return Err(escape_error(offset, "tag is the wrong length"));
+        }
+        push_unit(&mut out, unit);
+        out.extend(&tag[..4]);
+        if unit == TAG_START {
+            let params_len = u16::try_from(tag.len() - 4).map_err(|_| escape_error(offset, "tag is too long"))?;
+            out.extend(byte_order.u16_bytes(params_len));
+            out.extend(&tag[4..]);
+        }
+        offset += len;
+    }
+    push_unit(&mut out, 0);
+    Ok(out)
+}
+
+/// The hash labels are bucketed by
+fn label_hash(label: &str) -> u32 {
+    label
+        .bytes()
+        .fold(0u32, |hash, b| hash.wrapping_mul(0x492).wrapping_add(b as u32))
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>, MsbtError> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(MsbtError::InvalidHex(hex.to_owned()));
+    }
+    from_hex_string(hex).map_err(|_| MsbtError::InvalidHex(hex.to_owned()))
+}
+
+#[derive(Debug, Error)]
+pub enum MsbtError {
+    #[error("Not an MSBT file (bad magic)")]
+    Magic,
+
+    #[error("MSBT is truncated at {0:#X}")]
+    Truncated(usize),
+
+    #[error("Invalid byte order mark {0:02X?} in MSBT header")]
+    InvalidByteOrderMark([u8; 2]),
+
+    #[error("Unknown MSBT text encoding {0}")]
+    UnknownEncoding(u8),
+
+    #[error("{magic} section at {offset:#X} runs past the end of the file")]
+    InvalidSectionSize { magic: String, offset: usize },
+
+    #[error("{magic} section is malformed at {offset:#X}")]
+    InvalidSection { magic: &'static str, offset: usize },
+
+    #[error("Label {label:?} refers to message {index}, which doesn't exist")]
+    LabelIndexOutOfRange { label: String, index: u32 },
+
+    #[error("Label {0:?} is longer than 255 bytes")]
+    LabelTooLong(String),
+
+    #[error("Section magic {0:?} isn't four characters")]
+    InvalidSectionMagic(String),
+
+    #[error("Section {0} has no data, and isn't one that can be rebuilt from the messages")]
+    MissingSectionData(String),
+
+    #[error("Invalid hex \"{0}\". Expected an even number of hex digits")]
+    InvalidHex(String),
+
+    #[error("Message attributes are {found} bytes long, but the file's attributes are {expected} bytes")]
+    InconsistentAttributeLength { expected: usize, found: usize },
+
+    #[error("Invalid escape sequence at byte {offset}: {reason}")]
+    InvalidEscapeSequence { offset: usize, reason: &'static str },
+
+    #[error("Too many sections for an MSBT")]
+    TooManySections,
+
+    #[error("Message {index}: {source}")]
+    InMessage { index: usize, source: Box<MsbtError> },
+}
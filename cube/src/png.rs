@@ -0,0 +1,141 @@
+//! Converting decoded images to and from PNG. Only built with the `png` feature, since it
+//! pulls in the image crate, which programs that only need raw pixels can do without.
+
+use crate::{
+    bti::{BtiImage, TextureFormat},
+    gci::GciImage,
+};
+use image::{GrayAlphaImage, GrayImage, ImageError, ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, io::Cursor, str::FromStr};
+use thiserror::Error;
+
+/// How textures in the intensity formats, I4, I8, IA4, and IA8, are written as PNGs. They
+/// only have a brightness and maybe an alpha for each pixel, which RGBA hides.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IntensityPng {
+    /// RGBA like every other format, with I4 and I8's intensity in all four channels
+    #[default]
+    Rgba,
+    /// Grayscale, with an alpha channel for IA4 and IA8
+    Gray,
+    /// I4 and I8 as white with their intensity as its alpha, for textures games use as
+    /// masks. IA4 and IA8 are written the same as with [`IntensityPng::Gray`].
+    Alpha,
+}
+
+impl IntensityPng {
+    /// Turns the pixels of a PNG written this way for a texture in `format` back into the
+    /// colors the texture decodes to, so they encode to the same texture
+    pub fn restore(self, format: TextureFormat, pixels: &mut [[u8; 4]]) {
+        match (self, format) {
+            (IntensityPng::Gray, TextureFormat::I4 | TextureFormat::I8) => {
+                pixels.iter_mut().for_each(|pixel| pixel[3] = pixel[0]);
+            }
+            (IntensityPng::Alpha, TextureFormat::I4 | TextureFormat::I8) => {
+                pixels.iter_mut().for_each(|pixel| *pixel = [pixel[3]; 4]);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Display for IntensityPng {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IntensityPng::Rgba => write!(f, "rgba"),
+            IntensityPng::Gray => write!(f, "gray"),
+            IntensityPng::Alpha => write!(f, "alpha"),
+        }
+    }
+}
+
+impl FromStr for IntensityPng {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "rgba" => Ok(IntensityPng::Rgba),
+            "gray" | "grey" | "grayscale" => Ok(IntensityPng::Gray),
+            "alpha" => Ok(IntensityPng::Alpha),
+            _ => Err(format!(
+                "Invalid intensity PNG mode '{s}'. Expected 'rgba', 'gray', or 'alpha'"
+            )),
+        }
+    }
+}
+
+/// Encodes 8-bit RGBA pixels, given row by row, as a PNG
+pub fn encode_png(width: u32, height: u32, rgba: Vec<u8>) -> Result<Vec<u8>, PngError> {
+    let len = rgba.len();
+    let image = RgbaImage::from_vec(width, height, rgba).ok_or(PngError::WrongImageSize { width, height, len })?;
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+/// Decodes a PNG of any color type into its width, height, and 8-bit RGBA pixels row by row
+pub fn decode_png(png: &[u8]) -> Result<(u32, u32, Vec<[u8; 4]>), PngError> {
+    let image = image::load_from_memory_with_format(png, ImageFormat::Png)?.into_rgba8();
+    let (width, height) = image.dimensions();
+    Ok((width, height, image.pixels().map(|pixel| pixel.0).collect()))
+}
+
+impl BtiImage {
+    /// The decoded image as a PNG
+    pub fn to_png(&self) -> Result<Vec<u8>, PngError> {
+        encode_png(self.width, self.height, self.pixels().flatten().copied().collect())
+    }
+
+    /// Like [`BtiImage::to_png`], with textures in the intensity formats written the way
+    /// `intensity` says. [`IntensityPng::restore`] undoes it after decoding the PNG.
+    pub fn to_png_with(&self, intensity: IntensityPng) -> Result<Vec<u8>, PngError> {
+        let (width, height) = (self.width, self.height);
+        let wrong_size = |len| PngError::WrongImageSize { width, height, len };
+        let format = TextureFormat::from_id(self.header.format);
+        let mut png = Cursor::new(Vec::new());
+        match (intensity, format) {
+            (IntensityPng::Gray, Some(TextureFormat::I4 | TextureFormat::I8)) => {
+                let pixels: Vec<u8> = self.pixels().map(|pixel| pixel[0]).collect();
+                let len = pixels.len();
+                GrayImage::from_vec(width, height, pixels)
+                    .ok_or_else(|| wrong_size(len))?
+                    .write_to(&mut png, ImageFormat::Png)?;
+            }
+            (IntensityPng::Gray | IntensityPng::Alpha, Some(TextureFormat::IA4 | TextureFormat::IA8))
+            | (IntensityPng::Alpha, Some(TextureFormat::I4 | TextureFormat::I8)) => {
+                // Only I4 and I8 get here without their own alpha
+                let white = matches!(format, Some(TextureFormat::I4 | TextureFormat::I8));
+                let pixels: Vec<u8> = self
+                    .pixels()
+                    .flat_map(|pixel| match white {
+                        true => [0xFF, pixel[0]],
+                        false => [pixel[0], pixel[3]],
+                    })
+                    .collect();
+                let len = pixels.len();
+                GrayAlphaImage::from_vec(width, height, pixels)
+                    .ok_or_else(|| wrong_size(len))?
+                    .write_to(&mut png, ImageFormat::Png)?;
+            }
+            _ => return self.to_png(),
+        }
+        Ok(png.into_inner())
+    }
+}
+
+impl GciImage {
+    /// The banner or icon as a PNG
+    pub fn to_png(&self) -> Result<Vec<u8>, PngError> {
+        encode_png(self.width as u32, self.height as u32, self.pixels.concat())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PngError {
+    #[error(transparent)]
+    Image(#[from] ImageError),
+
+    #[error("Image is {width}x{height} but has {len} bytes of pixels")]
+    WrongImageSize { width: u32, height: u32, len: usize },
+}
@@ -0,0 +1,187 @@
+use crate::bti::BtiImage;
+
+type Color = [u8; 4];
+
+const BACKGROUND: Color = [0x20, 0x20, 0x20, 0xFF];
+const TEXT_COLOR: Color = [0xFF, 0xFF, 0xFF, 0xFF];
+const PADDING: u32 = 4;
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+const LABEL_HEIGHT: u32 = GLYPH_HEIGHT + PADDING;
+
+/// A grid of labeled thumbnails, useful for visually browsing the textures
+/// inside an archive without opening each one individually.
+pub struct ContactSheet {
+    thumb_size: u32,
+    thumbnails: Vec<Thumbnail>,
+}
+
+struct Thumbnail {
+    name: String,
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl ContactSheet {
+    /// `thumb_size` is the maximum width and height of each thumbnail in pixels.
+    pub fn new(thumb_size: u32) -> Self {
+        ContactSheet {
+            thumb_size: thumb_size.max(1),
+            thumbnails: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.thumbnails.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.thumbnails.is_empty()
+    }
+
+    pub fn add_bti(&mut self, name: impl Into<String>, image: &BtiImage) {
+        self.add_rgba(name, image.width, image.height, image.pixels().copied());
+    }
+
+    /// Adds an image from row-major RGBA pixels. The image is downscaled immediately
+    /// so large textures aren't kept around in full.
+    pub fn add_rgba(
+        &mut self,
+        name: impl Into<String>,
+        width: u32,
+        height: u32,
+        pixels: impl IntoIterator<Item = Color>,
+    ) {
+        let pixels: Vec<Color> = pixels.into_iter().collect();
+        if width == 0 || height == 0 || pixels.len() < (width * height) as usize {
+            return;
+        }
+
+        // Nearest neighbor scaling that preserves aspect ratio. Small images aren't upscaled.
+        let scale = (self.thumb_size as f32 / width.max(height) as f32).min(1.0);
+        let thumb_width = ((width as f32 * scale) as u32).max(1);
+        let thumb_height = ((height as f32 * scale) as u32).max(1);
+        let mut thumb_pixels = Vec::with_capacity((thumb_width * thumb_height) as usize);
+        for y in 0..thumb_height {
+            let src_y = y * height / thumb_height;
+            for x in 0..thumb_width {
+                let src_x = x * width / thumb_width;
+                thumb_pixels.push(pixels[(src_x + src_y * width) as usize]);
+            }
+        }
+
+        self.thumbnails.push(Thumbnail {
+            name: name.into(),
+            width: thumb_width,
+            height: thumb_height,
+            pixels: thumb_pixels,
+        });
+    }
+
+    /// Lays out all thumbnails in a roughly square grid and returns the sheet's
+    /// width, height, and row-major RGBA bytes.
+    pub fn render(&self) -> (u32, u32, Vec<u8>) {
+        let columns = (self.thumbnails.len() as f32).sqrt().ceil().max(1.0) as u32;
+        let rows = (self.thumbnails.len() as u32).div_ceil(columns).max(1);
+        let cell_width = self.thumb_size + PADDING * 2;
+        let cell_height = self.thumb_size + PADDING * 2 + LABEL_HEIGHT;
+        let width = columns * cell_width;
+        let height = rows * cell_height;
+
+        let mut canvas = vec![BACKGROUND; (width * height) as usize];
+        for (i, thumb) in self.thumbnails.iter().enumerate() {
+            let cell_x = (i as u32 % columns) * cell_width + PADDING;
+            let cell_y = (i as u32 / columns) * cell_height + PADDING;
+
+            // Center the thumbnail in its cell and blend it over the background
+            let offset_x = cell_x + (self.thumb_size - thumb.width) / 2;
+            let offset_y = cell_y + (self.thumb_size - thumb.height) / 2;
+            for y in 0..thumb.height {
+                for x in 0..thumb.width {
+                    let dest = &mut canvas[(offset_x + x + (offset_y + y) * width) as usize];
+                    *dest = blend(thumb.pixels[(x + y * thumb.width) as usize], *dest);
+                }
+            }
+
+            let max_chars = (self.thumb_size / (GLYPH_WIDTH + 1)) as usize;
+            draw_text(
+                &mut canvas,
+                width,
+                cell_x,
+                cell_y + self.thumb_size + PADDING,
+                thumb.name.chars().take(max_chars),
+            );
+        }
+
+        (width, height, canvas.into_iter().flatten().collect())
+    }
+}
+
+fn blend(src: Color, dest: Color) -> Color {
+    let alpha = src[3] as u32;
+    let mix = |s: u8, d: u8| ((s as u32 * alpha + d as u32 * (255 - alpha)) / 255) as u8;
+    [mix(src[0], dest[0]), mix(src[1], dest[1]), mix(src[2], dest[2]), 0xFF]
+}
+
+fn draw_text(canvas: &mut [Color], canvas_width: u32, x: u32, y: u32, text: impl Iterator<Item = char>) {
+    for (i, c) in text.enumerate() {
+        let glyph = glyph(c);
+        let glyph_x = x + i as u32 * (GLYPH_WIDTH + 1);
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> col) != 0 {
+                    canvas[(glyph_x + col + (y + row as u32) * canvas_width) as usize] = TEXT_COLOR;
+                }
+            }
+        }
+    }
+}
+
+/// Minimal 3x5 bitmap font. Lowercase letters are drawn as uppercase, and characters
+/// without a glyph are left blank.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT as usize] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        _ => [0; GLYPH_HEIGHT as usize],
+    }
+}
@@ -1,49 +1,410 @@
 use std::{
+    borrow::Cow,
     cmp::min,
-    collections::VecDeque,
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
     fmt::Display,
     fs::{metadata, read, read_dir},
+    io::{self, Write},
     path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
 };
 
-use itertools::Itertools;
+use encoding_rs::SHIFT_JIS;
+use log::debug;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    util::{pad_to, padded_index_to, read_str_until_null, read_u16, read_u32},
+    util::{padded_index_to, BinReader, OutOfBounds, Warnings},
     virtual_fs::VirtualFile,
-    Decode, Encode,
+    CodecError, Decode, Encode, FromBytes,
 };
 
+/// Start offsets of file data are aligned to this many bytes unless told otherwise,
+/// matching the archives shipped with games
+pub const DEFAULT_ALIGNMENT: u32 = 32;
+
+/// Byte order of an archive's header and tables. Games use big-endian archives, but some
+/// tools and Wii virtual console dumps byte-swap them, which turns the magic into "CRAR".
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ByteOrder {
+    #[default]
+    Big,
+    Little,
+}
+
+impl ByteOrder {
+    fn magic(self) -> &'static [u8; 4] {
+        match self {
+            ByteOrder::Big => b"RARC",
+            ByteOrder::Little => b"CRAR",
+        }
+    }
+
+    pub(crate) fn read_u16(self, data: &[u8], offset: u32) -> u16 {
+        let bytes = data[offset as usize..offset as usize + 2].try_into().unwrap();
+        match self {
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+        }
+    }
+
+    pub(crate) fn read_u32(self, data: &[u8], offset: u32) -> u32 {
+        let bytes = data[offset as usize..offset as usize + 4].try_into().unwrap();
+        match self {
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+        }
+    }
+
+    pub(crate) fn u16_bytes(self, value: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::Big => value.to_be_bytes(),
+            ByteOrder::Little => value.to_le_bytes(),
+        }
+    }
+
+    pub(crate) fn u32_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::Big => value.to_be_bytes(),
+            ByteOrder::Little => value.to_le_bytes(),
+        }
+    }
+}
+
+impl Display for ByteOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ByteOrder::Big => write!(f, "big"),
+            ByteOrder::Little => write!(f, "little"),
+        }
+    }
+}
+
+impl FromStr for ByteOrder {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "big" | "be" => Ok(ByteOrder::Big),
+            "little" | "le" => Ok(ByteOrder::Little),
+            _ => Err(format!("Invalid byte order '{s}'. Expected 'big' or 'little'")),
+        }
+    }
+}
+
+/// Where the game puts a file's data when it mounts the archive, kept in each file
+/// entry's type flags
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoadLocation {
+    /// Main RAM, where almost every file goes
+    #[default]
+    Mram,
+    /// Auxiliary RAM, which some games use for sound data
+    Aram,
+    /// Left on the disc and read when it's needed
+    Dvd,
+}
+
+impl LoadLocation {
+    fn flag(self) -> u8 {
+        match self {
+            LoadLocation::Mram => 0x10,
+            LoadLocation::Aram => 0x20,
+            LoadLocation::Dvd => 0x40,
+        }
+    }
+
+    fn from_flags(flags: u8) -> Option<LoadLocation> {
+        [LoadLocation::Mram, LoadLocation::Aram, LoadLocation::Dvd]
+            .into_iter()
+            .find(|location| flags & location.flag() != 0)
+    }
+}
+
+impl Display for LoadLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadLocation::Mram => write!(f, "mram"),
+            LoadLocation::Aram => write!(f, "aram"),
+            LoadLocation::Dvd => write!(f, "dvd"),
+        }
+    }
+}
+
+impl FromStr for LoadLocation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mram" => Ok(LoadLocation::Mram),
+            "aram" => Ok(LoadLocation::Aram),
+            "dvd" => Ok(LoadLocation::Dvd),
+            _ => Err(format!(
+                "Invalid load location '{s}'. Expected 'mram', 'aram', or 'dvd'"
+            )),
+        }
+    }
+}
+
+/// Where every file in an archive is loaded: a default, and the files that differ from
+/// it by their path in the archive
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoadLocations {
+    #[serde(default)]
+    pub default: LoadLocation,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<PathBuf, LoadLocation>,
+}
+
+impl LoadLocations {
+    pub fn get(&self, path: impl AsRef<Path>) -> LoadLocation {
+        self.files.get(path.as_ref()).copied().unwrap_or(self.default)
+    }
+
+    /// Whether every file goes in main RAM, as in archives packed without any overrides
+    pub fn is_default(&self) -> bool {
+        self.files
+            .values()
+            .chain([&self.default])
+            .all(|&location| location == LoadLocation::Mram)
+    }
+}
+
+/// What [`Rarc::metadata`] and [`Rarc::read_dir`] know about a file or folder in an
+/// archive, including changes made since parsing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RarcMetadata {
+    pub is_dir: bool,
+    /// Size of a file's data, or 0 for folders
+    pub size: u32,
+    /// Where a file is loaded. None for folders, and for files added or replaced since
+    /// parsing, which go wherever most files do once written.
+    pub load_location: Option<LoadLocation>,
+}
+
+impl RarcMetadata {
+    const DIR: RarcMetadata = RarcMetadata {
+        is_dir: true,
+        size: 0,
+        load_location: None,
+    };
+}
+
+/// A file or folder listed by [`Rarc::read_dir`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RarcDirEntry {
+    pub name: String,
+    /// Path inside the archive, which [`Rarc::open`] and [`Rarc::read_dir`] take
+    pub path: PathBuf,
+    pub metadata: RarcMetadata,
+}
+
+impl RarcDirEntry {
+    pub fn is_dir(&self) -> bool {
+        self.metadata.is_dir
+    }
+
+    pub fn size(&self) -> u32 {
+        self.metadata.size
+    }
+}
+
 pub struct Rarc<'a> {
     data: &'a [u8],
+    /// Byte order the archive was parsed with, which it's also written back out in
+    pub byte_order: ByteOrder,
     pub header: RarcHeader,
     pub info_block: RarcInfoBlock,
     pub nodes: Vec<RarcNode>,
     pub files: Vec<RarcFile>,
+    /// Whether [`Rarc::write`] puts [`Rarc::trailing_data`] back after the last file, for
+    /// repacking an archive byte for byte, instead of padding it like a freshly packed one
+    pub keep_trailing_data: bool,
+
+    /// Files added, replaced (Some), or removed (None) since parsing. The header, node,
+    /// and file lists above always describe the archive as originally parsed.
+    changes: BTreeMap<PathBuf, Option<Vec<u8>>>,
+    /// Indices of damaged entries in `files` that were passed over by a permissive parse
+    skipped: BTreeSet<usize>,
+    /// What's in each folder with the changes made, for [`Rarc::read_dir`] and
+    /// [`Rarc::metadata`]. Worked out the first time it's needed after each change.
+    listing: OnceLock<HashMap<PathBuf, Vec<RarcDirEntry>>>,
+}
+
+impl<'a> FromBytes<'a> for Rarc<'a> {
+    fn from_bytes(data: &'a [u8]) -> Result<Self, CodecError> {
+        Ok(Rarc::parse(data)?)
+    }
 }
 
 impl<'a> Decode for Rarc<'a> {
     type Out = Vec<VirtualFile>;
-    fn decode(&self) -> Self::Out {
-        self.files()
+    fn decode(&self) -> Result<Self::Out, CodecError> {
+        Ok(self
+            .files()
             .map(|(path, bytes)| VirtualFile {
                 path,
                 bytes: bytes.to_vec(),
             })
-            .collect()
+            .collect())
     }
 }
 
 impl<'a> Encode for Rarc<'a> {
-    type Error = RarcError;
-    fn encode<P: AsRef<Path>>(root: P) -> Result<VirtualFile, Self::Error> {
-        let root = root.as_ref();
-        if !metadata(root)?.is_dir() {
-            return Err(RarcError::NotADirError);
+    fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        Ok(self.write()?)
+    }
+}
+
+/// Adds what's in `dir` and every folder inside it to `listing`
+fn list_dir(
+    dir: &DirTree,
+    path: PathBuf,
+    load_locations: &HashMap<PathBuf, LoadLocation>,
+    listing: &mut HashMap<PathBuf, Vec<RarcDirEntry>>,
+) {
+    let mut entries = Vec::with_capacity(dir.entries.len());
+    for (name, entry) in dir.entries.iter() {
+        let path = path.join(name);
+        let metadata = match entry {
+            TreeEntry::Dir(subdir) => {
+                list_dir(subdir, path.clone(), load_locations, listing);
+                RarcMetadata::DIR
+            }
+            TreeEntry::File(_) | TreeEntry::DiskFile { .. } => RarcMetadata {
+                is_dir: false,
+                size: entry.size(),
+                load_location: load_locations.get(&path).copied(),
+            },
+        };
+        entries.push(RarcDirEntry {
+            name: name.clone(),
+            path,
+            metadata,
+        });
+    }
+    listing.insert(path, entries);
+}
+
+/// In-memory directory structure used to build RARC archives, either from a
+/// directory on disk or from a parsed archive that's been modified.
+#[derive(Debug)]
+struct DirTree<'a> {
+    name: String,
+    entries: BTreeMap<String, TreeEntry<'a>>,
+}
+
+#[derive(Debug)]
+enum TreeEntry<'a> {
+    File(Cow<'a, [u8]>),
+    /// A file that's only read from disk once its data is written out
+    DiskFile {
+        path: PathBuf,
+        size: u32,
+    },
+    Dir(DirTree<'a>),
+}
+
+impl TreeEntry<'_> {
+    fn size(&self) -> u32 {
+        match self {
+            TreeEntry::File(data) => data.len() as u32,
+            TreeEntry::DiskFile { size, .. } => *size,
+            TreeEntry::Dir(_) => 16, // always 16 for folders
+        }
+    }
+}
+
+impl<'a> DirTree<'a> {
+    fn new(name: String) -> Self {
+        DirTree {
+            name,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Recursively reads a directory from disk. The result only depends on the names
+    /// and contents of the files inside, never on the order the OS lists them in.
+    fn read(dir: &Path) -> Result<DirTree<'static>, RarcError> {
+        let name = dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let mut tree = DirTree::new(name);
+        for dir_entry in read_dir(dir)? {
+            let dir_entry = dir_entry?;
+            let file_name = dir_entry.file_name().to_string_lossy().into_owned();
+            let entry = if dir_entry.file_type()?.is_dir() {
+                TreeEntry::Dir(DirTree::read(&dir_entry.path())?)
+            } else {
+                TreeEntry::DiskFile {
+                    path: dir_entry.path(),
+                    size: u32::try_from(dir_entry.metadata()?.len())
+                        .map_err(|_| RarcError::FileTooLarge(dir_entry.path()))?,
+                }
+            };
+            // Names that aren't valid UTF-8 can collide after conversion, in which case
+            // which file ends up in the archive would depend on listing order.
+            if tree.entries.insert(file_name, entry).is_some() {
+                return Err(RarcError::DuplicateNameError(dir_entry.path()));
+            }
+        }
+        Ok(tree)
+    }
+
+    /// Inserts a file at the given path relative to this directory, creating
+    /// intermediate directories as needed. Replaces any existing entry.
+    fn insert(&mut self, path: &Path, data: Cow<'a, [u8]>) {
+        let mut components: Vec<String> = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let Some(file_name) = components.pop() else {
+            return;
+        };
+        let mut dir = self;
+        for component in components {
+            let entry = dir
+                .entries
+                .entry(component.clone())
+                .or_insert_with(|| TreeEntry::Dir(DirTree::new(component.clone())));
+            if let TreeEntry::File(_) = entry {
+                *entry = TreeEntry::Dir(DirTree::new(component));
+            }
+            let TreeEntry::Dir(subdir) = entry else { unreachable!() };
+            dir = subdir;
         }
+        dir.entries.insert(file_name, TreeEntry::File(data));
+    }
 
+    fn remove(&mut self, path: &Path) {
+        let mut components = path.components().peekable();
+        let mut dir = self;
+        while let Some(component) = components.next() {
+            let name = component.as_os_str().to_string_lossy();
+            if components.peek().is_none() {
+                dir.entries.remove(name.as_ref());
+                return;
+            }
+            match dir.entries.get_mut(name.as_ref()) {
+                Some(TreeEntry::Dir(subdir)) => dir = subdir,
+                _ => return,
+            }
+        }
+    }
+
+    /// Serializes this tree as a complete RARC archive with this directory as the root node.
+    /// Each file's data starts on a multiple of `alignment` bytes. `trailing_data`, if given,
+    /// takes the place of the padding after the last file.
+    fn write_to<W: Write>(
+        &self,
+        out: &mut W,
+        alignment: u32,
+        byte_order: ByteOrder,
+        load_locations: &LoadLocations,
+        trailing_data: Option<&[u8]>,
+    ) -> Result<(), RarcError> {
+        let alignment = alignment.max(1);
         let mut nodes = vec![RarcNode {
-            node_name: String::from("ROOT"),
+            node_name: *b"ROOT",
             name_offset: 5, // String table always starts with "." and ".." plus their null terminators, then the root node name
             num_files: 0,
             first_file_index: 0,
@@ -51,116 +412,145 @@ impl<'a> Encode for Rarc<'a> {
         let mut file_entries = vec![];
         let mut non_dir_file_entries = 0;
         let mut string_table = vec![];
+        // File data is only written once all the tables are, so just the entries are kept here
         let mut file_data = vec![];
+        let mut file_data_length = 0u32;
+        // Where the last file's data ends, before it's padded
+        let mut file_data_end = 0u32;
+        // How much of the file data is loaded into main RAM and ARAM, and where the last file
+        // goes and starts, whose padding is swapped for the trailing data
+        let mut mram_data_length = 0u32;
+        let mut aram_data_length = 0u32;
+        let mut last_file = None;
 
         // Initialize the string table
         string_table.extend(b".\0");
         string_table.extend(b"..\0");
-        string_table.extend(root.file_name().unwrap().to_string_lossy().as_bytes());
+        string_table.extend(encode_name(&self.name)?);
         string_table.push(b'\0');
 
+        // Directories are laid out breadth first, each with its path, the index of its node,
+        // and its parent's node
         let mut dir_queue = VecDeque::new();
-        dir_queue.push_back(root.to_owned());
+        dir_queue.push_back((self, PathBuf::new(), 0usize, u32::MAX));
 
-        while !dir_queue.is_empty() {
-            let dir = dir_queue.pop_front().unwrap();
+        while let Some((dir, dir_path, node_idx, parent_node_idx)) = dir_queue.pop_front() {
             let mut num_files = 2; // for . and .. added at the end
 
-            for dir_entry in read_dir(&dir)?
-                .filter_map(|entry| entry.ok())
-                .sorted_by_key(|entry| entry.file_name())
-            {
-                if dir_entry.file_type()?.is_dir() {
-                    dir_queue.push_back(dir_entry.path());
-                    let file_name = dir_entry.file_name().to_string_lossy().into_owned();
-                    file_entries.push(RarcFile {
-                        name: file_name.clone(),
-                        index: 0xFFFF,
-                        name_offset: string_table.len() as u16,
-                        data_size: 16, // always 16 for folders
-                        data_offset_or_node_index: nodes.len() as u32,
-                        file_type_flags: 0x0200, // Always this value for folders
-                    });
-                    num_files += 1;
-
-                    nodes.push(RarcNode {
-                        node_name: file_name[..4].to_ascii_uppercase(),
-                        name_offset: string_table.len() as u32,
-                        num_files: 0,        // Will be updated later
-                        first_file_index: 0, // Will be updated later
-                    });
-
-                    string_table.extend(file_name.as_bytes());
-                    string_table.push(b'\0');
-                } else {
-                    let data = read(dir_entry.path())?;
-                    let file_name = dir_entry.file_name().to_string_lossy().into_owned();
-                    file_entries.push(RarcFile {
-                        name: file_name.clone(),
-                        index: non_dir_file_entries,
-                        name_offset: string_table.len() as u16,
-                        data_size: data.len() as u32,
-                        data_offset_or_node_index: file_data.len() as u32,
-                        file_type_flags: 0x1100,
-                    });
-                    non_dir_file_entries += 1;
-                    string_table.extend(file_name.bytes());
-                    string_table.push(b'\0');
-                    file_data.extend(data.into_iter().map(|b| b.to_be()));
-                    num_files += 1;
+            for (file_name, entry) in dir.entries.iter() {
+                if file_name.is_empty() || file_name == "." || file_name == ".." {
+                    return Err(RarcError::InvalidNameError(file_name.clone()));
                 }
-            }
+                let encoded_name = encode_name(file_name)?;
+                // File entries only have room for 24 bit string table offsets
+                let name_offset = u32::try_from(string_table.len())
+                    .ok()
+                    .filter(|offset| *offset <= MAX_NAME_OFFSET)
+                    .ok_or(RarcError::StringTableOverflow)?;
+                match entry {
+                    TreeEntry::Dir(subdir) => {
+                        dir_queue.push_back((subdir, dir_path.join(file_name), nodes.len(), node_idx as u32));
+                        file_entries.push(RarcFile {
+                            name: file_name.clone(),
+                            index: 0xFFFF,
+                            name_offset,
+                            data_size: 16, // always 16 for folders
+                            data_offset_or_node_index: nodes.len() as u32,
+                            file_type_flags: DIR_FLAGS,
+                        });
 
-            let node_name = to_node_name(&dir, &root).unwrap();
-            let node_idx = nodes
-                .iter()
-                .find_position(|node| &node.node_name == &node_name)
-                .expect(&format!(
-                    "Expected to find a RarcNode named \"{node_name}\" while packing!"
-                ))
-                .0;
+                        nodes.push(RarcNode {
+                            node_name: to_node_name(&encoded_name),
+                            name_offset,
+                            num_files: 0,        // Will be updated later
+                            first_file_index: 0, // Will be updated later
+                        });
+                    }
+                    TreeEntry::File(_) | TreeEntry::DiskFile { .. } => {
+                        let load_location = load_locations.get(dir_path.join(file_name));
+                        file_entries.push(RarcFile {
+                            name: file_name.clone(),
+                            index: non_dir_file_entries,
+                            name_offset,
+                            data_size: entry.size(),
+                            data_offset_or_node_index: file_data_length,
+                            file_type_flags: FILE_FLAG | load_location.flag(),
+                        });
+                        let start = file_data_length;
+                        non_dir_file_entries = non_dir_file_entries.checked_add(1).ok_or(RarcError::TooManyFiles)?;
+                        file_data_end = file_data_length
+                            .checked_add(entry.size())
+                            .ok_or_else(|| RarcError::FileTooLarge(PathBuf::from(file_name)))?;
+                        file_data_length = (file_data_end as u64)
+                            .next_multiple_of(alignment as u64)
+                            .try_into()
+                            .map_err(|_| RarcError::FileTooLarge(PathBuf::from(file_name)))?;
+                        // Files loaded from the disc as they're needed aren't counted in either
+                        match load_location {
+                            LoadLocation::Mram => mram_data_length += file_data_length - start,
+                            LoadLocation::Aram => aram_data_length += file_data_length - start,
+                            LoadLocation::Dvd => {}
+                        }
+                        last_file = Some((load_location, start));
+                        file_data.push(entry);
+                    }
+                }
+                string_table.extend(encoded_name);
+                string_table.push(b'\0');
+                num_files += 1;
+            }
 
             // All directories contain . and .. files in the output archive
             file_entries.push(RarcFile {
                 name: ".".to_owned(),
-                index: file_entries.len() as u16,
+                index: 0xFFFF,
                 name_offset: 0,
                 data_size: 16,
                 data_offset_or_node_index: node_idx as u32,
-                file_type_flags: 0x0200,
+                file_type_flags: DIR_FLAGS,
             });
-            let parent_node_idx = dir
-                .parent()
-                .map(|parent| {
-                    let parent_node_name = to_node_name(&parent, &root)?;
-                    nodes.iter().find_position(|node| &node.node_name == &parent_node_name)
-                })
-                .flatten()
-                .map(|(idx, _)| idx as u32)
-                .unwrap_or(u32::MAX);
             file_entries.push(RarcFile {
                 name: "..".to_owned(),
-                index: file_entries.len() as u16,
+                index: 0xFFFF,
                 name_offset: 2,
                 data_size: 16,
                 data_offset_or_node_index: parent_node_idx,
-                file_type_flags: 0x0200, // Always this value for folders
+                file_type_flags: DIR_FLAGS,
             });
 
             // Update this Node's number of files and first file index
             let node = &mut nodes[node_idx];
-            node.num_files = num_files as u16;
+            node.num_files = u16::try_from(num_files).map_err(|_| RarcError::TooManyFiles)?;
             node.first_file_index = file_entries.len() as u32 - node.num_files as u32;
         }
 
+        if let Some(trailing_data) = trailing_data {
+            let padded_length = file_data_length;
+            file_data_length = u32::try_from(file_data_end as u64 + trailing_data.len() as u64)
+                .map_err(|_| RarcError::FileTooLarge(PathBuf::from(&self.name)))?;
+            let location_length = match last_file {
+                Some((LoadLocation::Mram, start)) => Some((&mut mram_data_length, start)),
+                Some((LoadLocation::Aram, start)) => Some((&mut aram_data_length, start)),
+                _ => None,
+            };
+            if let Some((length, start)) = location_length {
+                *length = *length - (padded_length - start) + (file_data_length - start);
+            }
+        }
+
         // Construct the final header and info block
         let node_list_offset = 0x20; // relative to start of info block
         let file_entries_list_offset = node_list_offset + (nodes.len() * 0x10) as u32;
         let string_table_offset = padded_index_to::<32>(file_entries_list_offset + (file_entries.len() * 0x14) as u32);
         let file_data_list_offset = padded_index_to::<32>(string_table_offset + string_table.len() as u32);
-        let final_file_length = file_data_list_offset + file_data.len() as u32 + 0x20;
+        let final_file_length = file_data_list_offset
+            .checked_add(file_data_length)
+            .and_then(|length| length.checked_add(0x20))
+            .ok_or(RarcError::ArchiveTooLarge)?;
         let header = RarcHeader {
-            file_data_length: file_data.len() as u32,
+            file_data_length,
+            mram_data_length,
+            aram_data_length,
             file_length: final_file_length,
             file_data_list_offset,
         };
@@ -168,7 +558,7 @@ impl<'a> Encode for Rarc<'a> {
             num_nodes: nodes.len() as u32,
             num_file_entries: file_entries.len() as u32,
             string_table_length: string_table.len() as u32,
-            num_files: file_entries.iter().filter(|entry| !entry.is_dir()).count() as u16,
+            num_files: non_dir_file_entries,
             node_list_offset,
             file_entries_list_offset,
             string_table_offset,
@@ -180,81 +570,170 @@ impl<'a> Encode for Rarc<'a> {
         // node list: num_nodes x 0x10
         // file entry list: num_file_entries x 0x14 + pad to 0x20
         // string table + pad to 0x20
-        // file data
+        // file data, each file padded to the alignment, or the last followed by the trailing data
 
-        let mut final_file_data = Vec::with_capacity(final_file_length as usize);
-        final_file_data.extend(header.write());
-        final_file_data.extend(info_block.write());
+        let file_entries_end = file_entries_list_offset + (file_entries.len() * 0x14) as u32;
+        let string_table_end = string_table_offset + string_table.len() as u32;
+        out.write_all(&header.write(byte_order))?;
+        out.write_all(&info_block.write(byte_order))?;
         for node in nodes {
-            final_file_data.extend(node.write(&string_table));
+            out.write_all(&node.write(&string_table, byte_order))?;
         }
         for file_entry in file_entries {
-            final_file_data.extend(file_entry.write());
+            out.write_all(&file_entry.write(byte_order))?;
+        }
+        out.write_all(&vec![0; (string_table_offset - file_entries_end) as usize])?;
+        out.write_all(&string_table)?;
+        out.write_all(&vec![0; (file_data_list_offset - string_table_end) as usize])?;
+        let num_file_data = file_data.len();
+        for (i, entry) in file_data.into_iter().enumerate() {
+            let data = match entry {
+                TreeEntry::File(data) => Cow::Borrowed(data.as_ref()),
+                TreeEntry::DiskFile { path, size } => {
+                    let data = read(path)?;
+                    if data.len() != *size as usize {
+                        return Err(RarcError::IOError(io::Error::other(format!(
+                            "{path:?} changed while being packed"
+                        ))));
+                    }
+                    Cow::Owned(data)
+                }
+                TreeEntry::Dir(_) => unreachable!("Only files have data"),
+            };
+            out.write_all(&data)?;
+            if trailing_data.is_none() || i + 1 < num_file_data {
+                let padding = (data.len() as u32).next_multiple_of(alignment) as usize - data.len();
+                out.write_all(&vec![0; padding])?;
+            }
+        }
+        if let Some(trailing_data) = trailing_data {
+            out.write_all(trailing_data)?;
         }
-        pad_to::<32>(&mut final_file_data);
-        final_file_data.extend(string_table);
-        pad_to::<32>(&mut final_file_data);
-        final_file_data.extend(file_data);
+        Ok(())
+    }
 
-        Ok(VirtualFile {
-            path: root.with_extension("arc"),
-            bytes: final_file_data,
-        })
+    fn build(&self, alignment: u32) -> Result<Vec<u8>, RarcError> {
+        let mut out = Vec::new();
+        self.write_to(&mut out, alignment, ByteOrder::Big, &LoadLocations::default(), None)?;
+        Ok(out)
     }
 }
 
 impl<'a> Rarc<'a> {
+    /// Parses an archive in either byte order
     pub fn parse(data: &'a [u8]) -> Result<Rarc<'a>, RarcError> {
-        if &data[0..4] != b"RARC" {
-            return Err(RarcError::MagicError(0));
+        Rarc::parse_with(data, &mut Warnings::strict())
+    }
+
+    /// Like [`Rarc::parse`], but salvages what it can from a truncated or damaged archive.
+    /// Tables that run past the end are cut short, and entries that point outside the
+    /// archive are skipped. Returns a description of each problem alongside the archive.
+    pub fn parse_permissive(data: &'a [u8]) -> Result<(Rarc<'a>, Vec<String>), RarcError> {
+        let mut warnings = Vec::new();
+        let rarc = Rarc::parse_with(data, &mut Warnings::permissive(&mut warnings))?;
+        Ok((rarc, warnings))
+    }
+
+    fn parse_with(data: &'a [u8], warnings: &mut Warnings) -> Result<Rarc<'a>, RarcError> {
+        let byte_order = match data.get(0..4) {
+            Some(b"RARC") => ByteOrder::Big,
+            Some(b"CRAR") => ByteOrder::Little,
+            _ => return Err(RarcError::MagicError(0)),
+        };
+        if data.len() < 0x40 {
+            return Err(RarcError::CorruptedError("it's too short to hold a header".into()));
         }
+        let mut reader = BinReader::at(data, 0x4).with_byte_order(byte_order);
 
-        let file_length = read_u32(data, 0x4);
+        let file_length = reader.u32()?;
         if file_length != data.len() as u32 {
-            return Err(RarcError::MetadataError(file_length));
+            let error = RarcError::CorruptedError(format!(
+                "the header says it's {file_length} bytes long, but it's {} bytes",
+                data.len()
+            ));
+            warnings.recover(error, "Reading the bytes that are there")?;
         }
 
-        let header_length = read_u32(data, 0x8);
+        let header_length = reader.u32()?;
         if header_length != 0x20 {
             return Err(RarcError::MagicError(1));
         }
 
-        let file_data_list_offset = read_u32(data, 0xC) + header_length;
-        let unk1 = read_u32(data, 0x1C);
+        // Offsets that overflow end up past the end of the archive, where they're caught below
+        let file_data_list_offset = reader.u32()?.saturating_add(header_length);
+        let file_data_length = reader.u32()?;
+        let mram_data_length = reader.u32()?;
+        let aram_data_length = reader.u32()?;
+        let unk1 = reader.u32()?;
         if unk1 != 0 {
             return Err(RarcError::MagicError(2));
         }
 
-        let file_data_length = read_u32(data, 0x10);
+        let mut num_nodes = reader.u32()?;
+        let node_list_offset = reader.u32()?.saturating_add(header_length);
+        let mut num_file_entries = reader.u32()?;
+        let file_entries_list_offset = reader.u32()?.saturating_add(header_length);
+        let mut string_table_length = reader.u32()?;
+        let string_table_offset = reader.u32()?.saturating_add(header_length);
+        let num_files = reader.u16()?;
 
-        let num_nodes = read_u32(data, header_length);
-        let node_list_offset = read_u32(data, header_length + 0x4) + header_length;
-        let num_file_entries = read_u32(data, header_length + 0x8);
-        let file_entries_list_offset = read_u32(data, header_length + 0x0C) + header_length;
-        let string_table_length = read_u32(data, header_length + 0x10);
-        let string_table_offset = read_u32(data, header_length + 0x14) + header_length;
-        let num_files = read_u16(data, header_length + 0x18);
+        let fits = |offset: u32, entry_size: usize| (data.len().saturating_sub(offset as usize) / entry_size) as u32;
+        for (count, offset, entry_size, table) in [
+            (&mut num_nodes, node_list_offset, 0x10, "node"),
+            (&mut num_file_entries, file_entries_list_offset, 0x14, "file entry"),
+        ] {
+            let fits = fits(offset, entry_size);
+            if *count > fits {
+                let error = RarcError::CorruptedError(format!(
+                    "the {table} table holds {count} entries, but only {fits} fit in the archive"
+                ));
+                warnings.recover(error, "Reading the ones that fit")?;
+                *count = fits;
+            }
+        }
+        if num_nodes == 0 {
+            return Err(RarcError::CorruptedError("there's no root folder".into()));
+        }
+        let string_table_fits = fits(string_table_offset, 1);
+        if string_table_length > string_table_fits {
+            let error = RarcError::CorruptedError(format!(
+                "the string table is {string_table_length} bytes, but only {string_table_fits} fit in the archive"
+            ));
+            warnings.recover(error, "Reading the names that are there")?;
+            string_table_length = string_table_fits;
+        }
 
+        reader.seek(node_list_offset as usize);
         let mut nodes = Vec::with_capacity(num_nodes as usize);
-        for node_idx in 0..num_nodes {
-            nodes.push(RarcNode::read(data, node_list_offset + node_idx * 0x10));
+        for _ in 0..num_nodes {
+            nodes.push(RarcNode::read(&mut reader)?);
         }
 
         let mut files = Vec::with_capacity(num_file_entries as usize);
+        let mut skipped = BTreeSet::new();
         for file_idx in 0..num_file_entries {
-            files.push(RarcFile::read(
-                data,
-                file_entries_list_offset + file_idx * 0x14,
-                string_table_offset,
-            ));
+            reader.seek(file_entries_list_offset as usize + file_idx as usize * 0x14);
+            let file = RarcFile::read(data, &mut reader, string_table_offset, string_table_length);
+            match file {
+                Ok(file) => files.push(file),
+                Err(error) => {
+                    warnings.recover(error, &format!("Skipping file entry {file_idx}"))?;
+                    // Keep its place so nodes still find their entries at the right indices
+                    files.push(RarcFile::default());
+                    skipped.insert(file_idx as usize);
+                }
+            }
         }
 
-        Ok(Rarc {
+        let mut rarc = Rarc {
             data,
+            byte_order,
             header: RarcHeader {
                 file_length,
                 file_data_list_offset,
                 file_data_length,
+                mram_data_length,
+                aram_data_length,
             },
             info_block: RarcInfoBlock {
                 num_nodes,
@@ -267,27 +746,359 @@ impl<'a> Rarc<'a> {
             },
             nodes,
             files,
+            keep_trailing_data: false,
+            changes: BTreeMap::new(),
+            skipped,
+            listing: OnceLock::new(),
+        };
+        rarc.check_tree(warnings)?;
+        debug!(
+            "Read RARC with {} nodes and {} file entries",
+            rarc.info_block.num_nodes, rarc.info_block.num_file_entries
+        );
+        Ok(rarc)
+    }
+
+    /// Makes sure everything reachable from the root folder points somewhere real, so
+    /// looking files up afterwards can't go out of bounds or loop forever
+    fn check_tree(&mut self, warnings: &mut Warnings) -> Result<(), RarcError> {
+        let mut visited = vec![false; self.nodes.len()];
+        visited[0] = true;
+        let mut queue = VecDeque::from([0]);
+        while let Some(node_idx) = queue.pop_front() {
+            let node = &mut self.nodes[node_idx];
+            let end = node.first_file_index as usize + node.num_files as usize;
+            if end > self.files.len() {
+                let error = RarcError::CorruptedError(format!(
+                    "folder {node_idx} lists entries up to {end}, but there are only {}",
+                    self.files.len()
+                ));
+                warnings.recover(error, "Reading the entries that are there")?;
+                node.first_file_index = min(node.first_file_index, self.files.len() as u32);
+                node.num_files = (self.files.len() - node.first_file_index as usize) as u16;
+            }
+
+            let first_file_index = node.first_file_index as usize;
+            for file_idx in first_file_index..first_file_index + node.num_files as usize {
+                let file = &self.files[file_idx];
+                if self.skipped.contains(&file_idx) || [".", ".."].contains(&&file.name[..]) {
+                    continue;
+                }
+                let problem = if file.is_dir() {
+                    let sub_node = file.data_offset_or_node_index as usize;
+                    match visited.get(sub_node) {
+                        Some(false) => {
+                            visited[sub_node] = true;
+                            queue.push_back(sub_node);
+                            None
+                        }
+                        Some(true) => Some(format!("folder {:?} leads back to a folder above it", file.name)),
+                        None => Some(format!(
+                            "folder {:?} is node {sub_node}, but there are only {}",
+                            file.name,
+                            self.nodes.len()
+                        )),
+                    }
+                } else {
+                    let start = self.header.file_data_list_offset as u64 + file.data_offset_or_node_index as u64;
+                    let end = start + file.data_size as u64;
+                    (end > self.data.len() as u64).then(|| {
+                        format!(
+                            "{:?} has data at {start:#X}..{end:#X}, past the end of the archive",
+                            file.name
+                        )
+                    })
+                };
+                if let Some(problem) = problem {
+                    warnings.recover(RarcError::CorruptedError(problem), "Skipping it")?;
+                    self.skipped.insert(file_idx);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds a new archive from in-memory files without touching the filesystem.
+    /// Paths are relative to the root directory, which is named `root_name`.
+    pub fn build<P: AsRef<Path>, D: AsRef<[u8]>>(
+        root_name: impl Into<String>,
+        files: impl IntoIterator<Item = (P, D)>,
+    ) -> Result<Vec<u8>, RarcError> {
+        let mut tree = DirTree::new(root_name.into());
+        for (path, data) in files {
+            tree.insert(path.as_ref(), Cow::Owned(data.as_ref().to_vec()));
+        }
+        tree.build(DEFAULT_ALIGNMENT)
+    }
+
+    /// Packs a directory on disk into a big-endian archive named after it, with default
+    /// alignment and every file loaded into main RAM
+    pub fn encode_dir(root: impl AsRef<Path>) -> Result<VirtualFile, RarcError> {
+        let root = root.as_ref();
+        let mut bytes = Vec::new();
+        Rarc::encode_to(
+            root,
+            &mut bytes,
+            DEFAULT_ALIGNMENT,
+            ByteOrder::Big,
+            &LoadLocations::default(),
+            None,
+        )?;
+        Ok(VirtualFile {
+            path: root.with_extension("arc"),
+            bytes,
         })
     }
 
+    /// Packs a directory on disk into `out`. Files are read one at a time as their data
+    /// is written, so only one is ever held in memory. Paths in `load_locations` are
+    /// relative to `root`. `trailing_data`, like an original archive's
+    /// [`Rarc::trailing_data`], goes after the last file in place of its padding.
+    pub fn encode_to<W: Write>(
+        root: impl AsRef<Path>,
+        out: &mut W,
+        alignment: u32,
+        byte_order: ByteOrder,
+        load_locations: &LoadLocations,
+        trailing_data: Option<&[u8]>,
+    ) -> Result<(), RarcError> {
+        let root = root.as_ref();
+        if !metadata(root)?.is_dir() {
+            return Err(RarcError::NotADirError);
+        }
+        DirTree::read(root)?.write_to(out, alignment, byte_order, load_locations, trailing_data)
+    }
+
+    /// Iterates over every file in the archive. File contents are borrowed straight from
+    /// the archive data, so nothing is copied unless the caller chooses to.
     pub fn files(&self) -> impl Iterator<Item = (PathBuf, &[u8])> {
         let root_node = &self.nodes[0];
         let files_with_paths = self.files_for_node(root_node, PathBuf::new());
-        files_with_paths
+        let original_files = files_with_paths
             .into_iter()
             .filter(|(_, file)| ![".", ".."].contains(&&file.name[..]))
             .map(|(mut path, file)| {
                 path.push(&file.name[..]);
-                let file_start = (self.header.file_data_list_offset + file.data_offset_or_node_index) as usize;
-                let file_end = file_start + file.data_size as usize;
-                (path, &self.data[file_start..file_end])
+                (path, self.file_data(file))
             })
+            .filter(|(path, _)| !self.changes.contains_key(path));
+        let changed_files = self
+            .changes
+            .iter()
+            .filter_map(|(path, data)| Some((path.clone(), data.as_deref()?)));
+        original_files.chain(changed_files)
+    }
+
+    /// Where each file is loaded when the game mounts the archive, with the most common
+    /// location as the default. Files added since parsing are left out.
+    pub fn load_locations(&self) -> LoadLocations {
+        let located: Vec<(PathBuf, LoadLocation)> = self
+            .files_for_node(&self.nodes[0], PathBuf::new())
+            .into_iter()
+            .filter_map(|(path, file)| Some((path.join(&file.name), file.load_location()?)))
+            .filter(|(path, _)| self.changes.get(path).is_none_or(|change| change.is_some()))
+            .collect();
+        let count = |location| located.iter().filter(|(_, l)| *l == location).count();
+        let default = [LoadLocation::Mram, LoadLocation::Aram, LoadLocation::Dvd]
+            .into_iter()
+            .max_by_key(|&location| (count(location), location == LoadLocation::Mram))
+            .unwrap();
+        LoadLocations {
+            default,
+            files: located
+                .into_iter()
+                .filter(|(_, location)| *location != default)
+                .collect(),
+        }
+    }
+
+    /// Looks up a single file by its path inside the archive without copying it
+    pub fn open(&self, path: impl AsRef<Path>) -> Option<&[u8]> {
+        let path = path.as_ref();
+        if let Some(change) = self.changes.get(path) {
+            return change.as_deref();
+        }
+        Some(self.file_data(self.original_file(path)?))
+    }
+
+    /// Lists a folder inside the archive by name, like [`std::fs::read_dir`] does on disk,
+    /// with an empty path for the root. Returns None if there's no such folder.
+    pub fn read_dir(&self, path: impl AsRef<Path>) -> Option<Vec<RarcDirEntry>> {
+        self.listing().get(path.as_ref()).cloned()
+    }
+
+    /// Looks up a single file or folder by its path inside the archive, like
+    /// [`std::fs::metadata`] does on disk. The root is the empty path.
+    pub fn metadata(&self, path: impl AsRef<Path>) -> Option<RarcMetadata> {
+        let path = path.as_ref();
+        let Some(name) = path.file_name() else {
+            return path.as_os_str().is_empty().then_some(RarcMetadata::DIR);
+        };
+        let name = name.to_str()?;
+        let parent = self.listing().get(path.parent().unwrap_or(Path::new("")))?;
+        // Listed in order of name
+        let index = parent.binary_search_by(|entry| entry.name.as_str().cmp(name)).ok()?;
+        Some(parent[index].metadata)
+    }
+
+    /// Adds a file to the archive at the given path, creating any intermediate directories.
+    /// If a file already exists at that path, it's replaced.
+    pub fn add_file(&mut self, path: impl AsRef<Path>, bytes: Vec<u8>) {
+        self.changes.insert(path.as_ref().to_owned(), Some(bytes));
+        self.listing.take();
+    }
+
+    /// Removes a file from the archive. Returns false if no file existed at the given path.
+    pub fn remove_file(&mut self, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        let exists = self.files().any(|(p, _)| p == path);
+        if exists {
+            self.changes.insert(path.to_owned(), None);
+            self.listing.take();
+        }
+        exists
+    }
+
+    /// Calls `f` with every file in the archive, replacing the ones it returns new contents
+    /// for, and returns how many changed. Nothing is rebuilt until the archive is written.
+    pub fn transform(&mut self, mut f: impl FnMut(&Path, &[u8]) -> Option<Vec<u8>>) -> usize {
+        let changed: Vec<(PathBuf, Vec<u8>)> = self
+            .files()
+            .filter_map(|(path, bytes)| Some((path.clone(), f(&path, bytes).filter(|new| new != bytes)?)))
+            .collect();
+        let count = changed.len();
+        for (path, bytes) in changed {
+            self.add_file(path, bytes);
+        }
+        count
+    }
+
+    /// Serializes the archive including any added or removed files. File data offsets
+    /// and all tables are recomputed from scratch.
+    pub fn write(&self) -> Result<Vec<u8>, RarcError> {
+        let mut out = Vec::new();
+        self.write_to(&mut out, DEFAULT_ALIGNMENT)?;
+        Ok(out)
+    }
+
+    /// Like [`Rarc::write`], but writes into `out` with each file's data starting on a
+    /// multiple of `alignment` bytes. The archive is written in [`Rarc::byte_order`], and
+    /// files keep their load locations, with added ones going wherever most files do.
+    pub fn write_to<W: Write>(&self, out: &mut W, alignment: u32) -> Result<(), RarcError> {
+        let tree = self.changed_tree();
+        let trailing_data = self.keep_trailing_data.then(|| self.trailing_data());
+        tree.write_to(out, alignment, self.byte_order, &self.load_locations(), trailing_data)
+    }
+
+    /// Whatever comes after the last file's data, up to the end of the archive. That's
+    /// usually just zeros padding it out to a multiple of 32 bytes, but some shipped
+    /// archives have other data left over there, which packing would otherwise drop.
+    pub fn trailing_data(&self) -> &[u8] {
+        let file_data_end = self
+            .files_for_node(&self.nodes[0], PathBuf::new())
+            .into_iter()
+            .filter(|(_, file)| !file.is_dir())
+            .map(|(_, file)| file.data_offset_or_node_index as usize + file.data_size as usize)
+            .max()
+            .unwrap_or(0);
+        let start = self.header.file_data_list_offset as usize + file_data_end;
+        self.data.get(start..).unwrap_or_default()
+    }
+
+    /// Whether [`Rarc::trailing_data`] is anything more than the zeros packing pads the
+    /// last file with anyway. Some tools leave out that padding, which isn't worth keeping
+    /// either.
+    pub fn has_trailing_data(&self) -> bool {
+        let trailing_data = self.trailing_data();
+        if trailing_data.is_empty() {
+            return false;
+        }
+        // Padding is counted from the start of the file data
+        let file_data_end =
+            (self.data.len() - trailing_data.len()).saturating_sub(self.header.file_data_list_offset as usize);
+        let padding = file_data_end.next_multiple_of(DEFAULT_ALIGNMENT as usize) - file_data_end;
+        trailing_data.len() != padding || trailing_data.iter().any(|&byte| byte != 0)
+    }
+
+    fn dir_tree(&self, node: &RarcNode) -> DirTree<'_> {
+        let name = read_name(
+            self.data,
+            self.info_block.string_table_offset,
+            self.info_block.string_table_length,
+            node.name_offset,
+        )
+        .unwrap_or_default();
+        let mut tree = DirTree::new(name);
+        for file in self.node_entries(node).filter(|f| ![".", ".."].contains(&&f.name[..])) {
+            let entry = if file.is_dir() {
+                TreeEntry::Dir(self.dir_tree(&self.nodes[file.data_offset_or_node_index as usize]))
+            } else {
+                TreeEntry::File(Cow::Borrowed(self.file_data(file)))
+            };
+            tree.entries.insert(file.name.clone(), entry);
+        }
+        tree
+    }
+
+    /// The archive's folders and files with the changes since parsing made
+    fn changed_tree(&self) -> DirTree<'_> {
+        let mut tree = self.dir_tree(&self.nodes[0]);
+        for (path, data) in self.changes.iter() {
+            match data {
+                Some(data) => tree.insert(path, Cow::Borrowed(data)),
+                None => tree.remove(path),
+            }
+        }
+        tree
+    }
+
+    fn listing(&self) -> &HashMap<PathBuf, Vec<RarcDirEntry>> {
+        self.listing.get_or_init(|| {
+            // Only files that haven't changed have a location yet
+            let load_locations: HashMap<PathBuf, LoadLocation> = self
+                .files_for_node(&self.nodes[0], PathBuf::new())
+                .into_iter()
+                .filter_map(|(path, file)| Some((path.join(&file.name), file.load_location()?)))
+                .filter(|(path, _)| !self.changes.contains_key(path))
+                .collect();
+            let mut listing = HashMap::new();
+            list_dir(&self.changed_tree(), PathBuf::new(), &load_locations, &mut listing);
+            listing
+        })
+    }
+
+    /// A file's entry in the archive as it was parsed
+    fn original_file(&self, path: &Path) -> Option<&RarcFile> {
+        let mut node = &self.nodes[0];
+        let mut components = path.components().peekable();
+        while let Some(component) = components.next() {
+            let name = component.as_os_str().to_str()?;
+            let file = self.node_entries(node).find(|f| f.name == name)?;
+            match (file.is_dir(), components.peek().is_some()) {
+                (false, false) => return Some(file),
+                (true, true) => node = self.nodes.get(file.data_offset_or_node_index as usize)?,
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    fn file_data(&self, file: &RarcFile) -> &[u8] {
+        let file_start = (self.header.file_data_list_offset + file.data_offset_or_node_index) as usize;
+        let file_end = file_start + file.data_size as usize;
+        &self.data[file_start..file_end]
+    }
+
+    fn node_entries(&self, node: &RarcNode) -> impl Iterator<Item = &RarcFile> {
+        let first_file_index = node.first_file_index as usize;
+        (first_file_index..first_file_index + node.num_files as usize)
+            .filter(|file_idx| !self.skipped.contains(file_idx))
+            .map(|file_idx| &self.files[file_idx])
     }
 
     fn files_for_node(&self, node: &RarcNode, parent_path: PathBuf) -> Vec<(PathBuf, &RarcFile)> {
-        let file_entries =
-            &self.files[node.first_file_index as usize..(node.first_file_index + node.num_files as u32) as usize];
-        let (dirs, files): (Vec<_>, Vec<_>) = file_entries.iter().partition(|e| e.is_dir());
+        let file_entries = self.node_entries(node);
+        let (dirs, files): (Vec<_>, Vec<_>) = file_entries.partition(|e| e.is_dir());
         let mut files_with_paths: Vec<_> = files.into_iter().map(|f| (parent_path.clone(), f)).collect();
         for file in dirs {
             if ![".", ".."].contains(&&file.name[..]) {
@@ -306,17 +1117,23 @@ pub struct RarcHeader {
     pub file_length: u32,
     pub file_data_list_offset: u32,
     pub file_data_length: u32,
+    /// How much of the file data is loaded into main RAM when the archive is mounted
+    pub mram_data_length: u32,
+    /// How much of the file data is loaded into ARAM. Files read from the disc as they're
+    /// needed are in neither.
+    pub aram_data_length: u32,
 }
 
 impl RarcHeader {
-    pub fn write(&self) -> [u8; 0x20] {
+    pub fn write(&self, byte_order: ByteOrder) -> [u8; 0x20] {
         let mut out = [0u8; 0x20];
-        out[..4].copy_from_slice(b"RARC");
-        out[4..8].copy_from_slice(&self.file_length.to_be_bytes());
-        out[8..0xC].copy_from_slice(&0x20u32.to_be_bytes());
-        out[0xC..0x10].copy_from_slice(&self.file_data_list_offset.to_be_bytes());
-        out[0x10..0x14].copy_from_slice(&self.file_data_length.to_be_bytes());
-        out[0x14..0x18].copy_from_slice(&self.file_data_length.to_be_bytes()); // Intentional duplication
+        out[..4].copy_from_slice(byte_order.magic());
+        out[4..8].copy_from_slice(&byte_order.u32_bytes(self.file_length));
+        out[8..0xC].copy_from_slice(&byte_order.u32_bytes(0x20));
+        out[0xC..0x10].copy_from_slice(&byte_order.u32_bytes(self.file_data_list_offset));
+        out[0x10..0x14].copy_from_slice(&byte_order.u32_bytes(self.file_data_length));
+        out[0x14..0x18].copy_from_slice(&byte_order.u32_bytes(self.mram_data_length));
+        out[0x18..0x1C].copy_from_slice(&byte_order.u32_bytes(self.aram_data_length));
         out
     }
 }
@@ -333,15 +1150,15 @@ pub struct RarcInfoBlock {
 }
 
 impl RarcInfoBlock {
-    pub fn write(&self) -> [u8; 0x20] {
+    pub fn write(&self, byte_order: ByteOrder) -> [u8; 0x20] {
         let mut out = [0u8; 0x20];
-        out[..4].copy_from_slice(&self.num_nodes.to_be_bytes());
-        out[4..8].copy_from_slice(&self.node_list_offset.to_be_bytes());
-        out[8..0xC].copy_from_slice(&self.num_file_entries.to_be_bytes());
-        out[0xC..0x10].copy_from_slice(&self.file_entries_list_offset.to_be_bytes());
-        out[0x10..0x14].copy_from_slice(&self.string_table_length.to_be_bytes());
-        out[0x14..0x18].copy_from_slice(&self.string_table_offset.to_be_bytes());
-        out[0x18..0x1A].copy_from_slice(&self.num_files.to_be_bytes());
+        out[..4].copy_from_slice(&byte_order.u32_bytes(self.num_nodes));
+        out[4..8].copy_from_slice(&byte_order.u32_bytes(self.node_list_offset));
+        out[8..0xC].copy_from_slice(&byte_order.u32_bytes(self.num_file_entries));
+        out[0xC..0x10].copy_from_slice(&byte_order.u32_bytes(self.file_entries_list_offset));
+        out[0x10..0x14].copy_from_slice(&byte_order.u32_bytes(self.string_table_length));
+        out[0x14..0x18].copy_from_slice(&byte_order.u32_bytes(self.string_table_offset));
+        out[0x18..0x1A].copy_from_slice(&byte_order.u16_bytes(self.num_files));
         out[0x1A] = 1; // Sync file IDs and indexes flag
         out
     }
@@ -349,106 +1166,159 @@ impl RarcInfoBlock {
 
 #[derive(Debug)]
 pub struct RarcNode {
-    pub node_name: String, // 4 character uppercase ID
-    pub name_offset: u32,  // this is the actual folder name
-    pub num_files: u16,    // number of ALL file entries, not just those that aren't directories
+    pub node_name: [u8; 4], // uppercase ID, usually the start of the folder name padded with NULs
+    pub name_offset: u32,   // this is the actual folder name
+    pub num_files: u16,     // number of ALL file entries, not just those that aren't directories
     pub first_file_index: u32,
 }
 
 impl RarcNode {
-    fn read(data: &[u8], node_offset: u32) -> Self {
-        let node_name = std::str::from_utf8(&read_u32(data, node_offset).to_be_bytes())
-            .expect("Invalid UTF8 in RARC node name")
-            .to_owned();
-        let name_offset = read_u32(data, node_offset + 0x4);
-        let num_files = read_u16(data, node_offset + 0xA);
-        let first_file_index = read_u32(data, node_offset + 0xC);
-
-        RarcNode {
+    fn read(reader: &mut BinReader) -> Result<Self, OutOfBounds> {
+        // The ID is stored as a number, so it's reversed in little-endian archives too
+        let node_name = reader.u32()?.to_be_bytes();
+        let name_offset = reader.u32()?;
+        reader.skip(0x2); // Name hash
+        let num_files = reader.u16()?;
+        let first_file_index = reader.u32()?;
+
+        Ok(RarcNode {
             node_name,
             name_offset,
             num_files,
             first_file_index,
-        }
+        })
     }
 
-    fn write(&self, string_table: &[u8]) -> [u8; 0x10] {
+    fn write(&self, string_table: &[u8], byte_order: ByteOrder) -> [u8; 0x10] {
         let mut out = [0u8; 0x10];
-        out[..4].copy_from_slice(self.node_name.as_bytes());
-        out[4..8].copy_from_slice(&self.name_offset.to_be_bytes());
-        let full_name = read_str_until_null(string_table, self.name_offset);
-        out[8..0xA].copy_from_slice(&string_hash(&full_name).to_be_bytes());
-        out[0xA..0xC].copy_from_slice(&self.num_files.to_be_bytes());
-        out[0xC..].copy_from_slice(&self.first_file_index.to_be_bytes());
+        out[..4].copy_from_slice(&byte_order.u32_bytes(u32::from_be_bytes(self.node_name)));
+        out[4..8].copy_from_slice(&byte_order.u32_bytes(self.name_offset));
+        let full_name = string_table[self.name_offset as usize..]
+            .split(|b| *b == 0)
+            .next()
+            .unwrap_or_default();
+        out[8..0xA].copy_from_slice(&byte_order.u16_bytes(string_hash(full_name)));
+        out[0xA..0xC].copy_from_slice(&byte_order.u16_bytes(self.num_files));
+        out[0xC..].copy_from_slice(&byte_order.u32_bytes(self.first_file_index));
         out
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct RarcFile {
     pub name: String,
     pub index: u16,
-    pub name_offset: u16,
+    pub name_offset: u32, // 24 bits, shares a u32 with the type flags
     pub data_size: u32,
     pub data_offset_or_node_index: u32,
-    pub file_type_flags: u16,
+    pub file_type_flags: u8,
 }
 
+/// Type flags for folders, including "." and ".."
+const DIR_FLAGS: u8 = 0x02;
+/// Type flag for files, which also have a flag for their [`LoadLocation`]
+const FILE_FLAG: u8 = 0x01;
+/// File entries keep their type flags in the top byte of the name offset
+const MAX_NAME_OFFSET: u32 = 0x00FFFFFF;
+
 impl RarcFile {
-    fn read(data: &[u8], file_offset: u32, string_list_offset: u32) -> Self {
-        let index = read_u16(data, file_offset);
-        let type_and_name_offset = read_u32(data, file_offset + 0x4);
-        let data_offset_or_node_index = read_u32(data, file_offset + 0x8);
-        let data_size = read_u32(data, file_offset + 0xC);
-        let file_type_flags = (type_and_name_offset & 0xFF000000) >> 24;
-        let name_offset = type_and_name_offset & 0x00FFFFFF;
-        let name = read_str_until_null(data, string_list_offset + name_offset).into_owned();
-
-        RarcFile {
+    fn read(
+        data: &[u8],
+        reader: &mut BinReader,
+        string_list_offset: u32,
+        string_table_length: u32,
+    ) -> Result<Self, RarcError> {
+        let index = reader.u16()?;
+        reader.skip(0x2); // Name hash
+        let type_and_name_offset = reader.u32()?;
+        let data_offset_or_node_index = reader.u32()?;
+        let data_size = reader.u32()?;
+        let file_type_flags = (type_and_name_offset >> 24) as u8;
+        let name_offset = type_and_name_offset & MAX_NAME_OFFSET;
+        let Some(name) = read_name(data, string_list_offset, string_table_length, name_offset) else {
+            return Err(RarcError::MetadataError(name_offset));
+        };
+
+        Ok(RarcFile {
             name,
             index,
-            name_offset: name_offset as u16,
+            name_offset,
             data_size,
             data_offset_or_node_index,
-            file_type_flags: file_type_flags as u16,
-        }
+            file_type_flags,
+        })
     }
 
-    fn write(&self) -> [u8; 0x14] {
+    fn write(&self, byte_order: ByteOrder) -> [u8; 0x14] {
         let mut out = [0u8; 0x14];
-        out[..2].copy_from_slice(&self.index.to_be_bytes());
-        out[2..4].copy_from_slice(&string_hash(&self.name).to_be_bytes());
-        out[4..6].copy_from_slice(&self.file_type_flags.to_be_bytes());
-        out[6..8].copy_from_slice(&self.name_offset.to_be_bytes());
-        out[8..0xC].copy_from_slice(&self.data_offset_or_node_index.to_be_bytes());
-        out[0xC..0x10].copy_from_slice(&self.data_size.to_be_bytes());
+        out[..2].copy_from_slice(&byte_order.u16_bytes(self.index));
+        out[2..4].copy_from_slice(&byte_order.u16_bytes(string_hash(&SHIFT_JIS.encode(&self.name).0)));
+        // Read back as one u32 holding the type in the top byte and the name offset below it
+        let type_and_name_offset = (self.file_type_flags as u32) << 24 | self.name_offset;
+        out[4..8].copy_from_slice(&byte_order.u32_bytes(type_and_name_offset));
+        out[8..0xC].copy_from_slice(&byte_order.u32_bytes(self.data_offset_or_node_index));
+        out[0xC..0x10].copy_from_slice(&byte_order.u32_bytes(self.data_size));
         // rest is unused / always 0
         out
     }
     fn is_dir(&self) -> bool {
-        self.file_type_flags & 0x02 != 0
+        self.file_type_flags & DIR_FLAGS != 0
     }
-}
 
-fn to_node_name(p: &Path, root: &Path) -> Option<String> {
-    if p == root {
-        Some("ROOT".to_string())
-    } else {
-        let file_name = p.file_name()?.to_string_lossy().to_ascii_uppercase();
-        let mut node_name = file_name[..min(4, file_name.len())].to_owned();
-        while node_name.len() < 4 {
-            node_name.push('\0');
+    /// Where the file's data is loaded. None for folders, and files without a location.
+    pub fn load_location(&self) -> Option<LoadLocation> {
+        match self.is_dir() {
+            true => None,
+            false => LoadLocation::from_flags(self.file_type_flags),
         }
-        Some(node_name)
     }
 }
 
+/// Reads the NUL-terminated name at `name_offset` in the string table, as long as it ends
+/// before the table does
+fn read_name(data: &[u8], string_table_offset: u32, string_table_length: u32, name_offset: u32) -> Option<String> {
+    let table = BinReader::at(data, string_table_offset as usize)
+        .bytes(string_table_length as usize)
+        .ok()?;
+    let name = table.get(name_offset as usize..)?;
+    let len = name.iter().position(|&b| b == 0)?;
+    Some(BinReader::new(name).str(len).ok()?.into_owned())
+}
+
+/// Names are stored in Shift-JIS, which is also how they're decoded when reading. Names
+/// with characters Shift-JIS can't represent, or with NULs, would come back different.
+fn encode_name(name: &str) -> Result<Vec<u8>, RarcError> {
+    let (encoded, _, had_errors) = SHIFT_JIS.encode(name);
+    if had_errors || encoded.contains(&0) {
+        return Err(RarcError::InvalidNameError(name.to_owned()));
+    }
+    Ok(encoded.into_owned())
+}
+
+/// Takes the first four bytes of the encoded name rather than characters, so a name with
+/// multibyte characters may end up with part of a character in its ID. Shorter names are
+/// padded with NULs.
+fn to_node_name(dir_name: &[u8]) -> [u8; 4] {
+    let mut node_name = [0u8; 4];
+    let len = min(4, dir_name.len());
+    node_name[..len].copy_from_slice(&dir_name[..len]);
+    node_name.make_ascii_uppercase();
+    node_name
+}
+
 #[allow(dead_code)]
 #[derive(Debug)]
 pub enum RarcError {
     MagicError(usize),
     MetadataError(u32),
+    CorruptedError(String),
     NotADirError,
+    DuplicateNameError(PathBuf),
+    InvalidNameError(String),
+    StringTableOverflow,
+    TooManyFiles,
+    FileTooLarge(PathBuf),
+    ArchiveTooLarge,
     IOError(std::io::Error),
 }
 
@@ -457,7 +1327,16 @@ impl Display for RarcError {
         match self {
             RarcError::MagicError(magic) => write!(f, "Error in magic numbers: {magic}"),
             RarcError::MetadataError(metadata) => write!(f, "Inconsistent metadata: {metadata}"),
+            RarcError::CorruptedError(problem) => write!(f, "Corrupted RARC archive: {problem}"),
             RarcError::NotADirError => write!(f, "Can only compress directories"),
+            RarcError::DuplicateNameError(path) => {
+                write!(f, "{path:?} has the same name as another file once converted to UTF-8")
+            }
+            RarcError::InvalidNameError(name) => write!(f, "{name:?} can't be used as a file name in a RARC archive"),
+            RarcError::StringTableOverflow => write!(f, "Too many file names to fit in a RARC archive's string table"),
+            RarcError::TooManyFiles => write!(f, "Too many files to fit in a RARC archive, or in one of its folders"),
+            RarcError::FileTooLarge(path) => write!(f, "{path:?} is too large to fit in a RARC archive"),
+            RarcError::ArchiveTooLarge => write!(f, "The files add up to more than a RARC archive can hold"),
             RarcError::IOError(e) => write!(f, "IO Error while processing RARC file: {e}"),
         }
     }
@@ -465,15 +1344,21 @@ impl Display for RarcError {
 
 impl std::error::Error for RarcError {}
 
+impl From<OutOfBounds> for RarcError {
+    fn from(value: OutOfBounds) -> Self {
+        RarcError::CorruptedError(value.to_string())
+    }
+}
+
 impl From<std::io::Error> for RarcError {
     fn from(value: std::io::Error) -> Self {
         RarcError::IOError(value)
     }
 }
 
-fn string_hash(string: &str) -> u16 {
+fn string_hash(string: &[u8]) -> u16 {
     let mut hash = 0u16;
-    for c in string.bytes() {
+    for &c in string {
         hash = hash.wrapping_mul(3);
         hash = hash.wrapping_add(c as u16);
     }
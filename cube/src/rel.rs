@@ -0,0 +1,275 @@
+use crate::util::{read_u16, read_u32};
+use serde::Serialize;
+use thiserror::Error;
+
+/// RELs are relocatable code modules loaded at runtime by main.dol, similar to DLLs.
+/// Many games ship most of their game code as RELs inside the disc filesystem.
+#[derive(Debug, Serialize)]
+pub struct Rel<'a> {
+    #[serde(skip)]
+    data: &'a [u8],
+    pub header: RelHeader,
+    pub sections: Vec<RelSection>,
+    pub imports: Vec<RelImport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelHeader {
+    pub module_id: u32,
+    pub num_sections: u32,
+    pub section_info_offset: u32,
+    pub name_offset: u32,
+    pub name_size: u32,
+    pub version: u32,
+    pub bss_size: u32,
+    pub relocation_offset: u32,
+    pub import_offset: u32,
+    pub import_size: u32,
+    pub prolog_section: u8,
+    pub epilog_section: u8,
+    pub unresolved_section: u8,
+    pub bss_section: u8,
+    pub prolog: u32,
+    pub epilog: u32,
+    pub unresolved: u32,
+    /// Version 2 and above only
+    pub align: Option<u32>,
+    /// Version 2 and above only
+    pub bss_align: Option<u32>,
+    /// Version 3 and above only
+    pub fix_size: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct RelSection {
+    pub offset: u32,
+    pub size: u32,
+    pub executable: bool,
+}
+
+impl RelSection {
+    /// Uninitialized sections have a size but no data in the file
+    pub fn is_bss(&self) -> bool {
+        self.offset == 0 && self.size != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offset == 0 && self.size == 0
+    }
+}
+
+/// A group of relocations against a single other module. Module 0 is main.dol.
+#[derive(Debug, Serialize)]
+pub struct RelImport {
+    pub module_id: u32,
+    pub offset: u32,
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Relocation {
+    /// Section in this module that gets patched
+    pub section: u8,
+    /// Offset within `section` of the patched location
+    pub offset: u32,
+    pub kind: u8,
+    /// Section in the imported module the relocation points into. Always 0 for main.dol,
+    /// in which case `addend` is an absolute address.
+    pub target_section: u8,
+    pub addend: u32,
+}
+
+impl Relocation {
+    pub const R_PPC_ADDR32: u8 = 1;
+    pub const R_PPC_ADDR24: u8 = 2;
+    pub const R_PPC_ADDR16: u8 = 3;
+    pub const R_PPC_ADDR16_LO: u8 = 4;
+    pub const R_PPC_ADDR16_HI: u8 = 5;
+    pub const R_PPC_ADDR16_HA: u8 = 6;
+    pub const R_PPC_REL24: u8 = 10;
+    pub const R_PPC_REL14: u8 = 11;
+    pub const R_DOLPHIN_NOP: u8 = 201;
+    pub const R_DOLPHIN_SECTION: u8 = 202;
+    pub const R_DOLPHIN_END: u8 = 203;
+
+    pub fn kind_name(&self) -> &'static str {
+        match self.kind {
+            Relocation::R_PPC_ADDR32 => "R_PPC_ADDR32",
+            Relocation::R_PPC_ADDR24 => "R_PPC_ADDR24",
+            Relocation::R_PPC_ADDR16 => "R_PPC_ADDR16",
+            Relocation::R_PPC_ADDR16_LO => "R_PPC_ADDR16_LO",
+            Relocation::R_PPC_ADDR16_HI => "R_PPC_ADDR16_HI",
+            Relocation::R_PPC_ADDR16_HA => "R_PPC_ADDR16_HA",
+            Relocation::R_PPC_REL24 => "R_PPC_REL24",
+            Relocation::R_PPC_REL14 => "R_PPC_REL14",
+            _ => "unknown",
+        }
+    }
+}
+
+/// A named location inside a REL. RELs don't carry a symbol table, but the module's
+/// entry points are always known.
+#[derive(Debug, Serialize)]
+pub struct RelSymbol {
+    pub name: &'static str,
+    pub section: u8,
+    pub offset: u32,
+}
+
+impl<'a> Rel<'a> {
+    const HEADER_SIZE_V1: usize = 0x40;
+
+    pub fn read(data: &'a [u8]) -> Result<Rel<'a>, RelError> {
+        if data.len() < Rel::HEADER_SIZE_V1 {
+            return Err(RelError::Truncated);
+        }
+
+        let version = read_u32(data, 0x1C);
+        let header_size = match version {
+            0 | 1 => 0x40,
+            2 => 0x48,
+            3 => 0x4C,
+            _ => return Err(RelError::UnsupportedVersion(version)),
+        };
+        if data.len() < header_size {
+            return Err(RelError::Truncated);
+        }
+
+        let header = RelHeader {
+            module_id: read_u32(data, 0x0),
+            num_sections: read_u32(data, 0xC),
+            section_info_offset: read_u32(data, 0x10),
+            name_offset: read_u32(data, 0x14),
+            name_size: read_u32(data, 0x18),
+            version,
+            bss_size: read_u32(data, 0x20),
+            relocation_offset: read_u32(data, 0x24),
+            import_offset: read_u32(data, 0x28),
+            import_size: read_u32(data, 0x2C),
+            prolog_section: data[0x30],
+            epilog_section: data[0x31],
+            unresolved_section: data[0x32],
+            bss_section: data[0x33],
+            prolog: read_u32(data, 0x34),
+            epilog: read_u32(data, 0x38),
+            unresolved: read_u32(data, 0x3C),
+            align: (version >= 2).then(|| read_u32(data, 0x40)),
+            bss_align: (version >= 2).then(|| read_u32(data, 0x44)),
+            fix_size: (version >= 3).then(|| read_u32(data, 0x48)),
+        };
+
+        let section_table_end = header.section_info_offset as usize + header.num_sections as usize * 8;
+        if section_table_end > data.len() {
+            return Err(RelError::Truncated);
+        }
+        let mut sections = Vec::with_capacity(header.num_sections as usize);
+        for i in 0..header.num_sections {
+            let entry_offset = header.section_info_offset + i * 8;
+            let raw_offset = read_u32(data, entry_offset);
+            let section = RelSection {
+                offset: raw_offset & !1,
+                size: read_u32(data, entry_offset + 4),
+                executable: raw_offset & 1 != 0,
+            };
+            if !section.is_bss() && section.offset as usize + section.size as usize > data.len() {
+                return Err(RelError::SectionOutOfBounds(i as usize));
+            }
+            sections.push(section);
+        }
+
+        if header.import_offset as usize + header.import_size as usize > data.len() {
+            return Err(RelError::Truncated);
+        }
+        let mut imports = Vec::with_capacity(header.import_size as usize / 8);
+        for i in 0..header.import_size / 8 {
+            let entry_offset = header.import_offset + i * 8;
+            let module_id = read_u32(data, entry_offset);
+            let offset = read_u32(data, entry_offset + 4);
+            imports.push(RelImport {
+                module_id,
+                offset,
+                relocations: read_relocations(data, offset)?,
+            });
+        }
+
+        Ok(Rel {
+            data,
+            header,
+            sections,
+            imports,
+        })
+    }
+
+    /// Raw contents of a section, or None for BSS and empty sections
+    pub fn section_data(&self, index: usize) -> Option<&'a [u8]> {
+        let section = self.sections.get(index)?;
+        if section.is_bss() || section.is_empty() {
+            return None;
+        }
+        Some(&self.data[section.offset as usize..(section.offset + section.size) as usize])
+    }
+
+    pub fn symbols(&self) -> Vec<RelSymbol> {
+        let header = &self.header;
+        [
+            ("_prolog", header.prolog_section, header.prolog),
+            ("_epilog", header.epilog_section, header.epilog),
+            ("_unresolved", header.unresolved_section, header.unresolved),
+        ]
+        .into_iter()
+        .filter(|(_, section, _)| *section != 0)
+        .map(|(name, section, offset)| RelSymbol { name, section, offset })
+        .collect()
+    }
+}
+
+fn read_relocations(data: &[u8], start: u32) -> Result<Vec<Relocation>, RelError> {
+    let mut relocations = Vec::new();
+    let mut entry_offset = start;
+    let mut section = 0;
+    let mut offset = 0u32;
+    loop {
+        if entry_offset as usize + 8 > data.len() {
+            return Err(RelError::Truncated);
+        }
+        offset = offset
+            .checked_add(read_u16(data, entry_offset) as u32)
+            .ok_or(RelError::RelocationOutOfRange(section))?;
+        let kind = data[entry_offset as usize + 2];
+        let target_section = data[entry_offset as usize + 3];
+        let addend = read_u32(data, entry_offset + 4);
+        entry_offset += 8;
+
+        match kind {
+            Relocation::R_DOLPHIN_END => break,
+            Relocation::R_DOLPHIN_NOP => {}
+            Relocation::R_DOLPHIN_SECTION => {
+                section = target_section;
+                offset = 0;
+            }
+            _ => relocations.push(Relocation {
+                section,
+                offset,
+                kind,
+                target_section,
+                addend,
+            }),
+        }
+    }
+    Ok(relocations)
+}
+
+#[derive(Debug, Error)]
+pub enum RelError {
+    #[error("REL data is truncated or contains offsets past the end of the file")]
+    Truncated,
+
+    #[error("Unsupported REL version {0}")]
+    UnsupportedVersion(u32),
+
+    #[error("Section {0} extends past the end of the file")]
+    SectionOutOfBounds(usize),
+
+    #[error("A relocation in section {0} is further into it than a section can be")]
+    RelocationOutOfRange(u8),
+}
@@ -1,16 +1,21 @@
-use crate::{rarc::Rarc, virtual_fs::VirtualFile};
-use std::io::Cursor;
-use yaz0::{Error as Yaz0Error, Yaz0Archive, Yaz0Writer};
-
-/// Extracts an (optionally Yaz0 compressed) SZS archive into a list of files with
-/// their respective paths and raw contents.
-pub fn extract_szs(data: Vec<u8>) -> Result<Vec<VirtualFile>, Yaz0Error> {
-    let arc = if &data[..4] == b"Yaz0" {
-        Yaz0Archive::new(Cursor::new(data))?.decompress()?
-    } else {
-        data
-    };
-    let rarc = Rarc::parse(arc.as_slice()).expect("Rarc decompression error!");
+use crate::{
+    rarc::{Rarc, RarcError},
+    util::read_u32,
+    virtual_fs::VirtualFile,
+    yay0::{self, Yay0Error},
+    CodecError, Decode, Encode, FromBytes,
+};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, io::Cursor, str::FromStr};
+use thiserror::Error;
+use yaz0::{Error as Yaz0Error, Yaz0Archive};
+
+/// Extracts an SZS archive, which may be Yaz0 compressed, Yay0 compressed (SZP), or not
+/// compressed at all, into a list of files with their respective paths and raw contents.
+pub fn extract_szs(data: Vec<u8>) -> Result<Vec<VirtualFile>, SzsError> {
+    let arc = decompress_szs(data)?;
+    let rarc = Rarc::parse(arc.as_slice())?;
     Ok(rarc
         .files()
         .map(|(path, bytes)| VirtualFile {
@@ -20,9 +25,428 @@ pub fn extract_szs(data: Vec<u8>) -> Result<Vec<VirtualFile>, Yaz0Error> {
         .collect())
 }
 
-pub fn yaz0_compress(bytes: &[u8]) -> Result<Vec<u8>, Yaz0Error> {
-    let mut out = Vec::new();
-    let yaz0_writer = Yaz0Writer::new(&mut out);
-    yaz0_writer.compress_and_write(bytes, yaz0::CompressionLevel::Lookahead { quality: 10 })?;
-    Ok(out)
+/// Decompresses an SZS or SZP archive if it's compressed, returning the raw RARC data.
+/// Use with [`Rarc::parse`] and [`Rarc::open`] to read only some of the files inside
+/// instead of copying all of them out with [`extract_szs`].
+pub fn decompress_szs(mut data: Vec<u8>) -> Result<Vec<u8>, SzsError> {
+    if let Some(info) = Yaz0Info::read(&data) {
+        if info.magic == Yaz0Magic::Yaz1 {
+            data[..4].copy_from_slice(b"Yaz0");
+        }
+        Ok(Yaz0Archive::new(Cursor::new(data))?.decompress()?)
+    } else if data.starts_with(b"Yay0") {
+        Ok(yay0::decompress(&data)?)
+    } else {
+        Ok(data)
+    }
+}
+
+/// An SZS or SZP archive, held decompressed along with how it was compressed so that it
+/// can be written back the same way
+#[derive(Debug, Clone)]
+pub struct Szs {
+    pub compression: SzsCompression,
+    /// How hard to try when compressing it again
+    pub level: CompressionLevel,
+    /// The RARC archive inside
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SzsCompression {
+    None,
+    Yaz0(Yaz0Info),
+    Yay0,
+}
+
+impl Szs {
+    pub fn read(data: Vec<u8>) -> Result<Self, SzsError> {
+        Ok(Szs {
+            compression: SzsCompression::detect(&data),
+            level: CompressionLevel::default(),
+            data: decompress_szs(data)?,
+        })
+    }
+
+    /// Parses the archive inside, to read or change its files
+    pub fn rarc(&self) -> Result<Rarc<'_>, RarcError> {
+        Rarc::parse(&self.data)
+    }
+}
+
+impl FromBytes<'_> for Szs {
+    fn from_bytes(data: &[u8]) -> Result<Self, CodecError> {
+        Ok(Szs::read(data.to_vec())?)
+    }
+}
+
+impl Decode for Szs {
+    type Out = Vec<VirtualFile>;
+    fn decode(&self) -> Result<Self::Out, CodecError> {
+        self.rarc()?.decode()
+    }
+}
+
+impl Encode for Szs {
+    fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        Ok(self.compression.compress(&self.data, self.level))
+    }
+}
+
+impl SzsCompression {
+    /// Tells how data is compressed from its header
+    pub fn detect(data: &[u8]) -> Self {
+        if let Some(info) = Yaz0Info::read(data) {
+            SzsCompression::Yaz0(info)
+        } else if data.starts_with(b"Yay0") {
+            SzsCompression::Yay0
+        } else {
+            SzsCompression::None
+        }
+    }
+
+    /// Compresses data this way again, with the same Yaz0 header
+    pub fn compress(self, data: &[u8], level: CompressionLevel) -> Vec<u8> {
+        match self {
+            SzsCompression::None => data.to_vec(),
+            SzsCompression::Yaz0(info) => {
+                let mut compressed = yaz0_compress(data, level);
+                info.apply(&mut compressed);
+                compressed
+            }
+            SzsCompression::Yay0 => yay0::compress(data, level),
+        }
+    }
+}
+
+const YAZ0_WINDOW: usize = 0x1000;
+const YAZ0_MIN_MATCH: usize = 3;
+const YAZ0_MAX_MATCH: usize = 0xFF + 0x12;
+
+/// Yaz0 compresses data, searching for matches as thoroughly as `level` says. Levels 1 to 10
+/// pick the smallest encoding of the matches they find, while [`CompressionLevel::Nintendo`]
+/// uses the same greedy search as Nintendo's own encoder so sizes come out like the
+/// originals', which matters when a game has a fixed amount of room for a file.
+pub fn yaz0_compress(data: &[u8], level: CompressionLevel) -> Vec<u8> {
+    let ops = match level {
+        CompressionLevel::None => data.iter().map(|_| Yaz0Op::Literal).collect(),
+        CompressionLevel::Fast => yaz0_parse_greedy(data, 8, false),
+        CompressionLevel::Lookahead(quality) => yaz0_parse_optimal(data, 4 << quality.clamp(1, 10)),
+        CompressionLevel::Nintendo => yaz0_parse_greedy(data, usize::MAX, true),
+    };
+
+    let mut out = Vec::with_capacity(0x10 + data.len() + data.len().div_ceil(8));
+    out.extend(b"Yaz0");
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend([0; 8]);
+    let mut pos = 0;
+    for chunk in ops.chunks(8) {
+        let code_idx = out.len();
+        out.push(0);
+        for (bit, op) in chunk.iter().enumerate() {
+            match *op {
+                Yaz0Op::Literal => {
+                    out[code_idx] |= 0x80 >> bit;
+                    out.push(data[pos]);
+                    pos += 1;
+                }
+                Yaz0Op::Match { length, distance } => {
+                    let distance = distance - 1;
+                    if length < 0x12 {
+                        out.push(((length - 2) << 4 | distance >> 8) as u8);
+                        out.push(distance as u8);
+                    } else {
+                        out.push((distance >> 8) as u8);
+                        out.push(distance as u8);
+                        out.push((length - 0x12) as u8);
+                    }
+                    pos += length;
+                }
+            }
+        }
+    }
+    debug!("Yaz0 compressed {} bytes to {} at level {level}", data.len(), out.len());
+    out
+}
+
+/// One chunk of a Yaz0 stream: a byte copied as is, or a copy of earlier output
+#[derive(Clone, Copy)]
+enum Yaz0Op {
+    Literal,
+    Match { length: usize, distance: usize },
+}
+
+/// Takes the longest match at each position. With `lookahead`, a byte is written as is
+/// instead if doing so leads to a match at least two bytes longer, like Nintendo's
+/// encoder does.
+fn yaz0_parse_greedy(data: &[u8], max_candidates: usize, lookahead: bool) -> Vec<Yaz0Op> {
+    let mut chains = HashChains::new(data.len());
+    let mut ops = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (mut length, distance) = chains.find_match(data, pos, max_candidates);
+        chains.insert(data, pos);
+        if length < YAZ0_MIN_MATCH {
+            ops.push(Yaz0Op::Literal);
+            pos += 1;
+            continue;
+        }
+        let mut op = Yaz0Op::Match { length, distance };
+        if lookahead {
+            let (next_length, next_distance) = chains.find_match(data, pos + 1, max_candidates);
+            if next_length >= length + 2 {
+                ops.push(Yaz0Op::Literal);
+                pos += 1;
+                chains.insert(data, pos);
+                length = next_length;
+                op = Yaz0Op::Match {
+                    length,
+                    distance: next_distance,
+                };
+            }
+        }
+        ops.push(op);
+        for i in pos + 1..pos + length {
+            chains.insert(data, i);
+        }
+        pos += length;
+    }
+    ops
+}
+
+/// Finds the longest match at every position, then works backwards to find the cheapest
+/// way to encode the whole thing from them. A match can also be used for fewer bytes than
+/// it covers, which is sometimes cheaper than taking all of it.
+fn yaz0_parse_optimal(data: &[u8], max_candidates: usize) -> Vec<Yaz0Op> {
+    let mut chains = HashChains::new(data.len());
+    // Kept small since there's one for every byte. Lengths and distances both fit in 16 bits.
+    let matches: Vec<(u16, u16)> = (0..data.len())
+        .map(|pos| {
+            let (length, distance) = chains.find_match(data, pos, max_candidates);
+            chains.insert(data, pos);
+            (length as u16, distance as u16)
+        })
+        .collect();
+
+    // Cost in bits of encoding everything from each position on, and the length to take
+    // there, where 1 is a literal. Every chunk also takes one bit of its group's code byte.
+    let mut cost = vec![0u32; data.len() + 1];
+    let mut take = vec![1u16; data.len()];
+    for pos in (0..data.len()).rev() {
+        cost[pos] = 9 + cost[pos + 1];
+        for length in YAZ0_MIN_MATCH..=matches[pos].0 as usize {
+            let bits = if length < 0x12 { 17 } else { 25 } + cost[pos + length];
+            if bits < cost[pos] {
+                cost[pos] = bits;
+                take[pos] = length as u16;
+            }
+        }
+    }
+
+    let mut ops = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let length = take[pos] as usize;
+        ops.push(match length {
+            1 => Yaz0Op::Literal,
+            length => Yaz0Op::Match {
+                length,
+                distance: matches[pos].1 as usize,
+            },
+        });
+        pos += length;
+    }
+    ops
+}
+
+/// Earlier positions in the data grouped by their first few bytes, newest first, so matches
+/// can be found without comparing against the whole window
+struct HashChains {
+    head: Vec<u32>,
+    prev: Vec<u32>,
+}
+
+impl HashChains {
+    const NONE: u32 = u32::MAX;
+    const HASH_BITS: u32 = 15;
+
+    fn new(len: usize) -> Self {
+        HashChains {
+            head: vec![Self::NONE; 1 << Self::HASH_BITS],
+            prev: vec![Self::NONE; len],
+        }
+    }
+
+    fn hash(data: &[u8], pos: usize) -> Option<usize> {
+        let bytes = data.get(pos..pos + YAZ0_MIN_MATCH)?;
+        let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        Some((value.wrapping_mul(0x9E37_79B1) >> (32 - Self::HASH_BITS)) as usize)
+    }
+
+    fn insert(&mut self, data: &[u8], pos: usize) {
+        if let Some(hash) = Self::hash(data, pos) {
+            self.prev[pos] = self.head[hash];
+            self.head[hash] = pos as u32;
+        }
+    }
+
+    /// Length and distance of the longest match for the data at `pos`, checking at most
+    /// `max_candidates` earlier positions. Ties go to the furthest match, like they do
+    /// in Nintendo's encoder, unless the match is as long as it can be.
+    fn find_match(&self, data: &[u8], pos: usize, max_candidates: usize) -> (usize, usize) {
+        let Some(hash) = Self::hash(data, pos) else {
+            return (0, 0);
+        };
+        let max_length = YAZ0_MAX_MATCH.min(data.len() - pos);
+        let mut best = (0, 0);
+        let mut candidate = self.head[hash];
+        let mut tries = 0;
+        while candidate != Self::NONE && pos - candidate as usize <= YAZ0_WINDOW && tries < max_candidates {
+            let start = candidate as usize;
+            candidate = self.prev[start];
+            tries += 1;
+            // Can't be as long as the best so far if the last byte it'd need is different
+            if best.0 > 0 && data[start + best.0 - 1] != data[pos + best.0 - 1] {
+                continue;
+            }
+            let length = (0..max_length)
+                .take_while(|&i| data[start + i] == data[pos + i])
+                .count();
+            if length >= YAZ0_MIN_MATCH && length >= best.0 {
+                best = (length, pos - start);
+                if length == max_length {
+                    break;
+                }
+            }
+        }
+        best
+    }
+}
+
+/// The parts of a Yaz0 header that don't come from the data itself. Yaz1 is the same
+/// format under a different magic. The reserved words are usually zero, but some tools
+/// and games store other things there, like the data alignment in Wii U archives, so
+/// they're worth keeping when recompressing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Yaz0Info {
+    #[serde(default)]
+    pub magic: Yaz0Magic,
+    #[serde(default)]
+    pub reserved: [u32; 2],
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Yaz0Magic {
+    #[default]
+    Yaz0,
+    Yaz1,
+}
+
+impl Yaz0Info {
+    const HEADER_SIZE: usize = 0x10;
+
+    /// Reads the header of Yaz0 or Yaz1 compressed data. Returns None for anything else.
+    pub fn read(data: &[u8]) -> Option<Self> {
+        let magic = match data.get(..4)? {
+            b"Yaz0" => Yaz0Magic::Yaz0,
+            b"Yaz1" => Yaz0Magic::Yaz1,
+            _ => return None,
+        };
+        if data.len() < Yaz0Info::HEADER_SIZE {
+            return None;
+        }
+        Some(Yaz0Info {
+            magic,
+            reserved: [read_u32(data, 0x8), read_u32(data, 0xC)],
+        })
+    }
+
+    /// Whether this is what every header written by [`yaz0_compress`] looks like
+    pub fn is_default(&self) -> bool {
+        *self == Yaz0Info::default()
+    }
+
+    /// Writes this into the header of compressed data, such as the output of [`yaz0_compress`]
+    pub fn apply(&self, data: &mut [u8]) {
+        let magic: &[u8; 4] = match self.magic {
+            Yaz0Magic::Yaz0 => b"Yaz0",
+            Yaz0Magic::Yaz1 => b"Yaz1",
+        };
+        data[..4].copy_from_slice(magic);
+        data[0x8..0xC].copy_from_slice(&self.reserved[0].to_be_bytes());
+        data[0xC..0x10].copy_from_slice(&self.reserved[1].to_be_bytes());
+    }
+}
+
+impl Display for Yaz0Magic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Yaz0Magic::Yaz0 => write!(f, "Yaz0"),
+            Yaz0Magic::Yaz1 => write!(f, "Yaz1"),
+        }
+    }
+}
+
+/// How hard to try when Yaz0 compressing. Higher levels produce smaller output but
+/// can be very slow on large archives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionLevel {
+    /// Valid Yaz0 stream made entirely of raw chunks. Fastest, but doesn't reduce size.
+    None,
+    /// Naive match search. Much faster than lookahead at a small cost in compression ratio.
+    Fast,
+    /// Thorough match search with the given quality from 1 to 10. Higher qualities check
+    /// more earlier positions for each match. Qualities outside that range are treated as
+    /// the nearest one in it.
+    Lookahead(u8),
+    /// Nintendo's own match search, for output the same size as the game's original files.
+    /// Faster than the highest levels, but the output is a little larger.
+    Nintendo,
+}
+
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        CompressionLevel::Lookahead(10)
+    }
+}
+
+impl Display for CompressionLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompressionLevel::None => write!(f, "none"),
+            CompressionLevel::Fast => write!(f, "fast"),
+            CompressionLevel::Lookahead(quality) => write!(f, "{quality}"),
+            CompressionLevel::Nintendo => write!(f, "nintendo"),
+        }
+    }
+}
+
+impl FromStr for CompressionLevel {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "store" => Ok(CompressionLevel::None),
+            "fast" => Ok(CompressionLevel::Fast),
+            "nintendo" => Ok(CompressionLevel::Nintendo),
+            level => match level.parse::<u8>() {
+                Ok(0) => Ok(CompressionLevel::None),
+                Ok(quality @ 1..=10) => Ok(CompressionLevel::Lookahead(quality)),
+                _ => Err(format!(
+                    "Invalid compression level '{s}'. Expected 'none', 'fast', 'nintendo', or a number from 0 to 10"
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SzsError {
+    #[error("Yaz0 decompression error: {0}")]
+    Yaz0(#[from] Yaz0Error),
+
+    #[error("Yay0 decompression error: {0}")]
+    Yay0(#[from] Yay0Error),
+
+    #[error("Invalid RARC archive: {0}")]
+    Rarc(#[from] RarcError),
 }
@@ -1,15 +1,32 @@
-use std::path::Path;
+use std::error::Error;
 
-use crate::virtual_fs::VirtualFile;
+/// Error from any format's [`Decode`] or [`Encode`] implementation. It's boxed so that
+/// every format can be used through the same trait object, like `dyn Encode`.
+pub type CodecError = Box<dyn Error>;
 
-/// For turning 'normal' files into GCN file formats
-pub trait Encode {
-    type Error;
-    fn encode<P: AsRef<Path>>(path: P) -> Result<VirtualFile, Self::Error>;
-}
-
-/// For turning files in GCN formats into 'normal' file formats
+/// For turning files in GCN formats into 'normal' file formats, or into the files they
+/// contain. Archives decode into a list of [`VirtualFile`](crate::virtual_fs::VirtualFile)s
+/// with paths relative to the archive's root.
 pub trait Decode {
     type Out;
-    fn decode(&self) -> Self::Out;
+    fn decode(&self) -> Result<Self::Out, CodecError>;
+}
+
+/// For reading a file in a GCN format from its bytes, to [`Decode`] it or work with it
+/// directly. Formats that only borrow the file's data, like archives, keep the lifetime
+/// of the slice.
+pub trait FromBytes<'a>: Sized {
+    fn from_bytes(data: &'a [u8]) -> Result<Self, CodecError>;
+}
+
+/// Reads a file and decodes it in one step, e.g. `decode_bytes::<Rarc>(&data)` for the
+/// files in an archive
+pub fn decode_bytes<'a, T: FromBytes<'a> + Decode>(data: &'a [u8]) -> Result<T::Out, CodecError> {
+    T::from_bytes(data)?.decode()
+}
+
+/// For turning 'normal' files into GCN file formats. Returns the complete file as it
+/// would be stored on disc.
+pub trait Encode {
+    fn encode(&self) -> Result<Vec<u8>, CodecError>;
 }
@@ -0,0 +1,329 @@
+//! Archives extracted entirely in memory, with archives nested inside them opened too.
+//! Unlike [`extract_szs`](crate::szs::extract_szs), which returns a flat list of files,
+//! the result keeps every folder and archive as a node in a tree, for programs like asset
+//! viewers that browse the contents rather than write them to disk. Archives and discs can
+//! also be edited the same way, all the way through, with [`transform_archive`] and
+//! [`transform_disc`].
+
+use crate::{
+    afs::{self, AfsArchive, AfsError, AfsFile},
+    iso::{extract_iso, patch_iso, Fst, FstEntry, IsoError, IsoReader},
+    rarc::{Rarc, RarcError},
+    szs::{decompress_szs, CompressionLevel, SzsCompression, SzsError},
+    u8_archive::{self, U8Archive, U8Error},
+};
+use log::warn;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Write,
+    path::{Component, Path, PathBuf},
+};
+use thiserror::Error;
+
+/// A folder in an archive, with its entries by name
+#[derive(Debug, Clone, Default)]
+pub struct Folder {
+    pub entries: BTreeMap<String, TreeEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub enum TreeEntry {
+    File(Vec<u8>),
+    Folder(Folder),
+    /// An archive inside another one, opened up in place of its data
+    Archive(ArchiveTree),
+}
+
+/// The contents of an archive, along with what's needed to tell how it was stored
+#[derive(Debug, Clone)]
+pub struct ArchiveTree {
+    pub kind: ArchiveKind,
+    pub compression: SzsCompression,
+    /// Size of the archive as it was stored, before decompressing it
+    pub size: usize,
+    /// Size of the archive once decompressed. The same as `size` if it wasn't compressed.
+    pub decompressed_size: usize,
+    pub root: Folder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Rarc,
+    U8,
+    Afs,
+    /// A disc image, from [`extract_disc_tree`]
+    Iso,
+}
+
+/// Extracts an archive and every archive inside it, however deeply nested, without
+/// touching the disk. Archives may be RARC, U8, or AFS, and compressed with Yaz0 or Yay0.
+/// Nested files that look like archives but can't be read are kept as plain files. Files
+/// in AFS archives are named the way [`AfsArchive::index`] names them.
+pub fn extract_tree(data: Vec<u8>) -> Result<ArchiveTree, TreeError> {
+    let compression = SzsCompression::detect(&data);
+    let size = data.len();
+    let data = decompress_szs(data)?;
+    let (kind, root) = match data.get(..4) {
+        Some(b"RARC" | b"CRAR") => (ArchiveKind::Rarc, open_members(Rarc::parse(&data)?.files())),
+        Some(magic) if *magic == u8_archive::MAGIC => (ArchiveKind::U8, open_members(U8Archive::parse(&data)?.files())),
+        Some(magic) if *magic == afs::MAGIC => {
+            let afs = AfsArchive::parse(&data)?;
+            let paths = afs.index().files.into_iter().map(|entry| PathBuf::from(entry.path));
+            let files = afs.files().map(|(_, bytes)| bytes);
+            (ArchiveKind::Afs, open_members(paths.zip(files)))
+        }
+        _ => return Err(TreeError::NotAnArchive),
+    };
+    Ok(ArchiveTree {
+        kind,
+        compression,
+        size,
+        decompressed_size: data.len(),
+        root,
+    })
+}
+
+/// Extracts every file on a disc image, opening the archives on it like [`extract_tree`]
+/// does. The disc's sizes are those of the image file, which for a GCZ is compressed.
+pub fn extract_disc_tree(path: impl AsRef<Path>) -> Result<ArchiveTree, TreeError> {
+    let path = path.as_ref();
+    let size = std::fs::metadata(path).map_err(IsoError::from)?.len() as usize;
+    let files = extract_iso(path)?;
+    Ok(ArchiveTree {
+        kind: ArchiveKind::Iso,
+        compression: SzsCompression::None,
+        size,
+        decompressed_size: size,
+        root: open_members(files.iter().map(|file| (file.path.clone(), file.bytes.as_slice()))),
+    })
+}
+
+/// Builds the folders an archive's files are in, opening any files that are archives themselves
+fn open_members<'a>(files: impl Iterator<Item = (PathBuf, &'a [u8])>) -> Folder {
+    let mut root = Folder::default();
+    for (path, bytes) in files {
+        let entry = match is_archive(bytes) {
+            true => match extract_tree(bytes.to_vec()) {
+                Ok(archive) => TreeEntry::Archive(archive),
+                Err(e) => {
+                    warn!("Keeping {path:?} as a file since it can't be opened: {e}");
+                    TreeEntry::File(bytes.to_vec())
+                }
+            },
+            false => TreeEntry::File(bytes.to_vec()),
+        };
+        root.insert(&path, entry);
+    }
+    root
+}
+
+/// Calls `f` with every file in an archive and the archives nested inside it, however
+/// deeply, replacing the ones it returns new contents for. Paths lead through nested
+/// archives like [`Folder::get`] takes. Only archives with files that changed are rebuilt,
+/// and they're compressed again the way they were at `level`. Returns the new archive, or
+/// `None` if nothing changed.
+pub fn transform_archive(
+    data: &[u8],
+    level: CompressionLevel,
+    mut f: impl FnMut(&Path, &[u8]) -> Option<Vec<u8>>,
+) -> Result<Option<Vec<u8>>, TreeError> {
+    transform_nested(data, Path::new(""), level, &mut f)
+}
+
+/// Like [`transform_archive`] for every file on a disc image and in the archives on it,
+/// writing the rebuilt disc to `out` the way [`patch_iso`] does. Files are read from the
+/// image one at a time. Returns how many files on the disc itself changed.
+pub fn transform_disc<W: Write>(
+    iso_path: impl AsRef<Path>,
+    out: &mut W,
+    level: CompressionLevel,
+    mut f: impl FnMut(&Path, &[u8]) -> Option<Vec<u8>>,
+) -> Result<usize, TreeError> {
+    let iso_path = iso_path.as_ref();
+    let mut disc = IsoReader::open(iso_path)?;
+    let mut changed = HashMap::new();
+    for entry in Fst::open(iso_path)?.entries {
+        let FstEntry::File { path, offset, size } = entry else {
+            continue;
+        };
+        let mut bytes = vec![0; size as usize];
+        disc.read_at(offset as u64, &mut bytes)?;
+        if let Some(bytes) = transform_member(Path::new(&path), &bytes, level, &mut f) {
+            changed.insert(path, bytes);
+        }
+    }
+    patch_iso(iso_path, &changed, out)?;
+    Ok(changed.len())
+}
+
+type TransformFn<'a> = dyn FnMut(&Path, &[u8]) -> Option<Vec<u8>> + 'a;
+
+fn transform_nested(
+    data: &[u8],
+    path: &Path,
+    level: CompressionLevel,
+    f: &mut TransformFn,
+) -> Result<Option<Vec<u8>>, TreeError> {
+    let compression = SzsCompression::detect(data);
+    let data = decompress_szs(data.to_vec())?;
+    let mut member = |member_path: &Path, bytes: &[u8]| transform_member(&path.join(member_path), bytes, level, f);
+    let rebuilt = match data.get(..4) {
+        Some(b"RARC" | b"CRAR") => {
+            let mut rarc = Rarc::parse(&data)?;
+            // Only the files that changed should be any different
+            rarc.keep_trailing_data = true;
+            if rarc.transform(member) == 0 {
+                return Ok(None);
+            }
+            rarc.write()?
+        }
+        Some(magic) if *magic == u8_archive::MAGIC => {
+            let u8_archive = U8Archive::parse(&data)?;
+            let changed: HashMap<PathBuf, Vec<u8>> = u8_archive
+                .files()
+                .filter_map(|(file_path, bytes)| Some((file_path.clone(), member(&file_path, bytes)?)))
+                .collect();
+            if changed.is_empty() {
+                return Ok(None);
+            }
+            U8Archive::build(
+                u8_archive
+                    .files()
+                    .map(|(file_path, bytes)| match changed.get(&file_path) {
+                        Some(new) => (file_path, new.as_slice()),
+                        None => (file_path, bytes),
+                    }),
+            )?
+        }
+        Some(magic) if *magic == afs::MAGIC => {
+            let afs = AfsArchive::parse(&data)?;
+            let index = afs.index();
+            let changed: Vec<Option<Vec<u8>>> = index
+                .files
+                .iter()
+                .zip(afs.files())
+                .map(|(entry, (_, bytes))| member(Path::new(&entry.path), bytes))
+                .collect();
+            if changed.iter().all(Option::is_none) {
+                return Ok(None);
+            }
+            let files: Vec<AfsFile> = afs
+                .files()
+                .zip(&changed)
+                .map(|((entry, bytes), new)| AfsFile {
+                    name: &entry.name,
+                    modified: entry.modified,
+                    data: new.as_deref().unwrap_or(bytes),
+                })
+                .collect();
+            AfsArchive::build(&files, afs.has_names)?
+        }
+        _ => return Err(TreeError::NotAnArchive),
+    };
+    Ok(Some(compression.compress(&rebuilt, level)))
+}
+
+/// New contents for a file in an archive or on a disc, if it or anything inside it changed
+fn transform_member(path: &Path, bytes: &[u8], level: CompressionLevel, f: &mut TransformFn) -> Option<Vec<u8>> {
+    if is_archive(bytes) {
+        match transform_nested(bytes, path, level, f) {
+            Ok(changed) => return changed,
+            // Compressed files that aren't archives are passed to `f` like any other
+            Err(TreeError::NotAnArchive) => {}
+            Err(e) => warn!("Treating {path:?} as a file since it can't be opened: {e}"),
+        }
+    }
+    f(path, bytes).filter(|new| new != bytes)
+}
+
+/// Whether data starts like an archive, or a compressed file that might be one
+pub fn is_archive(data: &[u8]) -> bool {
+    matches!(data.get(..4), Some(b"RARC" | b"CRAR" | b"Yaz0" | b"Yaz1" | b"Yay0"))
+        || data.starts_with(&u8_archive::MAGIC)
+        || data.starts_with(&afs::MAGIC)
+}
+
+impl Folder {
+    /// Looks up an entry by its path, which can lead into nested archives as if they were
+    /// folders, like `scene/map.szs/model.bdl`
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<&TreeEntry> {
+        let mut folder = self;
+        let mut components = path.as_ref().components().filter(|c| matches!(c, Component::Normal(_)));
+        let mut entry = folder.entries.get(components.next()?.as_os_str().to_str()?)?;
+        for component in components {
+            folder = match entry {
+                TreeEntry::Folder(folder) => folder,
+                TreeEntry::Archive(archive) => &archive.root,
+                TreeEntry::File(_) => return None,
+            };
+            entry = folder.entries.get(component.as_os_str().to_str()?)?;
+        }
+        Some(entry)
+    }
+
+    /// Every file in this folder and the ones inside it, including inside nested
+    /// archives, with paths that lead through archives like [`Folder::get`] takes
+    pub fn files(&self) -> Vec<(PathBuf, &[u8])> {
+        let mut files = Vec::new();
+        self.collect_files(PathBuf::new(), &mut files);
+        files
+    }
+
+    fn collect_files<'a>(&'a self, parent: PathBuf, files: &mut Vec<(PathBuf, &'a [u8])>) {
+        for (name, entry) in self.entries.iter() {
+            let path = parent.join(name);
+            match entry {
+                TreeEntry::File(bytes) => files.push((path, bytes)),
+                TreeEntry::Folder(folder) => folder.collect_files(path, files),
+                TreeEntry::Archive(archive) => archive.root.collect_files(path, files),
+            }
+        }
+    }
+
+    fn insert(&mut self, path: &Path, entry: TreeEntry) {
+        let mut components: Vec<String> = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let Some(file_name) = components.pop() else {
+            return;
+        };
+        let mut folder = self;
+        for component in components {
+            let subfolder = folder
+                .entries
+                .entry(component)
+                .or_insert_with(|| TreeEntry::Folder(Folder::default()));
+            let TreeEntry::Folder(subfolder) = subfolder else {
+                // Archives only list files, so a name can't be both a file and a folder
+                return;
+            };
+            folder = subfolder;
+        }
+        folder.entries.insert(file_name, entry);
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TreeError {
+    #[error("Not a RARC, U8, or AFS archive")]
+    NotAnArchive,
+
+    #[error(transparent)]
+    Szs(#[from] SzsError),
+
+    #[error("Invalid RARC archive: {0}")]
+    Rarc(#[from] RarcError),
+
+    #[error("Invalid U8 archive: {0}")]
+    U8(#[from] U8Error),
+
+    #[error("Invalid AFS archive: {0}")]
+    Afs(#[from] AfsError),
+
+    #[error("Invalid disc image: {0}")]
+    Iso(#[from] IsoError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
@@ -0,0 +1,282 @@
+//! U8, the archive format Wii discs use for banners and most other bundled files.
+//! A flat list of nodes describes the directory tree, with each directory recording the
+//! index of the first node after its contents.
+
+use crate::{
+    util::{pad_to, read_str, read_u32},
+    virtual_fs::VirtualFile,
+    CodecError, Decode, Encode, FromBytes,
+};
+use encoding_rs::SHIFT_JIS;
+use std::{
+    collections::BTreeMap,
+    fs::{read, read_dir},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+pub const MAGIC: [u8; 4] = [0x55, 0xAA, 0x38, 0x2D];
+const HEADER_SIZE: usize = 0x20;
+const NODE_SIZE: usize = 0xC;
+
+pub struct U8Archive<'a> {
+    data: &'a [u8],
+    nodes: Vec<U8Node>,
+    string_table_offset: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct U8Node {
+    is_dir: bool,
+    name_offset: u32,
+    /// Offset of the file's data, or the index of a directory's parent
+    data_offset_or_parent: u32,
+    /// Size of the file's data, or the index of the first node after a directory
+    size_or_end: u32,
+}
+
+impl<'a> FromBytes<'a> for U8Archive<'a> {
+    fn from_bytes(data: &'a [u8]) -> Result<Self, CodecError> {
+        Ok(U8Archive::parse(data)?)
+    }
+}
+
+impl<'a> Decode for U8Archive<'a> {
+    type Out = Vec<VirtualFile>;
+    fn decode(&self) -> Result<Self::Out, CodecError> {
+        Ok(self
+            .files()
+            .map(|(path, bytes)| VirtualFile {
+                path,
+                bytes: bytes.to_vec(),
+            })
+            .collect())
+    }
+}
+
+/// Rebuilds the archive from its files. Empty directories aren't kept.
+impl<'a> Encode for U8Archive<'a> {
+    fn encode(&self) -> Result<Vec<u8>, CodecError> {
+        Ok(U8Archive::build(self.files())?)
+    }
+}
+
+impl<'a> U8Archive<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, U8Error> {
+        if data.len() < HEADER_SIZE || data[..4] != MAGIC {
+            return Err(U8Error::Magic);
+        }
+        let root_offset = read_u32(data, 0x4) as usize;
+        let root = read_node(data, root_offset)?;
+        let num_nodes = root.size_or_end as usize;
+        let string_table_offset = root_offset + num_nodes * NODE_SIZE;
+        if !root.is_dir || string_table_offset > data.len() {
+            return Err(U8Error::OutOfBounds(root_offset));
+        }
+        let nodes = (0..num_nodes)
+            .map(|i| read_node(data, root_offset + i * NODE_SIZE))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(U8Archive {
+            data,
+            nodes,
+            string_table_offset,
+        })
+    }
+
+    /// Every file in the archive with its path, in the order they're stored
+    pub fn files(&self) -> impl Iterator<Item = (PathBuf, &'a [u8])> + '_ {
+        // Directories still open at each node, as (first node after it, path)
+        let mut dirs: Vec<(usize, PathBuf)> = vec![(self.nodes.len(), PathBuf::new())];
+        self.nodes.iter().enumerate().skip(1).filter_map(move |(index, node)| {
+            while dirs.len() > 1 && index >= dirs.last().unwrap().0 {
+                dirs.pop();
+            }
+            let path = dirs.last().unwrap().1.join(self.name(node)?);
+            if node.is_dir {
+                dirs.push((node.size_or_end as usize, path));
+                None
+            } else {
+                let start = node.data_offset_or_parent as usize;
+                let bytes = self.data.get(start..start + node.size_or_end as usize)?;
+                Some((path, bytes))
+            }
+        })
+    }
+
+    /// Packs a directory on disk into an archive named after it
+    pub fn encode_dir(root: impl AsRef<Path>) -> Result<VirtualFile, U8Error> {
+        let root = root.as_ref();
+        if !root.is_dir() {
+            return Err(U8Error::NotADir);
+        }
+        let mut files = Vec::new();
+        read_tree(root, PathBuf::new(), &mut files)?;
+        Ok(VirtualFile {
+            path: root.with_extension("u8"),
+            bytes: U8Archive::build(files)?,
+        })
+    }
+
+    /// Builds an archive from a list of files, sorted by path. Parent directories are
+    /// created as needed.
+    pub fn build<P: AsRef<Path>, D: AsRef<[u8]>>(files: impl IntoIterator<Item = (P, D)>) -> Result<Vec<u8>, U8Error> {
+        let mut root = BuildDir::default();
+        for (path, data) in files {
+            let path = path.as_ref();
+            let mut dir = &mut root;
+            let mut components = path.iter().map(|c| c.to_string_lossy().into_owned()).peekable();
+            while let Some(name) = components.next() {
+                if components.peek().is_none() {
+                    dir.files.insert(name, data.as_ref().to_vec());
+                } else {
+                    dir = dir.dirs.entry(name).or_default();
+                }
+            }
+        }
+
+        let mut nodes = Vec::new();
+        let mut string_table = vec![0u8]; // The root node has an empty name
+        let mut file_data = Vec::new();
+        nodes.push(U8Node {
+            is_dir: true,
+            name_offset: 0,
+            data_offset_or_parent: 0,
+            size_or_end: 0, // Filled in below
+        });
+        root.flatten(0, &mut nodes, &mut string_table, &mut file_data)?;
+        nodes[0].size_or_end = nodes.len() as u32;
+
+        let header_size = nodes.len() * NODE_SIZE + string_table.len();
+        let data_offset = (HEADER_SIZE + header_size).next_multiple_of(0x20);
+        let mut out = Vec::with_capacity(data_offset + file_data.len());
+        out.extend(MAGIC);
+        out.extend((HEADER_SIZE as u32).to_be_bytes());
+        out.extend((header_size as u32).to_be_bytes());
+        out.extend((data_offset as u32).to_be_bytes());
+        out.resize(HEADER_SIZE, 0);
+        for node in nodes.iter() {
+            let data_offset_or_parent = if node.is_dir {
+                node.data_offset_or_parent
+            } else {
+                node.data_offset_or_parent + data_offset as u32
+            };
+            out.extend(((node.is_dir as u32) << 24 | node.name_offset).to_be_bytes());
+            out.extend(data_offset_or_parent.to_be_bytes());
+            out.extend(node.size_or_end.to_be_bytes());
+        }
+        out.extend(string_table);
+        out.resize(data_offset, 0);
+        out.extend(file_data);
+        Ok(out)
+    }
+
+    fn name(&self, node: &U8Node) -> Option<String> {
+        let start = self.string_table_offset + node.name_offset as usize;
+        let len = self.data.get(start..)?.iter().position(|&b| b == 0)?;
+        Some(read_str(self.data, start as u32, len as u32).into_owned())
+    }
+}
+
+fn read_node(data: &[u8], offset: usize) -> Result<U8Node, U8Error> {
+    if offset + NODE_SIZE > data.len() {
+        return Err(U8Error::OutOfBounds(offset));
+    }
+    let type_and_name = read_u32(data, offset as u32);
+    Ok(U8Node {
+        is_dir: type_and_name >> 24 == 1,
+        name_offset: type_and_name & 0xFFFFFF,
+        data_offset_or_parent: read_u32(data, offset as u32 + 4),
+        size_or_end: read_u32(data, offset as u32 + 8),
+    })
+}
+
+/// A directory being built into an archive. Files come before subdirectories.
+#[derive(Default)]
+struct BuildDir {
+    files: BTreeMap<String, Vec<u8>>,
+    dirs: BTreeMap<String, BuildDir>,
+}
+
+impl BuildDir {
+    /// Adds this directory's contents to the node list, depth first. File data offsets
+    /// are relative to the start of the data section.
+    fn flatten(
+        &self,
+        index: usize,
+        nodes: &mut Vec<U8Node>,
+        string_table: &mut Vec<u8>,
+        file_data: &mut Vec<u8>,
+    ) -> Result<(), U8Error> {
+        for (name, data) in self.files.iter() {
+            nodes.push(U8Node {
+                is_dir: false,
+                name_offset: add_name(string_table, name)?,
+                data_offset_or_parent: file_data.len() as u32,
+                size_or_end: data.len() as u32,
+            });
+            file_data.extend(data);
+            pad_to::<0x20>(file_data);
+        }
+        for (name, dir) in self.dirs.iter() {
+            let dir_index = nodes.len();
+            nodes.push(U8Node {
+                is_dir: true,
+                name_offset: add_name(string_table, name)?,
+                data_offset_or_parent: index as u32,
+                size_or_end: 0, // Filled in once the contents are added
+            });
+            dir.flatten(dir_index, nodes, string_table, file_data)?;
+            nodes[dir_index].size_or_end = nodes.len() as u32;
+        }
+        Ok(())
+    }
+}
+
+fn add_name(string_table: &mut Vec<u8>, name: &str) -> Result<u32, U8Error> {
+    let offset = string_table.len() as u32;
+    if offset > 0xFFFFFF {
+        return Err(U8Error::StringTableOverflow);
+    }
+    // Names are read back as Shift-JIS, so they need to survive the round trip
+    let (encoded, _, had_errors) = SHIFT_JIS.encode(name);
+    if had_errors || encoded.contains(&0) {
+        return Err(U8Error::InvalidName(name.to_owned()));
+    }
+    string_table.extend(encoded.iter());
+    string_table.push(0);
+    Ok(offset)
+}
+
+fn read_tree(dir: &Path, prefix: PathBuf, files: &mut Vec<(PathBuf, Vec<u8>)>) -> Result<(), U8Error> {
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let path = prefix.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            read_tree(&entry.path(), path, files)?;
+        } else {
+            files.push((path, read(entry.path())?));
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Error)]
+pub enum U8Error {
+    #[error("Missing U8 magic number")]
+    Magic,
+
+    #[error("Node at {0:#X} is past the end of the archive")]
+    OutOfBounds(usize),
+
+    #[error("Can only pack directories")]
+    NotADir,
+
+    #[error("{0:?} can't be used as a file name in a U8 archive")]
+    InvalidName(String),
+
+    #[error("Too many file names to fit in a U8 archive's string table")]
+    StringTableOverflow,
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
@@ -1,5 +1,7 @@
+use crate::rarc::ByteOrder;
 use encoding_rs::SHIFT_JIS;
-use std::{borrow::Cow, num::ParseIntError};
+use std::{borrow::Cow, fmt::Display, num::ParseIntError};
+use thiserror::Error;
 
 pub fn read_u16(data: &[u8], offset: u32) -> u16 {
     u16::from_be_bytes(data[offset as usize..offset as usize + 2].try_into().unwrap())
@@ -9,20 +11,147 @@ pub fn read_u32(data: &[u8], offset: u32) -> u32 {
     u32::from_be_bytes(data[offset as usize..offset as usize + 4].try_into().unwrap())
 }
 
-pub fn read_u64(data: &[u8], offset: u32) -> u64 {
-    u64::from_be_bytes(data[offset as usize..offset as usize + 8].try_into().unwrap())
-}
-
 pub fn read_str(data: &[u8], offset: u32, len: u32) -> Cow<'_, str> {
     SHIFT_JIS.decode(&data[offset as usize..(offset + len) as usize]).0
 }
 
-pub fn read_str_until_null(data: &[u8], offset: u32) -> Cow<'_, str> {
-    let mut i = 0;
-    while data[offset as usize + i] != b"\0"[0] {
-        i += 1;
+/// Reads values one after another from a slice, keeping track of where it's up to.
+/// Every read is bounds checked, so a bad offset or count in a corrupted file is an
+/// error rather than a panic.
+#[derive(Debug, Clone)]
+pub(crate) struct BinReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    byte_order: ByteOrder,
+}
+
+/// A read that would have gone past the end of the data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("Reading {len} bytes at {offset:#X} runs past the end of the data ({size:#X} bytes)")]
+pub(crate) struct OutOfBounds {
+    pub offset: usize,
+    pub len: usize,
+    pub size: usize,
+}
+
+impl<'a> BinReader<'a> {
+    /// Starts at `offset` in `data`, reading big-endian values
+    pub fn at(data: &'a [u8], offset: usize) -> Self {
+        BinReader {
+            data,
+            position: offset,
+            byte_order: ByteOrder::Big,
+        }
+    }
+
+    pub fn new(data: &'a [u8]) -> Self {
+        Self::at(data, 0)
+    }
+
+    /// For byte-swapped files, such as little-endian RARCs
+    pub fn with_byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Moves to `offset` from the start of the data. Moving past the end is only an
+    /// error once something is read there.
+    pub fn seek(&mut self, offset: usize) {
+        self.position = offset;
+    }
+
+    pub fn skip(&mut self, len: usize) {
+        self.position = self.position.saturating_add(len);
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], OutOfBounds> {
+        let bytes = self
+            .position
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or(OutOfBounds {
+                offset: self.position,
+                len,
+                size: self.data.len(),
+            })?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], OutOfBounds> {
+        Ok(self.bytes(N)?.try_into().unwrap())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, OutOfBounds> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn i8(&mut self) -> Result<i8, OutOfBounds> {
+        Ok(self.u8()? as i8)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, OutOfBounds> {
+        let bytes = self.array()?;
+        Ok(match self.byte_order {
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+        })
+    }
+
+    pub fn i16(&mut self) -> Result<i16, OutOfBounds> {
+        Ok(self.u16()? as i16)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, OutOfBounds> {
+        let bytes = self.array()?;
+        Ok(match self.byte_order {
+            ByteOrder::Big => u32::from_be_bytes(bytes),
+            ByteOrder::Little => u32::from_le_bytes(bytes),
+        })
+    }
+
+    pub fn u64(&mut self) -> Result<u64, OutOfBounds> {
+        let bytes = self.array()?;
+        Ok(match self.byte_order {
+            ByteOrder::Big => u64::from_be_bytes(bytes),
+            ByteOrder::Little => u64::from_le_bytes(bytes),
+        })
+    }
+
+    /// `len` bytes of Shift-JIS text
+    pub fn str(&mut self, len: usize) -> Result<Cow<'a, str>, OutOfBounds> {
+        Ok(SHIFT_JIS.decode(self.bytes(len)?).0)
+    }
+}
+
+/// Where permissive readers note the damage they worked around. Strict readers have
+/// nowhere to put it, so the first problem they find is an error instead.
+pub(crate) struct Warnings<'a>(Option<&'a mut Vec<String>>);
+
+impl<'a> Warnings<'a> {
+    pub fn strict() -> Self {
+        Warnings(None)
+    }
+
+    pub fn permissive(warnings: &'a mut Vec<String>) -> Self {
+        Warnings(Some(warnings))
+    }
+
+    /// Fails with `error` when reading strictly. Otherwise records it along with what's
+    /// being done about it, and lets the reader carry on.
+    pub fn recover<E: Display>(&mut self, error: E, action: &str) -> Result<(), E> {
+        match &mut self.0 {
+            Some(warnings) => {
+                warnings.push(format!("{error}. {action}"));
+                Ok(())
+            }
+            None => Err(error),
+        }
     }
-    read_str(data, offset, i as u32)
 }
 
 pub fn to_hex_string(bytes: &[u8]) -> String {
@@ -35,13 +164,50 @@ pub fn to_hex_string(bytes: &[u8]) -> String {
 
 pub fn from_hex_string(string: &str) -> Result<Vec<u8>, ParseIntError> {
     (0..string.len() / 2)
-        .into_iter()
         .map(|idx| u8::from_str_radix(&string[idx * 2..(idx * 2) + 2], 16))
         .collect()
 }
 
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with padding, for raw bytes kept in JSON
+pub fn to_base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |group, (i, &b)| group | (b as u32) << (16 - i * 8));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64_ALPHABET[(group >> (18 - i * 6)) as usize & 0x3F] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+/// Reads base64 written by [`to_base64`]. Padding is optional, and whitespace isn't allowed.
+pub fn from_base64(string: &str) -> Option<Vec<u8>> {
+    let digits = string.trim_end_matches('=').as_bytes();
+    if digits.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(digits.len() * 3 / 4);
+    for chunk in digits.chunks(4) {
+        let mut group = 0u32;
+        for (i, &digit) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|&c| c == digit)?;
+            group |= (value as u32) << (18 - i * 6);
+        }
+        out.extend(&group.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(out)
+}
+
 pub fn pad_to<const N: usize>(buf: &mut Vec<u8>) {
-    while buf.len() % N != 0 {
+    while !buf.len().is_multiple_of(N) {
         buf.push(0);
     }
 }
@@ -49,3 +215,49 @@ pub fn pad_to<const N: usize>(buf: &mut Vec<u8>) {
 pub fn padded_index_to<const N: u32>(idx: u32) -> u32 {
     (idx + (N - 1)) & !(N - 1)
 }
+
+/// MD5, which Wii banners use to check their contents
+pub fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14, 20, 5, 9, 14,
+        20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6,
+        10, 15, 21,
+    ];
+    let constants: [u32; 64] = std::array::from_fn(|i| ((i as f64 + 1.0).sin().abs() * 4294967296.0) as u32);
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend(((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for chunk in message.chunks_exact(64) {
+        let words: [u32; 16] = std::array::from_fn(|i| u32::from_le_bytes(chunk[i * 4..i * 4 + 4].try_into().unwrap()));
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(constants[i])
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[i]);
+            (a, b, c, d) = (d, b.wrapping_add(rotated), b, c);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+
+    let mut out = [0u8; 16];
+    for (i, word) in state.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
@@ -0,0 +1,57 @@
+//! JavaScript bindings for WebAssembly builds, enabled with the `wasm` feature.
+//! Build with `wasm-pack build cube --features wasm`. None of these touch the filesystem,
+//! so they work in the browser as well as in Node.
+
+use crate::{bmg::Bmg, bti::BtiImage, szs::extract_szs};
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+/// Extracts an SZS archive, which may or may not be Yaz0 compressed, into an array of
+/// `{ path: string, bytes: Uint8Array }` objects.
+#[wasm_bindgen(js_name = extractSzs)]
+pub fn extract_szs_js(data: &[u8]) -> Result<Array, JsError> {
+    let files = Array::new();
+    for file in extract_szs(data.to_vec())? {
+        let path = file.path.to_string_lossy().replace('\\', "/");
+        let file = object(&[
+            ("path", JsValue::from_str(&path)),
+            ("bytes", Uint8Array::from(file.bytes.as_slice()).into()),
+        ])?;
+        files.push(&file);
+    }
+    Ok(files)
+}
+
+/// Decodes a BTI texture into `{ width: number, height: number, pixels: Uint8Array }`,
+/// where `pixels` is row-major RGBA and can be passed straight to `new ImageData()`.
+#[wasm_bindgen(js_name = decodeBti)]
+pub fn decode_bti(data: &[u8]) -> Result<Object, JsError> {
+    let bti = BtiImage::decode(data)?;
+    let pixels: Vec<u8> = bti.pixels().flatten().copied().collect();
+    object(&[
+        ("width", bti.width.into()),
+        ("height", bti.height.into()),
+        ("pixels", Uint8Array::from(pixels.as_slice()).into()),
+    ])
+}
+
+/// Converts a BMG file to the same JSON representation the CLI uses
+#[wasm_bindgen(js_name = bmgToJson)]
+pub fn bmg_to_json(data: &[u8]) -> Result<String, JsError> {
+    Ok(serde_json::to_string_pretty(&Bmg::read(data)?)?)
+}
+
+/// Converts JSON in the format produced by `bmgToJson` back to a BMG file
+#[wasm_bindgen(js_name = jsonToBmg)]
+pub fn json_to_bmg(json: &str) -> Result<Vec<u8>, JsError> {
+    let bmg: Bmg = serde_json::from_str(json)?;
+    Ok(bmg.write())
+}
+
+fn object(fields: &[(&str, JsValue)]) -> Result<Object, JsError> {
+    let object = Object::new();
+    for (key, value) in fields {
+        Reflect::set(&object, &JsValue::from_str(key), value).map_err(|_| JsError::new("Couldn't build object"))?;
+    }
+    Ok(object)
+}
@@ -0,0 +1,189 @@
+//! Yay0, the LZ compression scheme used by SZP archives. Unlike Yaz0, the flag bits,
+//! back-references, and literal bytes are stored in three separate streams.
+
+use crate::{szs::CompressionLevel, util::read_u32};
+use log::debug;
+use std::collections::HashMap;
+use thiserror::Error;
+
+const HEADER_LEN: usize = 0x10;
+const MAX_DISTANCE: usize = 0x1000;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 0xFF + 0x12;
+
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Yay0Error> {
+    if data.len() < HEADER_LEN || &data[..4] != b"Yay0" {
+        return Err(Yay0Error::Magic);
+    }
+    let size = read_u32(data, 0x4) as usize;
+    let mut link_offset = read_u32(data, 0x8) as usize;
+    let mut chunk_offset = read_u32(data, 0xC) as usize;
+    let mut mask_offset = HEADER_LEN;
+
+    // The size comes from the header, so don't reserve more than the data could expand to.
+    // No input byte makes more than the longest match does.
+    let mut out = Vec::with_capacity(size.min(data.len().saturating_mul(MAX_MATCH)));
+    let mut mask = 0u32;
+    let mut mask_bits = 0;
+    while out.len() < size {
+        if mask_bits == 0 {
+            mask = u32::from_be_bytes(read_array(data, mask_offset)?);
+            mask_offset += 4;
+            mask_bits = 32;
+        }
+
+        if mask & 0x80000000 != 0 {
+            out.push(*data.get(chunk_offset).ok_or(Yay0Error::Truncated)?);
+            chunk_offset += 1;
+        } else {
+            let link = u16::from_be_bytes(read_array(data, link_offset)?) as usize;
+            link_offset += 2;
+            let distance = (link & 0xFFF) + 1;
+            let count = match link >> 12 {
+                0 => {
+                    let count = *data.get(chunk_offset).ok_or(Yay0Error::Truncated)? as usize + 0x12;
+                    chunk_offset += 1;
+                    count
+                }
+                n => n + 2,
+            };
+            if distance > out.len() {
+                return Err(Yay0Error::InvalidBackReference(out.len()));
+            }
+            let start = out.len() - distance;
+            // Copy byte by byte since the source and destination can overlap
+            for i in 0..count.min(size - out.len()) {
+                out.push(out[start + i]);
+            }
+        }
+
+        mask <<= 1;
+        mask_bits -= 1;
+    }
+    Ok(out)
+}
+
+pub fn compress(data: &[u8], level: CompressionLevel) -> Vec<u8> {
+    // How many earlier positions with the same first bytes to try for each match
+    let max_candidates = match level {
+        CompressionLevel::None => 0,
+        CompressionLevel::Fast => 8,
+        CompressionLevel::Lookahead(quality) => quality.clamp(1, 10) as usize * 32,
+        CompressionLevel::Nintendo => MAX_DISTANCE,
+    };
+
+    let mut masks = Vec::new();
+    let mut links = Vec::new();
+    let mut chunks = Vec::new();
+    let mut mask = 0u32;
+    let mut mask_bits = 0;
+
+    let mut positions: HashMap<[u8; MIN_MATCH], Vec<usize>> = HashMap::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = find_match(data, pos, &positions, max_candidates);
+        mask <<= 1;
+        if length >= MIN_MATCH {
+            let distance = (distance - 1) as u16;
+            if length < 0x12 {
+                links.extend(((length as u16 - 2) << 12 | distance).to_be_bytes());
+            } else {
+                links.extend(distance.to_be_bytes());
+                chunks.push((length - 0x12) as u8);
+            }
+        } else {
+            mask |= 1;
+            chunks.push(data[pos]);
+        }
+        mask_bits += 1;
+        if mask_bits == 32 {
+            masks.extend(mask.to_be_bytes());
+            mask = 0;
+            mask_bits = 0;
+        }
+
+        let next = pos + length.max(1);
+        if max_candidates > 0 {
+            for i in pos..next.min(data.len().saturating_sub(MIN_MATCH - 1)) {
+                positions.entry(prefix(data, i)).or_default().push(i);
+            }
+        }
+        pos = next;
+    }
+    if mask_bits > 0 {
+        masks.extend((mask << (32 - mask_bits)).to_be_bytes());
+    }
+    // Keep the literal stream word aligned
+    if links.len() % 4 != 0 {
+        links.extend([0, 0]);
+    }
+
+    let link_offset = HEADER_LEN + masks.len();
+    let chunk_offset = link_offset + links.len();
+    let mut out = Vec::with_capacity(chunk_offset + chunks.len());
+    out.extend(b"Yay0");
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend((link_offset as u32).to_be_bytes());
+    out.extend((chunk_offset as u32).to_be_bytes());
+    out.extend(masks);
+    out.extend(links);
+    out.extend(chunks);
+    debug!("Yay0 compressed {} bytes to {} at level {level}", data.len(), out.len());
+    out
+}
+
+/// Finds the longest earlier occurrence of the data at `pos`, returning its length and distance
+fn find_match(
+    data: &[u8],
+    pos: usize,
+    positions: &HashMap<[u8; MIN_MATCH], Vec<usize>>,
+    max_candidates: usize,
+) -> (usize, usize) {
+    if max_candidates == 0 || pos + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+    let Some(candidates) = positions.get(&prefix(data, pos)) else {
+        return (0, 0);
+    };
+    let max_length = MAX_MATCH.min(data.len() - pos);
+    let mut best = (0, 0);
+    for &candidate in candidates
+        .iter()
+        .rev()
+        .take(max_candidates)
+        .take_while(|&&c| pos - c <= MAX_DISTANCE)
+    {
+        let length = (0..max_length)
+            .take_while(|&i| data[candidate + i] == data[pos + i])
+            .count();
+        if length > best.0 {
+            best = (length, pos - candidate);
+            if length == max_length {
+                break;
+            }
+        }
+    }
+    best
+}
+
+fn prefix(data: &[u8], pos: usize) -> [u8; MIN_MATCH] {
+    data[pos..pos + MIN_MATCH].try_into().unwrap()
+}
+
+fn read_array<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], Yay0Error> {
+    data.get(offset..offset + N)
+        .ok_or(Yay0Error::Truncated)
+        .map(|bytes| bytes.try_into().unwrap())
+}
+
+#[derive(Debug, Error)]
+pub enum Yay0Error {
+    #[error("Missing Yay0 magic number")]
+    Magic,
+
+    #[error("Compressed data ends early")]
+    Truncated,
+
+    #[error("Back-reference before the start of the data at output offset {0:#X}")]
+    InvalidBackReference(usize),
+}
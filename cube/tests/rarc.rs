@@ -0,0 +1,59 @@
+use cube_rs::rarc::{ByteOrder, LoadLocation, LoadLocations, Rarc};
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+#[test]
+fn header_splits_file_data_by_load_location() {
+    let root = std::env::temp_dir().join(format!("cube-rarc-test-{}", std::process::id()));
+    fs::create_dir_all(root.join("sub")).unwrap();
+    for (path, size) in [
+        ("mram.bin", 0x30),
+        ("aram.bin", 0x45),
+        ("dvd.bin", 0x10),
+        ("sub/aram.bin", 0x20),
+    ] {
+        fs::write(root.join(path), vec![0xAB; size]).unwrap();
+    }
+    let load_locations = LoadLocations {
+        default: LoadLocation::Mram,
+        files: BTreeMap::from([
+            (PathBuf::from("aram.bin"), LoadLocation::Aram),
+            (PathBuf::from("dvd.bin"), LoadLocation::Dvd),
+            (PathBuf::from("sub/aram.bin"), LoadLocation::Aram),
+        ]),
+    };
+    let mut packed = Vec::new();
+    let mut with_trailing_data = Vec::new();
+    let result = Rarc::encode_to(&root, &mut packed, 0x20, ByteOrder::Big, &load_locations, None).and_then(|_| {
+        let trailing_data = Some(&b"trailing data"[..]);
+        Rarc::encode_to(
+            &root,
+            &mut with_trailing_data,
+            0x20,
+            ByteOrder::Big,
+            &load_locations,
+            trailing_data,
+        )
+    });
+    fs::remove_dir_all(&root).unwrap();
+    result.unwrap();
+
+    let rarc = Rarc::parse(&packed).unwrap();
+    assert_eq!(rarc.header.mram_data_length, 0x40);
+    assert_eq!(rarc.header.aram_data_length, 0x60 + 0x20);
+    assert_eq!(rarc.header.file_data_length, 0x40 + 0x60 + 0x20 + 0x20);
+    let read_locations = rarc.load_locations();
+    for path in ["mram.bin", "aram.bin", "dvd.bin", "sub/aram.bin"] {
+        assert_eq!(read_locations.get(path), load_locations.get(path));
+    }
+
+    let repacked = rarc.write().unwrap();
+    assert_eq!(repacked[..0x20], packed[..0x20]);
+    assert_eq!(repacked, packed);
+
+    // The last file is in ARAM, so that's where the trailing data is counted
+    let mut rarc = Rarc::parse(&with_trailing_data).unwrap();
+    assert_eq!(rarc.header.mram_data_length, 0x40);
+    assert_eq!(rarc.header.aram_data_length, 0x60 + 0x20 + 13);
+    rarc.keep_trailing_data = true;
+    assert_eq!(rarc.write().unwrap(), with_trailing_data);
+}
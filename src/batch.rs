@@ -0,0 +1,161 @@
+use crate::{
+    commands::{Cli, Commands},
+    convert::try_convert,
+    extract::try_extract,
+    pack::{default_pack_output, try_pack},
+    report::{OutputFile, Report},
+};
+use clap::{Command, FromArgMatches};
+use serde::Deserialize;
+use std::{
+    error::Error,
+    ffi::OsString,
+    fs::{metadata, read_to_string},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+/// A list of jobs read from a TOML, YAML, or JSON file, e.g.
+///
+/// ```toml
+/// [[jobs]]
+/// command = "extract"
+/// input = "files/user/Kando/map/tutorial/texts.szs"
+/// output = "build/texts"
+/// args = ["--extract-bti", "true"]
+/// ```
+///
+/// or in YAML
+///
+/// ```yaml
+/// jobs:
+///   - command: extract
+///     input: files/user/Kando/map/tutorial/texts.szs
+///     output: build/texts
+///     args: ["--extract-bti", "true"]
+/// ```
+///
+/// Relative paths are relative to the worklist file.
+#[derive(Debug, Deserialize)]
+struct Worklist {
+    jobs: Vec<Job>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Job {
+    /// One of extract, pack, or convert
+    command: String,
+    input: PathBuf,
+    /// Required for convert. Used the same way as `-o` for extract and pack.
+    output: Option<PathBuf>,
+    /// Any other options, exactly as they'd be given on the command line
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+/// Runs every job in the worklist on up to `threads` threads, carrying on past any that
+/// fail. `cli` is the command line parser including any defaults from the config file.
+pub fn try_batch(worklist_path: &Path, threads: usize, cli: &Command, json: bool) -> Result<(), Box<dyn Error>> {
+    let contents = read_to_string(worklist_path).map_err(|e| format!("Couldn't read {worklist_path:?}: {e}"))?;
+    let extension = worklist_path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    let worklist: Worklist = match extension.as_deref() {
+        Some("toml") => toml::from_str(&contents)?,
+        Some("yaml" | "yml") => serde_yaml::from_str(&contents)?,
+        _ => serde_json::from_str(&contents)?,
+    };
+    let base_dir = worklist_path.parent().unwrap_or(Path::new(""));
+
+    // Each thread takes the next job that hasn't been started yet
+    let next_job = AtomicUsize::new(0);
+    let reports: Mutex<Vec<(usize, Report)>> = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, worklist.jobs.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next_job.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = worklist.jobs.get(index) else {
+                    break;
+                };
+                let start = Instant::now();
+                let result = run_job(job, base_dir, cli);
+                let report = Report::new(job.command.clone(), vec![job.input.clone()], result, start.elapsed());
+                reports.lock().unwrap().push((index, report));
+            });
+        }
+    });
+    let mut reports = reports.into_inner().unwrap();
+    reports.sort_by_key(|(index, _)| *index);
+    let reports: Vec<Report> = reports.into_iter().map(|(_, report)| report).collect();
+
+    let failed = reports.iter().filter(|report| !report.errors.is_empty()).count();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        for report in reports.iter() {
+            let status = if report.errors.is_empty() { "ok" } else { "FAILED" };
+            println!(
+                "[{status}] {} {} ({} files, {}ms)",
+                report.command,
+                report.inputs[0].to_string_lossy(),
+                report.outputs.len(),
+                report.duration_ms
+            );
+            for e in report.errors.iter() {
+                println!("    {e}");
+            }
+        }
+        println!("{} of {} jobs succeeded", reports.len() - failed, reports.len());
+    }
+
+    if failed > 0 {
+        return Err(format!("{failed} of {} jobs failed", reports.len()).into());
+    }
+    Ok(())
+}
+
+fn run_job(job: &Job, base_dir: &Path, cli: &Command) -> Result<Vec<OutputFile>, Box<dyn Error>> {
+    let input = base_dir.join(&job.input);
+    if !input.exists() {
+        return Err(format!("{input:?} doesn't exist").into());
+    }
+    let output = job.output.as_ref().map(|output| base_dir.join(output));
+
+    // Parse the job like a command line so it gets the same options and defaults
+    let mut args: Vec<OsString> = vec!["cube".into(), job.command.clone().into(), input.into()];
+    match (job.command.as_str(), output) {
+        ("convert", Some(output)) => args.push(output.into()),
+        ("convert", None) => return Err("Convert jobs need an output".into()),
+        (_, Some(output)) => args.extend(["-o".into(), output.into()]),
+        (_, None) => {}
+    }
+    args.extend(job.args.iter().map(OsString::from));
+    let matches = cli.clone().try_get_matches_from(args)?;
+
+    match Cli::from_arg_matches(&matches)?.subcommand {
+        Commands::Extract { files, out, options } => try_extract(files, out.as_deref(), &options),
+        // Watching never finishes, so the rest of the batch would never run
+        Commands::Pack { watch: true, .. } => Err("Pack jobs can't use --watch".into()),
+        Commands::Pack { file, out, options, .. } => {
+            let out = out.or_else(|| default_pack_output(&file, &options));
+            Ok(try_pack(file, out.as_deref(), &options)?)
+        }
+        Commands::Convert { input, output, options } => {
+            try_convert(&input, &output, &options)?;
+            Ok(vec![OutputFile {
+                size: metadata(&output)?.len() as usize,
+                source: input,
+                path: output,
+                format: None,
+                archive: None,
+                load_location: None,
+            }])
+        }
+        _ => Err(format!("'{}' can't be used in a batch", job.command).into()),
+    }
+}
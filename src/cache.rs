@@ -0,0 +1,77 @@
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{create_dir_all, read, read_dir, write},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Folder packed archives are cached in, relative to wherever `cube pack` is run from
+pub const CACHE_DIR: &str = ".cube-cache";
+
+/// Packed archives from earlier runs, looked up by a hash of everything that went into
+/// them so unchanged folders don't have to be compressed again
+pub struct PackCache {
+    key: String,
+}
+
+impl PackCache {
+    /// Hashes the contents of `dir` along with `settings`, which should describe everything
+    /// else that changes the packed output. File names are part of the hash, but not when
+    /// the files were modified.
+    pub fn new(dir: &Path, settings: &[u8]) -> io::Result<Self> {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hash_field(&mut hasher, settings);
+        hash_tree(&mut hasher, dir, Path::new(""))?;
+        Ok(PackCache {
+            key: format!("{:x}", hasher.finalize()),
+        })
+    }
+
+    fn path(&self) -> PathBuf {
+        Path::new(CACHE_DIR).join(&self.key)
+    }
+
+    pub fn get(&self) -> Option<Vec<u8>> {
+        let bytes = read(self.path()).ok()?;
+        debug!("Reusing cached archive {:?}", self.path());
+        Some(bytes)
+    }
+
+    /// Saves packed output for next time. Failing to is only worth a warning, since the
+    /// output itself is fine.
+    pub fn put(&self, bytes: &[u8]) {
+        if let Err(e) = create_dir_all(CACHE_DIR).and_then(|_| write(self.path(), bytes)) {
+            warn!("Couldn't cache packed archive in {CACHE_DIR:?}: {e}");
+        }
+    }
+}
+
+/// Length-prefixed, so that fields running into each other can't hash the same
+fn hash_field(hasher: &mut Sha256, bytes: &[u8]) {
+    hasher.update((bytes.len() as u64).to_le_bytes());
+    hasher.update(bytes);
+}
+
+/// Hashes every file and folder in `dir` in sorted order, so the hash doesn't depend on
+/// the order the file system lists them in
+fn hash_tree(hasher: &mut Sha256, dir: &Path, relative: &Path) -> io::Result<()> {
+    let mut entries = read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        let name = path.file_name().unwrap_or_default();
+        let relative = relative.join(name);
+        hash_field(hasher, relative.as_os_str().as_encoded_bytes());
+        if path.is_dir() {
+            hasher.update([0]);
+            hash_tree(hasher, &path, &relative)?;
+        } else {
+            hasher.update([1]);
+            hash_field(hasher, &read(&path)?);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::{read, read_to_string, write},
+    path::{absolute, Path, PathBuf},
+};
+
+/// Results of checking a tree against its checksum manifest. Files that match aren't listed.
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub checked: usize,
+    pub changed: Vec<PathBuf>,
+    pub missing: Vec<PathBuf>,
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Writes the checksums of files in the format `sha256sum` uses, so the manifest can also
+/// be checked with `sha256sum -c`. Paths are relative to the manifest's folder where
+/// possible, so the tree can be moved along with it.
+pub fn write_checksums(manifest_path: &Path, checksums: &BTreeMap<PathBuf, String>) -> Result<(), Box<dyn Error>> {
+    let manifest_dir = absolute(manifest_path)?.parent().unwrap_or(Path::new("")).to_owned();
+    let mut manifest = String::new();
+    for (path, checksum) in checksums {
+        let path = absolute(path)?;
+        let relative = path.strip_prefix(&manifest_dir).unwrap_or(&path);
+        manifest.push_str(&format!("{checksum}  {}\n", relative.to_string_lossy()));
+    }
+    write(manifest_path, manifest)?;
+    Ok(())
+}
+
+pub fn try_verify_tree(manifest_path: &Path, json: bool) -> Result<(), Box<dyn Error>> {
+    let report = verify_tree(manifest_path)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for path in report.changed.iter() {
+            println!("changed  {}", path.to_string_lossy());
+        }
+        for path in report.missing.iter() {
+            println!("missing  {}", path.to_string_lossy());
+        }
+    }
+    let problems = report.changed.len() + report.missing.len();
+    if problems > 0 {
+        return Err(format!("{problems} of {} files don't match {manifest_path:?}", report.checked).into());
+    }
+    if !json {
+        println!("All {} files match", report.checked);
+    }
+    Ok(())
+}
+
+/// Checks every file listed in a manifest written by `--write-checksums`
+pub fn verify_tree(manifest_path: &Path) -> Result<VerifyReport, Box<dyn Error>> {
+    let manifest_dir = manifest_path.parent().unwrap_or(Path::new(""));
+    let mut report = VerifyReport::default();
+    for (line_number, line) in read_to_string(manifest_path)?.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        // sha256sum marks files hashed in binary mode with '*' instead of a second space
+        let Some((checksum, path)) = line.split_once("  ").or_else(|| line.split_once(" *")) else {
+            return Err(format!(
+                "Line {} of {manifest_path:?} isn't a checksum and a path",
+                line_number + 1
+            )
+            .into());
+        };
+        let path = PathBuf::from(path);
+        report.checked += 1;
+        match read(manifest_dir.join(&path)) {
+            Ok(bytes) if sha256_hex(&bytes).eq_ignore_ascii_case(checksum) => {}
+            Ok(_) => report.changed.push(path),
+            Err(_) => report.missing.push(path),
+        }
+    }
+    Ok(report)
+}
@@ -1,6 +1,15 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use crate::{handlers::registry, profile::Profile, recompress::DevCompression, template::PathTemplate};
 use clap::{ArgAction, Args, Parser, Subcommand};
+use clap_complete::Shell;
+use cube_rs::{
+    bti::{BtiQuality, TextureFormat},
+    detect::FileFormat,
+    png::IntensityPng,
+    rarc::{ByteOrder, LoadLocation, DEFAULT_ALIGNMENT},
+    szs::CompressionLevel,
+};
 
 #[derive(Parser, Debug)]
 #[clap(name="cube", author, version, about, long_about = None)]
@@ -8,8 +17,29 @@ pub struct Cli {
     #[clap(subcommand)]
     pub subcommand: Commands,
 
-    #[clap(global = true, default_value_t = 0, short = 'v')]
+    /// Log more about what's happening. Repeat for more detail, e.g. `-vv`
+    #[clap(global = true, short = 'v', long = "verbose", action = ArgAction::Count)]
     pub verbosity: u8,
+
+    /// Only log errors, not warnings
+    #[clap(
+        global = true,
+        short = 'q',
+        long,
+        default_value_t = false,
+        conflicts_with = "verbosity"
+    )]
+    pub quiet: bool,
+
+    /// Also append log messages to this file, one JSON object per line. The file gets
+    /// progress messages even when the terminal doesn't.
+    #[clap(global = true, long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Print output as JSON. Extract and pack print a report of their inputs, outputs,
+    /// warnings, and errors instead of log lines.
+    #[clap(long, global = true, default_value_t = false)]
+    pub json: bool,
 }
 
 #[derive(Debug, Subcommand)]
@@ -41,21 +71,497 @@ pub enum Commands {
         #[clap(short = 'o', long)]
         out: Option<PathBuf>,
 
+        /// Keep running, packing again whenever anything in FILE changes. Only the archives
+        /// with changes in them are packed again, like with --incremental, and an extracted
+        /// disc is rebuilt once the archives on it have been.
+        #[clap(long, default_value_t = false, conflicts_with_all = ["delete_originals", "json"])]
+        watch: bool,
+
+        /// Also put the packed file into this disc image, in the same place it has in the
+        /// extracted disc FILE is in, so a changed archive is on the disc in one step. The
+        /// file has to be on the image already.
+        #[clap(long, value_name = "ISO", conflicts_with = "watch")]
+        inject_into: Option<PathBuf>,
+
         #[clap(flatten)]
         options: PackOptions,
     },
+
+    /// Print detailed information about the structure of a file
+    #[clap(arg_required_else_help = true, visible_alias = "info")]
+    Inspect { files: Vec<PathBuf> },
+
+    /// Identify files by their contents and print their format and key metadata
+    #[clap(arg_required_else_help = true)]
+    Detect { files: Vec<PathBuf> },
+
+    /// Print everything inside discs and archives as a tree, with nested archives opened
+    /// in place and the size and compression of each. `--json` prints the tree as JSON.
+    #[clap(arg_required_else_help = true)]
+    Tree {
+        files: Vec<PathBuf>,
+
+        /// Print a Graphviz graph instead, e.g. for `cube tree game.iso --dot | dot -Tsvg`
+        #[clap(long, default_value_t = false, conflicts_with = "json")]
+        dot: bool,
+    },
+
+    /// Convert a single file directly from one format to another, e.g. `cube convert
+    /// texture.bti texture.png`. Formats are taken from the file extensions.
+    #[clap(arg_required_else_help = true)]
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+
+        #[clap(flatten)]
+        options: ConvertOptions,
+    },
+
+    /// Run a list of extract, pack, and convert jobs from a TOML, YAML, or JSON worklist file,
+    /// continuing past any that fail and printing a summary at the end
+    #[clap(arg_required_else_help = true)]
+    Batch {
+        worklist: PathBuf,
+
+        /// Number of jobs to run at once
+        #[clap(short = 'j', long, default_value_t = 1)]
+        jobs: usize,
+    },
+
+    /// Compare the contents of two archives, ISOs, or BMG files
+    #[clap(arg_required_else_help = true)]
+    Diff { a: PathBuf, b: PathBuf },
+
+    /// Search the text of every BMG message inside discs, archives, and BMG files, printing
+    /// which file and message each match is in. Matches plain text, not patterns.
+    #[clap(arg_required_else_help = true)]
+    Grep {
+        pattern: String,
+
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Ignore case when matching
+        #[clap(short = 'i', long, default_value_t = false)]
+        ignore_case: bool,
+
+        /// Also match the paths of files inside discs and archives
+        #[clap(long, default_value_t = false)]
+        file_names: bool,
+
+        /// Also search the raw bytes of every file, for the text as UTF-8 and Shift-JIS
+        #[clap(long, default_value_t = false)]
+        bytes: bool,
+    },
+
+    /// Check an extracted tree against the checksums written by `cube extract
+    /// --write-checksums`, listing every file that changed or is missing
+    #[clap(arg_required_else_help = true)]
+    VerifyTree { manifest: PathBuf },
+
+    /// Check files against a game's limits, such as how long BMG message lines can be and
+    /// how big archives and textures can get, listing everything that breaks them. Discs
+    /// and archives are checked all the way through.
+    #[clap(arg_required_else_help = true)]
+    Lint {
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Name of a built-in profile or one saved in ~/.config/cube/profiles, or the path
+        /// to a profile's TOML file
+        #[clap(short, long, default_value = "gamecube", value_parser = parse_profile)]
+        profile: Profile,
+    },
+
+    /// Show a disc or archive as a read-only folder, with the archives inside it as folders
+    /// too, to browse or search it without extracting it. Runs until it's unmounted, e.g.
+    /// with `umount`. Needs FUSE, or macFUSE on macOS.
+    #[cfg(all(unix, feature = "mount"))]
+    #[clap(arg_required_else_help = true)]
+    Mount { image: PathBuf, mountpoint: PathBuf },
+
+    /// List every format that can be extracted or packed
+    Formats,
+
+    /// Compress archives packed with `--arc-dev-compression` the way they'd have been
+    /// packed without it, for a release. Folders are searched for them all the way through.
+    #[clap(arg_required_else_help = true)]
+    Recompress {
+        #[clap(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Yaz0 or Yay0 compression level: 'none', 'fast', 'nintendo', or 1-10
+        #[clap(long, default_value_t = CompressionLevel::default())]
+        compression_level: CompressionLevel,
+    },
+
+    /// Print a shell completion script, e.g. `cube completions bash >
+    /// ~/.local/share/bash-completion/completions/cube`
+    #[clap(arg_required_else_help = true)]
+    Completions { shell: Shell },
+
+    /// Print the man page, or write one for every subcommand into a folder
+    Manpage {
+        /// Folder to write `cube.1` and a page for each subcommand, like `cube-extract.1`, into
+        #[clap(short = 'o', long)]
+        out: Option<PathBuf>,
+    },
+
+    /// Work with BMG text files
+    #[clap(arg_required_else_help = true)]
+    Bmg {
+        #[clap(subcommand)]
+        command: BmgCommands,
+    },
+
+    /// Work with the textures in archives
+    #[clap(arg_required_else_help = true)]
+    Textures {
+        #[clap(subcommand)]
+        command: TexturesCommands,
+    },
 }
 
-#[derive(Debug, Clone, Copy, Args)]
+#[derive(Debug, Subcommand)]
+pub enum BmgCommands {
+    /// Overlay translated messages from a BMG's JSON onto the original BMG. Messages are
+    /// matched by ID if the BMG has them, or else by position, and any message that isn't
+    /// translated is kept exactly as it was.
+    #[clap(arg_required_else_help = true)]
+    Merge {
+        base: PathBuf,
+        translated: PathBuf,
+
+        #[clap(short = 'o', long)]
+        out: PathBuf,
+
+        /// Replace characters that the BMG's text encoding can't represent instead of failing
+        #[clap(long, default_value_t = false)]
+        lossy_text: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TexturesCommands {
+    /// Replace the textures in an archive, and the archives inside it, with PNGs from a
+    /// folder. Each PNG replaces the BTIs with the same name, e.g. `grad.png` or
+    /// `grad.bti.png` for `grad.bti`, and folders in front of the name narrow down which.
+    /// Textures keep their original format and settings unless there's a `.bti.json`
+    /// next to the PNG, as extracting writes.
+    #[clap(arg_required_else_help = true)]
+    Replace {
+        archive: PathBuf,
+
+        /// Folder of PNGs, searched along with the folders inside it
+        #[clap(long)]
+        from: PathBuf,
+
+        #[clap(short = 'o', long)]
+        out: PathBuf,
+
+        /// Compression level for archives that were compressed: 'none', 'fast', 'nintendo',
+        /// or 1-10
+        #[clap(long, default_value_t = CompressionLevel::default())]
+        compression_level: CompressionLevel,
+
+        /// How carefully to compress CMPR textures: 'fast', or 'high' for less banding in
+        /// gradients at the cost of speed
+        #[clap(long, default_value_t = BtiQuality::default())]
+        bti_quality: BtiQuality,
+    },
+}
+
+#[derive(Debug, Default, Clone, Args)]
 pub struct ExtractOptions {
     #[clap(long, default_value_t = false, action = ArgAction::Set)]
     pub extract_bti: bool,
 
+    /// Raw palette data to use for BTIs whose own palette is missing or points outside
+    /// the file, e.g. a palette stored in a sibling file
+    #[clap(long, value_parser = read_raw_file)]
+    pub bti_palette: Option<RawFile>,
+
+    /// Write each extracted texture's header settings (wrap modes, filters, LOD, etc.)
+    /// to a .bti.json file next to its PNG so they can be restored when packing
+    #[clap(long, default_value_t = true, action = ArgAction::Set)]
+    pub bti_header: bool,
+
+    /// How to write textures in the intensity formats (I4, I8, IA4, and IA8) as PNGs:
+    /// 'rgba' like every other format, 'gray' as grayscale with alpha for IA4 and IA8, or
+    /// 'alpha' with I4 and I8 as white with their intensity as alpha, for mask textures.
+    /// Anything but 'rgba' always writes the .bti.json, which says how to read it back.
+    #[clap(long, default_value_t = IntensityPng::Rgba)]
+    pub bti_intensity: IntensityPng,
+
     #[clap(long, default_value_t = true, action = ArgAction::Set)]
     pub extract_bmg: bool,
 
+    /// Game profile, as used by `cube lint`. With `bmg.font_metrics` in it, each message in
+    /// extracted BMG JSON notes how wide its lines are drawn and which are wider than
+    /// `bmg.max_line_width`. Packing ignores the notes.
+    #[clap(long, value_parser = parse_profile)]
+    pub profile: Option<Profile>,
+
     #[clap(long, default_value_t = false, action = ArgAction::Set)]
     pub szs_preserve_extension: bool,
+
+    /// Also write each extracted texture to a PNG named the way Dolphin names the textures
+    /// it dumps, like tex1_64x64_m_0123456789abcdef_14.png. Edited copies of these can go
+    /// straight into Dolphin's Load/Textures/<game ID> folder to replace the originals.
+    #[clap(long, default_value_t = false)]
+    pub dolphin_textures: bool,
+
+    /// Also write a single PNG containing labeled thumbnails of every extracted texture
+    #[clap(long, default_value_t = false)]
+    pub contact_sheet: bool,
+
+    #[clap(long, default_value_t = true, action = ArgAction::Set)]
+    pub extract_bcsv: bool,
+
+    /// Convert BLO screen layouts to JSON listing the textures, fonts, and text they use,
+    /// which can be edited and packed back into the layout
+    #[clap(long, default_value_t = false, action = ArgAction::Set)]
+    pub extract_blo: bool,
+
+    /// Split BMD/BDL models into their textures and the rest of the model. Textures
+    /// are extracted like any other BTI, and packing the folder puts them back in.
+    #[clap(long, default_value_t = false, action = ArgAction::Set)]
+    pub extract_models: bool,
+
+    /// Split REL modules into their individual sections plus a JSON summary of the
+    /// header, section table, and imports
+    #[clap(long, default_value_t = false, action = ArgAction::Set)]
+    pub extract_rel: bool,
+
+    /// Split GCI saves into their banner, icon frames, save data, and a JSON header with
+    /// their comments. Pack the folder to rebuild the save with any edits.
+    #[clap(long, default_value_t = true, action = ArgAction::Set)]
+    pub extract_gci: bool,
+
+    /// Convert BCSV/JMap tables to CSV instead of JSON
+    #[clap(long, default_value_t = false)]
+    pub bcsv_csv: bool,
+
+    /// File containing BCSV field names, one per line, used to recover column names
+    /// from their hashes. A small set of common names is always included.
+    #[clap(long, value_parser = read_name_list)]
+    pub bcsv_hash_names: Option<NameList>,
+
+    /// For disc images, write the file system table (every file's path, offset, and size)
+    /// to JSON instead of extracting any files. Pack the JSON to rebuild an fst.bin.
+    #[clap(long, default_value_t = false)]
+    pub fst_only: bool,
+
+    /// For disc images, also extract the system files that aren't part of the file system
+    /// (boot.bin, bi2.bin, apploader.img, main.dol, and fst.bin) into a sys folder
+    #[clap(long, default_value_t = false, action = ArgAction::Set)]
+    pub extract_sys_files: bool,
+
+    /// Only extract files inside archives and discs whose path matches one of these
+    /// patterns, where `*` matches any run of characters and `?` matches one. Nested
+    /// archives are always searched. Can be given multiple times.
+    #[clap(long = "include", value_name = "PATTERN")]
+    pub include: Vec<String>,
+
+    /// Open at most this many levels of nested discs and archives. For example, 1 extracts
+    /// an ISO but leaves the archives inside it packed. Unlimited by default.
+    #[clap(long)]
+    pub max_depth: Option<usize>,
+
+    /// Don't open archives of these formats when they're found inside another disc or
+    /// archive, e.g. `--no-recurse-formats szs,arc`. They're kept as they are instead.
+    #[clap(long, value_delimiter = ',', value_parser = parse_format)]
+    pub no_recurse_formats: Vec<FileFormat>,
+
+    /// Extract the discs of a multi-disc game into one folder instead of one folder each.
+    /// Files that are the same on every disc go in `shared`, the rest in `disc1`, `disc2`,
+    /// etc., and `discs.json` records which disc image each folder came from. The result
+    /// can't be packed back into discs.
+    #[clap(long, default_value_t = false, conflicts_with_all = ["out_template", "fst_only"])]
+    pub merge_discs: bool,
+
+    /// Salvage what's readable from damaged or truncated RARC archives and BMGs instead
+    /// of failing on the first problem. Each problem is logged as a warning.
+    #[clap(long, default_value_t = false)]
+    pub permissive: bool,
+
+    /// Identify files whose extension doesn't say what they are by their contents, like
+    /// `cube detect` does. They're treated as if they had their format's usual extension,
+    /// so a BMG named `message` is extracted to `message.bmg.json`.
+    #[clap(long, default_value_t = false)]
+    pub sniff: bool,
+
+    /// Directory to put extracted files in when no output path is given. Files keep the
+    /// names and layout they'd have next to the input file.
+    #[clap(long)]
+    pub output_dir: Option<PathBuf>,
+
+    /// Lay out extracted files according to this template instead of mirroring the
+    /// archives they came from, e.g. `{stem}/{format}/{name}` to group files by format.
+    /// Variables are {stem}, {format}, {dir}, {name}, {ext}, and {path}. Paths are
+    /// relative to the output path, or the input file's folder if there isn't one.
+    #[clap(long, value_name = "TEMPLATE")]
+    pub out_template: Option<PathTemplate>,
+
+    /// Write everything extracted into a single tar archive at this path instead of as
+    /// separate files, or to stdout with `-`. Paths inside it are the ones the files
+    /// would otherwise have been written to.
+    #[clap(long, value_name = "PATH")]
+    pub out_archive: Option<PathBuf>,
+
+    /// Print every file that would be written, and where, without writing anything
+    #[clap(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    /// Keep a journal of the files written in .cube-cache, and skip any it says were
+    /// already written with the same contents. An interrupted extraction carries on where
+    /// it stopped, and extracting a disc again after it changes only rewrites what changed.
+    /// Files on a disc that are where they were last time aren't even read.
+    #[clap(long, default_value_t = false, conflicts_with_all = ["out_archive", "dry_run"])]
+    pub resume: bool,
+
+    /// Write a JSON manifest of every extracted file and its source to this path. Also
+    /// lists output paths that more than one file would be written to.
+    #[clap(long)]
+    pub manifest: Option<PathBuf>,
+
+    /// Write the SHA-256 checksum of every extracted file to this path, in the format
+    /// `sha256sum` uses. Check a tree against it later with `cube verify-tree`.
+    #[clap(long, value_name = "PATH")]
+    pub write_checksums: Option<PathBuf>,
+
+    /// Print how many files were extracted and their total size for each format, and
+    /// for each archive inside the input. Included in the report with `--json`.
+    #[clap(long, default_value_t = false)]
+    pub stats: bool,
+
+    /// Give extracted files the modification time of the archive or disc they came from,
+    /// so `cube pack --incremental` and other build tools see them as unchanged
+    #[clap(long, default_value_t = false)]
+    pub preserve_mtime: bool,
+}
+
+impl ExtractOptions {
+    /// Whether a file at this path inside an archive or disc passes the `--include` filters
+    pub fn includes(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy().replace('\\', "/");
+        self.include.is_empty() || self.include.iter().any(|pattern| glob_match(pattern, &path))
+    }
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it's currently matched up to
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn parse_format(extension: &str) -> Result<FileFormat, String> {
+    FileFormat::from_extension(extension).ok_or_else(|| format!("Unknown format '{extension}'"))
+}
+
+fn parse_profile(name: &str) -> Result<Profile, String> {
+    Profile::load(name).map_err(|e| e.to_string())
+}
+
+fn parse_pack_format(format: &str) -> Result<&'static str, String> {
+    let format = format.to_ascii_lowercase();
+    let formats: Vec<&'static str> = registry()
+        .handlers()
+        .flat_map(|handler| handler.pack_formats().iter().copied())
+        .collect();
+    formats
+        .iter()
+        .find(|&&known| known == format)
+        .copied()
+        .ok_or_else(|| format!("Can't pack into '{format}'. Expected one of: {}", formats.join(", ")))
+}
+
+/// A list of names read from a newline separated file
+#[derive(Debug, Clone)]
+pub struct NameList(pub Vec<String>);
+
+fn read_name_list(path: &str) -> Result<NameList, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| format!("Couldn't read {path}: {e}"))?;
+    Ok(NameList(
+        contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(ToOwned::to_owned)
+            .collect(),
+    ))
+}
+
+/// The contents of a file passed as an argument
+#[derive(Debug, Clone)]
+pub struct RawFile(pub Vec<u8>);
+
+fn read_raw_file(path: &str) -> Result<RawFile, String> {
+    std::fs::read(path)
+        .map(RawFile)
+        .map_err(|e| format!("Couldn't read {path}: {e}"))
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ConvertOptions {
+    /// Format of the input file, if its extension doesn't say
+    #[clap(long)]
+    pub from: Option<String>,
+
+    /// Format of the output file, if its extension doesn't say
+    #[clap(long)]
+    pub to: Option<String>,
+
+    /// Compression level when converting to SZS or SZP: 'none', 'fast', 'nintendo', or 1-10
+    #[clap(long, default_value_t = CompressionLevel::default())]
+    pub compression_level: CompressionLevel,
+
+    /// Replace characters that a BMG's text encoding can't represent instead of failing
+    #[clap(long, default_value_t = false)]
+    pub lossy_text: bool,
+
+    /// Game profile, as used by `cube lint`, whose `bmg.default_attributes` are given to
+    /// BMG messages with "default" attributes. Converting a BMG to JSON notes how wide
+    /// each message's lines are drawn if it has `bmg.font_metrics`.
+    #[clap(long, value_parser = parse_profile)]
+    pub profile: Option<Profile>,
+
+    /// When converting a PNG to a BTI, pick the smallest texture format that suits the
+    /// image instead of using RGB5A3
+    #[clap(long, default_value_t = false)]
+    pub auto_format: bool,
+
+    /// Texture format to encode a PNG into when converting it to a BTI, e.g. 'cmpr' or 'i8'
+    #[clap(long, conflicts_with = "auto_format")]
+    pub format: Option<TextureFormat>,
+
+    /// How carefully to compress CMPR textures: 'fast', or 'high' for less banding in
+    /// gradients at the cost of speed
+    #[clap(long, default_value_t = BtiQuality::default())]
+    pub bti_quality: BtiQuality,
 }
 
 #[derive(Debug, Clone, Args)]
@@ -63,17 +569,110 @@ pub struct PackOptions {
     #[clap(long, short = 'd', default_value_t = false)]
     pub delete_originals: bool,
 
+    /// Pack the input into this format instead of guessing from its name and the output's
+    /// extension, e.g. `--as u8` for a folder that should be a U8 archive. Any format listed
+    /// under PACKS INTO by `cube formats`. Files inside a folder are still guessed.
+    #[clap(long = "as", value_name = "FORMAT", value_parser = parse_pack_format)]
+    pub pack_as: Option<&'static str>,
+
     #[clap(long, default_value_t = true, action = ArgAction::Set)]
     pub arc_yaz0_compress: bool,
 
+    /// Compress archives with Yay0 instead of Yaz0, producing SZP archives like the ones
+    /// some Mario games use
+    #[clap(long, default_value_t = false)]
+    pub arc_yay0: bool,
+
     #[clap(long)]
     pub arc_extension: Option<String>,
+
+    /// Start each file's data in archives on a multiple of this many bytes. Some games
+    /// can't load archives packed with less than the usual 32.
+    #[clap(long, default_value_t = DEFAULT_ALIGNMENT, value_parser = clap::value_parser!(u32).range(1..))]
+    pub arc_alignment: u32,
+
+    /// Store archives that would be Yaz0 or Yay0 compressed uncompressed ('none') or with
+    /// zstd ('zstd') instead, which packs many times faster while working on a mod. Games
+    /// that check for a Yaz0 header load uncompressed archives in Dolphin, but none can read
+    /// zstd ones. A `.dev.json` marker is written next to each, and `cube recompress` turns
+    /// them into what they'd normally be for a release.
+    #[clap(long, value_name = "COMPRESSION")]
+    pub arc_dev_compression: Option<DevCompression>,
+
+    /// Byte order to write archives in: 'big', as games expect, or 'little' for the
+    /// byte-swapped "CRAR" archives some tools and virtual console dumps use
+    #[clap(long, default_value_t = ByteOrder::default())]
+    pub arc_byte_order: ByteOrder,
+
+    /// Where games load the files in packed archives from: 'mram' (main RAM, the default),
+    /// 'aram', or 'dvd'. Replaces the default in the `.load.json` written next to a folder
+    /// when extracting, but not the locations it lists for single files.
+    #[clap(long)]
+    pub arc_load_location: Option<LoadLocation>,
+
+    /// Put back whatever extracted archives had after their last file, kept in a
+    /// `.trailing.bin` next to the folder, so they repack byte for byte
+    #[clap(long, default_value_t = false)]
+    pub arc_keep_trailing_data: bool,
+
+    /// Yaz0 or Yay0 compression level: 'none' (store only), 'fast', or 1-10 for increasingly
+    /// thorough (and slower) compression. 'nintendo' compresses the way Nintendo's tools do,
+    /// so files come out the same size as the originals for games that have to fit them in
+    /// a fixed amount of space.
+    #[clap(long, default_value_t = CompressionLevel::default())]
+    pub compression_level: CompressionLevel,
+
+    /// Guarantee byte-identical output for identical input trees. Files are packed in
+    /// sorted order, file names that aren't valid UTF-8 are rejected instead of being
+    /// converted lossily, and packed files are dated SOURCE_DATE_EPOCH if it's set, or
+    /// else with the time the newest file in them was changed.
+    #[clap(long, default_value_t = false)]
+    pub deterministic: bool,
+
+    /// Replace characters that a BMG's text encoding can't represent instead of failing
+    #[clap(long, default_value_t = false)]
+    pub lossy_text: bool,
+
+    /// Game profile, as used by `cube lint`, whose `bmg.default_attributes` are given to
+    /// BMG messages with "default" attributes
+    #[clap(long, value_parser = parse_profile)]
+    pub profile: Option<Profile>,
+
+    /// Pick the smallest texture format that suits each PNG packed into a BTI, based on
+    /// its colors and transparency, instead of the format it was extracted from. The
+    /// format picked for an extracted texture is saved in its .bti.json.
+    #[clap(long, default_value_t = false)]
+    pub auto_format: bool,
+
+    /// How carefully to compress CMPR textures: 'fast', or 'high' for less banding in
+    /// gradients at the cost of speed
+    #[clap(long, default_value_t = BtiQuality::default())]
+    pub bti_quality: BtiQuality,
+
+    /// Number of files to pack at once. Everything inside a folder is always packed
+    /// before the folder itself.
+    #[clap(short = 'j', long, default_value_t = 1)]
+    pub jobs: usize,
+
+    /// Skip anything whose output is already newer than every file that goes into it,
+    /// like make does, and leave outputs that would come out the same untouched
+    #[clap(long, default_value_t = false, conflicts_with = "delete_originals")]
+    pub incremental: bool,
+
+    /// Always pack folders into archives from scratch, instead of reusing an archive
+    /// cached in .cube-cache when nothing in the folder has changed
+    #[clap(long, default_value_t = false)]
+    pub no_cache: bool,
 }
 
 impl PackOptions {
     pub fn arc_extension(&self) -> &str {
         self.arc_extension
             .as_deref()
-            .unwrap_or_else(|| if self.arc_yaz0_compress { "szs" } else { "arc" })
+            .unwrap_or(match (self.arc_yaz0_compress, self.arc_yay0) {
+                (false, _) => "arc",
+                (true, false) => "szs",
+                (true, true) => "szp",
+            })
     }
 }
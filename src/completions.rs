@@ -0,0 +1,27 @@
+use crate::commands::Cli;
+use clap::CommandFactory;
+use clap_complete::{generate, Shell};
+use clap_mangen::Man;
+use std::{error::Error, fs::create_dir_all, io::stdout, path::Path};
+
+// Both use the commands as built in rather than with defaults from cube.toml, so the
+// output is the same for everyone and can be shipped with packages
+
+/// Prints a completion script for `shell`
+pub fn try_completions(shell: Shell) -> Result<(), Box<dyn Error>> {
+    generate(shell, &mut Cli::command(), "cube", &mut stdout());
+    Ok(())
+}
+
+/// Prints the man page for `cube`, or if `out` is given, writes one for it and another for
+/// each subcommand into that folder
+pub fn try_manpage(out: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    match out {
+        Some(out) => {
+            create_dir_all(out)?;
+            clap_mangen::generate_to(Cli::command(), out)?;
+        }
+        None => Man::new(Cli::command()).render(&mut stdout())?,
+    }
+    Ok(())
+}
@@ -0,0 +1,132 @@
+use clap::{ArgAction, Command};
+use std::{
+    env,
+    error::Error,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+const FILE_NAME: &str = "cube.toml";
+
+/// Default option values read from a cube.toml file. Each table is named after a
+/// subcommand and its keys are that subcommand's option names in snake_case, e.g.
+///
+/// ```toml
+/// [extract]
+/// extract_bti = true
+/// output_dir = "extracted"
+///
+/// [pack]
+/// arc_yaz0_compress = false
+/// compression_level = "fast"
+/// ```
+///
+/// Top level keys set global options such as `verbosity`. Options given on the command
+/// line always take precedence, and flags turned on here can be turned off again with
+/// e.g. `--incremental=false`.
+pub struct Config {
+    pub path: PathBuf,
+    table: Table,
+}
+
+impl Config {
+    /// Loads cube.toml from the current directory, or failing that from the user's
+    /// config directory, if either exists
+    pub fn load() -> Result<Option<Self>, Box<dyn Error>> {
+        let Some(path) = find_config() else {
+            return Ok(None);
+        };
+        let contents = read_to_string(&path).map_err(|e| format!("Couldn't read {path:?}: {e}"))?;
+        let table = contents
+            .parse()
+            .map_err(|e| format!("Invalid config file {path:?}: {e}"))?;
+        Ok(Some(Config { path, table }))
+    }
+
+    /// Sets the configured values as the defaults of the matching options
+    pub fn apply(&self, mut command: Command) -> Result<Command, Box<dyn Error>> {
+        for (key, value) in self.table.iter() {
+            if let Value::Table(options) = value {
+                let subcommand = command
+                    .find_subcommand(key)
+                    .ok_or_else(|| format!("Unknown command [{key}] in {:?}", self.path))?;
+                let defaults =
+                    parse_defaults(subcommand, options).map_err(|e| format!("{e} under [{key}] in {:?}", self.path))?;
+                command = command.mut_subcommand(key, |subcommand| set_defaults(subcommand, defaults));
+            } else {
+                let option = Table::from_iter([(key.clone(), value.clone())]);
+                let defaults = parse_defaults(&command, &option).map_err(|e| format!("{e} in {:?}", self.path))?;
+                command = set_defaults(command, defaults);
+            }
+        }
+        Ok(command)
+    }
+}
+
+/// Checks that every option exists in this command and converts its values to strings
+/// for clap to parse like any other argument
+fn parse_defaults(command: &Command, options: &Table) -> Result<Vec<(String, Vec<String>)>, String> {
+    options
+        .iter()
+        .map(|(name, value)| {
+            let known = command
+                .get_arguments()
+                .any(|arg| arg.get_id() == name.as_str() && !arg.is_positional());
+            if !known {
+                return Err(format!("Unknown option '{name}'"));
+            }
+            let values = match value {
+                Value::Array(values) => values.iter().map(option_value).collect(),
+                value => option_value(value).map(|value| vec![value]),
+            };
+            let values = values.ok_or_else(|| format!("Unsupported value for '{name}'"))?;
+            Ok((name.clone(), values))
+        })
+        .collect()
+}
+
+fn set_defaults(command: Command, defaults: Vec<(String, Vec<String>)>) -> Command {
+    defaults.into_iter().fold(command, |command, (name, values)| {
+        command.mut_arg(name, |arg| {
+            // A flag that's only ever switched on couldn't be switched back off once it's
+            // on by default, so it takes a value too, as in `--json=false`
+            let arg = match arg.get_action() {
+                ArgAction::SetTrue => arg
+                    .action(ArgAction::Set)
+                    .num_args(0..=1)
+                    .require_equals(true)
+                    .default_missing_value("true"),
+                _ => arg,
+            };
+            arg.default_values(values)
+        })
+    })
+}
+
+fn option_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        Value::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn find_config() -> Option<PathBuf> {
+    let local = PathBuf::from(FILE_NAME);
+    if local.is_file() {
+        return Some(local);
+    }
+    Some(config_dir()?.join(FILE_NAME)).filter(|path| path.is_file())
+}
+
+/// The user's cube config folder, `$XDG_CONFIG_HOME/cube` or `~/.config/cube`
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| Some(Path::new(&env::var_os("HOME")?).join(".config")))?;
+    Some(config_dir.join("cube"))
+}
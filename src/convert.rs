@@ -0,0 +1,184 @@
+use crate::{
+    commands::ConvertOptions,
+    extract::bmg_to_json,
+    pack::{bmg_from_json, bti_from_png},
+};
+use cube_rs::{
+    bcsv::{Bcsv, COMMON_FIELD_NAMES},
+    bmg::Bmg,
+    bti::{BtiHeader, BtiImage},
+    png::decode_png,
+    rel::Rel,
+    szs::{decompress_szs, yaz0_compress},
+    yay0,
+};
+use log::info;
+use std::{
+    error::Error,
+    fs::{read, write},
+    path::Path,
+};
+
+type ConvertFn = fn(Vec<u8>, &ConvertOptions) -> Result<Vec<u8>, Box<dyn Error>>;
+
+/// Converts a single file between two formats, identified by extension
+struct Converter {
+    from: &'static str,
+    to: &'static str,
+    convert: ConvertFn,
+}
+
+/// Every supported conversion. BCSV conversions also apply to JMap files, which
+/// use the same format with a .jmp extension.
+static CONVERTERS: &[Converter] = &[
+    Converter {
+        from: "szs",
+        to: "arc",
+        convert: decompress,
+    },
+    Converter {
+        from: "szp",
+        to: "arc",
+        convert: decompress,
+    },
+    Converter {
+        from: "arc",
+        to: "szs",
+        convert: compress_yaz0,
+    },
+    Converter {
+        from: "arc",
+        to: "szp",
+        convert: compress_yay0,
+    },
+    Converter {
+        from: "szs",
+        to: "szp",
+        convert: |data, options| compress_yay0(decompress(data, options)?, options),
+    },
+    Converter {
+        from: "szp",
+        to: "szs",
+        convert: |data, options| compress_yaz0(decompress(data, options)?, options),
+    },
+    Converter {
+        from: "bti",
+        to: "png",
+        convert: bti_to_png,
+    },
+    Converter {
+        from: "png",
+        to: "bti",
+        convert: png_to_bti,
+    },
+    Converter {
+        from: "bmg",
+        to: "json",
+        convert: |data, options| bmg_to_json(&Bmg::read(&data)?, options.profile.as_ref()),
+    },
+    Converter {
+        from: "json",
+        to: "bmg",
+        convert: |data, options| Ok(bmg_from_json(&data, options.lossy_text, options.profile.as_ref())?.write()),
+    },
+    Converter {
+        from: "bmg",
+        to: "csv",
+        convert: |data, _| Ok(Bmg::read(&data)?.to_csv().into_bytes()),
+    },
+    Converter {
+        from: "bcsv",
+        to: "json",
+        convert: |data, _| Ok(serde_json::to_vec_pretty(&read_bcsv(&data)?)?),
+    },
+    Converter {
+        from: "bcsv",
+        to: "csv",
+        convert: |data, _| Ok(read_bcsv(&data)?.to_csv().into_bytes()),
+    },
+    Converter {
+        from: "json",
+        to: "bcsv",
+        convert: |data, _| Ok(serde_json::from_slice::<Bcsv>(&data)?.write()?),
+    },
+    Converter {
+        from: "csv",
+        to: "bcsv",
+        convert: |data, _| Ok(Bcsv::from_csv(&String::from_utf8(data)?)?.write()?),
+    },
+    Converter {
+        from: "rel",
+        to: "json",
+        convert: |data, _| Ok(serde_json::to_vec_pretty(&Rel::read(&data)?)?),
+    },
+];
+
+pub fn try_convert(input: &Path, output: &Path, options: &ConvertOptions) -> Result<(), Box<dyn Error>> {
+    let from = format_of(input, options.from.as_deref())?;
+    let to = format_of(output, options.to.as_deref())?;
+    let converter = find_converter(&from, &to).ok_or_else(|| unsupported(&from, &to))?;
+
+    info!("Converting {input:?} ({from}) => {output:?} ({to})");
+    let converted = (converter.convert)(read(input)?, options)?;
+    write(output, converted)?;
+    Ok(())
+}
+
+fn format_of(path: &Path, explicit: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let format = explicit
+        .map(ToOwned::to_owned)
+        .or_else(|| Some(path.extension()?.to_string_lossy().into_owned()))
+        .ok_or_else(|| format!("Can't tell the format of {path:?} from its extension. Use --from or --to."))?;
+    Ok(format.to_ascii_lowercase())
+}
+
+fn find_converter(from: &str, to: &str) -> Option<&'static Converter> {
+    let bcsv_alias = |format: &str| if format == "jmp" { "bcsv" } else { format }.to_owned();
+    let (from, to) = (bcsv_alias(from), bcsv_alias(to));
+    CONVERTERS.iter().find(|c| c.from == from && c.to == to)
+}
+
+fn unsupported(from: &str, to: &str) -> Box<dyn Error> {
+    let targets: Vec<&str> = CONVERTERS.iter().filter(|c| c.from == from).map(|c| c.to).collect();
+    if targets.is_empty() {
+        format!("Converting from {from} isn't supported").into()
+    } else {
+        format!(
+            "Converting {from} to {to} isn't supported. {from} can be converted to: {}",
+            targets.join(", ")
+        )
+        .into()
+    }
+}
+
+fn decompress(data: Vec<u8>, _: &ConvertOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(decompress_szs(data)?)
+}
+
+fn compress_yaz0(data: Vec<u8>, options: &ConvertOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(yaz0_compress(&data, options.compression_level))
+}
+
+fn compress_yay0(data: Vec<u8>, options: &ConvertOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(yay0::compress(&data, options.compression_level))
+}
+
+fn bti_to_png(data: Vec<u8>, _: &ConvertOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(BtiImage::decode(&data)?.to_png()?)
+}
+
+/// Encodes a PNG in the format from `--format`, or else the way packing one does
+fn png_to_bti(data: Vec<u8>, options: &ConvertOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+    let Some(format) = options.format else {
+        return bti_from_png(&data, None, options.auto_format, options.bti_quality);
+    };
+    let (width, height, pixels) = decode_png(&data)?;
+    let header = BtiHeader::new(format, &pixels);
+    Ok(BtiImage::encode(width, height, &pixels, &header, options.bti_quality)?)
+}
+
+fn read_bcsv(data: &[u8]) -> Result<Bcsv, Box<dyn Error>> {
+    let mut bcsv = Bcsv::read(data)?;
+    bcsv.resolve_names(COMMON_FIELD_NAMES.iter().copied());
+    Ok(bcsv)
+}
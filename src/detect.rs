@@ -0,0 +1,107 @@
+use cube_rs::detect::{detect, FileFormat, SNIFF_LEN};
+use serde_json::{json, Map, Value};
+use std::{error::Error, fs::File, io::Read, path::PathBuf};
+
+struct Row {
+    path: String,
+    format: Option<&'static str>,
+    version: Option<String>,
+    matched_by: Option<&'static str>,
+    metadata: Vec<(&'static str, String)>,
+}
+
+pub fn try_detect(files: &[PathBuf], json: bool) -> Result<(), Box<dyn Error>> {
+    let mut rows = Vec::with_capacity(files.len());
+    for path in files {
+        let mut header = Vec::with_capacity(SNIFF_LEN);
+        File::open(path)?.take(SNIFF_LEN as u64).read_to_end(&mut header)?;
+
+        let path_string = path.to_string_lossy().into_owned();
+        let row = if let Some(detection) = detect(&header) {
+            Row {
+                path: path_string,
+                format: Some(detection.format.name()),
+                version: detection.version,
+                matched_by: Some("contents"),
+                metadata: detection.metadata,
+            }
+        } else {
+            // Some formats have no magic number, so fall back to the file extension
+            let format = FileFormat::from_path(path);
+            Row {
+                path: path_string,
+                format: format.map(|f| f.name()),
+                version: None,
+                matched_by: format.map(|_| "extension"),
+                metadata: Vec::new(),
+            }
+        };
+        rows.push(row);
+    }
+
+    if json {
+        let rows: Vec<Value> = rows
+            .into_iter()
+            .map(|row| {
+                let metadata: Map<String, Value> = row
+                    .metadata
+                    .into_iter()
+                    .map(|(key, value)| (key.to_owned(), Value::String(value)))
+                    .collect();
+                json!({
+                    "path": row.path,
+                    "format": row.format,
+                    "version": row.version,
+                    "matched_by": row.matched_by,
+                    "metadata": metadata,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+    } else {
+        print_table(&rows);
+    }
+    Ok(())
+}
+
+fn print_table(rows: &[Row]) {
+    let cells: Vec<[String; 4]> = rows
+        .iter()
+        .map(|row| {
+            let mut details: Vec<String> = row
+                .metadata
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect();
+            if row.matched_by == Some("extension") {
+                details.push(String::from("(identified by extension only)"));
+            }
+            [
+                row.path.clone(),
+                row.format.unwrap_or("unknown").to_owned(),
+                row.version.clone().unwrap_or_else(|| String::from("-")),
+                details.join(" "),
+            ]
+        })
+        .collect();
+
+    let header = ["FILE", "FORMAT", "VERSION", "DETAILS"].map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(&cells) {
+        println!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use crate::{commands::ExtractOptions, extract::extract};
+use cube_rs::{
+    bmg::{Bmg, MessageId},
+    virtual_fs::VirtualFile,
+};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+/// Summary of the differences between two extracted inputs. Internal paths are
+/// relative to the root of each input so archives with different names can be compared.
+#[derive(Debug, Default, Serialize)]
+pub struct DiffReport {
+    pub added: Vec<FileSummary>,
+    pub removed: Vec<FileSummary>,
+    pub changed: Vec<ChangedFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileSummary {
+    pub path: PathBuf,
+    pub size: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangedFile {
+    pub path: PathBuf,
+    pub old_size: usize,
+    pub new_size: usize,
+
+    /// Only populated for BMG files that could be parsed on both sides
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<MessageChange>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MessageChange {
+    pub index: usize,
+    pub id: Option<MessageId>,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+pub fn try_diff(a: &Path, b: &Path, json: bool) -> Result<(), Box<dyn Error>> {
+    let report = diff(a, b)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+pub fn diff(a: &Path, b: &Path) -> Result<DiffReport, Box<dyn Error>> {
+    let old_files = extract_for_diff(a)?;
+    let mut new_files = extract_for_diff(b)?;
+    let mut report = DiffReport::default();
+
+    for (path, old) in old_files {
+        match new_files.remove(&path) {
+            Some(new) if new.bytes != old.bytes => {
+                let messages = if is_bmg(&path) {
+                    diff_bmg(&old.bytes, &new.bytes)
+                } else {
+                    Vec::new()
+                };
+                report.changed.push(ChangedFile {
+                    path,
+                    old_size: old.bytes.len(),
+                    new_size: new.bytes.len(),
+                    messages,
+                });
+            }
+            Some(_) => {}
+            None => report.removed.push(FileSummary {
+                path,
+                size: old.bytes.len(),
+            }),
+        }
+    }
+
+    report
+        .added
+        .extend(new_files.into_iter().map(|(path, new)| FileSummary {
+            path,
+            size: new.bytes.len(),
+        }));
+
+    Ok(report)
+}
+
+/// Runs the normal extraction pipeline without any conversions so files are compared
+/// in their original formats, keyed by their path relative to the input.
+fn extract_for_diff(path: &Path) -> Result<BTreeMap<PathBuf, VirtualFile>, Box<dyn Error>> {
+    let options = ExtractOptions {
+        extract_bti: false,
+        extract_bmg: false,
+        ..Default::default()
+    };
+    let vfile = VirtualFile::read(path)?;
+    let extracted = extract(vfile, &options)?;
+
+    let root = path.with_extension("");
+    Ok(extracted
+        .into_iter()
+        .map(|vfile| {
+            let key = if vfile.path == path {
+                // Not a container, so compare the file itself regardless of its name
+                PathBuf::new()
+            } else {
+                vfile.path.strip_prefix(&root).unwrap_or(&vfile.path).to_owned()
+            };
+            (key, vfile)
+        })
+        .collect())
+}
+
+fn diff_bmg(old: &[u8], new: &[u8]) -> Vec<MessageChange> {
+    let (Ok(old), Ok(new)) = (Bmg::read(old), Bmg::read(new)) else {
+        return Vec::new();
+    };
+    let old_messages: Vec<_> = old.messages().collect();
+    let new_messages: Vec<_> = new.messages().collect();
+
+    let mut changes = Vec::new();
+    for index in 0..old_messages.len().max(new_messages.len()) {
+        let old = old_messages.get(index);
+        let new = new_messages.get(index);
+        if old.map(|m| &m.message) != new.map(|m| &m.message) {
+            changes.push(MessageChange {
+                index,
+                id: new.or(old).and_then(|m| m.id),
+                old: old.map(|m| m.message.clone()),
+                new: new.map(|m| m.message.clone()),
+            });
+        }
+    }
+    changes
+}
+
+fn is_bmg(path: &Path) -> bool {
+    path.as_os_str().is_empty() || path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bmg"))
+}
+
+fn print_report(report: &DiffReport) {
+    for file in &report.removed {
+        println!("- {} ({} bytes)", display_path(&file.path), file.size);
+    }
+    for file in &report.added {
+        println!("+ {} ({} bytes)", display_path(&file.path), file.size);
+    }
+    for file in &report.changed {
+        println!(
+            "~ {} ({} => {} bytes)",
+            display_path(&file.path),
+            file.old_size,
+            file.new_size
+        );
+        for message in &file.messages {
+            println!("    message {}:", message.index);
+            if let Some(old) = &message.old {
+                println!("      - {old:?}");
+            }
+            if let Some(new) = &message.new {
+                println!("      + {new:?}");
+            }
+        }
+    }
+    println!(
+        "{} added, {} removed, {} changed",
+        report.added.len(),
+        report.removed.len(),
+        report.changed.len()
+    );
+}
+
+fn display_path(path: &Path) -> String {
+    if path.as_os_str().is_empty() {
+        String::from("<root>")
+    } else {
+        path.to_string_lossy().into_owned()
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{
+    commands::ExtractOptions,
+    extract::{plan_extraction, Extracted, Plan},
+};
+use cube_rs::{
+    detect::FileFormat,
+    iso::{IsoMetadata, Region},
+    virtual_fs::VirtualFile,
+};
+use log::info;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+/// Name of the folder holding files that are identical on every disc
+pub const SHARED_FOLDER: &str = "shared";
+/// Name of the JSON file describing a merged set of discs
+pub const DISC_SET_FILE: &str = "discs.json";
+
+/// Links the discs of a multi-disc game extracted into one tree: each disc is its shared
+/// folder with its own folder laid over the top. Only written, for looking through; a merged
+/// tree can't be packed back into discs.
+#[derive(Debug, Serialize)]
+struct DiscSet {
+    game_id: String,
+    title: String,
+    region: Region,
+    shared_folder: &'static str,
+    shared_files: usize,
+    discs: Vec<DiscSetEntry>,
+}
+
+#[derive(Debug, Serialize)]
+struct DiscSetEntry {
+    /// One-based, as printed on the disc
+    disc: u16,
+    folder: String,
+    /// The disc image this disc was extracted from
+    source: PathBuf,
+    revision: u8,
+    /// Files only in this disc's folder
+    files: usize,
+}
+
+/// Extracts every disc of a multi-disc game into one folder, with files that are the same
+/// on every disc in `shared/`, the rest in `disc1/`, `disc2/`, etc., and a `discs.json`
+/// linking them. Returns each disc image with the files planned for it; shared files and
+/// the `discs.json` are listed under the first disc. Every disc is held in memory until
+/// they've all been read, since a file can't be known to be shared before then.
+pub fn plan_merged_discs(
+    files: &[PathBuf],
+    out_path: Option<&Path>,
+    options: &ExtractOptions,
+) -> Result<Vec<Plan>, Box<dyn Error>> {
+    let mut discs = Vec::with_capacity(files.len());
+    for path in files {
+        if FileFormat::from_path(path) != Some(FileFormat::Gcm) {
+            return Err(format!("--merge-discs only works with disc images, but got {path:?}").into());
+        }
+        discs.push((path, IsoMetadata::open(path)?));
+    }
+    if discs.len() < 2 {
+        return Err("--merge-discs needs at least two disc images".into());
+    }
+    discs.sort_by_key(|(_, metadata)| metadata.disc_number);
+    let first = &discs[0].1;
+    for window in discs.windows(2) {
+        let ((path_a, a), (path_b, b)) = (&window[0], &window[1]);
+        if a.game_id != b.game_id {
+            return Err(format!(
+                "{path_a:?} ({}) and {path_b:?} ({}) are different games",
+                a.game_id, b.game_id
+            )
+            .into());
+        } else if a.disc_number == b.disc_number {
+            let disc = u16::from(a.disc_number) + 1;
+            return Err(format!("{path_a:?} and {path_b:?} are both disc {disc}").into());
+        }
+    }
+
+    // Named like a single disc would be, but without the disc number
+    let root = match (out_path, &options.output_dir) {
+        (Some(out_path), _) => out_path.to_owned(),
+        (None, output_dir) => {
+            let set_metadata = IsoMetadata {
+                disc_number: 0,
+                ..first.clone()
+            };
+            let input_dir = discs[0].0.parent().unwrap_or(Path::new(""));
+            output_dir
+                .as_deref()
+                .unwrap_or(input_dir)
+                .join(set_metadata.folder_name())
+        }
+    };
+
+    let mut planned = Vec::with_capacity(discs.len());
+    for (path, metadata) in discs.iter() {
+        let folder = format!("disc{}", u16::from(metadata.disc_number) + 1);
+        let disc_root = root.join(&folder);
+        let contents: BTreeMap<PathBuf, Extracted> = plan_extraction(path, Some(&disc_root), options, None)?
+            .into_iter()
+            .map(|extracted| {
+                let path = &extracted.file.path;
+                (path.strip_prefix(&disc_root).unwrap_or(path).to_owned(), extracted)
+            })
+            .collect();
+        planned.push((folder, contents));
+    }
+
+    // A file is shared if every disc has it at the same path with the same contents
+    let (first_folder, first_contents) = &planned[0];
+    let shared: Vec<PathBuf> = first_contents
+        .iter()
+        .filter(|(path, extracted)| {
+            planned[1..].iter().all(|(_, contents)| {
+                contents
+                    .get(*path)
+                    .is_some_and(|other| other.file.bytes == extracted.file.bytes)
+            })
+        })
+        .map(|(path, _)| path.clone())
+        .collect();
+    info!(
+        "{} of {} files in {first_folder} are the same on every disc",
+        shared.len(),
+        first_contents.len()
+    );
+
+    let mut shared_files = Vec::with_capacity(shared.len());
+    for (_, contents) in planned.iter_mut() {
+        for path in shared.iter() {
+            let mut extracted = contents.remove(path).expect("Shared file is on every disc");
+            if shared_files.len() < shared.len() {
+                extracted.file.set_path(root.join(SHARED_FOLDER).join(path));
+                shared_files.push(extracted);
+            }
+        }
+    }
+
+    let disc_set = DiscSet {
+        game_id: first.game_id.clone(),
+        title: first.internal_name.clone(),
+        region: first.region,
+        shared_folder: SHARED_FOLDER,
+        shared_files: shared_files.len(),
+        discs: discs
+            .iter()
+            .zip(planned.iter())
+            .map(|((path, metadata), (folder, contents))| DiscSetEntry {
+                disc: u16::from(metadata.disc_number) + 1,
+                folder: folder.clone(),
+                source: PathBuf::from(path.file_name().unwrap_or_default()),
+                revision: metadata.revision,
+                files: contents.len(),
+            })
+            .collect(),
+    };
+    shared_files.push(
+        VirtualFile {
+            path: root.join(DISC_SET_FILE),
+            bytes: serde_json::to_vec_pretty(&disc_set)?,
+        }
+        .into(),
+    );
+
+    let mut shared_files = Some(shared_files);
+    Ok(discs
+        .into_iter()
+        .zip(planned)
+        .map(|((path, _), (folder, contents))| {
+            let disc_root = root.join(folder);
+            let mut files = shared_files.take().unwrap_or_default();
+            files.extend(contents.into_iter().map(|(relative, mut extracted)| {
+                extracted.file.set_path(disc_root.join(relative));
+                extracted
+            }));
+            (path.clone(), files)
+        })
+        .collect())
+}
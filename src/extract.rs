@@ -1,36 +1,323 @@
-use crate::commands::ExtractOptions;
-use cube_rs::{bmg::Bmg, bti::BtiImage, iso::extract_iso, szs::extract_szs, virtual_fs::VirtualFile};
-use image::{ImageFormat, RgbaImage};
-use log::{debug, error, info};
+use crate::{
+    checksums::{sha256_hex, write_checksums},
+    commands::ExtractOptions,
+    discs::plan_merged_discs,
+    handlers::registry,
+    journal::{DiscMember, ExtractJournal, FinishedMembers, MemberOutput, WrittenFile},
+    profile::Profile,
+    report::{cube_error, OutputFile},
+    stream::{create_output_dir, is_std_stream, read_stdin_file, set_modified, write_output, write_tar},
+    template::TemplateValues,
+};
+use cube_rs::{
+    bmg::Bmg,
+    bti::{BtiError, BtiImage},
+    detect::{detect, identify, FileFormat},
+    error::CubeError,
+    iso::IsoMetadata,
+    png::{decode_png, encode_png},
+    preview::ContactSheet,
+    rarc::LoadLocation,
+    virtual_fs::VirtualFile,
+};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use serde_json::Value;
 use std::{
+    collections::{BTreeMap, HashMap},
     error::Error,
-    fs::{create_dir_all, write},
-    io::{BufWriter, Cursor},
+    fs::write,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
-pub fn try_extract(files: Vec<PathBuf>, out: Option<&Path>, options: ExtractOptions) -> Result<(), Box<dyn Error>> {
-    for path in files {
-        extract_and_write(&path, out, options)?;
+/// Everything an extraction wrote, or would have written in a dry run
+#[derive(Debug, Default, Serialize)]
+struct Manifest {
+    files: Vec<OutputFile>,
+    /// Output paths that more than one extracted file would be written to
+    collisions: Vec<PathBuf>,
+    /// Files renamed because their path only differed in case from one written before them
+    renamed: Vec<RenamedFile>,
+}
+
+#[derive(Debug, Serialize)]
+struct RenamedFile {
+    original: PathBuf,
+    path: PathBuf,
+}
+
+/// An input file and everything extracted from it, at the paths they'll be written to
+pub(crate) type Plan = (PathBuf, Vec<Extracted>);
+
+/// Returns every file that was extracted, or would have been in a dry run
+pub fn try_extract(
+    files: Vec<PathBuf>,
+    out: Option<&Path>,
+    options: &ExtractOptions,
+) -> Result<Vec<OutputFile>, Box<dyn Error>> {
+    let mut manifest = Manifest::default();
+    // Keyed by the path folded to lowercase, since Windows and macOS treat paths that only
+    // differ in case as the same file and one would silently overwrite the other
+    let mut seen_paths = HashMap::new();
+    let mut archived = Vec::new();
+    let mut checksums = BTreeMap::new();
+    let (mut journal, finished) = match options.resume {
+        true => {
+            // Options that only change what's reported about the extraction keep the same journal
+            let settings = ExtractOptions {
+                manifest: None,
+                write_checksums: None,
+                stats: false,
+                ..options.clone()
+            };
+            let (journal, finished) = ExtractJournal::open(&files, out, &format!("{settings:?}"))
+                .map_err(|e| format!("Couldn't open the extraction journal: {e}"))?;
+            (Some(journal), Some(finished))
+        }
+        false => (None, None),
+    };
+    let mut skipped = 0;
+    // Discs being merged have to be planned together, since what goes where depends on
+    // every disc. Otherwise each file is extracted and written before the next is read.
+    let plans: Box<dyn Iterator<Item = Result<Plan, Box<dyn Error>>>> = match options.merge_discs {
+        true => Box::new(plan_merged_discs(&files, out, options)?.into_iter().map(Ok)),
+        false => Box::new(
+            files
+                .into_iter()
+                .map(|path| Ok((path.clone(), plan_extraction(&path, out, options, finished.as_ref())?))),
+        ),
+    };
+    for plan in plans {
+        let (path, mut planned) = plan?;
+        if out.is_some_and(is_std_stream) && planned.len() > 1 && options.out_archive.is_none() {
+            return Err(format!(
+                "Can't write {} files to stdout. Use --out-archive - to get them as a tar archive instead",
+                planned.len()
+            )
+            .into());
+        }
+        for extracted in planned.iter_mut() {
+            let size = extracted.size();
+            let Extracted {
+                file,
+                format,
+                archive,
+                load_location,
+                ..
+            } = extracted;
+            match seen_paths.get(&fold_case(&file.path)) {
+                Some(seen) if *seen == file.path => {
+                    warn!("{:?} would be written more than once", &file.path);
+                    manifest.collisions.push(file.path.clone());
+                }
+                Some(seen) => {
+                    let renamed = rename_for_case(&file.path, &seen_paths);
+                    warn!(
+                        "{:?} only differs in case from {seen:?}, so it's renamed to {renamed:?}",
+                        &file.path
+                    );
+                    manifest.renamed.push(RenamedFile {
+                        original: file.path.clone(),
+                        path: renamed.clone(),
+                    });
+                    seen_paths.insert(fold_case(&renamed), renamed.clone());
+                    file.set_path(renamed);
+                }
+                None => {
+                    seen_paths.insert(fold_case(&file.path), file.path.clone());
+                }
+            }
+            manifest.files.push(OutputFile {
+                source: path.clone(),
+                path: file.path.clone(),
+                size,
+                format: *format,
+                archive: archive.clone(),
+                load_location: *load_location,
+            });
+        }
+
+        if options.dry_run {
+            continue;
+        }
+        if options.write_checksums.is_some() {
+            for extracted in planned.iter() {
+                let checksum = match extracted.written() {
+                    Some(written) => written.checksum.clone(),
+                    None => sha256_hex(&extracted.file.bytes),
+                };
+                checksums.insert(extracted.file.path.clone(), checksum);
+            }
+        }
+        if options.out_archive.is_some() {
+            archived.extend(planned.into_iter().map(|e| e.file));
+            continue;
+        }
+        // Input from stdin has no modification time to copy
+        let modified = match options.preserve_mtime && !is_std_stream(&path) {
+            true => Some(path.metadata()?.modified()?),
+            false => None,
+        };
+        for Extracted { file, member, .. } in planned {
+            let Some(journal) = journal.as_mut().filter(|_| !is_std_stream(&file.path)) else {
+                write_extracted(&file, modified)?;
+                continue;
+            };
+            let written = member.as_ref().and_then(|(_, output)| output.written.as_ref());
+            if written.is_some_and(|written| written.path != file.path) {
+                return Err(format!(
+                    "{:?} was extracted to somewhere else last time. Extract it again without --resume",
+                    &file.path
+                )
+                .into());
+            }
+            let checksum = match written {
+                Some(written) => written.checksum.clone(),
+                None => sha256_hex(&file.bytes),
+            };
+            if written.is_some() || journal.is_done(&file.path, &checksum, file.bytes.len()) {
+                debug!("Skipping {:?}, which was already extracted", &file.path);
+                skipped += 1;
+                journal.keep(&file.path, checksum.clone());
+            } else {
+                write_extracted(&file, modified)?;
+                journal.record(&file.path, checksum.clone());
+            }
+            if let Some((member, mut output)) = member {
+                output.written = Some(WrittenFile::new(&file.path, checksum)?);
+                journal.record_member_output(&member, output);
+            }
+        }
+    }
+    if skipped > 0 {
+        info!("Skipped {skipped} files that were already extracted");
+    }
+    if let Some(journal) = journal {
+        journal.finish();
+    }
+
+    if let (Some(archive_path), false) = (&options.out_archive, options.dry_run) {
+        debug!("Writing {} files to archive {archive_path:?}", archived.len());
+        let mut archive = Vec::new();
+        write_tar(&mut archive, &archived)?;
+        write_output(archive_path, &archive)?;
     }
 
+    if let (Some(checksums_path), false) = (&options.write_checksums, options.dry_run) {
+        write_checksums(checksums_path, &checksums)?;
+    }
+
+    if let Some(manifest_path) = &options.manifest {
+        write(manifest_path, serde_json::to_vec_pretty(&manifest)?)?;
+    }
+
+    Ok(manifest.files)
+}
+
+fn write_extracted(file: &VirtualFile, modified: Option<SystemTime>) -> Result<(), Box<dyn Error>> {
+    debug!("Writing file {:?}", &file.path);
+    create_output_dir(file.path.parent().expect("Path has no parent"))?;
+    write_output(&file.path, &file.bytes)?;
+    if let Some(modified) = modified.filter(|_| !is_std_stream(&file.path)) {
+        set_modified(&file.path, modified)?;
+    }
     Ok(())
 }
 
-fn extract_and_write(path: &Path, out_path: Option<&Path>, options: ExtractOptions) -> Result<(), Box<dyn Error>> {
-    let vfile = VirtualFile::read(path)?;
-    let extracted_files = extract(vfile, options)?;
+fn fold_case(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// A name for a file whose path clashes with another's once case is ignored, with a
+/// number added before its extensions like `name (2).bti.json`
+fn rename_for_case(path: &Path, seen_paths: &HashMap<String, PathBuf>) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    // Split at the first dot that isn't at the start, so sidecars stay paired with their files
+    let (stem, extensions) = match name.char_indices().skip(1).find(|&(_, c)| c == '.') {
+        Some((idx, _)) => name.split_at(idx),
+        None => (&*name, ""),
+    };
+    (2..)
+        .map(|n| path.with_file_name(format!("{stem} ({n}){extensions}")))
+        .find(|renamed| !seen_paths.contains_key(&fold_case(renamed)))
+        .expect("Ran out of numbers")
+}
 
-    if extracted_files.len() < 1 {
+/// Extracts a file and decides where each output file goes, without writing anything
+pub(crate) fn plan_extraction(
+    path: &Path,
+    out_path: Option<&Path>,
+    options: &ExtractOptions,
+    resume: Option<&FinishedMembers>,
+) -> Result<Vec<Extracted>, Box<dyn Error>> {
+    let mut vfile = match is_std_stream(path) {
+        true => read_stdin_file()?,
+        false => VirtualFile::read(path).map_err(|e| cube_error(e.into()).in_container(path))?,
+    };
+    sniff_extension(&mut vfile, options);
+    // Input from stdin is laid out as if it were a file in the current directory
+    let path = &vfile.path.clone();
+    let cx = ExtractContext {
+        options,
+        depth: 0,
+        path: vec![path.clone()],
+        resume,
+    };
+    let mut extracted_files = extract_nested(vfile, &cx).map_err(|e| cx.error(e))?;
+    // Archives are named by where they are inside the input, or by the input's own name
+    for extracted in extracted_files.iter_mut() {
+        if let Some(archive) = &mut extracted.archive {
+            let relative = relative_to_input(archive, path, options);
+            *archive = match relative.as_os_str().is_empty() {
+                true => PathBuf::from(path.file_name().unwrap_or_default()),
+                false => relative.to_owned(),
+            };
+        }
+    }
+
+    if extracted_files.is_empty() && !options.include.is_empty() {
+        warn!("Nothing in {path:?} matched --include");
+        return Ok(extracted_files);
+    } else if extracted_files.is_empty() {
         return Err("No output files?".into());
     }
 
+    let contact_sheet = if options.contact_sheet {
+        let sheet_path = match out_path {
+            Some(out_path) if extracted_files.len() > 1 => out_path.join("contact_sheet.png"),
+            _ => path.with_extension("contact_sheet.png"),
+        };
+        render_contact_sheet(extracted_files.iter().map(|e| &e.file), &sheet_path, options)?
+    } else {
+        None
+    };
+
+    if let Some(template) = &options.out_template {
+        let root = out_path
+            .or(options.output_dir.as_deref())
+            .unwrap_or(path.parent().unwrap_or(Path::new("")));
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        for extracted in extracted_files.iter_mut() {
+            let values = TemplateValues {
+                stem: &stem,
+                format: extracted.format.unwrap_or("other"),
+                relative_path: relative_to_input(&extracted.file.path, path, options),
+            };
+            extracted.file.set_path(root.join(template.render(&values)));
+        }
+        if let Some(mut contact_sheet) = contact_sheet {
+            contact_sheet.set_path(root.join(contact_sheet.path.file_name().unwrap_or_default()));
+            extracted_files.push(contact_sheet.into());
+        }
+        return Ok(extracted_files);
+    }
+
     // If we have exactly one extracted file, the output path becomes its filename
     if extracted_files.len() == 1 {
-        let out_file = &extracted_files[0];
-        let out_path = out_path.unwrap_or(&out_file.path);
-        create_dir_all(out_path.parent().expect("Path has no parent"))?;
-        write(out_path, &out_file.bytes)?;
+        if let Some(out_path) = out_path {
+            extracted_files[0].file.set_path(out_path);
+        }
     }
     // We have multiple extracted files.
     else {
@@ -40,10 +327,28 @@ fn extract_and_write(path: &Path, out_path: Option<&Path>, options: ExtractOptio
 
         // If the user did not provide an output path we use the name of the input
         // file minus its file extension as the output folder name
-        if parent.is_none() {
+        // Discs are named after their game ID and title instead, e.g. "GPVE01 - Pikmin 2"
+        if parent.is_none() && FileFormat::from_path(path) == Some(FileFormat::Gcm) {
+            let metadata = IsoMetadata::open(path)?;
+            parent = Some(path.with_file_name(metadata.folder_name()));
+        } else if parent.is_none() {
             let out_path = path.with_extension("");
-            // ... unless all the extracted files already start with this path
-            let should_create_folder = !extracted_files.iter().all(|ef| ef.path.starts_with(&out_path));
+            // ... unless all the extracted files already start with this path, or sit next
+            // to the input and are named after it, like a texture's PNG and header sidecar
+            let input_name = path.file_name().unwrap_or_default().to_string_lossy();
+            let beside_input = |ef: &Extracted| {
+                ef.file.path.parent() == path.parent()
+                    && ef
+                        .file
+                        .path
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .starts_with(&*input_name)
+            };
+            let should_create_folder = !extracted_files
+                .iter()
+                .all(|ef| ef.file.path.starts_with(&out_path) || beside_input(ef));
             if should_create_folder {
                 parent = Some(out_path);
             }
@@ -51,77 +356,298 @@ fn extract_and_write(path: &Path, out_path: Option<&Path>, options: ExtractOptio
         // If the user provided multiple input files and there are multiple output
         // files, we just dump everything in the current directory (do nothing).
 
-        for mut extracted in extracted_files {
-            if let Some(out_path) = &parent {
-                extracted.set_path(out_path.join(&extracted.path.strip_prefix(path).unwrap_or(&extracted.path)));
+        if let Some(out_path) = &parent {
+            for Extracted { file, .. } in extracted_files.iter_mut() {
+                file.set_path(out_path.join(file.path.strip_prefix(path).unwrap_or(&file.path)));
             }
-            debug!("Writing file {:?}", &extracted.path);
-            create_dir_all(&extracted.path.parent().expect("Path has no parent"))?;
-            write(extracted.path, &extracted.bytes)?;
         }
     }
 
-    Ok(())
+    extracted_files.extend(contact_sheet.map(Extracted::from));
+
+    if let (None, Some(output_dir)) = (out_path, &options.output_dir) {
+        let input_dir = path.parent().unwrap_or(Path::new(""));
+        for Extracted { file, .. } in extracted_files.iter_mut() {
+            file.set_path(output_dir.join(file.path.strip_prefix(input_dir).unwrap_or(&file.path)));
+        }
+    }
+    Ok(extracted_files)
 }
 
-fn extract(vfile: VirtualFile, options: ExtractOptions) -> Result<Vec<VirtualFile>, Box<dyn Error>> {
-    let path_string = vfile.path.to_string_lossy();
-    let extension = path_string
-        .rsplit_once('.')
-        .map(|(_prefix, extension)| extension.to_ascii_lowercase());
+/// Where an extracted file is within the folder it would normally be extracted to.
+/// Converted files that sit next to the input have no folder.
+fn relative_to_input<'a>(extracted: &'a Path, input: &Path, options: &ExtractOptions) -> &'a Path {
+    let input_dir = input.parent().unwrap_or(Path::new(""));
+    extracted
+        .strip_prefix(extracted_folder(input, options))
+        .or_else(|_| extracted.strip_prefix(input))
+        .or_else(|_| extracted.strip_prefix(input_dir))
+        .unwrap_or(extracted)
+}
 
-    match extension.as_deref() {
-        Some("iso") => {
-            let extracted: Vec<VirtualFile> = extract_iso(&vfile.path)?
-                .into_iter()
-                .flat_map(|vfile| extract(vfile, options))
-                .flatten()
-                .collect();
-            info!("Extracted {path_string} into {} files", extracted.len());
-            Ok(extracted)
+/// Collects every texture among the extracted files into a single preview image.
+/// Textures may be either raw BTIs or already converted to PNG.
+fn render_contact_sheet<'a>(
+    files: impl IntoIterator<Item = &'a VirtualFile>,
+    dest: &Path,
+    options: &ExtractOptions,
+) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+    let mut sheet = ContactSheet::new(128);
+    for file in files {
+        let path_string = file.path.to_string_lossy().to_ascii_lowercase();
+        let name = file.path.file_name().unwrap_or_default().to_string_lossy();
+        if path_string.ends_with(".bti") {
+            match decode_bti(&file.bytes, options) {
+                Ok(bti) => sheet.add_bti(name, &bti),
+                Err(e) => warn!("Skipping {name} in contact sheet: {e}"),
+            }
+        } else if path_string.ends_with(".bti.png") {
+            let (width, height, pixels) = decode_png(&file.bytes)?;
+            sheet.add_rgba(name, width, height, pixels);
         }
-        Some("szs") | Some("arc") => {
-            let mut extracted_folder_path = vfile.path.clone();
-            if !options.szs_preserve_extension {
-                extracted_folder_path.set_extension("");
+    }
+
+    if sheet.is_empty() {
+        info!("No textures found, skipping contact sheet");
+        return Ok(None);
+    }
+
+    let (width, height, pixels) = sheet.render();
+    info!("Rendered contact sheet of {} textures for {dest:?}", sheet.len());
+    Ok(Some(VirtualFile {
+        path: dest.to_owned(),
+        bytes: encode_png(width, height, pixels)?,
+    }))
+}
+
+/// Decodes a BTI, falling back to the user supplied palette if the BTI's own is unusable
+pub(crate) fn decode_bti(bytes: &[u8], options: &ExtractOptions) -> Result<BtiImage, BtiError> {
+    match (BtiImage::decode(bytes), &options.bti_palette) {
+        (Err(BtiError::MissingPalette | BtiError::PaletteOutOfBounds { .. }), Some(palette)) => {
+            BtiImage::decode_with_palette(bytes, &palette.0)
+        }
+        (result, _) => result,
+    }
+}
+
+/// Writes a BMG as JSON. If the profile has font metrics, each message gets a `stats`
+/// object with the width of each of its lines, which reading the JSON back skips over.
+pub(crate) fn bmg_to_json(bmg: &Bmg, profile: Option<&Profile>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let rules = profile.map(|profile| &profile.bmg);
+    let Some(font) = rules.and_then(|rules| rules.font.as_ref()) else {
+        return Ok(serde_json::to_vec_pretty(bmg)?);
+    };
+    let max_line_width = rules.and_then(|rules| rules.max_line_width);
+    let mut json = serde_json::to_value(bmg)?;
+    let messages = json.get_mut("messages").and_then(Value::as_array_mut);
+    for (message, text) in messages.into_iter().flatten().zip(bmg.messages()) {
+        message["stats"] = serde_json::to_value(font.message_stats(&text.message, max_line_width))?;
+    }
+    Ok(serde_json::to_vec_pretty(&json)?)
+}
+
+pub(crate) fn extract(vfile: VirtualFile, options: &ExtractOptions) -> Result<Vec<VirtualFile>, Box<dyn Error>> {
+    let cx = ExtractContext {
+        options,
+        depth: 0,
+        path: vec![vfile.path.clone()],
+        resume: None,
+    };
+    let extracted = extract_nested(vfile, &cx).map_err(|e| cx.error(e))?;
+    Ok(extracted.into_iter().map(|e| e.file).collect())
+}
+
+/// A file produced by extraction and the name of the format it was extracted from
+pub struct Extracted {
+    pub file: VirtualFile,
+    pub format: Option<&'static str>,
+    /// The outermost archive the file came out of, not counting discs. Set as extraction
+    /// unwinds, so handlers leave it empty.
+    pub archive: Option<PathBuf>,
+    /// Where the game loads the file from, for files in RARC archives
+    pub load_location: Option<LoadLocation>,
+    /// The disc file it came from when resuming, and how it came out of the disc. Files
+    /// that were already written have no contents, and say where they were written.
+    pub member: Option<(DiscMember, MemberOutput)>,
+}
+
+impl Extracted {
+    /// Where the file was already written, if the journal said it was when resuming
+    pub fn written(&self) -> Option<&WrittenFile> {
+        self.member.as_ref().and_then(|(_, output)| output.written.as_ref())
+    }
+
+    pub fn size(&self) -> usize {
+        match self.written() {
+            Some(written) => written.size as usize,
+            None => self.file.bytes.len(),
+        }
+    }
+}
+
+impl From<VirtualFile> for Extracted {
+    fn from(file: VirtualFile) -> Self {
+        Extracted {
+            file,
+            format: None,
+            archive: None,
+            load_location: None,
+            member: None,
+        }
+    }
+}
+
+/// Where in a tree of nested containers a file is being extracted from
+pub struct ExtractContext<'a> {
+    pub options: &'a ExtractOptions,
+    /// Number of containers the file is inside, where the input file itself is depth 0
+    pub depth: usize,
+    /// The input file, then the file's path inside each container on the way to it, for
+    /// saying where errors happened
+    pub path: Vec<PathBuf>,
+    /// Disc files a journal says were already extracted, when resuming
+    pub resume: Option<&'a FinishedMembers>,
+}
+
+impl ExtractContext<'_> {
+    /// Whether a disc or archive should be opened, according to `--max-depth` and
+    /// `--no-recurse-formats`. The input file itself is always opened unless the max
+    /// depth is 0.
+    pub fn opens_container(&self, vfile: &VirtualFile) -> bool {
+        let format = file_format(vfile);
+        let within_depth = self.options.max_depth.is_none_or(|max_depth| self.depth < max_depth);
+        let format_allowed =
+            self.depth == 0 || format.is_none_or(|format| !self.options.no_recurse_formats.contains(&format));
+        within_depth && format_allowed
+    }
+
+    /// Extracts the members of a container into `folder`, recursing into any nested
+    /// containers. Member paths are relative to the container.
+    pub fn extract_members(&self, folder: &Path, members: impl IntoIterator<Item = VirtualFile>) -> Vec<Extracted> {
+        let mut extracted = Vec::new();
+        for member in members {
+            let mut path = self.path.clone();
+            path.push(member.path.clone());
+            let cx = ExtractContext {
+                options: self.options,
+                depth: self.depth + 1,
+                path,
+                resume: self.resume,
+            };
+            let mut subfile = VirtualFile {
+                path: folder.join(&member.path),
+                bytes: member.bytes,
+            };
+            sniff_extension(&mut subfile, self.options);
+            // Members are only extracted once they've passed the filters
+            if !cx.is_nested_archive(&subfile) && !self.options.includes(&member.path) {
+                continue;
             }
-            let contents = extract_szs(vfile.bytes.clone())?;
-
-            let mut extracted = Vec::new();
-            for subfile in contents {
-                let subpath = extracted_folder_path.join(&subfile.path);
-                match extract(subfile.with_path(subpath.clone()), options) {
-                    Ok(subfiles) => extracted.extend(subfiles),
-                    Err(e) => error!("Couldn't extract {}: {e}", subpath.to_string_lossy()),
-                }
+            match extract_nested(subfile, &cx) {
+                Ok(subfiles) => extracted.extend(subfiles),
+                Err(e) => error!("{}", cx.error(e)),
             }
+        }
+        extracted
+    }
+
+    /// Notes where an error extracting this file happened
+    pub fn error(&self, error: Box<dyn Error>) -> CubeError {
+        self.path
+            .iter()
+            .rev()
+            .fold(cube_error(error), |error, container| error.in_container(container))
+    }
 
-            info!("Extracted {path_string} into {} files", extracted.len());
+    /// Archives that will be opened are always extracted so that `--include` filters can
+    /// apply to their contents. Ones that won't be are filtered like any other file.
+    fn is_nested_archive(&self, vfile: &VirtualFile) -> bool {
+        registry()
+            .find(vfile)
+            .is_some_and(|handler| handler.is_container() && handler.extracts(vfile, self))
+    }
+}
+
+/// With `--sniff`, identifies a file that no handler recognizes by its contents, and adds
+/// the usual extension for its format so it's extracted like any other file in that format
+fn sniff_extension(vfile: &mut VirtualFile, options: &ExtractOptions) {
+    if !options.sniff || registry().find(vfile).is_some() {
+        return;
+    }
+    // Discs are read from their path on disk, so they can't be renamed
+    let Some(format) = detect(&vfile.bytes).map(|detection| detection.format) else {
+        return;
+    };
+    let Some(extension) = format.info().extensions.first().filter(|_| format != FileFormat::Gcm) else {
+        return;
+    };
+    let mut path = vfile.path.clone().into_os_string();
+    path.push(".");
+    path.push(extension);
+    debug!("Sniffed {:?} as {}", &vfile.path, format.name());
+    vfile.set_path(path);
+}
+
+/// Extracts a file with the handler for its format, or keeps it as it is if there isn't
+/// one or the options say not to
+fn extract_nested(vfile: VirtualFile, cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+    let handler = registry().find(&vfile);
+    match handler {
+        Some(handler) if handler.extracts(&vfile, cx) => {
+            let archive =
+                (handler.is_container() && file_format(&vfile) != Some(FileFormat::Gcm)).then(|| vfile.path.clone());
+            let mut extracted = handler.extract(vfile, cx)?;
+            for file in extracted.iter_mut() {
+                if !handler.is_container() {
+                    file.format.get_or_insert(handler.name());
+                } else if archive.is_some() {
+                    // Containers further out replace this as extraction unwinds
+                    file.archive.clone_from(&archive);
+                }
+            }
             Ok(extracted)
         }
-        Some("bti") if options.extract_bti => {
-            let bti = BtiImage::decode(&vfile.bytes);
-            let mut dest = BufWriter::new(Cursor::new(Vec::new()));
-            RgbaImage::from_vec(bti.width, bti.height, bti.pixels().flatten().cloned().collect())
-                .unwrap()
-                .write_to(&mut dest, ImageFormat::Png)?;
+        _ => Ok(vec![Extracted {
+            file: vfile,
+            format: handler.map(|handler| handler.name()),
+            archive: None,
+            load_location: None,
+            member: None,
+        }]),
+    }
+}
 
-            let output_path = vfile.path.with_extension("bti.png");
-            info!("Extracted {path_string} => {output_path:?}");
-            Ok(vec![VirtualFile {
-                path: output_path,
-                bytes: dest.into_inner()?.into_inner(),
-            }])
-        }
-        Some("bmg") if options.extract_bmg => {
-            let bmg = Bmg::read(&vfile.bytes)?;
-            let output_path = vfile.path.with_extension("bmg.json");
-            info!("Extracted {path_string} => {output_path:?}");
-            Ok(vec![VirtualFile {
-                path: output_path,
-                bytes: serde_json::to_vec_pretty(&bmg)?,
-            }])
-        }
-        _ => Ok(vec![vfile]),
+pub(crate) fn extracted_folder(path: &Path, options: &ExtractOptions) -> PathBuf {
+    if options.szs_preserve_extension {
+        path.to_owned()
+    } else {
+        path.with_extension("")
     }
 }
+
+pub(crate) fn file_format(vfile: &VirtualFile) -> Option<FileFormat> {
+    identify(&vfile.path, &vfile.bytes)
+}
+
+/// Names of the files a banner is split into besides its archive's members, which pack
+/// looks for
+pub const IMET_HEADER_FILE: &str = "imet.bin";
+pub const IMD5_HEADER_FILE: &str = "imd5.bin";
+/// Holds the contents of a banner part that isn't a U8 archive
+pub const BANNER_PAYLOAD_FILE: &str = "payload.bin";
+
+/// Names of the files a GCI is split into besides its images, which pack looks for
+pub const GCI_METADATA_FILE: &str = "header.gci.json";
+pub const GCI_DATA_FILE: &str = "data.bin";
+
+/// Lists the files an AFS archive is split into and their order, which pack looks for
+pub const AFS_INDEX_FILE: &str = "index.afs.json";
+
+/// Lists the particles and textures a JPC is split into, which pack looks for
+pub const JPC_INDEX_FILE: &str = "index.jpc.json";
+
+/// The whole model an extracted model's textures are packed back into
+pub const BMD_MODEL_FILE: &str = "model.j3d";
+
+/// Lists the chunks and parts an audio archive is split into, which pack looks for
+pub const BAA_INDEX_FILE: &str = "index.baa.json";
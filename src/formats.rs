@@ -0,0 +1,59 @@
+use crate::handlers::registry;
+use serde_json::{json, Value};
+use std::error::Error;
+
+pub fn try_formats(json: bool) -> Result<(), Box<dyn Error>> {
+    if json {
+        let formats: Vec<Value> = registry()
+            .handlers()
+            .map(|handler| {
+                json!({
+                    "name": handler.name(),
+                    "description": handler.description(),
+                    "extensions": handler.extensions(),
+                    "container": handler.is_container(),
+                    "packs_into": handler.pack_formats(),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&formats)?);
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 4]> = registry()
+        .handlers()
+        .map(|handler| {
+            let list = |items: &[&str]| match items {
+                [] => String::from("-"),
+                items => items.join(", "),
+            };
+            [
+                handler.name().to_owned(),
+                list(&handler.extensions()),
+                list(handler.pack_formats()),
+                handler.description().to_owned(),
+            ]
+        })
+        .collect();
+
+    let header = ["FORMAT", "EXTENSIONS", "PACKS INTO", "DESCRIPTION"].map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        println!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use crate::{commands::ExtractOptions, extract::extract};
+use cube_rs::{
+    bmg::{Bmg, MessageId},
+    detect::{detect, FileFormat},
+    virtual_fs::VirtualFile,
+};
+use encoding_rs::SHIFT_JIS;
+use serde::Serialize;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+/// What to search besides BMG text
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GrepOptions {
+    pub ignore_case: bool,
+    pub file_names: bool,
+    pub bytes: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GrepMatch {
+    /// The file that was searched, as given on the command line
+    pub container: PathBuf,
+    /// Where the match is inside the container. Empty if it's the container itself.
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub location: MatchLocation,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MatchLocation {
+    /// A line of a BMG message's text
+    Message {
+        index: usize,
+        id: Option<MessageId>,
+        line: String,
+    },
+    FileName,
+    Bytes {
+        offset: usize,
+    },
+}
+
+pub fn try_grep(pattern: &str, files: &[PathBuf], options: GrepOptions, json: bool) -> Result<(), Box<dyn Error>> {
+    let mut matches = Vec::new();
+    for file in files {
+        matches.extend(grep(pattern, file, options)?);
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&matches)?);
+    } else {
+        for found in matches.iter() {
+            print_match(found);
+        }
+    }
+    Ok(())
+}
+
+/// Searches everything inside a file, opening nested discs and archives
+pub fn grep(pattern: &str, path: &Path, options: GrepOptions) -> Result<Vec<GrepMatch>, Box<dyn Error>> {
+    // Files are searched in their original formats, like `cube diff` compares them
+    let extract_options = ExtractOptions {
+        extract_bti: false,
+        extract_bmg: false,
+        extract_bcsv: false,
+        extract_gci: false,
+        ..Default::default()
+    };
+    let extracted = extract(VirtualFile::read(path)?, &extract_options)?;
+    let matcher = Matcher::new(pattern, options.ignore_case);
+
+    let root = path.with_extension("");
+    let mut matches = Vec::new();
+    for file in extracted {
+        let inner_path = if file.path == path {
+            PathBuf::new()
+        } else {
+            file.path.strip_prefix(&root).unwrap_or(&file.path).to_owned()
+        };
+        let mut found = |location| {
+            matches.push(GrepMatch {
+                container: path.to_owned(),
+                path: inner_path.clone(),
+                location,
+            })
+        };
+
+        if options.file_names && matcher.is_match(&inner_path.to_string_lossy()) {
+            found(MatchLocation::FileName);
+        }
+        if detect(&file.bytes).is_some_and(|detection| detection.format == FileFormat::Bmg) {
+            // Anything that looks like a BMG but can't be read is still searched by bytes
+            if let Ok(bmg) = Bmg::read(&file.bytes) {
+                for (index, message) in bmg.messages().enumerate() {
+                    for line in message.message.lines().filter(|line| matcher.is_match(line)) {
+                        found(MatchLocation::Message {
+                            index,
+                            id: message.id,
+                            line: line.to_owned(),
+                        });
+                    }
+                }
+            }
+        }
+        if options.bytes {
+            for offset in matcher.byte_matches(&file.bytes) {
+                found(MatchLocation::Bytes { offset });
+            }
+        }
+    }
+    Ok(matches)
+}
+
+/// A plain text pattern, along with the bytes it's stored as in game files
+struct Matcher {
+    pattern: String,
+    ignore_case: bool,
+    /// UTF-8, plus Shift-JIS if it's different
+    encodings: Vec<Vec<u8>>,
+}
+
+impl Matcher {
+    fn new(pattern: &str, ignore_case: bool) -> Self {
+        let mut encodings = vec![pattern.as_bytes().to_vec()];
+        let (shift_jis, _, unencodable) = SHIFT_JIS.encode(pattern);
+        if !unencodable && *shift_jis != *pattern.as_bytes() {
+            encodings.push(shift_jis.into_owned());
+        }
+        Matcher {
+            pattern: match ignore_case {
+                true => pattern.to_lowercase(),
+                false => pattern.to_owned(),
+            },
+            ignore_case,
+            encodings,
+        }
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        match self.ignore_case {
+            true => text.to_lowercase().contains(&self.pattern),
+            false => text.contains(&self.pattern),
+        }
+    }
+
+    /// Offsets of every place the pattern appears in any of its encodings. Ignoring case
+    /// only covers ASCII letters here.
+    fn byte_matches(&self, bytes: &[u8]) -> Vec<usize> {
+        let mut offsets: Vec<usize> = self
+            .encodings
+            .iter()
+            .filter(|needle| !needle.is_empty())
+            .flat_map(|needle| {
+                bytes.windows(needle.len()).enumerate().filter_map(|(offset, window)| {
+                    let found = match self.ignore_case {
+                        true => window.eq_ignore_ascii_case(needle),
+                        false => window == needle.as_slice(),
+                    };
+                    found.then_some(offset)
+                })
+            })
+            .collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        offsets
+    }
+}
+
+fn print_match(found: &GrepMatch) {
+    let mut location = found.container.to_string_lossy().into_owned();
+    if !found.path.as_os_str().is_empty() {
+        location.push(':');
+        location.push_str(&found.path.to_string_lossy());
+    }
+    match &found.location {
+        MatchLocation::Message {
+            index,
+            id: Some(id),
+            line,
+        } => println!("{location}: message {index} ({id}): {line}"),
+        MatchLocation::Message { index, id: None, line } => println!("{location}: message {index}: {line}"),
+        MatchLocation::FileName => println!("{location}"),
+        MatchLocation::Bytes { offset } => println!("{location}: bytes at {offset:#X}"),
+    }
+}
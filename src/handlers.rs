@@ -0,0 +1,1612 @@
+use crate::{
+    cache::PackCache,
+    commands::PackOptions,
+    extract::{
+        bmg_to_json, decode_bti, extracted_folder, file_format, ExtractContext, Extracted, AFS_INDEX_FILE,
+        BAA_INDEX_FILE, BANNER_PAYLOAD_FILE, BMD_MODEL_FILE, GCI_DATA_FILE, GCI_METADATA_FILE, IMD5_HEADER_FILE,
+        IMET_HEADER_FILE, JPC_INDEX_FILE,
+    },
+    journal::{DiscMember, FinishedMembers, MemberOutput},
+    pack::{bmg_from_json, bti_from_png, read_banner_tree, read_png, BtiMetadata},
+    recompress::dev_compression,
+    stream::{is_std_stream, read_input},
+};
+use cube_rs::{
+    afs::{AfsArchive, AfsFile, AfsIndex, AfsIndexEntry},
+    baa::{Baa, BaaFile, BaaIndex},
+    banner::BannerHeader,
+    bcsv::{Bcsv, COMMON_FIELD_NAMES},
+    blo::Blo,
+    bmd::{Bmd, BmdTexture},
+    bmg::Bmg,
+    bti::BtiHeader,
+    decode_bytes,
+    detect::{detect, FileFormat},
+    dolphin,
+    gci::{Gci, GciMetadata},
+    handler::{FormatHandler, Registry, Tool},
+    iso::{extract_iso, list_iso, write_iso, Fst, Iso, IsoReader, ShiftedRegion},
+    jpc::{JpaResource, Jpc, JpcIndex, JpcTexture},
+    msbt::Msbt,
+    png::IntensityPng,
+    rarc::{LoadLocations, Rarc},
+    rel::Rel,
+    szs::{decompress_szs, yaz0_compress, Yaz0Info},
+    u8_archive::U8Archive,
+    virtual_fs::VirtualFile,
+    yay0,
+};
+use log::{debug, info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fs::{read, write, File},
+    io::{self, BufWriter, IntoInnerError, Read},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// What the handlers extract and pack for
+pub struct Cli;
+
+impl Tool for Cli {
+    type ExtractContext<'a> = ExtractContext<'a>;
+    type PackOptions = PackOptions;
+    type Extracted = Extracted;
+}
+
+pub type Handler = dyn FormatHandler<Cli>;
+
+/// Every format cube extracts and packs. Extract and pack look formats up here rather
+/// than knowing about any of them, so a new format only needs a handler and a line here.
+pub fn builtin_registry() -> Registry<Cli> {
+    let mut registry = Registry::new();
+    registry.register(GcmHandler);
+    registry.register(RarcHandler);
+    registry.register(U8Handler);
+    registry.register(AfsHandler);
+    registry.register(GciHandler);
+    registry.register(JpcHandler);
+    registry.register(BmdHandler);
+    registry.register(BaaHandler);
+    registry.register(BannerHandler);
+    registry.register(BtiHandler);
+    registry.register(BcsvHandler);
+    registry.register(RelHandler);
+    registry.register(MsbtHandler);
+    registry.register(BloHandler);
+    // Claims any other JSON file when packing, so it has to come last
+    registry.register(BmgHandler);
+    registry
+}
+
+/// The handlers used by every command
+pub fn registry() -> &'static Registry<Cli> {
+    static REGISTRY: OnceLock<Registry<Cli>> = OnceLock::new();
+    REGISTRY.get_or_init(builtin_registry)
+}
+
+/// Extracted files are only ever guessed from their name, while folders are guessed from
+/// the files an extracted save or banner leaves in them
+fn file_name_ends_with(path: &Path, suffix: &str) -> bool {
+    !path.is_dir() && path.to_string_lossy().ends_with(suffix)
+}
+
+/// Reports what a permissive read had to work around, so damage shows up in `--json` reports
+fn log_salvage_warnings(path: &Path, warnings: &[String]) {
+    for warning in warnings {
+        warn!("{}: {warning}", path.to_string_lossy());
+    }
+}
+
+struct GcmHandler;
+
+impl FormatHandler<Cli> for GcmHandler {
+    fn name(&self) -> &'static str {
+        "gcm"
+    }
+
+    fn description(&self) -> &'static str {
+        "GameCube disc image"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Gcm]
+    }
+
+    fn is_container(&self) -> bool {
+        true
+    }
+
+    fn extracts(&self, vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.options.fst_only || cx.opens_container(vfile)
+    }
+
+    fn extract(&self, vfile: VirtualFile, cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let path_string = vfile.path.to_string_lossy();
+        if cx.options.fst_only {
+            let fst = Fst::open(&vfile.path)?;
+            let output_path = vfile.path.with_extension("fst.json");
+            info!("Extracted file system table of {path_string} => {output_path:?}");
+            return Ok(vec![Extracted {
+                file: VirtualFile {
+                    path: output_path,
+                    bytes: serde_json::to_vec_pretty(&fst)?,
+                },
+                format: Some(self.name()),
+                archive: None,
+                load_location: None,
+                member: None,
+            }]);
+        }
+
+        let system_files = if cx.options.extract_sys_files {
+            Iso::open(&vfile.path)?.system_files()
+        } else {
+            Vec::new()
+        };
+        // Disc paths are already relative to the folder the disc is extracted to
+        let extracted = match cx.resume.filter(|_| !cx.options.contact_sheet) {
+            Some(finished) => {
+                let mut extracted = cx.extract_members(Path::new(""), system_files);
+                extracted.extend(extract_disc_resuming(&vfile.path, cx, finished)?);
+                extracted
+            }
+            None => cx.extract_members(Path::new(""), system_files.into_iter().chain(extract_iso(&vfile.path)?)),
+        };
+        info!("Extracted {path_string} into {} files", extracted.len());
+        Ok(extracted)
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["fst", "iso"]
+    }
+
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        if file_name_ends_with(path, ".fst.json") {
+            Some("fst")
+        } else {
+            // Only folders extracted with their system files have what's needed to rebuild a disc
+            path.join(Iso::SYSTEM_FOLDER)
+                .join("boot.bin")
+                .is_file()
+                .then_some("iso")
+        }
+    }
+
+    fn pack(&self, path: &Path, format: &str, _options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        // Only discs written to stdout end up here, the rest are streamed
+        if format == "iso" {
+            if !path.is_dir() {
+                return Ok(None);
+            }
+            let mut bytes = Vec::new();
+            log_shifted(&write_iso(path, &mut bytes)?);
+            warn_disc_size(path, bytes.len() as u64);
+            return Ok(Some(VirtualFile {
+                path: path.with_extension("iso"),
+                bytes,
+            }));
+        }
+        let fst: Fst = serde_json::from_slice(&read_input(path)?)?;
+        Ok(Some(VirtualFile {
+            path: path.with_extension("bin"),
+            bytes: fst.write(),
+        }))
+    }
+
+    fn streamed_output(&self, path: &Path, format: &str, _options: &PackOptions) -> Option<PathBuf> {
+        (format == "iso" && path.is_dir()).then(|| path.with_extension("iso"))
+    }
+
+    fn pack_to(&self, path: &Path, _format: &str, _options: &PackOptions, out: &Path) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(out)?);
+        log_shifted(&write_iso(path, &mut writer)?);
+        let size = writer
+            .into_inner()
+            .map_err(IntoInnerError::into_error)?
+            .metadata()?
+            .len();
+        warn_disc_size(path, size);
+        Ok(())
+    }
+}
+
+/// Extracts a disc's files one at a time, without reading the ones the journal says were
+/// already extracted from the same place on the same disc. Those are stood in for by
+/// what they were extracted into, which isn't written again.
+fn extract_disc_resuming(
+    path: &Path,
+    cx: &ExtractContext,
+    finished: &FinishedMembers,
+) -> Result<Vec<Extracted>, Box<dyn Error>> {
+    let disc_modified = path.metadata()?.modified()?;
+    let mut reader = IsoReader::open(path)?;
+    let mut extracted = Vec::new();
+    let mut skipped = 0;
+    for file in list_iso(path)? {
+        let member = DiscMember {
+            disc: path.to_owned(),
+            disc_modified,
+            path: file.path.clone(),
+            offset: file.offset,
+            size: file.size,
+        };
+        if let Some(outputs) = finished.outputs(&member) {
+            extracted.extend(outputs.iter().map(|output| output.placeholder(&member)));
+            skipped += 1;
+            continue;
+        }
+        let mut bytes = vec![0; file.size as usize];
+        reader.read_at(file.offset, &mut bytes)?;
+        for mut output in cx.extract_members(Path::new(""), [VirtualFile { path: file.path, bytes }]) {
+            output.member = Some((member.clone(), MemberOutput::new(&output)));
+            extracted.push(output);
+        }
+    }
+    if skipped > 0 {
+        debug!("Didn't read {skipped} files from {path:?}, which were already extracted");
+    }
+    Ok(extracted)
+}
+
+fn warn_disc_size(path: &Path, size: u64) {
+    if size > Iso::DISC_SIZE {
+        warn!(
+            "{} is too large to fit on a GameCube disc, so it'll only work in emulators",
+            path.to_string_lossy()
+        );
+    }
+}
+
+/// Logs which files moved when a disc was rebuilt
+pub(crate) fn log_shifted(shifted: &[ShiftedRegion]) {
+    for region in shifted.iter() {
+        let files = match region.files.len() {
+            1 => format!("{:?}", region.files[0]),
+            2 => format!("{:?} and the file after it", region.files[0]),
+            count => format!("{:?} and the {} files after it", region.files[0], count - 1),
+        };
+        info!(
+            "Moved {files} from {:#X} to {:#X} to make room",
+            region.old_offset, region.new_offset
+        );
+    }
+}
+
+struct RarcHandler;
+
+impl FormatHandler<Cli> for RarcHandler {
+    fn name(&self) -> &'static str {
+        "rarc"
+    }
+
+    fn description(&self) -> &'static str {
+        "Archive, optionally Yaz0 or Yay0 compressed"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Yaz0, FileFormat::Yay0, FileFormat::Rarc]
+    }
+
+    fn is_container(&self) -> bool {
+        true
+    }
+
+    fn extracts(&self, vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.opens_container(vfile)
+    }
+
+    fn extract(&self, vfile: VirtualFile, cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let yaz0_info = Yaz0Info::read(&vfile.bytes).filter(|info| !info.is_default());
+        let compressed = Yaz0Info::read(&vfile.bytes).is_some() || vfile.bytes.starts_with(b"Yay0");
+        let arc = decompress_szs(vfile.bytes)?;
+        let mut extracted = if compressed && !matches!(arc.get(..4), Some(b"RARC" | b"CRAR")) {
+            // Some games compress lone files too, which come out next to the input as they are
+            let file = VirtualFile {
+                path: bare_file_name(&vfile.path, &arc),
+                bytes: arc,
+            };
+            cx.extract_members(vfile.path.parent().unwrap_or(Path::new("")), [file])
+        } else {
+            let rarc = if cx.options.permissive {
+                let (rarc, warnings) = Rarc::parse_permissive(&arc)?;
+                log_salvage_warnings(&vfile.path, &warnings);
+                rarc
+            } else {
+                Rarc::parse(&arc)?
+            };
+            let folder = extracted_folder(&vfile.path, cx.options);
+            let load_locations = rarc.load_locations();
+            let mut extracted = Vec::new();
+            for (path, bytes) in rarc.files() {
+                let load_location = load_locations.get(&path);
+                let member = VirtualFile {
+                    path,
+                    bytes: bytes.to_vec(),
+                };
+                // Files from archives nested inside this one keep their own archive's location
+                extracted.extend(cx.extract_members(&folder, [member]).into_iter().map(|mut e| {
+                    e.load_location.get_or_insert(load_location);
+                    e
+                }));
+            }
+            // Archives with files that aren't loaded into main RAM get a list of where they
+            // go next to the archive, so packing can put them back
+            if !load_locations.is_default() {
+                extracted.push(Extracted {
+                    file: VirtualFile {
+                        path: load_locations_path(&vfile.path),
+                        bytes: serde_json::to_vec_pretty(&load_locations)?,
+                    },
+                    format: Some(self.name()),
+                    archive: None,
+                    load_location: None,
+                    member: None,
+                });
+            }
+            // Anything left after the last file is kept too, for packing byte for byte
+            // with --arc-keep-trailing-data
+            if rarc.has_trailing_data() {
+                extracted.push(Extracted {
+                    file: VirtualFile {
+                        path: trailing_data_path(&vfile.path),
+                        bytes: rarc.trailing_data().to_vec(),
+                    },
+                    format: Some(self.name()),
+                    archive: None,
+                    load_location: None,
+                    member: None,
+                });
+            }
+            extracted
+        };
+        // Keep a nonstandard header next to the archive so packing can restore it
+        if let Some(info) = yaz0_info {
+            extracted.push(Extracted {
+                file: VirtualFile {
+                    path: yaz0_info_path(&vfile.path),
+                    bytes: serde_json::to_vec_pretty(&info)?,
+                },
+                format: Some(self.name()),
+                archive: None,
+                load_location: None,
+                member: None,
+            });
+        }
+        info!(
+            "Extracted {} into {} files",
+            vfile.path.to_string_lossy(),
+            extracted.len()
+        );
+        Ok(extracted)
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["szs", "szp", "arc"]
+    }
+
+    // Folders are never guessed, otherwise every nested folder would be packed into an
+    // archive. Yaz0 header, load location, and trailing data sidecars are packed along with
+    // their folder, and dev compression markers belong to an archive that's already packed.
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        is_archive_sidecar(path).then_some("szs")
+    }
+
+    fn pack(&self, path: &Path, format: &str, options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        if is_archive_sidecar(path) {
+            return Ok(None);
+        }
+        // Named up front, since the archive's sidecars are found by its name
+        let archive_path = archive_output(path, format, options);
+
+        // A lone file is compressed as it is, for games that keep bare Yaz0 files
+        let bytes = if path.is_dir() {
+            let ArchiveSidecars {
+                load_locations,
+                trailing_data,
+            } = ArchiveSidecars::read(&archive_path, options)?;
+            let mut settings = format!(
+                "{format} {} {:?} {} {} {} {}",
+                options.arc_yaz0_compress,
+                dev_compression(format, options),
+                options.compression_level,
+                options.arc_alignment,
+                options.arc_byte_order,
+                serde_json::to_string(&load_locations)?
+            )
+            .into_bytes();
+            if let Some(trailing_data) = trailing_data.as_ref() {
+                settings.push(b' ');
+                settings.extend(trailing_data);
+            }
+            let cache = match options.no_cache {
+                true => None,
+                false => Some(PackCache::new(path, &settings)?),
+            };
+            match cache.as_ref().and_then(PackCache::get) {
+                Some(bytes) => bytes,
+                None => {
+                    let mut bytes = Vec::new();
+                    Rarc::encode_to(
+                        path,
+                        &mut bytes,
+                        options.arc_alignment,
+                        options.arc_byte_order,
+                        &load_locations,
+                        trailing_data.as_deref(),
+                    )?;
+                    let bytes = compress_archive(bytes, format, options)?;
+                    if let Some(cache) = cache {
+                        cache.put(&bytes);
+                    }
+                    bytes
+                }
+            }
+        } else if format == "arc" {
+            return Err(format!("Only folders can be packed into archives, but {path:?} is a file").into());
+        } else {
+            compress_archive(read_input(path)?, format, options)?
+        };
+
+        let mut rarc = VirtualFile {
+            bytes,
+            path: archive_path,
+        };
+
+        let info_path = yaz0_info_path(&rarc.path);
+        if Yaz0Info::read(&rarc.bytes).is_some() && info_path.is_file() {
+            let info: Yaz0Info = serde_json::from_slice(&read(&info_path)?)?;
+            info.apply(&mut rarc.bytes);
+        }
+
+        Ok(Some(rarc))
+    }
+
+    // Archives are only built in memory when they're compressed, which needs all of them
+    fn streamed_output(&self, path: &Path, format: &str, options: &PackOptions) -> Option<PathBuf> {
+        let compressed = options.arc_yaz0_compress && matches!(format, "szs" | "szp");
+        let streamed = path.is_dir() && !is_archive_sidecar(path) && !compressed;
+        (streamed && dev_compression(format, options).is_none()).then(|| archive_output(path, format, options))
+    }
+
+    fn pack_to(&self, path: &Path, format: &str, options: &PackOptions, out: &Path) -> Result<(), Box<dyn Error>> {
+        let sidecars = ArchiveSidecars::read(&archive_output(path, format, options), options)?;
+        let mut writer = BufWriter::new(File::create(out)?);
+        Rarc::encode_to(
+            path,
+            &mut writer,
+            options.arc_alignment,
+            options.arc_byte_order,
+            &sidecars.load_locations,
+            sidecars.trailing_data.as_deref(),
+        )?;
+        writer.into_inner().map_err(IntoInnerError::into_error)?;
+        Ok(())
+    }
+}
+
+/// Where a file or folder packed by [`RarcHandler`] goes by default
+fn archive_output(path: &Path, format: &str, options: &PackOptions) -> PathBuf {
+    let mut archive_path = match path.is_dir() {
+        true => path.with_extension("arc"),
+        false => path.to_owned(),
+    };
+    if options.arc_yaz0_compress && matches!(format, "szs" | "szp") {
+        archive_path.set_extension(format);
+    }
+    if let Some(ext) = options.arc_extension.as_ref() {
+        archive_path.set_extension(ext);
+    }
+    archive_path
+}
+
+/// What's kept next to an archive's folder to be packed along with it
+struct ArchiveSidecars {
+    load_locations: LoadLocations,
+    trailing_data: Option<Vec<u8>>,
+}
+
+impl ArchiveSidecars {
+    fn read(archive_path: &Path, options: &PackOptions) -> Result<Self, Box<dyn Error>> {
+        let locations_path = load_locations_path(archive_path);
+        let mut load_locations: LoadLocations = match locations_path.is_file() {
+            true => serde_json::from_slice(&read(&locations_path)?)?,
+            false => LoadLocations::default(),
+        };
+        if let Some(location) = options.arc_load_location {
+            load_locations.default = location;
+        }
+        let trailing_path = trailing_data_path(archive_path);
+        let trailing_data = match options.arc_keep_trailing_data && trailing_path.is_file() {
+            true => Some(read(&trailing_path)?),
+            false => None,
+        };
+        Ok(ArchiveSidecars {
+            load_locations,
+            trailing_data,
+        })
+    }
+}
+
+fn compress_archive(bytes: Vec<u8>, format: &str, options: &PackOptions) -> Result<Vec<u8>, Box<dyn Error>> {
+    if let Some(compression) = dev_compression(format, options) {
+        return Ok(compression.compress(bytes)?);
+    }
+    Ok(match (options.arc_yaz0_compress, format) {
+        (true, "szs") => yaz0_compress(&bytes, options.compression_level),
+        (true, "szp") => yay0::compress(&bytes, options.compression_level),
+        _ => bytes,
+    })
+}
+
+/// Name for the contents of a compressed file that isn't an archive. It keeps any extension
+/// left once the compressed one is dropped, like `model.bdl.szs`, or else gets one for
+/// whatever format it's detected as.
+fn bare_file_name(compressed: &Path, contents: &[u8]) -> PathBuf {
+    let name = PathBuf::from(compressed.file_name().unwrap_or_default()).with_extension("");
+    if name.extension().is_some() {
+        return name;
+    }
+    let format = detect(contents).map(|detection| detection.format);
+    let extension = format.and_then(|format| format.info().extensions.first().copied());
+    name.with_extension(extension.unwrap_or("bin"))
+}
+
+/// Suffix of the file a nonstandard Yaz0 header is kept in, next to its archive
+const YAZ0_INFO_EXTENSION: &str = ".yaz0.json";
+
+pub(crate) fn yaz0_info_path(archive: &Path) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(YAZ0_INFO_EXTENSION);
+    path.into()
+}
+
+/// Suffix of the file listing where an archive's files are loaded, next to the archive
+const LOAD_LOCATIONS_EXTENSION: &str = ".load.json";
+
+pub(crate) fn load_locations_path(archive: &Path) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(LOAD_LOCATIONS_EXTENSION);
+    path.into()
+}
+
+/// Suffix of the file holding whatever an archive had after its last file, next to the archive
+const TRAILING_DATA_EXTENSION: &str = ".trailing.bin";
+
+pub(crate) fn trailing_data_path(archive: &Path) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(TRAILING_DATA_EXTENSION);
+    path.into()
+}
+
+/// Suffix of the marker next to an archive packed with `--arc-dev-compression`
+pub(crate) const DEV_MARKER_EXTENSION: &str = ".dev.json";
+
+pub(crate) fn dev_marker_path(archive: &Path) -> PathBuf {
+    let mut path = archive.as_os_str().to_owned();
+    path.push(DEV_MARKER_EXTENSION);
+    path.into()
+}
+
+fn is_archive_sidecar(path: &Path) -> bool {
+    [
+        YAZ0_INFO_EXTENSION,
+        LOAD_LOCATIONS_EXTENSION,
+        TRAILING_DATA_EXTENSION,
+        DEV_MARKER_EXTENSION,
+    ]
+    .iter()
+    .any(|extension| file_name_ends_with(path, extension))
+}
+
+struct U8Handler;
+
+impl FormatHandler<Cli> for U8Handler {
+    fn name(&self) -> &'static str {
+        "u8"
+    }
+
+    fn description(&self) -> &'static str {
+        "Archive"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::U8]
+    }
+
+    fn is_container(&self) -> bool {
+        true
+    }
+
+    fn extracts(&self, vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.opens_container(vfile)
+    }
+
+    fn extract(&self, vfile: VirtualFile, cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let members = decode_bytes::<U8Archive>(&vfile.bytes)?;
+        let extracted = cx.extract_members(&extracted_folder(&vfile.path, cx.options), members);
+        info!(
+            "Extracted {} into {} files",
+            vfile.path.to_string_lossy(),
+            extracted.len()
+        );
+        Ok(extracted)
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["u8"]
+    }
+
+    fn pack(&self, path: &Path, _format: &str, options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        let mut u8 = U8Archive::encode_dir(path)?;
+        if let Some(ext) = options.arc_extension.as_ref() {
+            u8.set_path(u8.path.with_extension(ext));
+        }
+        Ok(Some(u8))
+    }
+}
+
+struct AfsHandler;
+
+impl FormatHandler<Cli> for AfsHandler {
+    fn name(&self) -> &'static str {
+        "afs"
+    }
+
+    fn description(&self) -> &'static str {
+        "CRI archive, used by many third-party games"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Afs]
+    }
+
+    fn is_container(&self) -> bool {
+        true
+    }
+
+    fn extracts(&self, vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.opens_container(vfile)
+    }
+
+    fn extract(&self, vfile: VirtualFile, cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let afs = AfsArchive::parse(&vfile.bytes)?;
+        let index = afs.index();
+        index.check_paths()?;
+        // Compared ignoring case, since that's how some file systems will see them
+        let clashes = |entry: &AfsIndexEntry| entry.path.eq_ignore_ascii_case(AFS_INDEX_FILE);
+        if index.files.iter().any(clashes) {
+            return Err(format!("A file in the archive is named {AFS_INDEX_FILE}, like its index").into());
+        }
+        let mut members = vec![VirtualFile {
+            path: PathBuf::from(AFS_INDEX_FILE),
+            bytes: serde_json::to_vec_pretty(&index)?,
+        }];
+        for (entry, (_, bytes)) in index.files.iter().zip(afs.files()) {
+            members.push(VirtualFile {
+                path: PathBuf::from(&entry.path),
+                bytes: bytes.to_vec(),
+            });
+        }
+        let extracted = cx.extract_members(&extracted_folder(&vfile.path, cx.options), members);
+        info!(
+            "Extracted {} into {} files",
+            vfile.path.to_string_lossy(),
+            extracted.len()
+        );
+        Ok(extracted)
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["afs"]
+    }
+
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        // The index is packed along with the rest of its folder
+        (path.join(AFS_INDEX_FILE).is_file() || file_name_ends_with(path, ".afs.json")).then_some("afs")
+    }
+
+    fn pack(&self, path: &Path, _format: &str, _options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        if !path.is_dir() {
+            return Ok(None);
+        }
+        let index: AfsIndex = serde_json::from_slice(&read(path.join(AFS_INDEX_FILE))?)?;
+        let data = index
+            .files
+            .iter()
+            .map(|entry| {
+                read(path.join(&entry.path))
+                    .map_err(|e| format!("Couldn't read {:?} listed in {AFS_INDEX_FILE}: {e}", entry.path))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let files: Vec<AfsFile> = index
+            .files
+            .iter()
+            .zip(&data)
+            .map(|(entry, data)| AfsFile {
+                name: entry.name.as_deref().unwrap_or(&entry.path),
+                modified: entry.modified,
+                data,
+            })
+            .collect();
+        Ok(Some(VirtualFile {
+            path: path.with_extension("afs"),
+            bytes: AfsArchive::build(&files, index.has_names)?,
+        }))
+    }
+}
+
+struct GciHandler;
+
+impl FormatHandler<Cli> for GciHandler {
+    fn name(&self) -> &'static str {
+        "gci"
+    }
+
+    fn description(&self) -> &'static str {
+        "Memory card save file"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Gci]
+    }
+
+    fn extracts(&self, _vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.options.extract_gci
+    }
+
+    fn extract(&self, vfile: VirtualFile, _cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let gci = Gci::read(&vfile.bytes)?;
+        let out_dir = vfile.path.with_extension("");
+        let mut extracted = vec![
+            VirtualFile {
+                path: out_dir.join(GCI_METADATA_FILE),
+                bytes: serde_json::to_vec_pretty(&gci.metadata()?)?,
+            },
+            VirtualFile {
+                path: out_dir.join(GCI_DATA_FILE),
+                bytes: gci.data.clone(),
+            },
+        ];
+        if let Some(banner) = gci.banner()? {
+            extracted.push(VirtualFile {
+                path: out_dir.join("banner.png"),
+                bytes: banner.to_png()?,
+            });
+        }
+        for (frame, icon) in gci.icons()?.into_iter().enumerate() {
+            if let Some(icon) = icon {
+                extracted.push(VirtualFile {
+                    path: out_dir.join(format!("icon_{frame}.png")),
+                    bytes: icon.to_png()?,
+                });
+            }
+        }
+        info!(
+            "Extracted {} into {} files",
+            vfile.path.to_string_lossy(),
+            extracted.len()
+        );
+        Ok(extracted.into_iter().map(Into::into).collect())
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["gci"]
+    }
+
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        // The metadata sidecar is packed along with the rest of its folder
+        (path.join(GCI_METADATA_FILE).is_file() || file_name_ends_with(path, ".gci.json")).then_some("gci")
+    }
+
+    fn pack(&self, path: &Path, _format: &str, _options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        if !path.is_dir() {
+            return Ok(None);
+        }
+        let metadata: GciMetadata = serde_json::from_slice(&read(path.join(GCI_METADATA_FILE))?)?;
+        let mut gci = Gci {
+            header: metadata.header,
+            data: read(path.join(GCI_DATA_FILE))?,
+        };
+        gci.set_comments(&metadata.comments)?;
+        let banner_path = path.join("banner.png");
+        if banner_path.is_file() {
+            gci.set_banner(&read_png(&banner_path)?)?;
+        }
+        for frame in 0..gci.icons()?.len() {
+            let icon_path = path.join(format!("icon_{frame}.png"));
+            if icon_path.is_file() {
+                gci.set_icon(frame, &read_png(&icon_path)?)?;
+            }
+        }
+        Ok(Some(VirtualFile {
+            path: path.with_extension("gci"),
+            bytes: gci.write(),
+        }))
+    }
+}
+
+struct JpcHandler;
+
+impl FormatHandler<Cli> for JpcHandler {
+    fn name(&self) -> &'static str {
+        "jpc"
+    }
+
+    fn description(&self) -> &'static str {
+        "Particle effects, split into one file per particle and texture"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Jpc]
+    }
+
+    fn is_container(&self) -> bool {
+        true
+    }
+
+    fn extracts(&self, vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.opens_container(vfile)
+    }
+
+    fn extract(&self, vfile: VirtualFile, cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let jpc = Jpc::read(&vfile.bytes)?;
+        let index = jpc.index()?;
+        let mut members = vec![VirtualFile {
+            path: PathBuf::from(JPC_INDEX_FILE),
+            bytes: serde_json::to_vec_pretty(&index)?,
+        }];
+        let mut seen = HashSet::new();
+        for particle in jpc.particles {
+            if !seen.insert(particle.id()) {
+                return Err(format!("Particle {} appears more than once", particle.id()).into());
+            }
+            members.push(VirtualFile {
+                path: jpa_path(particle.id()),
+                bytes: particle.bytes().to_vec(),
+            });
+        }
+        let mut seen = HashSet::new();
+        for texture in jpc.textures {
+            if !seen.insert(texture.name.clone()) {
+                return Err(format!("Texture {:?} appears more than once", texture.name).into());
+            }
+            members.push(VirtualFile {
+                path: jpc_texture_path(&texture.name),
+                bytes: texture.bti,
+            });
+        }
+        let extracted = cx.extract_members(&extracted_folder(&vfile.path, cx.options), members);
+        info!(
+            "Extracted {} into {} files",
+            vfile.path.to_string_lossy(),
+            extracted.len()
+        );
+        Ok(extracted)
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["jpc"]
+    }
+
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        // The index is packed along with the rest of its folder
+        (path.join(JPC_INDEX_FILE).is_file() || file_name_ends_with(path, ".jpc.json")).then_some("jpc")
+    }
+
+    fn pack(&self, path: &Path, _format: &str, _options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        if !path.is_dir() {
+            return Ok(None);
+        }
+        let index: JpcIndex = serde_json::from_slice(&read(path.join(JPC_INDEX_FILE))?)?;
+        let texture_indices: HashMap<&str, u16> = index
+            .textures
+            .iter()
+            .enumerate()
+            .map(|(i, name)| (name.as_str(), i as u16))
+            .collect();
+        let particles = index
+            .particles
+            .iter()
+            .map(|entry| {
+                let mut particle = JpaResource::new(read(path.join(jpa_path(entry.id)))?)?;
+                particle.set_id(entry.id);
+                let textures = entry
+                    .textures
+                    .iter()
+                    .map(|name| {
+                        texture_indices
+                            .get(name.as_str())
+                            .copied()
+                            .ok_or_else(|| format!("Particle {} uses texture {name:?}, which isn't listed", entry.id))
+                    })
+                    .collect::<Result<Vec<u16>, _>>()?;
+                particle.set_texture_indices(&textures)?;
+                Ok(particle)
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        let textures = index
+            .textures
+            .iter()
+            .map(|name| {
+                Ok(JpcTexture {
+                    name: name.clone(),
+                    bti: read(path.join(jpc_texture_path(name)))?,
+                })
+            })
+            .collect::<Result<_, io::Error>>()?;
+        let jpc = Jpc {
+            version: index.version,
+            particles,
+            textures,
+        };
+        Ok(Some(VirtualFile {
+            path: path.with_extension("jpc"),
+            bytes: jpc.write()?,
+        }))
+    }
+}
+
+fn jpa_path(id: u16) -> PathBuf {
+    Path::new("particles").join(format!("{id}.jpa"))
+}
+
+fn jpc_texture_path(name: &str) -> PathBuf {
+    Path::new("textures").join(format!("{name}.bti"))
+}
+
+struct BmdHandler;
+
+impl FormatHandler<Cli> for BmdHandler {
+    fn name(&self) -> &'static str {
+        "bmd"
+    }
+
+    fn description(&self) -> &'static str {
+        "3D models, split into their textures and the rest of the model"
+    }
+
+    // The model kept in an extracted model's folder is claimed too, so that it's never
+    // sniffed and renamed
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Bmd]
+    }
+
+    // The rest of an extracted model is packed back up along with its textures
+    fn detect(&self, vfile: &VirtualFile) -> bool {
+        file_format(vfile) == Some(FileFormat::Bmd) || vfile.path.file_name().is_some_and(|name| name == BMD_MODEL_FILE)
+    }
+
+    fn is_container(&self) -> bool {
+        true
+    }
+
+    fn extracts(&self, vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        let kept_model = vfile.path.file_name().is_some_and(|name| name == BMD_MODEL_FILE);
+        cx.options.extract_models && !kept_model && cx.opens_container(vfile)
+    }
+
+    fn extract(&self, vfile: VirtualFile, cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let bmd = Bmd::read(&vfile.bytes)?;
+        let textures = bmd.textures()?;
+        let paths = bmd_texture_paths(&textures);
+        let mut members = vec![VirtualFile {
+            path: PathBuf::from(BMD_MODEL_FILE),
+            bytes: vfile.bytes.clone(),
+        }];
+        members.extend(textures.into_iter().zip(paths).map(|(texture, path)| VirtualFile {
+            path,
+            bytes: texture.bti,
+        }));
+        let extracted = cx.extract_members(&extracted_folder(&vfile.path, cx.options), members);
+        info!(
+            "Extracted {} into {} files",
+            vfile.path.to_string_lossy(),
+            extracted.len()
+        );
+        Ok(extracted)
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["bmd", "bdl"]
+    }
+
+    // The model is packed along with the rest of its folder, into whichever format it's in
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        let model = path.join(BMD_MODEL_FILE);
+        let mut version = [0u8; 8];
+        File::open(model)
+            .and_then(|mut file| file.read_exact(&mut version))
+            .ok()?;
+        Some(if &version[4..7] == b"bdl" { "bdl" } else { "bmd" })
+    }
+
+    fn pack(&self, path: &Path, format: &str, _options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        if !path.is_dir() {
+            return Ok(None);
+        }
+        let mut bmd = Bmd::read(&read(path.join(BMD_MODEL_FILE))?)?;
+        let textures = bmd.textures()?;
+        // Only textures that changed are replaced, so an untouched model packs back into
+        // exactly what it was. Ones deleted from the folder are left as they are.
+        for (i, (texture, texture_path)) in textures.iter().zip(bmd_texture_paths(&textures)).enumerate() {
+            let bti = match read(path.join(&texture_path)) {
+                Ok(bti) => bti,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if bti != texture.bti {
+                bmd.replace_texture(i, &bti)
+                    .map_err(|e| format!("{texture_path:?}: {e}"))?;
+            }
+        }
+        Ok(Some(VirtualFile {
+            path: path.with_extension(format),
+            bytes: bmd.bytes().to_vec(),
+        }))
+    }
+}
+
+/// Where each of a model's textures is extracted to. Names can repeat, since models can
+/// use the same texture with different settings, so later ones get their index added.
+fn bmd_texture_paths(textures: &[BmdTexture]) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    textures
+        .iter()
+        .enumerate()
+        .map(|(i, texture)| {
+            let name = match seen.insert(texture.name.as_str()) {
+                true => format!("{}.bti", texture.name),
+                false => format!("{}.{i}.bti", texture.name),
+            };
+            Path::new("textures").join(name)
+        })
+        .collect()
+}
+
+struct BaaHandler;
+
+impl FormatHandler<Cli> for BaaHandler {
+    fn name(&self) -> &'static str {
+        "baa"
+    }
+
+    fn description(&self) -> &'static str {
+        "Audio archives, split into their sound tables, sequences, banks, and wave systems"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Baa]
+    }
+
+    fn is_container(&self) -> bool {
+        true
+    }
+
+    fn extracts(&self, vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.opens_container(vfile)
+    }
+
+    fn extract(&self, vfile: VirtualFile, cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let baa = Baa::read(&vfile.bytes)?;
+        let mut members = vec![VirtualFile {
+            path: PathBuf::from(BAA_INDEX_FILE),
+            bytes: serde_json::to_vec_pretty(&baa.index())?,
+        }];
+        members.extend(baa.files.into_iter().map(|file| VirtualFile {
+            path: PathBuf::from(file.name),
+            bytes: file.data,
+        }));
+        let extracted = cx.extract_members(&extracted_folder(&vfile.path, cx.options), members);
+        info!(
+            "Extracted {} into {} files",
+            vfile.path.to_string_lossy(),
+            extracted.len()
+        );
+        Ok(extracted)
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["baa"]
+    }
+
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        // The index is packed along with the rest of its folder
+        (path.join(BAA_INDEX_FILE).is_file() || file_name_ends_with(path, ".baa.json")).then_some("baa")
+    }
+
+    fn pack(&self, path: &Path, _format: &str, _options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        if !path.is_dir() {
+            return Ok(None);
+        }
+        let index: BaaIndex = serde_json::from_slice(&read(path.join(BAA_INDEX_FILE))?)?;
+        let files = index
+            .files
+            .into_iter()
+            .map(|name| {
+                Ok(BaaFile {
+                    data: read(path.join(&name))?,
+                    name,
+                })
+            })
+            .collect::<Result<_, io::Error>>()?;
+        let baa = Baa {
+            alignment: index.alignment,
+            chunks: index.chunks,
+            files,
+        };
+        Ok(Some(VirtualFile {
+            path: path.with_extension("baa"),
+            bytes: baa.write()?,
+        }))
+    }
+}
+
+struct BannerHandler;
+
+impl FormatHandler<Cli> for BannerHandler {
+    fn name(&self) -> &'static str {
+        "banner"
+    }
+
+    fn description(&self) -> &'static str {
+        "Wii banner, or a hashed part of one"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Imet, FileFormat::Imd5]
+    }
+
+    fn is_container(&self) -> bool {
+        true
+    }
+
+    fn extracts(&self, vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.opens_container(vfile)
+    }
+
+    fn extract(&self, vfile: VirtualFile, cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let path_string = vfile.path.to_string_lossy();
+        let (header, contents) = BannerHeader::unwrap(&vfile.bytes).ok_or("Not an IMET or IMD5 header")?;
+        if !header.verify(contents) {
+            warn!("{path_string} doesn't match the hash in its header");
+        }
+        let folder = extracted_folder(&vfile.path, cx.options);
+        let header_file = match header {
+            BannerHeader::Imet(_) => IMET_HEADER_FILE,
+            BannerHeader::Imd5(_) => IMD5_HEADER_FILE,
+        };
+        let mut extracted = vec![Extracted {
+            file: VirtualFile {
+                path: folder.join(header_file),
+                bytes: header.bytes().to_vec(),
+            },
+            format: Some(self.name()),
+            archive: None,
+            load_location: None,
+            member: None,
+        }];
+        // Banner parts are often LZ77 compressed, which can't be opened yet
+        match U8Archive::parse(contents) {
+            Ok(archive) => {
+                let members = archive.files().map(|(path, bytes)| VirtualFile {
+                    path,
+                    bytes: bytes.to_vec(),
+                });
+                extracted.extend(cx.extract_members(&folder, members));
+            }
+            Err(_) => extracted.push(Extracted {
+                file: VirtualFile {
+                    path: folder.join(BANNER_PAYLOAD_FILE),
+                    bytes: contents.to_vec(),
+                },
+                format: Some(self.name()),
+                archive: None,
+                load_location: None,
+                member: None,
+            }),
+        }
+        info!("Extracted {path_string} into {} files", extracted.len());
+        Ok(extracted)
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["bnr", "imd5"]
+    }
+
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        if path.join(IMET_HEADER_FILE).is_file() {
+            Some("bnr")
+        } else if path.join(IMD5_HEADER_FILE).is_file() {
+            Some("imd5")
+        } else {
+            None
+        }
+    }
+
+    fn pack(&self, path: &Path, format: &str, _options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        if !path.is_dir() {
+            return Ok(None);
+        }
+        let header_file = if format == "bnr" {
+            IMET_HEADER_FILE
+        } else {
+            IMD5_HEADER_FILE
+        };
+        let header = BannerHeader::from_bytes(&read(path.join(header_file))?)?;
+        let payload_path = path.join(BANNER_PAYLOAD_FILE);
+        let contents = if payload_path.is_file() {
+            read(payload_path)?
+        } else {
+            let mut files = Vec::new();
+            read_banner_tree(path, PathBuf::new(), &mut files)?;
+            files.retain(|(path, _)| path != Path::new(header_file));
+            U8Archive::build(files)?
+        };
+        let extension = if format == "bnr" { "bnr" } else { "bin" };
+        Ok(Some(VirtualFile {
+            path: path.with_extension(extension),
+            bytes: header.wrap(&contents)?,
+        }))
+    }
+}
+
+struct BtiHandler;
+
+impl FormatHandler<Cli> for BtiHandler {
+    fn name(&self) -> &'static str {
+        "bti"
+    }
+
+    fn description(&self) -> &'static str {
+        "Texture"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Bti]
+    }
+
+    fn extracts(&self, _vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.options.extract_bti
+    }
+
+    fn extract(&self, vfile: VirtualFile, cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let bti = decode_bti(&vfile.bytes, cx.options)?;
+
+        let output_path = vfile.path.with_extension("bti.png");
+        info!("Extracted {} => {output_path:?}", vfile.path.to_string_lossy());
+        let intensity = cx.options.bti_intensity;
+        let mut extracted = vec![VirtualFile {
+            path: output_path,
+            bytes: bti.to_png_with(intensity)?,
+        }];
+        // The PNG can't be read back properly without knowing how it was written
+        if cx.options.bti_header || intensity != IntensityPng::Rgba {
+            let metadata = BtiMetadata {
+                header: bti.header,
+                png: intensity,
+            };
+            extracted.push(VirtualFile {
+                path: vfile.path.with_extension("bti.json"),
+                bytes: serde_json::to_vec_pretty(&metadata)?,
+            });
+        }
+        // Dolphin only loads textures named like this, so it's a copy rather than a rename
+        if cx.options.dolphin_textures {
+            let palette = cx.options.bti_palette.as_ref().map(|palette| palette.0.as_slice());
+            let name = dolphin::texture_name(&vfile.bytes, palette)?;
+            extracted.push(VirtualFile {
+                path: vfile.path.with_file_name(format!("{name}.png")),
+                bytes: extracted[0].bytes.clone(),
+            });
+        }
+        Ok(extracted.into_iter().map(Into::into).collect())
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["bti"]
+    }
+
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        // Header sidecars belong to their PNG and aren't BMGs. Other PNGs, like contact
+        // sheets, are left alone unless packed explicitly.
+        (file_name_ends_with(path, ".bti.json") || file_name_ends_with(path, ".bti.png")).then_some("bti")
+    }
+
+    fn pack(&self, path: &Path, _format: &str, options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        if !is_std_stream(path) && !path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+            return Ok(None);
+        }
+        // Extracted textures have their original header next to them
+        let metadata_path = path.with_extension("json");
+        let extracted = path.to_string_lossy().ends_with(".bti.png");
+        let metadata: Option<BtiMetadata> = match extracted && metadata_path.is_file() {
+            true => Some(serde_json::from_slice(&read(&metadata_path)?)?),
+            false => None,
+        };
+        let bytes = bti_from_png(
+            &read_input(path)?,
+            metadata.clone(),
+            options.auto_format,
+            options.bti_quality,
+        )?;
+        // The format that was picked goes in the header sidecar, so it's on record and
+        // packing again without --auto-format keeps it
+        if options.auto_format && extracted {
+            let header = BtiHeader::read(&bytes)?;
+            let metadata = metadata.unwrap_or_else(|| header.clone().into());
+            let png = metadata.png;
+            match metadata.with_header(header) {
+                Some(metadata) => write(&metadata_path, serde_json::to_vec_pretty(&metadata)?)?,
+                None => warn!("Couldn't record the new format in {metadata_path:?}, since its PNG is in {png} mode"),
+            }
+        }
+        Ok(Some(VirtualFile {
+            path: path.with_extension("").with_extension("bti"),
+            bytes,
+        }))
+    }
+}
+
+struct BcsvHandler;
+
+impl FormatHandler<Cli> for BcsvHandler {
+    fn name(&self) -> &'static str {
+        "bcsv"
+    }
+
+    fn description(&self) -> &'static str {
+        "Data table"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Bcsv]
+    }
+
+    fn extracts(&self, _vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.options.extract_bcsv
+    }
+
+    fn extract(&self, vfile: VirtualFile, cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let path_string = vfile.path.to_string_lossy();
+        let mut bcsv = Bcsv::read(&vfile.bytes)?;
+        bcsv.resolve_names(COMMON_FIELD_NAMES.iter().copied());
+        if let Some(names) = &cx.options.bcsv_hash_names {
+            bcsv.resolve_names(names.0.iter().map(String::as_str));
+        }
+        let (output_path, bytes) = if cx.options.bcsv_csv {
+            (PathBuf::from(format!("{path_string}.csv")), bcsv.to_csv().into_bytes())
+        } else {
+            (
+                PathBuf::from(format!("{path_string}.json")),
+                serde_json::to_vec_pretty(&bcsv)?,
+            )
+        };
+        info!("Extracted {path_string} => {output_path:?}");
+        Ok(vec![VirtualFile {
+            path: output_path,
+            bytes,
+        }
+        .into()])
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["bcsv", "jmp"]
+    }
+
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        ["bcsv", "jmp"].into_iter().find(|format| {
+            file_name_ends_with(path, &format!(".{format}.json"))
+                || file_name_ends_with(path, &format!(".{format}.csv"))
+        })
+    }
+
+    fn pack(&self, path: &Path, format: &str, _options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        let bytes = read_input(path)?;
+        let bcsv = if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv")) {
+            Bcsv::from_csv(&String::from_utf8(bytes)?)?
+        } else {
+            serde_json::from_slice(&bytes)?
+        };
+        Ok(Some(VirtualFile {
+            path: path.with_extension("").with_extension(format),
+            bytes: bcsv.write()?,
+        }))
+    }
+}
+
+struct RelHandler;
+
+impl FormatHandler<Cli> for RelHandler {
+    fn name(&self) -> &'static str {
+        "rel"
+    }
+
+    fn description(&self) -> &'static str {
+        "Relocatable code module"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Rel]
+    }
+
+    fn extracts(&self, _vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.options.extract_rel
+    }
+
+    fn extract(&self, vfile: VirtualFile, _cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let rel = Rel::read(&vfile.bytes)?;
+        let out_dir = vfile.path.with_extension("");
+        let mut extracted = vec![VirtualFile {
+            path: out_dir.join("rel.json"),
+            bytes: serde_json::to_vec_pretty(&rel)?,
+        }];
+        for (idx, section) in rel.sections.iter().enumerate() {
+            if let Some(data) = rel.section_data(idx) {
+                let kind = if section.executable { "text" } else { "data" };
+                extracted.push(VirtualFile {
+                    path: out_dir.join(format!("section_{idx}_{kind}.bin")),
+                    bytes: data.to_vec(),
+                });
+            }
+        }
+        info!(
+            "Extracted {} into {} files",
+            vfile.path.to_string_lossy(),
+            extracted.len()
+        );
+        Ok(extracted.into_iter().map(Into::into).collect())
+    }
+}
+
+struct MsbtHandler;
+
+impl FormatHandler<Cli> for MsbtHandler {
+    fn name(&self) -> &'static str {
+        "msbt"
+    }
+
+    fn description(&self) -> &'static str {
+        "Text dictionary from later consoles"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Msbt]
+    }
+
+    fn extracts(&self, _vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.options.extract_bmg
+    }
+
+    fn extract(&self, vfile: VirtualFile, _cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let msbt = Msbt::read(&vfile.bytes)?;
+        let output_path = vfile.path.with_extension("msbt.json");
+        info!("Extracted {} => {output_path:?}", vfile.path.to_string_lossy());
+        Ok(vec![VirtualFile {
+            path: output_path,
+            bytes: serde_json::to_vec_pretty(&msbt)?,
+        }
+        .into()])
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["msbt"]
+    }
+
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        file_name_ends_with(path, ".msbt.json").then_some("msbt")
+    }
+
+    fn pack(&self, path: &Path, _format: &str, _options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        let msbt: Msbt = serde_json::from_slice(&read_input(path)?)?;
+        Ok(Some(VirtualFile {
+            path: path.with_extension("").with_extension("msbt"),
+            bytes: msbt.write()?,
+        }))
+    }
+}
+
+struct BloHandler;
+
+impl FormatHandler<Cli> for BloHandler {
+    fn name(&self) -> &'static str {
+        "blo"
+    }
+
+    fn description(&self) -> &'static str {
+        "Screen layout"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Blo]
+    }
+
+    fn extracts(&self, _vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.options.extract_blo
+    }
+
+    fn extract(&self, vfile: VirtualFile, _cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let blo = Blo::read(&vfile.bytes)?;
+        let output_path = vfile.path.with_extension("blo.json");
+        info!(
+            "Extracted {} => {output_path:?}, which uses textures {:?}",
+            vfile.path.to_string_lossy(),
+            blo.textures()
+        );
+        Ok(vec![VirtualFile {
+            path: output_path,
+            bytes: serde_json::to_vec_pretty(&blo)?,
+        }
+        .into()])
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["blo"]
+    }
+
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        file_name_ends_with(path, ".blo.json").then_some("blo")
+    }
+
+    fn pack(&self, path: &Path, _format: &str, _options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        let blo: Blo = serde_json::from_slice(&read_input(path)?)?;
+        Ok(Some(VirtualFile {
+            path: path.with_extension("").with_extension("blo"),
+            bytes: blo.write()?,
+        }))
+    }
+}
+
+struct BmgHandler;
+
+impl FormatHandler<Cli> for BmgHandler {
+    fn name(&self) -> &'static str {
+        "bmg"
+    }
+
+    fn description(&self) -> &'static str {
+        "Text dictionary"
+    }
+
+    fn formats(&self) -> &'static [FileFormat] {
+        &[FileFormat::Bmg]
+    }
+
+    fn extracts(&self, _vfile: &VirtualFile, cx: &ExtractContext) -> bool {
+        cx.options.extract_bmg
+    }
+
+    fn extract(&self, vfile: VirtualFile, cx: &ExtractContext) -> Result<Vec<Extracted>, Box<dyn Error>> {
+        let bmg = if cx.options.permissive {
+            let (bmg, warnings) = Bmg::read_permissive(&vfile.bytes)?;
+            log_salvage_warnings(&vfile.path, &warnings);
+            bmg
+        } else {
+            Bmg::read(&vfile.bytes)?
+        };
+        let output_path = vfile.path.with_extension("bmg.json");
+        info!("Extracted {} => {output_path:?}", vfile.path.to_string_lossy());
+        Ok(vec![VirtualFile {
+            path: output_path,
+            bytes: bmg_to_json(&bmg, cx.options.profile.as_ref())?,
+        }
+        .into()])
+    }
+
+    fn pack_formats(&self) -> &'static [&'static str] {
+        &["bmg"]
+    }
+
+    fn guess_pack_format(&self, path: &Path) -> Option<&'static str> {
+        file_name_ends_with(path, "json").then_some("bmg")
+    }
+
+    fn pack(&self, path: &Path, _format: &str, options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
+        let bmg = bmg_from_json(&read_input(path)?, options.lossy_text, options.profile.as_ref())?;
+        Ok(Some(VirtualFile {
+            path: path.with_extension("").with_extension("bmg"),
+            bytes: bmg.write(),
+        }))
+    }
+}
@@ -0,0 +1,86 @@
+use crate::handlers::log_shifted;
+use cube_rs::iso::{patch_iso, Fst, FstEntry, Iso, ShiftedRegion};
+use log::info;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{read, remove_file, rename, File},
+    io::{BufWriter, Write},
+    path::{absolute, Component, Path, PathBuf},
+};
+
+/// Puts a freshly packed file into the disc image its extracted disc came from, in the
+/// same place. The file has to be somewhere in a disc extracted with its system files, and
+/// already be on the image; everything after it moves up if it grew, like packing a disc.
+pub fn try_inject(packed: &Path, iso_path: &Path) -> Result<(), Box<dyn Error>> {
+    let is_raw_image = iso_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("iso") || ext.eq_ignore_ascii_case("gcm"));
+    if !is_raw_image {
+        return Err(format!("Files can only be injected into .iso or .gcm images, not {iso_path:?}").into());
+    }
+    let disc_path = disc_path(packed)?;
+    let on_disc = Fst::open(iso_path)?
+        .entries
+        .iter()
+        .any(|entry| matches!(entry, FstEntry::File { path, .. } if *path == disc_path));
+    if !on_disc {
+        return Err(
+            format!("{disc_path:?} isn't on {iso_path:?}, so there's nothing for {packed:?} to replace").into(),
+        );
+    }
+
+    info!("Injecting {packed:?} into {iso_path:?} at {disc_path:?}");
+    let files = HashMap::from([(disc_path, read(packed)?)]);
+    // The image is read while it's rebuilt, so it's only replaced once that's done
+    let mut temp_path = iso_path.as_os_str().to_owned();
+    temp_path.push(".inject");
+    let temp_path = PathBuf::from(temp_path);
+    let shifted = match write_patched(iso_path, &files, &temp_path) {
+        Ok(shifted) => shifted,
+        Err(e) => {
+            let _ = remove_file(&temp_path);
+            return Err(e);
+        }
+    };
+    log_shifted(&shifted);
+    rename(&temp_path, iso_path)?;
+    Ok(())
+}
+
+fn write_patched(
+    iso_path: &Path,
+    files: &HashMap<String, Vec<u8>>,
+    out_path: &Path,
+) -> Result<Vec<ShiftedRegion>, Box<dyn Error>> {
+    let mut out = BufWriter::new(File::create(out_path)?);
+    let shifted = patch_iso(iso_path, files, &mut out)?;
+    out.flush()?;
+    Ok(shifted)
+}
+
+/// Where a file in an extracted disc is on the disc, as its file system table lists it.
+/// The disc's folder is the closest one above the file with a `sys/boot.bin` in it.
+fn disc_path(file: &Path) -> Result<String, Box<dyn Error>> {
+    let file = absolute(file)?;
+    let root = file
+        .ancestors()
+        .skip(1)
+        .find(|folder| folder.join(Iso::SYSTEM_FOLDER).join("boot.bin").is_file())
+        .ok_or_else(|| format!("{file:?} isn't in a disc extracted with its system files"))?;
+    let components: Vec<String> = file
+        .strip_prefix(root)?
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) => name
+                .to_str()
+                .map(ToOwned::to_owned)
+                .ok_or_else(|| format!("{name:?} isn't valid UTF-8, so it can't be on a disc")),
+            _ => Err(format!("Couldn't work out where {file:?} is on the disc")),
+        })
+        .collect::<Result<_, _>>()?;
+    if components.first().map(String::as_str) == Some(Iso::SYSTEM_FOLDER) {
+        return Err(format!("{file:?} is a system file, which can't be injected").into());
+    }
+    Ok(components.join("/"))
+}
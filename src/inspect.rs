@@ -0,0 +1,281 @@
+use cube_rs::{
+    bmd::Bmd,
+    bmg::Bmg,
+    bti::{BtiHeader, TextureFormat},
+    detect::FileFormat,
+    iso::IsoMetadata,
+    rel::Rel,
+    szs::Yaz0Info,
+    virtual_fs::VirtualFile,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+/// Details about a single file, in both human readable and machine readable form
+struct Report {
+    text: String,
+    json: Value,
+}
+
+pub fn try_inspect(files: &[PathBuf], json: bool) -> Result<(), Box<dyn Error>> {
+    let mut json_reports = Vec::new();
+    for path in files {
+        let Some(report) = inspect(path)? else {
+            return Err(format!("Don't know how to inspect {path:?}").into());
+        };
+
+        if json {
+            json_reports.push(json!({ "path": path, "info": report.json }));
+        } else {
+            println!("{}:", path.to_string_lossy());
+            print!("{}", report.text);
+        }
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&json_reports)?);
+    }
+    Ok(())
+}
+
+fn inspect(path: &Path) -> Result<Option<Report>, Box<dyn Error>> {
+    match FileFormat::from_path(path) {
+        // Only the header is needed, so avoid reading the whole disc
+        Some(FileFormat::Gcm) => Ok(Some(inspect_iso(&IsoMetadata::open(path)?)?)),
+        Some(FileFormat::Rel) => Ok(Some(inspect_rel(&Rel::read(&VirtualFile::read(path)?.bytes)?)?)),
+        Some(FileFormat::Bmg) => Ok(Some(inspect_bmg(&Bmg::read(&VirtualFile::read(path)?.bytes)?)?)),
+        Some(FileFormat::Bmd) => Ok(Some(inspect_bmd(&Bmd::read(&VirtualFile::read(path)?.bytes)?)?)),
+        Some(FileFormat::Yaz0) => {
+            let data = VirtualFile::read(path)?.bytes;
+            // SZS files aren't always compressed
+            Yaz0Info::read(&data).map(|info| inspect_yaz0(&data, info)).transpose()
+        }
+        _ => Ok(None),
+    }
+}
+
+fn inspect_iso(metadata: &IsoMetadata) -> Result<Report, Box<dyn Error>> {
+    let mut text = String::new();
+    writeln!(text, "  Game ID: {}", metadata.game_id)?;
+    writeln!(text, "  Title: {}", metadata.internal_name)?;
+    writeln!(text, "  Region: {}", metadata.region)?;
+    writeln!(text, "  Disc: {}", u16::from(metadata.disc_number) + 1)?;
+    writeln!(text, "  Revision: {}", metadata.revision)?;
+
+    Ok(Report {
+        text,
+        json: json!({
+            "format": "iso",
+            "header": metadata,
+        }),
+    })
+}
+
+fn inspect_rel(rel: &Rel) -> Result<Report, Box<dyn Error>> {
+    let header = &rel.header;
+    let mut text = String::new();
+    writeln!(text, "  Module ID: {}", header.module_id)?;
+    writeln!(text, "  Version: {}", header.version)?;
+    writeln!(text, "  BSS size: {:#X}", header.bss_size)?;
+
+    writeln!(text, "  Sections:")?;
+    for (idx, section) in rel.sections.iter().enumerate() {
+        let kind = match section {
+            s if s.is_empty() => continue,
+            s if s.is_bss() => "bss",
+            s if s.executable => "text",
+            _ => "data",
+        };
+        writeln!(
+            text,
+            "    {idx:>2}: {kind:<4} offset {:#08X} size {:#X}",
+            section.offset, section.size
+        )?;
+    }
+
+    let symbols = rel.symbols();
+    writeln!(text, "  Symbols:")?;
+    for symbol in &symbols {
+        writeln!(
+            text,
+            "    {:<11} section {} + {:#X}",
+            symbol.name, symbol.section, symbol.offset
+        )?;
+    }
+
+    writeln!(text, "  Imports:")?;
+    for import in &rel.imports {
+        let module = if import.module_id == 0 {
+            String::from("main.dol")
+        } else {
+            format!("module {}", import.module_id)
+        };
+        writeln!(text, "    {module}: {} relocations", import.relocations.len())?;
+    }
+
+    Ok(Report {
+        text,
+        json: json!({
+            "format": "rel",
+            "header": header,
+            "sections": rel.sections,
+            "symbols": symbols,
+            "imports": rel.imports,
+        }),
+    })
+}
+
+fn inspect_yaz0(data: &[u8], info: Yaz0Info) -> Result<Report, Box<dyn Error>> {
+    let decompressed_size = u32::from_be_bytes(data[0x4..0x8].try_into()?);
+    let mut text = String::new();
+    writeln!(text, "  Magic: {}", info.magic)?;
+    writeln!(text, "  Compressed size: {:#X}", data.len())?;
+    writeln!(text, "  Decompressed size: {decompressed_size:#X}")?;
+    writeln!(
+        text,
+        "  Reserved: {:#010X} {:#010X}",
+        info.reserved[0], info.reserved[1]
+    )?;
+
+    Ok(Report {
+        text,
+        json: json!({
+            "format": "yaz0",
+            "magic": info.magic,
+            "compressed_size": data.len(),
+            "decompressed_size": decompressed_size,
+            "reserved": info.reserved,
+        }),
+    })
+}
+
+fn inspect_bmg(bmg: &Bmg) -> Result<Report, Box<dyn Error>> {
+    let sections = bmg.sections();
+    let layouts = bmg.message_layouts();
+    let messages: Vec<_> = bmg.messages().collect();
+
+    // Escape sequences are grouped by their first three bytes, which hold the tag's
+    // group and type. The rest are parameters.
+    let mut escape_counts: BTreeMap<String, usize> = BTreeMap::new();
+    for layout in layouts.iter() {
+        for escape in layout.escape_sequences.iter() {
+            *escape_counts.entry(hex(&escape[..escape.len().min(3)])).or_default() += 1;
+        }
+    }
+
+    let mut text = String::new();
+    writeln!(text, "  Encoding: {:?}", bmg.encoding())?;
+    writeln!(text, "  File ID: {}", bmg.file_id())?;
+    writeln!(text, "  Messages: {}", messages.len())?;
+    writeln!(text, "  Block alignment: {}", bmg.block_alignment())?;
+    writeln!(text, "  Sections:")?;
+    for section in sections.iter() {
+        let unknown = if section.known { "" } else { " (unknown)" };
+        writeln!(text, "    {:<4} {:>#8X} bytes{unknown}", section.magic, section.size)?;
+    }
+    writeln!(text, "  Escape sequences:")?;
+    for (tag, count) in escape_counts.iter() {
+        writeln!(text, "    {tag:<6} {count:>6} uses")?;
+    }
+    writeln!(text, "  Messages:")?;
+    writeln!(
+        text,
+        "    {:>5}  {:<10}  {:>8}  {:>6}  {:>7}",
+        "index", "id", "offset", "size", "escapes"
+    )?;
+    for (idx, (message, layout)) in messages.iter().zip(layouts.iter()).enumerate() {
+        let id = message.id.map(|id| id.to_string()).unwrap_or_else(|| String::from("-"));
+        writeln!(
+            text,
+            "    {idx:>5}  {id:<10}  {:>#8X}  {:>6}  {:>7}",
+            layout.text_offset,
+            layout.size,
+            layout.escape_sequences.len()
+        )?;
+    }
+
+    let message_json: Vec<Value> = messages
+        .iter()
+        .zip(layouts.iter())
+        .enumerate()
+        .map(|(index, (message, layout))| {
+            json!({
+                "index": index,
+                "id": message.id,
+                "text_offset": layout.text_offset,
+                "size": layout.size,
+                "escape_sequences": layout.escape_sequences.iter().map(|e| hex(e)).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    Ok(Report {
+        text,
+        json: json!({
+            "format": "bmg",
+            "encoding": bmg.encoding(),
+            "file_id": bmg.file_id(),
+            "default_color": bmg.default_color(),
+            "index_entry_size": bmg.index_entry_size(),
+            "block_alignment": bmg.block_alignment(),
+            "sections": sections,
+            "escape_sequence_counts": escape_counts,
+            "messages": message_json,
+        }),
+    })
+}
+
+fn inspect_bmd(bmd: &Bmd) -> Result<Report, Box<dyn Error>> {
+    let mut text = String::new();
+    writeln!(text, "  Version: {}", bmd.version())?;
+    writeln!(text, "  Sections:")?;
+    for section in bmd.sections() {
+        writeln!(
+            text,
+            "    {:<4} offset {:#08X} size {:#X}",
+            section.magic, section.offset, section.size
+        )?;
+    }
+
+    let mut texture_json = Vec::new();
+    writeln!(text, "  Textures:")?;
+    for (idx, texture) in bmd.textures()?.iter().enumerate() {
+        let header = BtiHeader::read(&texture.bti)?;
+        let format = TextureFormat::from_id(header.format).map(|format| format.to_string());
+        let format = format.unwrap_or_else(|| format!("{:#X}", header.format));
+        writeln!(
+            text,
+            "    {idx:>3}: {:<20} {format:<7} {}x{}, {} mipmaps",
+            texture.name,
+            header.width,
+            header.height,
+            header.mipmap_count.max(1)
+        )?;
+        texture_json.push(json!({
+            "index": idx,
+            "name": texture.name,
+            "format": format,
+            "width": header.width,
+            "height": header.height,
+            "mipmap_count": header.mipmap_count,
+        }));
+    }
+
+    Ok(Report {
+        text,
+        json: json!({
+            "format": "bmd",
+            "version": bmd.version(),
+            "sections": bmd.sections(),
+            "textures": texture_json,
+        }),
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
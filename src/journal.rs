@@ -0,0 +1,280 @@
+use crate::{cache::CACHE_DIR, checksums::sha256_hex, extract::Extracted, handlers::registry};
+use cube_rs::{rarc::LoadLocation, virtual_fs::VirtualFile};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, metadata, read_to_string, rename, File},
+    io::{self, BufWriter, Write},
+    path::{absolute, Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Record of the files an extraction has finished writing and what was in them, so
+/// `--resume` can pick up where an interrupted one left off. Each file is added as soon
+/// as it's written, in the same format `sha256sum` uses. Files on discs are also listed,
+/// in a second file next to it, with everything they were extracted into so they can be
+/// skipped without being read.
+pub struct ExtractJournal {
+    path: PathBuf,
+    done: HashMap<PathBuf, String>,
+    file: File,
+    members: File,
+    /// Everything noted this time, which the journal is rewritten with once the
+    /// extraction finishes so that it doesn't keep growing
+    written: Vec<(PathBuf, String)>,
+    finished: Vec<MemberRecord>,
+    /// The disc file whose outputs are being written, which is finished once the next
+    /// one starts
+    pending: Option<MemberRecord>,
+}
+
+/// Disc files that were extracted before, from [`ExtractJournal::open`]
+#[derive(Default)]
+pub struct FinishedMembers(HashMap<DiscMember, Vec<MemberOutput>>);
+
+/// A file on a disc, told apart by where it is on the disc rather than by what's in it
+/// so that it can be skipped without being read. Discs changed since are extracted again.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DiscMember {
+    pub disc: PathBuf,
+    pub disc_modified: SystemTime,
+    pub path: PathBuf,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// One of the files a disc file was extracted into, as the disc's handler returned it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberOutput {
+    pub path: PathBuf,
+    pub format: Option<String>,
+    pub archive: Option<PathBuf>,
+    pub load_location: Option<LoadLocation>,
+    /// Where it was written to, once it has been
+    pub written: Option<WrittenFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WrittenFile {
+    pub path: PathBuf,
+    pub checksum: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MemberRecord {
+    member: DiscMember,
+    outputs: Vec<MemberOutput>,
+}
+
+impl ExtractJournal {
+    /// Opens the journal for extracting `inputs` to `out` with `settings`, kept in the
+    /// pack cache folder of wherever cube is run from, and starts one if there isn't one
+    /// yet. Also returns the disc files it lists as extracted.
+    pub fn open(inputs: &[PathBuf], out: Option<&Path>, settings: &str) -> io::Result<(Self, FinishedMembers)> {
+        let mut key = format!("{} {settings}", env!("CARGO_PKG_VERSION")).into_bytes();
+        key.push(0);
+        for path in inputs.iter().map(PathBuf::as_path).chain(out) {
+            key.extend(absolute(path)?.as_os_str().as_encoded_bytes());
+            key.push(0);
+        }
+        let path = Path::new(CACHE_DIR).join(format!("extract-{}.journal", sha256_hex(&key)));
+        // A line cut short by an interruption is skipped, so its file is written again
+        let done: HashMap<_, _> = read_journal(&path)?
+            .lines()
+            .filter_map(|line| {
+                let (checksum, path) = line.split_once("  ")?;
+                (checksum.len() == 64).then(|| (PathBuf::from(path), checksum.to_owned()))
+            })
+            .collect();
+        let finished = read_journal(&members_path(&path))?
+            .lines()
+            .filter_map(|line| serde_json::from_str::<MemberRecord>(line).ok())
+            .map(|record| (record.member, record.outputs))
+            .collect();
+        let finished = FinishedMembers(finished);
+        debug!(
+            "Resuming from {path:?}, which lists {} files and {} disc files",
+            done.len(),
+            finished.0.len()
+        );
+        create_dir_all(CACHE_DIR)?;
+        let file = File::options().create(true).append(true).open(&path)?;
+        let members = File::options().create(true).append(true).open(members_path(&path))?;
+        let journal = ExtractJournal {
+            path,
+            done,
+            file,
+            members,
+            written: Vec::new(),
+            finished: Vec::new(),
+            pending: None,
+        };
+        Ok((journal, finished))
+    }
+
+    /// Whether a file was already written with these contents and is still there
+    pub fn is_done(&self, path: &Path, checksum: &str, size: usize) -> bool {
+        self.done.get(path).is_some_and(|done| done == checksum)
+            && metadata(path).is_ok_and(|metadata| metadata.len() == size as u64)
+    }
+
+    /// Notes that a file has been written. Failing to is only worth a warning, since it
+    /// just means the file is written again when resuming.
+    pub fn record(&mut self, path: &Path, checksum: String) {
+        if let Err(e) = writeln!(self.file, "{}", checksum_line(path, &checksum)) {
+            warn!("Couldn't note that {path:?} was extracted: {e}");
+        }
+        self.done.insert(path.to_owned(), checksum.clone());
+        self.written.push((path.to_owned(), checksum));
+    }
+
+    /// Notes that a file the journal listed was still there, so it stays listed once the
+    /// journal's tidied up
+    pub fn keep(&mut self, path: &Path, checksum: String) {
+        self.written.push((path.to_owned(), checksum));
+    }
+
+    /// Notes that one of the files a disc file was extracted into has been written, or
+    /// was already there. The disc file is only noted as extracted once all of them have
+    /// been, which is when the next disc file's start or the extraction finishes.
+    pub fn record_member_output(&mut self, member: &DiscMember, output: MemberOutput) {
+        if self.pending.as_ref().is_some_and(|pending| pending.member != *member) {
+            self.finish_member();
+        }
+        self.pending
+            .get_or_insert_with(|| MemberRecord {
+                member: member.clone(),
+                outputs: Vec::new(),
+            })
+            .outputs
+            .push(output);
+    }
+
+    fn finish_member(&mut self) {
+        let Some(record) = self.pending.take() else {
+            return;
+        };
+        let line = serde_json::to_string(&record).expect("Journal records are always serializable");
+        if let Err(e) = writeln!(self.members, "{line}") {
+            warn!("Couldn't note that {:?} was extracted: {e}", record.member.path);
+        }
+        self.finished.push(record);
+    }
+
+    /// Rewrites the journal with only what this extraction wrote or found already there,
+    /// dropping anything left from earlier ones
+    pub fn finish(mut self) {
+        self.finish_member();
+        let compacted = write_lines(
+            &self.path,
+            self.written
+                .iter()
+                .map(|(path, checksum)| checksum_line(path, checksum)),
+        )
+        .and_then(|_| {
+            write_lines(
+                &members_path(&self.path),
+                self.finished
+                    .iter()
+                    .map(|record| serde_json::to_string(record).expect("Journal records are always serializable")),
+            )
+        });
+        if let Err(e) = compacted {
+            warn!("Couldn't tidy up the extraction journal {:?}: {e}", self.path);
+        }
+    }
+}
+
+impl FinishedMembers {
+    /// What a disc file was extracted into before, as long as every one of those files
+    /// is still there and hasn't been changed since
+    pub fn outputs(&self, member: &DiscMember) -> Option<&[MemberOutput]> {
+        let outputs = self.0.get(member)?;
+        let unchanged = |written: &WrittenFile| {
+            metadata(&written.path).is_ok_and(|metadata| {
+                metadata.len() == written.size && metadata.modified().is_ok_and(|time| time == written.modified)
+            })
+        };
+        outputs
+            .iter()
+            .all(|output| output.written.as_ref().is_some_and(unchanged))
+            .then_some(outputs)
+    }
+}
+
+impl WrittenFile {
+    /// Notes a file that's just been written, or was found already there
+    pub fn new(path: &Path, checksum: String) -> io::Result<Self> {
+        let metadata = metadata(path)?;
+        Ok(WrittenFile {
+            path: path.to_owned(),
+            checksum,
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+}
+
+impl MemberOutput {
+    /// Notes how a file extracted from a disc file came out of the disc's handler, before
+    /// it's moved to where it's written
+    pub fn new(extracted: &Extracted) -> Self {
+        MemberOutput {
+            path: extracted.file.path.clone(),
+            format: extracted.format.map(str::to_owned),
+            archive: extracted.archive.clone(),
+            load_location: extracted.load_location,
+            written: None,
+        }
+    }
+
+    /// Stands in for the file as it came out of the disc's handler, without its contents
+    pub fn placeholder(&self, member: &DiscMember) -> Extracted {
+        Extracted {
+            file: VirtualFile {
+                path: self.path.clone(),
+                bytes: Vec::new(),
+            },
+            format: self.format.as_deref().and_then(|format| {
+                registry()
+                    .handlers()
+                    .map(|handler| handler.name())
+                    .find(|name| *name == format)
+            }),
+            archive: self.archive.clone(),
+            load_location: self.load_location,
+            member: Some((member.clone(), self.clone())),
+        }
+    }
+}
+
+fn checksum_line(path: &Path, checksum: &str) -> String {
+    format!("{checksum}  {}", path.to_string_lossy())
+}
+
+fn members_path(journal: &Path) -> PathBuf {
+    journal.with_extension("members")
+}
+
+/// A journal's contents, or nothing if it hasn't been started
+fn read_journal(path: &Path) -> io::Result<String> {
+    match read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(String::new()),
+        result => result,
+    }
+}
+
+/// Replaces a file with `lines`, writing them somewhere else first so an interruption
+/// can't leave it half written
+fn write_lines(path: &Path, lines: impl Iterator<Item = String>) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+    let mut out = BufWriter::new(File::create(&temp)?);
+    for line in lines {
+        writeln!(out, "{line}")?;
+    }
+    out.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+    rename(temp, path)
+}
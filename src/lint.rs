@@ -0,0 +1,303 @@
+use crate::profile::Profile;
+use cube_rs::{
+    bmd::Bmd,
+    bmg::{text_segments, Bmg, MessageId, TextSegment},
+    bti::BtiHeader,
+    detect::{detect, FileFormat},
+    iso::IsoMetadata,
+    szs::decompress_szs,
+    tree::{extract_disc_tree, extract_tree, ArchiveKind, ArchiveTree, Folder, TreeEntry, TreeError},
+};
+use log::warn;
+use serde::Serialize;
+use std::{
+    error::Error,
+    fs::read,
+    path::{Path, PathBuf},
+};
+
+/// Something in a file that breaks one of a profile's rules
+#[derive(Debug, Serialize)]
+pub struct Violation {
+    /// The file that was checked, as given on the command line
+    pub file: PathBuf,
+    /// Where the problem is inside the file. Empty if it's the file itself.
+    pub path: PathBuf,
+    /// The profile setting that was broken, e.g. `bmg.max_lines`
+    pub rule: &'static str,
+    pub message: String,
+    #[serde(flatten)]
+    pub location: Location,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Location {
+    File,
+    /// A BMG message, with the line the problem is on if it's about one line
+    Message {
+        index: usize,
+        id: Option<MessageId>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        line: Option<usize>,
+    },
+    /// A texture inside a model. Models can have more than one texture with a name.
+    Texture {
+        index: usize,
+        name: String,
+    },
+}
+
+pub fn try_lint(files: &[PathBuf], profile: &Profile, json: bool) -> Result<(), Box<dyn Error>> {
+    let mut violations = Vec::new();
+    for file in files {
+        violations.extend(lint(file, profile).map_err(|e| format!("Couldn't check {file:?}: {e}"))?);
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&violations)?);
+    } else {
+        for violation in violations.iter() {
+            print_violation(violation);
+        }
+    }
+    if !violations.is_empty() {
+        return Err(format!("{} problems found using the {} profile", violations.len(), profile.name).into());
+    }
+    if !json {
+        println!("No problems found using the {} profile", profile.name);
+    }
+    Ok(())
+}
+
+/// Checks a file against a profile, along with everything inside it if it's a disc or
+/// an archive
+pub fn lint(path: &Path, profile: &Profile) -> Result<Vec<Violation>, Box<dyn Error>> {
+    let mut linter = Linter {
+        profile,
+        file: path,
+        violations: Vec::new(),
+    };
+    // Discs are opened from their path, since extract_tree only knows about archives
+    if IsoMetadata::open(path).is_ok() {
+        linter.check_archive(&extract_disc_tree(path)?, Path::new(""));
+        return Ok(linter.violations);
+    }
+    let data = read(path)?;
+    match extract_tree(data.clone()) {
+        Ok(archive) => linter.check_archive(&archive, Path::new("")),
+        Err(TreeError::NotAnArchive) => {
+            let name = path.file_name().unwrap_or_default();
+            linter.check_file(&decompress_szs(data)?, Path::new(""), Path::new(name));
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Ok(linter.violations)
+}
+
+struct Linter<'a> {
+    profile: &'a Profile,
+    file: &'a Path,
+    violations: Vec<Violation>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, path: &Path, rule: &'static str, message: String, location: Location) {
+        self.violations.push(Violation {
+            file: self.file.to_owned(),
+            path: path.to_owned(),
+            rule,
+            message,
+            location,
+        });
+    }
+
+    fn check_archive(&mut self, archive: &ArchiveTree, path: &Path) {
+        // Discs aren't loaded into memory whole
+        let max_size = self
+            .profile
+            .archives
+            .max_size
+            .filter(|_| archive.kind != ArchiveKind::Iso);
+        if let Some(max_size) = max_size.filter(|&max| archive.decompressed_size > max) {
+            let message = format!("{} bytes decompressed, more than {max_size}", archive.decompressed_size);
+            self.report(path, "archives.max_size", message, Location::File);
+        }
+        self.check_folder(&archive.root, path);
+    }
+
+    fn check_folder(&mut self, folder: &Folder, path: &Path) {
+        for (name, entry) in folder.entries.iter() {
+            let entry_path = path.join(name);
+            match entry {
+                TreeEntry::File(bytes) => self.check_file(bytes, &entry_path, &entry_path),
+                TreeEntry::Folder(folder) => self.check_folder(folder, &entry_path),
+                TreeEntry::Archive(archive) => self.check_archive(archive, &entry_path),
+            }
+        }
+    }
+
+    /// Checks a file that isn't an archive. `name` is what its format is guessed from if
+    /// its contents don't say, which for a file given on the command line isn't `path`.
+    fn check_file(&mut self, bytes: &[u8], path: &Path, name: &Path) {
+        // Textures too big for the game don't look like BTIs, so they're found by name
+        let format = detect(bytes)
+            .map(|detection| detection.format)
+            .or_else(|| FileFormat::from_path(name));
+        let result: Result<(), Box<dyn Error>> = match format {
+            Some(FileFormat::Bmg) => Bmg::read(bytes)
+                .map(|bmg| self.check_bmg(&bmg, path))
+                .map_err(Into::into),
+            Some(FileFormat::Bti) => BtiHeader::read(bytes)
+                .map(|header| self.check_texture(&header, path, Location::File))
+                .map_err(Into::into),
+            Some(FileFormat::Bmd) => self.check_model(bytes, path),
+            _ => Ok(()),
+        };
+        // Files the game couldn't read either are beyond what linting can help with
+        if let Err(e) = result {
+            warn!("Couldn't check {:?}: {e}", self.file.join(path));
+        }
+    }
+
+    fn check_model(&mut self, bytes: &[u8], path: &Path) -> Result<(), Box<dyn Error>> {
+        for (index, texture) in Bmd::read(bytes)?.textures()?.into_iter().enumerate() {
+            let header = BtiHeader::read(&texture.bti)?;
+            let location = Location::Texture {
+                index,
+                name: texture.name,
+            };
+            self.check_texture(&header, path, location);
+        }
+        Ok(())
+    }
+
+    fn check_texture(&mut self, header: &BtiHeader, path: &Path, location: Location) {
+        let rules = &self.profile.textures;
+        let (width, height) = (header.width, header.height);
+        let mut problems = Vec::new();
+        if rules.power_of_two && !(width.is_power_of_two() && height.is_power_of_two()) {
+            problems.push((
+                "textures.power_of_two",
+                format!("{width}x{height} isn't a power of two size"),
+            ));
+        }
+        if let Some(max_width) = rules.max_width.filter(|&max| width > max) {
+            problems.push((
+                "textures.max_width",
+                format!("{width} pixels wide, more than {max_width}"),
+            ));
+        }
+        if let Some(max_height) = rules.max_height.filter(|&max| height > max) {
+            problems.push((
+                "textures.max_height",
+                format!("{height} pixels tall, more than {max_height}"),
+            ));
+        }
+        for (rule, message) in problems {
+            self.report(path, rule, message, location.clone());
+        }
+    }
+
+    fn check_bmg(&mut self, bmg: &Bmg, path: &Path) {
+        let rules = &self.profile.bmg;
+        let (max_line_length, max_lines) = (rules.max_line_length, rules.max_lines);
+        let (font, max_line_width) = (rules.font.as_ref(), rules.max_line_width);
+        let box_break = rules.box_break.as_ref().map(|box_break| box_break.0.as_slice());
+        for (index, message) in bmg.messages().enumerate() {
+            let id = message.id;
+            for message_box in message_boxes(&message.message, box_break) {
+                if let Some(max_lines) = max_lines.filter(|&max| message_box.lines.len() > max) {
+                    let text = format!("{} lines in one box, more than {max_lines}", message_box.lines.len());
+                    let location = Location::Message {
+                        index,
+                        id,
+                        line: Some(message_box.first_line),
+                    };
+                    self.report(path, "bmg.max_lines", text, location);
+                }
+                for (i, line) in message_box.lines.iter().enumerate() {
+                    let location = Location::Message {
+                        index,
+                        id,
+                        line: Some(message_box.first_line + i),
+                    };
+                    let length = line.chars().count();
+                    if let Some(max_line_length) = max_line_length.filter(|&max| length > max) {
+                        let text = format!("{length} characters, more than {max_line_length}: {line:?}");
+                        self.report(path, "bmg.max_line_length", text, location.clone());
+                    }
+                    if let Some((font, max_line_width)) = font.zip(max_line_width) {
+                        let width = font.line_width(line);
+                        if width > max_line_width {
+                            let text = format!("{width} wide, more than {max_line_width}: {line:?}");
+                            self.report(path, "bmg.max_line_width", text, location);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The text of one message box, without escape sequences
+struct MessageBox {
+    lines: Vec<String>,
+    /// Line number in the whole message that the box starts on, counting from 1
+    first_line: usize,
+}
+
+/// Splits a message into boxes at every escape sequence starting with `box_break`, or
+/// treats it as one box without it
+fn message_boxes(text: &str, box_break: Option<&[u8]>) -> Vec<MessageBox> {
+    let mut boxes = Vec::new();
+    let mut current = String::new();
+    let mut first_line = 1;
+    let mut finish_box = |current: &mut String, first_line: &mut usize| {
+        let mut lines: Vec<String> = current.split('\n').map(String::from).collect();
+        let next_line = *first_line + lines.len() - 1;
+        // A line break just before a box break or the end of the message doesn't start
+        // another line of its own
+        if lines.len() > 1 && lines.last().is_some_and(String::is_empty) {
+            lines.pop();
+        }
+        boxes.push(MessageBox {
+            lines,
+            first_line: *first_line,
+        });
+        *first_line = next_line;
+        current.clear();
+    };
+    for segment in text_segments(text) {
+        match segment {
+            TextSegment::Text(text) => current.push_str(text),
+            TextSegment::Escape(tag) if box_break.is_some_and(|box_break| tag.starts_with(box_break)) => {
+                finish_box(&mut current, &mut first_line);
+            }
+            TextSegment::Escape(_) => {}
+        }
+    }
+    finish_box(&mut current, &mut first_line);
+    boxes
+}
+
+fn print_violation(violation: &Violation) {
+    let mut location = violation.file.to_string_lossy().into_owned();
+    if !violation.path.as_os_str().is_empty() {
+        location.push(':');
+        location.push_str(&violation.path.to_string_lossy());
+    }
+    match &violation.location {
+        Location::File => {}
+        Location::Message { index, id, line } => {
+            location.push_str(&format!(": message {index}"));
+            if let Some(id) = id {
+                location.push_str(&format!(" ({id})"));
+            }
+            if let Some(line) = line {
+                location.push_str(&format!(", line {line}"));
+            }
+        }
+        Location::Texture { index, name } => location.push_str(&format!(": texture {index} ({name})")),
+    }
+    println!("{location}: {} ({})", violation.message, violation.rule);
+}
@@ -0,0 +1,74 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, LineWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Passes records on to the usual logger and also appends them to a file, for `--log-file`
+pub struct TeeLogger {
+    console: Box<dyn Log>,
+    file: Mutex<LineWriter<File>>,
+    file_level: LevelFilter,
+}
+
+/// A line in the log file
+#[derive(Serialize)]
+struct LogLine<'a> {
+    /// Seconds since the Unix epoch
+    time: f64,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+}
+
+impl TeeLogger {
+    /// The file always gets at least progress messages, so a quiet run still leaves a full
+    /// record of what it did
+    pub fn new(console: Box<dyn Log>, console_level: LevelFilter, path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(TeeLogger {
+            console,
+            file: Mutex::new(LineWriter::new(file)),
+            file_level: console_level.max(LevelFilter::Info),
+        })
+    }
+
+    /// Never less than the console's level, since the file's is at least that
+    pub fn max_level(&self) -> LevelFilter {
+        self.file_level
+    }
+}
+
+impl Log for TeeLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.file_level || self.console.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.console.log(record);
+        if record.level() > self.file_level {
+            return;
+        }
+        let line = LogLine {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |time| time.as_secs_f64()),
+            level: record.level().as_str(),
+            target: record.target(),
+            message: record.args().to_string(),
+        };
+        let mut file = self.file.lock().unwrap();
+        // There's nowhere left to report a failed write to
+        let _ = serde_json::to_writer(&mut *file, &line);
+        let _ = file.write_all(b"\n");
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+        let _ = self.file.lock().unwrap().flush();
+    }
+}
@@ -1,40 +1,244 @@
+mod batch;
+mod cache;
+mod checksums;
 mod commands;
+mod completions;
+mod config;
+mod convert;
+mod detect;
+mod diff;
+mod discs;
 mod extract;
+mod formats;
+mod grep;
+mod handlers;
+mod inject;
+mod inspect;
+mod journal;
+mod lint;
+mod logging;
+mod merge;
+#[cfg(all(unix, feature = "mount"))]
+mod mount;
 mod pack;
+mod profile;
+mod recompress;
+mod report;
+mod stats;
+mod stream;
+mod template;
+mod textures;
+mod tree;
+mod watch;
 
-use clap::Parser;
-use commands::{Cli, Commands};
+use batch::try_batch;
+use checksums::try_verify_tree;
+use clap::{CommandFactory, FromArgMatches};
+use commands::{BmgCommands, Cli, Commands, TexturesCommands};
+use completions::{try_completions, try_manpage};
+use config::Config;
+use convert::try_convert;
+use detect::try_detect;
+use diff::try_diff;
 use extract::try_extract;
-use log::LevelFilter;
-use pack::try_pack;
+use formats::try_formats;
+use grep::{try_grep, GrepOptions};
+use inject::try_inject;
+use inspect::try_inspect;
+use lint::try_lint;
+use log::{info, LevelFilter, Log};
+use logging::TeeLogger;
+use merge::try_merge;
+#[cfg(all(unix, feature = "mount"))]
+use mount::try_mount;
+use pack::{default_pack_output, try_pack};
+use recompress::try_recompress;
+use report::{OutputFile, Report, ReportLogger};
 use simple_logger::SimpleLogger;
-use std::error::Error;
+use stats::ExtractStats;
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+    process::exit,
+    time::Instant,
+};
+use textures::try_replace_textures;
+use tree::try_tree;
+use watch::try_watch;
 
 pub fn main() -> Result<(), Box<dyn Error>> {
-    let args = Cli::parse();
-    init_logger(args.verbosity);
+    // Options from the config file become defaults, so anything on the command line wins
+    let config = Config::load()?;
+    let mut command = Cli::command();
+    if let Some(config) = &config {
+        command = config.apply(command)?;
+    }
+    let args = Cli::from_arg_matches(&command.get_matches_mut()).unwrap_or_else(|e| e.exit());
+    // JSON reports replace log output, so hold on to warnings and errors for them instead
+    let report = args.json
+        && matches!(
+            args.subcommand,
+            Commands::Extract { .. } | Commands::Pack { .. } | Commands::Batch { .. }
+        );
+    init_logger(report, args.verbosity, args.quiet, args.log_file.as_deref())?;
+    if let Some(config) = &config {
+        info!("Using defaults from {:?}", config.path);
+    }
 
+    let start = Instant::now();
     match args.subcommand {
-        Commands::Extract { files, out, options } => try_extract(files, out.as_deref(), options)?,
-        Commands::Pack { file, mut out, options } => {
-            if out.is_none() && file.is_dir() {
-                out = Some(file.with_extension(options.arc_extension()));
+        Commands::Extract { files, out, options } => {
+            let result = try_extract(files.clone(), out.as_deref(), &options);
+            if report {
+                print_report("extract", files, result, start, options.stats)?;
+            } else {
+                let outputs = result?;
+                if options.dry_run {
+                    for file in outputs.iter() {
+                        println!("{} ({} bytes)", file.path.to_string_lossy(), file.size);
+                    }
+                }
+                if options.stats {
+                    ExtractStats::new(&outputs).print();
+                }
+            }
+        }
+        Commands::Pack {
+            file,
+            out,
+            watch: true,
+            options,
+            ..
+        } => {
+            let out = out.or_else(|| default_pack_output(&file, &options));
+            try_watch(&file, out.as_deref(), options)?
+        }
+        Commands::Pack {
+            file,
+            out,
+            inject_into,
+            options,
+            ..
+        } => {
+            let out = out.or_else(|| default_pack_output(&file, &options));
+            let result = try_pack(file.clone(), out.as_deref(), &options)
+                .map_err(Into::into)
+                .and_then(|outputs| {
+                    if let Some(iso_path) = inject_into.as_deref() {
+                        let packed = out.as_deref().ok_or_else(|| {
+                            format!("{file:?} doesn't pack into anything, so there's nothing to inject")
+                        })?;
+                        try_inject(packed, iso_path)?;
+                    }
+                    Ok(outputs)
+                });
+            if report {
+                print_report("pack", vec![file], result, start, false)?;
+            } else {
+                result?;
             }
-            try_pack(file, out.as_deref(), &options)?
         }
+        Commands::Inspect { files } => try_inspect(&files, args.json)?,
+        Commands::Detect { files } => try_detect(&files, args.json)?,
+        Commands::Tree { files, dot } => try_tree(&files, dot, args.json)?,
+        Commands::Convert { input, output, options } => try_convert(&input, &output, &options)?,
+        Commands::Batch { worklist, jobs } => try_batch(&worklist, jobs, &command, args.json)?,
+        Commands::Diff { a, b } => try_diff(&a, &b, args.json)?,
+        Commands::Grep {
+            pattern,
+            files,
+            ignore_case,
+            file_names,
+            bytes,
+        } => {
+            let options = GrepOptions {
+                ignore_case,
+                file_names,
+                bytes,
+            };
+            try_grep(&pattern, &files, options, args.json)?
+        }
+        Commands::VerifyTree { manifest } => try_verify_tree(&manifest, args.json)?,
+        Commands::Lint { files, profile } => try_lint(&files, &profile, args.json)?,
+        #[cfg(all(unix, feature = "mount"))]
+        Commands::Mount { image, mountpoint } => try_mount(&image, &mountpoint)?,
+        Commands::Formats => try_formats(args.json)?,
+        Commands::Recompress {
+            files,
+            compression_level,
+        } => try_recompress(&files, compression_level, args.json)?,
+        Commands::Completions { shell } => try_completions(shell)?,
+        Commands::Manpage { out } => try_manpage(out.as_deref())?,
+        Commands::Bmg {
+            command:
+                BmgCommands::Merge {
+                    base,
+                    translated,
+                    out,
+                    lossy_text,
+                },
+        } => try_merge(&base, &translated, &out, lossy_text, args.json)?,
+        Commands::Textures {
+            command:
+                TexturesCommands::Replace {
+                    archive,
+                    from,
+                    out,
+                    compression_level,
+                    bti_quality,
+                },
+        } => try_replace_textures(&archive, &from, &out, compression_level, bti_quality, args.json)?,
     }
 
     Ok(())
 }
 
-fn init_logger(level: u8) {
-    let log_level = match level {
-        0 => LevelFilter::Warn,
-        1 => LevelFilter::Info,
-        _ => LevelFilter::Debug,
+/// Prints the JSON report for a command, exiting with an error code if anything failed
+fn print_report(
+    command: &'static str,
+    inputs: Vec<PathBuf>,
+    result: Result<Vec<OutputFile>, Box<dyn Error>>,
+    start: Instant,
+    stats: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut report = Report::new(command, inputs, result, start.elapsed());
+    if stats {
+        report.stats = Some(ExtractStats::new(&report.outputs));
+    }
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    if !report.errors.is_empty() {
+        exit(1);
+    }
+    Ok(())
+}
+
+fn init_logger(report: bool, verbosity: u8, quiet: bool, log_file: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let level = match (quiet, verbosity) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, 2) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    let logger = SimpleLogger::new().with_level(level);
+    // SimpleLogger only sets up colored output when it's installed by itself, so it's
+    // only wrapped when there's a log file too
+    let Some(path) = log_file else {
+        match report {
+            true => {
+                log::set_logger(&ReportLogger).expect("Failed to initialize logger");
+                log::set_max_level(LevelFilter::Warn);
+            }
+            false => logger.init().expect("Failed to initialize logger"),
+        }
+        return Ok(());
     };
-    SimpleLogger::new()
-        .with_level(log_level)
-        .init()
-        .expect("Failed to initialize logger");
+    let (console, level): (Box<dyn Log>, _) = match report {
+        true => (Box::new(ReportLogger), LevelFilter::Warn),
+        false => (Box::new(logger), level),
+    };
+    let logger = TeeLogger::new(console, level, path).map_err(|e| format!("Couldn't open log file {path:?}: {e}"))?;
+    log::set_max_level(logger.max_level());
+    log::set_boxed_logger(Box::new(logger)).expect("Failed to initialize logger");
+    Ok(())
 }
@@ -0,0 +1,132 @@
+use crate::pack::check_unencodable;
+use cube_rs::bmg::{is_default_attributes, Bmg, BmgMessage, MessageId};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{read, write},
+    path::Path,
+};
+
+/// The messages from a BMG's JSON. Its metadata is ignored since the base BMG's is kept.
+#[derive(Debug, Deserialize)]
+struct Translation {
+    messages: Vec<BmgMessage>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct MergeReport {
+    /// Messages whose text was replaced
+    pub replaced: usize,
+    /// Translated messages that were the same as the base BMG's
+    pub unchanged: usize,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// A translated message that couldn't be merged cleanly
+#[derive(Debug, Serialize)]
+pub struct Conflict {
+    /// Position of the message in the translated JSON
+    pub index: usize,
+    pub id: Option<MessageId>,
+    pub reason: String,
+}
+
+pub fn try_merge(
+    base: &Path,
+    translated: &Path,
+    out: &Path,
+    lossy_text: bool,
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut bmg = Bmg::read(&read(base)?)?;
+    let translation: Translation = serde_json::from_slice(&read(translated)?)?;
+    let report = merge(&mut bmg, translation.messages)?;
+    check_unencodable(&bmg, lossy_text)?;
+    write(out, bmg.write())?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for conflict in report.conflicts.iter() {
+            match conflict.id {
+                Some(id) => warn!("Translated message {} ({id}): {}", conflict.index, conflict.reason),
+                None => warn!("Translated message {}: {}", conflict.index, conflict.reason),
+            }
+        }
+        info!(
+            "Replaced {} messages in {out:?}, {} were already the same, {} conflicts",
+            report.replaced,
+            report.unchanged,
+            report.conflicts.len()
+        );
+    }
+    Ok(())
+}
+
+/// Replaces the text of each message in `bmg` that has a translation. Translations are
+/// matched to messages by ID when both have one, or else by position. Conflicting
+/// translations are skipped, except for ones whose attributes differ, which are merged
+/// keeping the base BMG's attributes.
+pub fn merge(bmg: &mut Bmg, translations: Vec<BmgMessage>) -> Result<MergeReport, Box<dyn Error>> {
+    let base_messages: Vec<BmgMessage> = bmg.messages().collect();
+    let mut id_counts: HashMap<MessageId, usize> = HashMap::new();
+    for id in base_messages.iter().filter_map(|message| message.id) {
+        *id_counts.entry(id).or_default() += 1;
+    }
+
+    let mut report = MergeReport::default();
+    // Which translation each base message has been given, by position
+    let mut merged_from: HashMap<usize, usize> = HashMap::new();
+    for (index, translation) in translations.into_iter().enumerate() {
+        let mut conflict = |reason: String| {
+            report.conflicts.push(Conflict {
+                index,
+                id: translation.id,
+                reason,
+            })
+        };
+        let target = match translation.id {
+            Some(id) if !id_counts.is_empty() => match id_counts.get(&id) {
+                None => Err(format!("There's no message {id} in the base BMG")),
+                Some(&count @ 2..) => Err(format!("{count} messages in the base BMG have this ID")),
+                Some(_) => Ok(bmg.find_message(id).unwrap()),
+            },
+            _ if index >= base_messages.len() => Err(format!(
+                "The base BMG only has {} messages to match this one with by position",
+                base_messages.len()
+            )),
+            _ => Ok(index),
+        };
+        let target = match target {
+            Ok(target) => target,
+            Err(reason) => {
+                conflict(reason);
+                continue;
+            }
+        };
+        if let Some(previous) = merged_from.get(&target) {
+            conflict(format!(
+                "Translated message {previous} is already being merged into message {target}"
+            ));
+            continue;
+        }
+        merged_from.insert(target, index);
+        // Translations that leave attributes as "default" keep the base BMG's on purpose
+        let attributes = &translation.attributes;
+        if !is_default_attributes(attributes) && !attributes.eq_ignore_ascii_case(&base_messages[target].attributes) {
+            conflict(format!(
+                "Attributes {} differ from the base BMG's {}, which are kept",
+                translation.attributes, base_messages[target].attributes
+            ));
+        }
+
+        if bmg.replace_message(target, &translation.message)? {
+            report.replaced += 1;
+        } else {
+            report.unchanged += 1;
+        }
+    }
+    Ok(report)
+}
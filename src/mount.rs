@@ -0,0 +1,336 @@
+//! `cube mount`, which shows a disc or archive as a read-only folder through FUSE. Files on
+//! a disc are only read from the image when something reads them, and archives on it are
+//! only opened once something looks inside, so mounting even a whole disc is quick.
+
+use cube_rs::{
+    iso::{Fst, FstEntry, Iso, IsoMetadata, IsoReader},
+    szs::decompress_szs,
+    tree::{extract_tree, is_archive, Folder, TreeEntry},
+};
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, Request,
+    FUSE_ROOT_ID,
+};
+use libc::{EIO, ENOENT, ENOTDIR};
+use log::{info, warn};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    ffi::OsStr,
+    fs::{metadata, read},
+    os::unix::fs::MetadataExt,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+/// How long the kernel can cache what it's told. Nothing in the mount ever changes.
+const TTL: Duration = Duration::from_secs(60 * 60);
+
+pub fn try_mount(image: &Path, mountpoint: &Path) -> Result<(), Box<dyn Error>> {
+    let fs = ImageFs::open(image).map_err(|e| format!("Couldn't open {image:?}: {e}"))?;
+    let name = image.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let options = [
+        MountOption::RO,
+        MountOption::FSName(name),
+        MountOption::Subtype("cube".to_owned()),
+    ];
+    info!("Mounting {image:?} at {mountpoint:?} until it's unmounted, e.g. with `umount {mountpoint:?}`");
+    fuser::mount2(fs, mountpoint, &options).map_err(|e| format!("Couldn't mount at {mountpoint:?}: {e}"))?;
+    Ok(())
+}
+
+/// Where a file's contents come from
+enum Data {
+    /// Part of the disc image, read when it's needed
+    Disc {
+        offset: u64,
+        size: u64,
+    },
+    Memory(Vec<u8>),
+}
+
+impl Data {
+    fn size(&self) -> u64 {
+        match self {
+            Data::Disc { size, .. } => *size,
+            Data::Memory(bytes) => bytes.len() as u64,
+        }
+    }
+}
+
+enum Node {
+    Dir {
+        parent: u64,
+        entries: BTreeMap<String, u64>,
+    },
+    File(Data),
+    /// A file on the disc that looks like an archive. It's shown as a folder, and only
+    /// read and turned into one once something asks what's in it.
+    Archive {
+        parent: u64,
+        offset: u64,
+        size: u64,
+    },
+}
+
+struct ImageFs {
+    /// Indexed by inode number minus one, so the root is first
+    nodes: Vec<Node>,
+    disc: Option<IsoReader>,
+    /// Everything belongs to whoever owns the image and was last changed when it was
+    uid: u32,
+    gid: u32,
+    mtime: SystemTime,
+}
+
+impl ImageFs {
+    fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let file_metadata = metadata(path)?;
+        let mut fs = ImageFs {
+            nodes: vec![Node::Dir {
+                parent: FUSE_ROOT_ID,
+                entries: BTreeMap::new(),
+            }],
+            disc: None,
+            uid: file_metadata.uid(),
+            gid: file_metadata.gid(),
+            mtime: file_metadata.modified()?,
+        };
+        if IsoMetadata::open(path).is_ok() {
+            fs.add_disc(path)?;
+        } else {
+            let archive = extract_tree(read(path)?)?;
+            fs.add_folder(FUSE_ROOT_ID, archive.root);
+        }
+        Ok(fs)
+    }
+
+    /// Adds the system files and the file system table, peeking at the start of each file
+    /// to find the archives
+    fn add_disc(&mut self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let sys = self.add(FUSE_ROOT_ID, Iso::SYSTEM_FOLDER, new_dir(FUSE_ROOT_ID));
+        for file in Iso::open(path)?.system_files() {
+            let name = file.path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            self.add(sys, &name, Node::File(Data::Memory(file.bytes)));
+        }
+
+        let mut disc = IsoReader::open(path)?;
+        let mut dirs: HashMap<String, u64> = HashMap::new();
+        for entry in Fst::open(path)?.entries {
+            let (FstEntry::File { path, .. } | FstEntry::Dir { path }) = &entry;
+            let (parent, name) = match path.rsplit_once('/') {
+                Some((parent, name)) => (dirs.get(parent).copied().unwrap_or(FUSE_ROOT_ID), name),
+                None => (FUSE_ROOT_ID, path.as_str()),
+            };
+            match entry {
+                FstEntry::Dir { ref path } => {
+                    let ino = self.add(parent, name, new_dir(parent));
+                    dirs.insert(path.clone(), ino);
+                }
+                FstEntry::File { offset, size, .. } => {
+                    let (offset, size) = (offset as u64, size as u64);
+                    let mut magic = [0u8; 4];
+                    let node = match size >= 4 && disc.read_at(offset, &mut magic).is_ok() && is_archive(&magic) {
+                        true => Node::Archive { parent, offset, size },
+                        false => Node::File(Data::Disc { offset, size }),
+                    };
+                    self.add(parent, name, node);
+                }
+            }
+        }
+        self.disc = Some(disc);
+        Ok(())
+    }
+
+    /// Adds a node to a folder, returning its inode number
+    fn add(&mut self, parent: u64, name: &str, node: Node) -> u64 {
+        self.nodes.push(node);
+        let ino = self.nodes.len() as u64;
+        if let Some(Node::Dir { entries, .. }) = self.nodes.get_mut(parent as usize - 1) {
+            entries.insert(name.to_owned(), ino);
+        }
+        ino
+    }
+
+    /// Adds everything in an extracted folder, with nested archives as folders
+    fn add_folder(&mut self, parent: u64, folder: Folder) {
+        for (name, entry) in folder.entries {
+            match entry {
+                TreeEntry::File(bytes) => {
+                    self.add(parent, &name, Node::File(Data::Memory(bytes)));
+                }
+                TreeEntry::Folder(folder) => {
+                    let ino = self.add(parent, &name, new_dir(parent));
+                    self.add_folder(ino, folder);
+                }
+                TreeEntry::Archive(archive) => {
+                    let ino = self.add(parent, &name, new_dir(parent));
+                    self.add_folder(ino, archive.root);
+                }
+            }
+        }
+    }
+
+    fn node(&self, ino: u64) -> Option<&Node> {
+        self.nodes.get((ino as usize).checked_sub(1)?)
+    }
+
+    /// Opens an archive on the disc the first time something looks inside it, returning
+    /// its entries, or an error number if it's not a folder
+    fn entries(&mut self, ino: u64) -> Result<&BTreeMap<String, u64>, i32> {
+        if let Some(&Node::Archive { parent, offset, size }) = self.node(ino) {
+            self.open_archive(ino, parent, offset, size)?;
+        }
+        match self.node(ino) {
+            Some(Node::Dir { entries, .. }) => Ok(entries),
+            Some(_) => Err(ENOTDIR),
+            None => Err(ENOENT),
+        }
+    }
+
+    fn open_archive(&mut self, ino: u64, parent: u64, offset: u64, size: u64) -> Result<(), i32> {
+        let disc = self.disc.as_mut().ok_or(EIO)?;
+        let mut bytes = vec![0u8; size as usize];
+        disc.read_at(offset, &mut bytes).map_err(|e| {
+            warn!("Couldn't read an archive from the disc: {e}");
+            EIO
+        })?;
+        self.nodes[ino as usize - 1] = new_dir(parent);
+        let name = self.name(parent, ino).unwrap_or_default();
+        match extract_tree(bytes.clone()) {
+            Ok(archive) => self.add_folder(ino, archive.root),
+            // A compressed file that isn't an archive, e.g. a model, is shown decompressed
+            // inside a folder with its name, and one that can't be read as it's stored
+            Err(e) => {
+                let bytes = match decompress_szs(bytes.clone()) {
+                    Ok(decompressed) if !is_archive(&decompressed) => decompressed,
+                    _ => {
+                        warn!("Showing {name} as it's stored since it can't be opened: {e}");
+                        bytes
+                    }
+                };
+                self.add(ino, &name, Node::File(Data::Memory(bytes)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds the name a node has in its parent folder
+    fn name(&self, parent: u64, ino: u64) -> Option<String> {
+        let Some(Node::Dir { entries, .. }) = self.node(parent) else {
+            return None;
+        };
+        entries
+            .iter()
+            .find(|(_, &child)| child == ino)
+            .map(|(name, _)| name.clone())
+    }
+
+    fn attr(&self, ino: u64) -> Option<FileAttr> {
+        let (kind, size, perm) = match self.node(ino)? {
+            Node::Dir { .. } | Node::Archive { .. } => (FileType::Directory, 0, 0o555),
+            Node::File(data) => (FileType::RegularFile, data.size(), 0o444),
+        };
+        Some(FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: self.mtime,
+            mtime: self.mtime,
+            ctime: self.mtime,
+            crtime: self.mtime,
+            kind,
+            perm,
+            nlink: 1,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        })
+    }
+}
+
+fn new_dir(parent: u64) -> Node {
+    Node::Dir {
+        parent,
+        entries: BTreeMap::new(),
+    }
+}
+
+impl Filesystem for ImageFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let ino = match self.entries(parent) {
+            Ok(entries) => name.to_str().and_then(|name| entries.get(name)).copied(),
+            Err(errno) => return reply.error(errno),
+        };
+        match ino.and_then(|ino| self.attr(ino)) {
+            Some(attr) => reply.entry(&TTL, &attr, 0),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(Node::File(data)) = self.nodes.get((ino as usize).wrapping_sub(1)) else {
+            return reply.error(ENOENT);
+        };
+        let start = (offset.max(0) as u64).min(data.size());
+        let end = (start + size as u64).min(data.size());
+        match data {
+            Data::Memory(bytes) => reply.data(&bytes[start as usize..end as usize]),
+            Data::Disc { offset, .. } => {
+                let mut buf = vec![0u8; (end - start) as usize];
+                match self.disc.as_mut().map(|disc| disc.read_at(offset + start, &mut buf)) {
+                    Some(Ok(())) => reply.data(&buf),
+                    Some(Err(e)) => {
+                        warn!("Couldn't read from the disc: {e}");
+                        reply.error(EIO)
+                    }
+                    None => reply.error(EIO),
+                }
+            }
+        }
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let entries = match self.entries(ino) {
+            Ok(entries) => entries.clone(),
+            Err(errno) => return reply.error(errno),
+        };
+        let parent = match self.node(ino) {
+            Some(Node::Dir { parent, .. }) => *parent,
+            _ => FUSE_ROOT_ID,
+        };
+        let listing = [(ino, ".".to_owned()), (parent, "..".to_owned())]
+            .into_iter()
+            .chain(entries.into_iter().map(|(name, child)| (child, name)));
+        for (i, (child, name)) in listing.enumerate().skip(offset.max(0) as usize) {
+            let kind = match self.node(child) {
+                Some(Node::File(_)) => FileType::RegularFile,
+                _ => FileType::Directory,
+            };
+            // The offset given with each entry is where to carry on from after it
+            if reply.add(child, i as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
@@ -1,86 +1,806 @@
-use cube_rs::{bmg::Bmg, rarc::Rarc, szs::yaz0_compress, virtual_fs::VirtualFile, Encode};
-use log::info;
+use cube_rs::{
+    bmg::{is_default_attributes, Bmg},
+    bti::{BtiHeader, BtiImage, BtiQuality, TextureFormat},
+    error::CubeError,
+    png::{decode_png, IntensityPng},
+    rarc::RarcError,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::{
+    env,
     error::Error,
-    fs::{remove_dir_all, remove_file, write},
+    fmt,
+    fs::{metadata, read, read_dir, remove_dir_all, remove_file, rename, File},
+    io::{self, BufRead, BufReader, Read},
     path::{Path, PathBuf},
+    sync::{Condvar, Mutex},
+    thread::{self, ThreadId},
+    time::{Duration, SystemTime},
+};
+use thiserror::Error;
+
+use crate::{
+    commands::PackOptions,
+    discs::DISC_SET_FILE,
+    handlers::{dev_marker_path, load_locations_path, registry, trailing_data_path, yaz0_info_path, Handler},
+    profile::Profile,
+    recompress::update_dev_marker,
+    report::{adopt_messages, cube_error, OutputFile},
+    stream::{is_std_stream, set_modified, write_output},
 };
 
-use crate::commands::PackOptions;
+/// Something to pack, which can't start until everything inside it has been packed
+struct PackTask {
+    path: PathBuf,
+    out: Option<PathBuf>,
+    /// Format from `--as`, which only applies to the file given on the command line
+    format: Option<&'static str>,
+    parent: Option<usize>,
+    children: usize,
+}
+
+/// Progress shared between the threads packing a tree
+struct Scheduler {
+    /// Tasks that can be started
+    ready: Vec<usize>,
+    /// Number of tasks inside each task that haven't finished yet
+    waiting_on: Vec<usize>,
+    /// Tasks that won't be packed because something inside them failed
+    blocked: Vec<bool>,
+    finished: usize,
+    outputs: Vec<(usize, OutputFile)>,
+    failures: Vec<(usize, PackError)>,
+}
+
+/// Why a file or folder couldn't be packed
+#[derive(Error)]
+pub enum PackError {
+    #[error("{path:?}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+
+    #[error("{path:?}: {reason}")]
+    InvalidName { path: PathBuf, reason: String },
+
+    #[error("{path:?}: {reason}")]
+    Format { path: PathBuf, reason: String },
 
-pub fn try_pack(file: PathBuf, out: Option<&Path>, options: &PackOptions) -> Result<(), Box<dyn Error>> {
-    let out_format = out.map(|p| {
+    #[error("Packing from stdin needs --as, or -o with an extension, to pick the format")]
+    StdinNeedsFormat,
+
+    /// Some of the files in a tree couldn't be packed, along with any folders they're in
+    #[error("{} of {total} files failed to pack{}", failures.len(), match blocked {
+        0 => String::new(),
+        blocked => format!(", so {blocked} folders containing them weren't packed"),
+    })]
+    Failed {
+        failures: Vec<PackError>,
+        total: usize,
+        blocked: usize,
+    },
+}
+
+/// The same as [`Display`](fmt::Display), since this is what `main` prints when it returns an error
+impl fmt::Debug for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+impl PackError {
+    /// Sorts out an error from packing `path` by what caused it. Format errors are kept as
+    /// their message, with their error code, since they can't be sent between the threads
+    /// packing a tree.
+    fn new(path: &Path, error: Box<dyn Error>) -> Self {
+        let path = path.to_owned();
+        let error = match error.downcast::<io::Error>() {
+            Ok(source) => return PackError::Io { path, source: *source },
+            Err(error) => error,
+        };
+        match error.downcast::<RarcError>().map(|e| *e) {
+            Ok(RarcError::IOError(source)) => PackError::Io { path, source },
+            Ok(
+                e
+                @ (RarcError::InvalidNameError(_) | RarcError::DuplicateNameError(_) | RarcError::StringTableOverflow),
+            ) => PackError::InvalidName {
+                path,
+                reason: e.to_string(),
+            },
+            Ok(e) => PackError::Format {
+                path,
+                reason: CubeError::new(e).to_string(),
+            },
+            Err(e) => PackError::Format {
+                path,
+                reason: cube_error(e).to_string(),
+            },
+        }
+    }
+}
+
+/// Returns every file that was written, including nested archives. Everything inside a
+/// directory is packed before the directory itself, spread across `options.jobs` threads.
+/// Files that fail don't stop the others, but the directories they're in aren't packed,
+/// and the result is [`PackError::Failed`] with all of them once everything else is done.
+pub fn try_pack(file: PathBuf, out: Option<&Path>, options: &PackOptions) -> Result<Vec<OutputFile>, PackError> {
+    let mut tasks = Vec::new();
+    let root = plan_tasks(file, out.map(ToOwned::to_owned), options, &mut tasks)?;
+    tasks[root].format = options.pack_as;
+    let task_count = tasks.len();
+    let scheduler = Mutex::new(Scheduler {
+        // Taken from the end, so the first task listed is started first
+        ready: (0..task_count).rev().filter(|&idx| tasks[idx].children == 0).collect(),
+        waiting_on: tasks.iter().map(|task| task.children).collect(),
+        blocked: vec![false; task_count],
+        finished: 0,
+        outputs: Vec::new(),
+        failures: Vec::new(),
+    });
+    let wake = Condvar::new();
+
+    let worker_threads: Vec<ThreadId> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..options.jobs.clamp(1, task_count))
+            .map(|_| scope.spawn(|| run_pack_tasks(&tasks, &scheduler, &wake, options)))
+            .collect();
+        workers.into_iter().map(|worker| worker.join().unwrap()).collect()
+    });
+    // Keep anything the workers logged with the rest of this command's messages
+    for thread in worker_threads {
+        adopt_messages(thread);
+    }
+
+    let mut scheduler = scheduler.into_inner().unwrap();
+    if !scheduler.failures.is_empty() {
+        scheduler.failures.sort_by_key(|(idx, _)| *idx);
+        for (_, e) in scheduler.failures.iter() {
+            error!("Couldn't pack {e}");
+        }
+        return Err(PackError::Failed {
+            failures: scheduler.failures.into_iter().map(|(_, e)| e).collect(),
+            total: task_count,
+            blocked: scheduler.blocked.iter().filter(|&&blocked| blocked).count(),
+        });
+    }
+    let mut outputs = scheduler.outputs;
+    outputs.sort_by_key(|(idx, _)| *idx);
+    Ok(outputs.into_iter().map(|(_, output)| output).collect())
+}
+
+/// Packs tasks as they become ready until they're all done or can't be done. Returns this
+/// thread's ID so its log messages can be collected.
+fn run_pack_tasks(tasks: &[PackTask], scheduler: &Mutex<Scheduler>, wake: &Condvar, options: &PackOptions) -> ThreadId {
+    loop {
+        let idx = {
+            let mut state = scheduler.lock().unwrap();
+            loop {
+                if state.finished == tasks.len() {
+                    return thread::current().id();
+                }
+                match state.ready.pop() {
+                    Some(idx) => break idx,
+                    None => state = wake.wait(state).unwrap(),
+                }
+            }
+        };
+        let task = &tasks[idx];
+        let result = pack_file(&task.path, task.out.as_deref(), task.format, options);
+
+        let mut state = scheduler.lock().unwrap();
+        state.finished += 1;
+        match result {
+            Ok(output) => {
+                state.outputs.extend(output.map(|output| (idx, output)));
+                if let Some(parent) = task.parent {
+                    state.waiting_on[parent] -= 1;
+                    if state.waiting_on[parent] == 0 && !state.blocked[parent] {
+                        state.ready.push(parent);
+                    }
+                }
+            }
+            Err(e) => {
+                state.failures.push((idx, e));
+                // Everything this is inside is given up on now rather than waited for
+                let mut parent = task.parent;
+                while let Some(idx) = parent.filter(|&idx| !state.blocked[idx]) {
+                    state.blocked[idx] = true;
+                    state.finished += 1;
+                    parent = tasks[idx].parent;
+                }
+            }
+        }
+        wake.notify_all();
+    }
+}
+
+/// Adds a task for `file` and everything inside it, with the contents listed first
+fn plan_tasks(
+    file: PathBuf,
+    out: Option<PathBuf>,
+    options: &PackOptions,
+    tasks: &mut Vec<PackTask>,
+) -> Result<usize, PackError> {
+    if options.deterministic && file.file_name().is_some_and(|name| name.to_str().is_none()) {
+        return Err(PackError::InvalidName {
+            path: file,
+            reason: String::from("File name isn't valid UTF-8"),
+        });
+    }
+
+    // Collect entries up front since packing can write new files into this directory
+    let mut subfiles = Vec::new();
+    if file.is_dir() {
+        if file.join(DISC_SET_FILE).is_file() {
+            return Err(PackError::Format {
+                path: file,
+                reason: String::from(
+                    "Discs extracted with --merge-discs can't be packed back together. Extract each disc on its own to repack it",
+                ),
+            });
+        }
+        subfiles = file
+            .read_dir()
+            .and_then(|entries| entries.map(|entry| Ok(entry?.path())).collect())
+            .map_err(|source| PackError::Io {
+                path: file.clone(),
+                source,
+            })?;
+        if options.deterministic {
+            subfiles.sort();
+        }
+    }
+
+    let children: Vec<usize> = subfiles
+        .into_iter()
+        .map(|subfile| plan_tasks(subfile, None, options, tasks))
+        .collect::<Result<_, _>>()?;
+    let idx = tasks.len();
+    for &child in children.iter() {
+        tasks[child].parent = Some(idx);
+    }
+    tasks.push(PackTask {
+        path: file,
+        out,
+        format: None,
+        parent: None,
+        children: children.len(),
+    });
+    Ok(idx)
+}
+
+/// Packs a single file or directory, assuming everything inside it already has been.
+/// `format` overrides whatever format would be guessed for it.
+fn pack_file(
+    file: &Path,
+    out: Option<&Path>,
+    format: Option<&'static str>,
+    options: &PackOptions,
+) -> Result<Option<OutputFile>, PackError> {
+    // Stdout has no extension, so the format is the one it'd be packed into by default
+    let format_path = match out {
+        Some(out) if is_std_stream(out) => default_output(file, format, options),
+        out => out.map(ToOwned::to_owned),
+    };
+    let out_format = format_path.map(|p| {
         p.extension()
             .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
             .unwrap_or(String::from(""))
     });
+    if is_std_stream(file) && format.is_none() && out_format.as_deref().unwrap_or("").is_empty() {
+        return Err(PackError::StdinNeedsFormat);
+    }
 
-    if file.is_dir() {
-        for subfile in file.read_dir()? {
-            try_pack(subfile?.path(), None, &options)?;
+    if options.incremental && !is_std_stream(file) {
+        let expected_out = out
+            .map(ToOwned::to_owned)
+            .or_else(|| default_output(file, format, options));
+        if let Some(expected_out) = expected_out.filter(|expected_out| is_up_to_date(file, expected_out)) {
+            info!("{expected_out:?} is up to date");
+            return Ok(None);
         }
     }
 
-    let vfile = pack(&file, out_format.as_deref(), &options)?;
-    if let Some(vfile) = vfile {
-        info!("Packing {:?} => {:?}", &file, &vfile.path);
-        write(out.unwrap_or(&vfile.path), &vfile.bytes)?;
+    let Some((packed_format, handler)) = packer(file, out_format.as_deref(), format) else {
+        return Ok(None);
+    };
+    let streamed_output = handler
+        .streamed_output(file, packed_format, options)
+        .filter(|_| !out.is_some_and(is_std_stream));
+    let (out_path, size) = if let Some(default_out) = streamed_output {
+        let out_path = out.map(ToOwned::to_owned).unwrap_or(default_out);
+        info!("Packing {:?} => {:?}", file, &out_path);
+        // Written next to where it goes and only moved there once it's done, so a failure
+        // doesn't leave part of a file behind, or replace an unchanged one
+        let mut temp_path = out_path.as_os_str().to_owned();
+        temp_path.push(".part");
+        let temp_path = PathBuf::from(temp_path);
+        if let Err(e) = handler.pack_to(file, packed_format, options, &temp_path) {
+            let _ = remove_file(&temp_path);
+            return Err(PackError::new(file, e));
+        }
+        let io_error = |source| PackError::Io {
+            path: out_path.clone(),
+            source,
+        };
+        if options.incremental && same_contents(&temp_path, &out_path).map_err(io_error)? {
+            remove_file(&temp_path).map_err(io_error)?;
+            mark_unchanged(file, &out_path, options)?;
+            return Ok(None);
+        }
+        rename(&temp_path, &out_path).map_err(io_error)?;
+        let size = metadata(&out_path).map_err(io_error)?.len() as usize;
+        (out_path, size)
+    } else {
+        let packed = handler.pack(file, packed_format, options);
+        let Some(vfile) = packed.map_err(|e| PackError::new(file, e))? else {
+            return Ok(None);
+        };
+        let out_path = out.unwrap_or(&vfile.path);
+        // Rewriting an identical file would make everything it's packed into look out of date,
+        // so it's only marked as up to date if it wasn't already
+        if options.incremental && !is_std_stream(out_path) && read(out_path).is_ok_and(|bytes| bytes == vfile.bytes) {
+            mark_unchanged(file, out_path, options)?;
+            return Ok(None);
+        }
+        info!("Packing {:?} => {:?}", file, &vfile.path);
+        write_output(out_path, &vfile.bytes).map_err(|source| PackError::Io {
+            path: out_path.to_owned(),
+            source,
+        })?;
+        (out_path.to_owned(), vfile.bytes.len())
+    };
+    let out_path = out_path.as_path();
+    if options.deterministic && !is_std_stream(out_path) {
+        build_time(file, out_path)
+            .and_then(|time| set_modified(out_path, time))
+            .map_err(|source| PackError::Io {
+                path: out_path.to_owned(),
+                source,
+            })?;
+    }
+    if !is_std_stream(out_path) {
+        update_dev_marker(out_path, packed_format, options).map_err(|source| PackError::Io {
+            path: dev_marker_path(out_path),
+            source,
+        })?;
+    }
 
-        if options.delete_originals {
-            if file.is_dir() {
-                remove_dir_all(&file)?;
-            } else {
-                remove_file(&file)?;
-            }
+    if options.delete_originals && !is_std_stream(file) {
+        let removed = match file.is_dir() {
+            true => remove_dir_all(file),
+            false => remove_file(file),
+        };
+        removed.map_err(|source| PackError::Io {
+            path: file.to_owned(),
+            source,
+        })?;
+    }
+
+    Ok(Some(OutputFile {
+        source: file.to_owned(),
+        path: out_path.to_owned(),
+        size,
+        format: None,
+        archive: None,
+        load_location: None,
+    }))
+}
+
+/// Whether `out` was modified after every file in `file` and its sidecars, so packing
+/// `file` again would give the same result. Anything that can't be checked isn't.
+fn is_up_to_date(file: &Path, out: &Path) -> bool {
+    let Ok(built) = out.metadata().and_then(|metadata| metadata.modified()) else {
+        return false;
+    };
+    let older = |input: &Path| newest_modified(input).is_ok_and(|modified| modified <= built);
+    let sidecars = [yaz0_info_path(out), load_locations_path(out), trailing_data_path(out)];
+    older(file)
+        && sidecars
+            .iter()
+            .filter(|sidecar| sidecar.exists())
+            .all(|sidecar| older(sidecar))
+}
+
+/// What a file packed from `file` into `out` is dated with `--deterministic`, so the same
+/// inputs give the same output down to its timestamp. That's `SOURCE_DATE_EPOCH` if it's
+/// set, like other reproducible builds, or else when the newest input was changed.
+fn build_time(file: &Path, out: &Path) -> io::Result<SystemTime> {
+    if let Ok(epoch) = env::var("SOURCE_DATE_EPOCH") {
+        let seconds = epoch
+            .trim()
+            .parse()
+            .map_err(|_| io::Error::other(format!("SOURCE_DATE_EPOCH {epoch:?} isn't a number of seconds")))?;
+        return Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds));
+    }
+    let sidecars = [yaz0_info_path(out), load_locations_path(out), trailing_data_path(out)];
+    let mut newest = newest_modified(file)?;
+    for sidecar in sidecars.iter().filter(|sidecar| sidecar.exists()) {
+        newest = newest.max(newest_modified(sidecar)?);
+    }
+    Ok(newest)
+}
+
+/// Modification time of the most recently changed file in `path`, or of `path` itself if
+/// it's a file. Folders' own times are left out, since writing files into them changes it.
+fn newest_modified(path: &Path) -> io::Result<SystemTime> {
+    if !path.is_dir() {
+        return path.metadata()?.modified();
+    }
+    let mut newest = SystemTime::UNIX_EPOCH;
+    for entry in read_dir(path)? {
+        newest = newest.max(newest_modified(&entry?.path())?);
+    }
+    Ok(newest)
+}
+
+/// Directories are packed into an archive next to them unless told otherwise, or into
+/// a save file, banner, particle container, model, or disc image if they hold an extracted one
+pub fn default_pack_output(file: &Path, options: &PackOptions) -> Option<PathBuf> {
+    default_output(file, options.pack_as, options)
+}
+
+/// Like [`default_pack_output`], for a file being packed into `format` if it's given
+fn default_output(file: &Path, format: Option<&str>, options: &PackOptions) -> Option<PathBuf> {
+    // What's read from stdin is written to stdout, since it has no name to go next to
+    if is_std_stream(file) {
+        return format.map(|_| file.to_owned());
+    }
+    match format.or_else(|| guess_dest_format(file)) {
+        Some(format @ ("gci" | "bnr" | "jpc" | "bmd" | "bdl" | "baa" | "iso" | "u8" | "afs")) => {
+            Some(file.with_extension(format))
         }
+        Some("imd5") => Some(file.with_extension("bin")),
+        Some(format @ ("szs" | "szp" | "arc")) if file.is_dir() => Some(file.with_extension(format)),
+        _ => file.is_dir().then(|| file.with_extension(options.arc_extension())),
     }
+}
 
-    Ok(())
+/// Finds what packs a file or folder into `pack_as` if it's given, and otherwise guesses
+/// the format, preferring the output's extension in `format`. Returns the format it'll be
+/// packed into along with it.
+fn packer<'a>(path: &Path, format: Option<&'a str>, pack_as: Option<&'a str>) -> Option<(&'a str, &'static Handler)> {
+    // Banners keep whatever extension they had, so their header decides the format
+    let dest_format = match guess_dest_format(path) {
+        _ if pack_as.is_some() => pack_as,
+        banner @ Some("bnr" | "imd5") => banner,
+        guess => format.or(guess),
+    };
+    dest_format.and_then(|format| Some((format, registry().packer(format)?)))
 }
 
-fn pack(path: &Path, format: Option<&str>, options: &PackOptions) -> Result<Option<VirtualFile>, Box<dyn Error>> {
-    let dest_format = format.or(guess_dest_format(path));
-    match dest_format {
-        Some("szs") | Some("arc") => {
-            let mut rarc = Rarc::encode(path)?;
+/// Marks an output that came out the same as it already was as up to date with its input,
+/// without rewriting it
+fn mark_unchanged(file: &Path, out_path: &Path, options: &PackOptions) -> Result<(), PackError> {
+    info!("{out_path:?} is unchanged");
+    if is_up_to_date(file, out_path) {
+        return Ok(());
+    }
+    let time = match options.deterministic {
+        true => build_time(file, out_path),
+        false => Ok(SystemTime::now()),
+    };
+    time.and_then(|time| set_modified(out_path, time))
+        .map_err(|source| PackError::Io {
+            path: out_path.to_owned(),
+            source,
+        })
+}
 
-            if options.arc_yaz0_compress && dest_format.is_some_and(|f| f == "szs") {
-                rarc = VirtualFile {
-                    bytes: yaz0_compress(&rarc.bytes)?,
-                    path: rarc.path.with_extension("szs"),
-                };
-            }
+/// Whether two files hold the same bytes, reading them a piece at a time. A file that
+/// doesn't exist isn't the same as anything.
+fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let (Ok(a), Ok(b)) = (File::open(a), File::open(b)) else {
+        return Ok(false);
+    };
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+    let (mut a, mut b) = (BufReader::new(a), BufReader::new(b));
+    loop {
+        let chunk = a.fill_buf()?;
+        if chunk.is_empty() {
+            return Ok(true);
+        }
+        let len = chunk.len();
+        let mut other = vec![0; len];
+        b.read_exact(&mut other)?;
+        if chunk != other {
+            return Ok(false);
+        }
+        a.consume(len);
+    }
+}
 
-            if let Some(ext) = options.arc_extension.as_ref() {
-                rarc.set_path(rarc.path.with_extension(ext));
+/// Reads a BMG from JSON. Characters its text encoding can't represent are an error
+/// unless `lossy_text` is set, in which case they're replaced with a warning. Messages
+/// with "default" attributes get the profile's template if it has one.
+pub fn bmg_from_json(bytes: &[u8], lossy_text: bool, profile: Option<&Profile>) -> Result<Bmg, Box<dyn Error>> {
+    let template = profile.and_then(|profile| profile.bmg.default_attributes.as_ref());
+    let bmg: Bmg = match template {
+        Some(template) => {
+            let mut json: Value = serde_json::from_slice(bytes)?;
+            let messages = json.get_mut("messages").and_then(Value::as_array_mut);
+            for message in messages.into_iter().flatten() {
+                if message["attributes"].as_str().is_some_and(is_default_attributes) {
+                    message["attributes"] = template.to_string().into();
+                }
             }
+            serde_json::from_value(json)?
+        }
+        None => serde_json::from_slice(bytes)?,
+    };
+    check_unencodable(&bmg, lossy_text)?;
+    Ok(bmg)
+}
 
-            Ok(Some(rarc))
+/// Reports every character that had to be replaced while adding text to a BMG, failing
+/// unless `lossy_text` is set
+pub fn check_unencodable(bmg: &Bmg, lossy_text: bool) -> Result<(), Box<dyn Error>> {
+    let unencodable = bmg.unencodable_chars();
+    if unencodable.is_empty() {
+        return Ok(());
+    }
+    for c in unencodable {
+        if lossy_text {
+            warn!("{c}, replacing it");
+        } else {
+            error!("{c}");
         }
-        Some("bmg") => {
-            let vfile = VirtualFile::read(path)?;
-            let bmg: Bmg = serde_json::from_slice(&vfile.bytes)?;
-            Ok(Some(VirtualFile {
-                path: path.with_extension("").with_extension("bmg"),
-                bytes: bmg.write(),
-            }))
+    }
+    if lossy_text {
+        return Ok(());
+    }
+    Err(format!(
+        "{} characters can't be encoded. Replace them, or use --lossy-text to have it done automatically",
+        unencodable.len()
+    )
+    .into())
+}
+
+/// What's in the `.bti.json` next to an extracted texture's PNG
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BtiMetadata {
+    #[serde(flatten)]
+    pub header: BtiHeader,
+    /// How the PNG was written, if it's an intensity texture that isn't in RGBA
+    #[serde(default, skip_serializing_if = "is_rgba")]
+    pub png: IntensityPng,
+}
+
+impl From<BtiHeader> for BtiMetadata {
+    fn from(header: BtiHeader) -> Self {
+        BtiMetadata {
+            header,
+            png: IntensityPng::Rgba,
         }
-        _ => Ok(None),
     }
 }
 
+impl BtiMetadata {
+    /// Metadata for the same PNG packed with a different header, like one with a format
+    /// picked by `--auto-format`. None if the PNG was written for an intensity format and
+    /// the new one isn't, since the PNG's colors then can't be described.
+    pub fn with_header(self, header: BtiHeader) -> Option<Self> {
+        let intensity = |format| {
+            matches!(
+                TextureFormat::from_id(format),
+                Some(TextureFormat::I4 | TextureFormat::I8)
+            )
+        };
+        let png = match (self.png, intensity(self.header.format), intensity(header.format)) {
+            (png, true, true) => png,
+            // Anything else was written as the colors it decodes to
+            (_, false, _) | (IntensityPng::Rgba, _, _) => IntensityPng::Rgba,
+            (_, true, false) => return None,
+        };
+        Some(BtiMetadata { header, png })
+    }
+}
+
+fn is_rgba(png: &IntensityPng) -> bool {
+    *png == IntensityPng::Rgba
+}
+
+/// Encodes a PNG as a BTI with the settings in `metadata`, or as RGB5A3 with default
+/// settings if there isn't any. With `auto_format`, the format is chosen to suit the image.
+pub fn bti_from_png(
+    png: &[u8],
+    metadata: Option<BtiMetadata>,
+    auto_format: bool,
+    quality: BtiQuality,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let (width, height, mut pixels) = decode_png(png)?;
+    // Grayscale and alpha PNGs of intensity textures go back to the colors they decode to
+    let header = metadata.map(|metadata| {
+        if let Some(format) = TextureFormat::from_id(metadata.header.format) {
+            metadata.png.restore(format, &mut pixels);
+        }
+        metadata.header
+    });
+    let format = match (&header, auto_format) {
+        (_, true) => {
+            let format = TextureFormat::choose(&pixels);
+            info!("Chose {format} for a {width}x{height} texture");
+            format
+        }
+        (Some(header), false) => TextureFormat::from_id(header.format).ok_or("Unknown texture format in header")?,
+        (None, false) => TextureFormat::RGB5A3,
+    };
+    let header = match header {
+        Some(mut header) => {
+            header.set_format(format, &pixels);
+            header
+        }
+        None => BtiHeader::new(format, &pixels),
+    };
+    Ok(BtiImage::encode(width, height, &pixels, &header, quality)?)
+}
+
 fn guess_dest_format(path: &Path) -> Option<&'static str> {
-    let path_str = path.to_string_lossy();
-    if path.is_dir() {
-        // Never guess ARC, otherwise every nested folder will be ARC encoded
-        return None;
-    } else {
-        if path_str.ends_with("json") {
-            return Some("bmg");
-        } else if path_str.ends_with("png") {
-            return Some("bti");
+    registry().guess_pack_format(path)
+}
+
+/// Reads the files to pack into a banner's archive. Banner parts inside it were extracted
+/// to their own folders and have already been packed back up next to them, so those
+/// folders are skipped.
+pub(crate) fn read_banner_tree(
+    dir: &Path,
+    prefix: PathBuf,
+    files: &mut Vec<(PathBuf, Vec<u8>)>,
+) -> Result<(), Box<dyn Error>> {
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let path = prefix.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            if !matches!(guess_dest_format(&entry.path()), Some("bnr" | "imd5")) {
+                read_banner_tree(&entry.path(), path, files)?;
+            }
+        } else {
+            files.push((path, read(entry.path())?));
         }
     }
+    Ok(())
+}
+
+pub(crate) fn read_png(path: &Path) -> Result<Vec<[u8; 4]>, Box<dyn Error>> {
+    Ok(decode_png(&read(path)?)?.2)
+}
+
+/// Each format the README lists as deterministic, packed from two copies of the same tree
+/// that were written in opposite orders and dated differently
+#[cfg(test)]
+mod tests {
+    use super::try_pack;
+    use crate::commands::{Cli, Commands};
+    use clap::Parser;
+    use cube_rs::iso::{Fst, FstEntry};
+    use std::{
+        env,
+        fs::{create_dir_all, read, remove_dir_all, File},
+        io::Write,
+        process,
+        time::{Duration, SystemTime},
+    };
+
+    const STAGE: [(&str, &[u8]); 4] = [
+        (
+            "stage/b.bin",
+            b"second file, with enough repeated text to compress: abcabcabcabcabc",
+        ),
+        ("stage/a.bin", b"first file"),
+        ("stage/sub/d.bin", &[0xAB; 0x45]),
+        ("stage/sub/c.bin", b""),
+    ];
+
+    /// Packs `input` from `files` into `output` twice with `--deterministic` and `args`, and
+    /// checks both come out the same
+    fn assert_packs_identically(test: &str, files: &[(&str, &[u8])], input: &str, output: &str, args: &[&str]) {
+        let root = env::temp_dir().join(format!("cube-deterministic-{test}-{}", process::id()));
+        let packed: Vec<_> = [(0, false), (1, true)]
+            .into_iter()
+            .map(|(copy, reversed)| {
+                let tree = root.join(copy.to_string());
+                let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000 + copy * 86_400);
+                let mut files = files.to_vec();
+                if reversed {
+                    files.reverse();
+                }
+                for (path, contents) in files {
+                    let path = tree.join(path);
+                    create_dir_all(path.parent().unwrap()).unwrap();
+                    let mut file = File::create(&path).unwrap();
+                    file.write_all(contents).unwrap();
+                    file.set_modified(modified).unwrap();
+                }
+                let input = tree.join(input);
+                let args = ["cube", "pack", "--deterministic", "--no-cache"]
+                    .into_iter()
+                    .chain(args.iter().copied());
+                let Commands::Pack { options, .. } = Cli::parse_from(args.chain([input.to_str().unwrap()])).subcommand
+                else {
+                    unreachable!()
+                };
+                try_pack(input, Some(&tree.join(output)), &options)
+                    .map_err(|e| e.to_string())
+                    .and_then(|_| read(tree.join(output)).map_err(|e| e.to_string()))
+            })
+            .collect();
+        // Cleaned up before checking, so a failure doesn't leave the trees behind
+        remove_dir_all(&root).unwrap();
+        let packed: Vec<Vec<u8>> = packed.into_iter().collect::<Result<_, _>>().unwrap();
+        assert!(packed[0] == packed[1], "{output} came out differently the second time");
+    }
+
+    #[test]
+    fn rarc() {
+        assert_packs_identically("rarc", &STAGE, "stage", "stage.arc", &["--arc-yaz0-compress", "false"]);
+    }
+
+    #[test]
+    fn szs() {
+        assert_packs_identically("szs", &STAGE, "stage", "stage.szs", &[]);
+    }
 
-    None
+    #[test]
+    fn szp() {
+        assert_packs_identically("szp", &STAGE, "stage", "stage.szp", &["--arc-yay0"]);
+    }
+
+    #[test]
+    fn bmg() {
+        let json = br#"{
+            "metadata": { "encoding": "ShiftJIS", "bmg_file_id": 0, "default_color": 0 },
+            "messages": [
+                { "message": "Hello", "attributes": "00000000" },
+                { "message": "World", "attributes": "00000001" }
+            ]
+        }"#;
+        assert_packs_identically("bmg", &[("text.bmg.json", json)], "text.bmg.json", "text.bmg", &[]);
+    }
+
+    #[test]
+    fn bcsv() {
+        let csv = b"Name:StringOffset:0:0xFFFFFFFF:0,Count:Long:4:0xFFFFFFFF:0\nfirst,1\nsecond,2\n";
+        assert_packs_identically("bcsv", &[("table.bcsv.csv", csv)], "table.bcsv.csv", "table.bcsv", &[]);
+    }
+
+    #[test]
+    fn iso() {
+        let listed: Vec<(&str, &[u8])> = [("readme.txt", &b"hello"[..])].into_iter().chain(STAGE).collect();
+        let fst = Fst {
+            entries: listed
+                .iter()
+                .zip(0u32..)
+                .map(|(&(path, contents), idx)| FstEntry::File {
+                    path: path.to_owned(),
+                    offset: 0x20000 + idx * 0x8000,
+                    size: contents.len() as u32,
+                })
+                .collect(),
+        }
+        .write();
+        let mut boot_bin = vec![0; 0x440];
+        boot_bin[..6].copy_from_slice(b"GTST01");
+        boot_bin[0x1C..0x20].copy_from_slice(&0xC2339F3Du32.to_be_bytes());
+        boot_bin[0x420..0x424].copy_from_slice(&0x10000u32.to_be_bytes());
+        boot_bin[0x424..0x428].copy_from_slice(&0x11000u32.to_be_bytes());
+        boot_bin[0x428..0x42C].copy_from_slice(&(fst.len() as u32).to_be_bytes());
+        let mut files = vec![
+            (String::from("game/sys/boot.bin"), &boot_bin[..]),
+            (String::from("game/sys/bi2.bin"), &[0; 0x2000][..]),
+            (String::from("game/sys/apploader.img"), &[0; 0x20][..]),
+            (String::from("game/sys/main.dol"), &[0; 0x100][..]),
+            (String::from("game/sys/fst.bin"), &fst[..]),
+        ];
+        files.extend(
+            listed
+                .iter()
+                .map(|&(path, contents)| (format!("game/{path}"), contents)),
+        );
+        let files: Vec<_> = files
+            .iter()
+            .map(|(path, contents)| (path.as_str(), *contents))
+            .collect();
+        assert_packs_identically("iso", &files, "game", "game.iso", &[]);
+    }
 }
@@ -0,0 +1,133 @@
+use crate::config::config_dir;
+use cube_rs::font_metrics::FontMetrics;
+use serde::Deserialize;
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{read, read_to_string},
+    path::{Path, PathBuf},
+};
+
+/// Profiles that come with cube, by name
+const BUILTIN_PROFILES: &[(&str, &str)] = &[("gamecube", include_str!("../profiles/gamecube.toml"))];
+
+/// What cube knows about a particular game, read from a TOML file: the limits `cube lint`
+/// checks files against, and what to fill in for new BMG messages. Everything is
+/// optional, so a profile only needs what its game cares about.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub bmg: BmgRules,
+    #[serde(default)]
+    pub archives: ArchiveRules,
+    #[serde(default)]
+    pub textures: TextureRules,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BmgRules {
+    /// Characters per line, not counting escape sequences
+    pub max_line_length: Option<usize>,
+    /// Lines per message box
+    pub max_lines: Option<usize>,
+    /// How wide a line can be drawn, measured with `font_metrics`
+    pub max_line_width: Option<u32>,
+    /// JSON file with the width of each character in the game's font, relative to the
+    /// profile
+    pub font_metrics: Option<PathBuf>,
+    /// `font_metrics` as read when the profile was loaded
+    #[serde(skip)]
+    pub font: Option<FontMetrics>,
+    /// What escape sequences that start a new message box begin with
+    pub box_break: Option<HexBytes>,
+    /// Attributes for new messages whose attributes are "default", instead of the ones
+    /// most messages in the same BMG have
+    pub default_attributes: Option<HexBytes>,
+}
+
+/// Bytes written in hex in a profile, e.g. `"0100"`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct HexBytes(pub Vec<u8>);
+
+impl TryFrom<String> for HexBytes {
+    type Error = String;
+    fn try_from(hex: String) -> Result<Self, Self::Error> {
+        let bytes = (hex.len().is_multiple_of(2) && hex.is_ascii())
+            .then(|| {
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+                    .collect::<Option<Vec<u8>>>()
+            })
+            .flatten()
+            .filter(|bytes| !bytes.is_empty());
+        bytes
+            .map(HexBytes)
+            .ok_or_else(|| format!("\"{hex}\" isn't hex bytes like \"0100\""))
+    }
+}
+
+impl Display for HexBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().try_for_each(|b| write!(f, "{b:02X}"))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveRules {
+    /// Largest size of a decompressed RARC or U8 archive, in bytes
+    pub max_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextureRules {
+    #[serde(default)]
+    pub power_of_two: bool,
+    pub max_width: Option<u16>,
+    pub max_height: Option<u16>,
+}
+
+impl Profile {
+    /// Finds a profile by name, first in the user's config folder and then among the
+    /// built-in ones, or reads it from a path to a TOML file
+    pub fn load(name: &str) -> Result<Profile, Box<dyn Error>> {
+        let path = Path::new(name);
+        let user_profile = config_dir().map(|dir| dir.join("profiles").join(format!("{name}.toml")));
+        let (source, contents) = if path.extension().is_some_and(|ext| ext == "toml") || path.is_file() {
+            (
+                path.to_owned(),
+                read_to_string(path).map_err(|e| format!("Couldn't read {path:?}: {e}"))?,
+            )
+        } else if let Some(user_profile) = user_profile.filter(|path| path.is_file()) {
+            let contents = read_to_string(&user_profile)?;
+            (user_profile, contents)
+        } else if let Some((_, contents)) = BUILTIN_PROFILES.iter().find(|(builtin, _)| *builtin == name) {
+            (PathBuf::from(name), contents.to_string())
+        } else {
+            let builtins: Vec<&str> = BUILTIN_PROFILES.iter().map(|(name, _)| *name).collect();
+            return Err(format!(
+                "No profile named '{name}'. Built-in profiles are {}, or give the path to a profile file",
+                builtins.join(", ")
+            )
+            .into());
+        };
+        let mut profile: Profile =
+            toml::from_str(&contents).map_err(|e| format!("Invalid lint profile {source:?}: {e}"))?;
+        if let Some(path) = profile.bmg.font_metrics.as_ref() {
+            let path = source.parent().unwrap_or(Path::new("")).join(path);
+            let metrics = read(&path).map_err(|e| format!("Couldn't read font metrics {path:?}: {e}"))?;
+            let metrics =
+                serde_json::from_slice(&metrics).map_err(|e| format!("Invalid font metrics {path:?}: {e}"))?;
+            profile.bmg.font = Some(metrics);
+        } else if profile.bmg.max_line_width.is_some() {
+            return Err(format!("bmg.max_line_width in {source:?} needs bmg.font_metrics to measure lines").into());
+        }
+        Ok(profile)
+    }
+}
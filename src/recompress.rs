@@ -0,0 +1,209 @@
+use crate::{
+    commands::PackOptions,
+    handlers::{dev_marker_path, yaz0_info_path, DEV_MARKER_EXTENSION},
+};
+use cube_rs::{
+    szs::{yaz0_compress, CompressionLevel, Yaz0Info},
+    yay0,
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    fs::{read, read_dir, remove_file, write},
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// What archives that would be Yaz0 or Yay0 compressed are stored as instead with
+/// `--arc-dev-compression`, to pack much faster while working on a mod
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DevCompression {
+    /// Not compressed at all. Games that check for a Yaz0 header before decompressing
+    /// load these as they are.
+    None,
+    /// Zstandard, which no game can read, for keeping packed archives around between builds
+    Zstd,
+}
+
+impl DevCompression {
+    pub fn compress(self, bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+        match self {
+            DevCompression::None => Ok(bytes),
+            DevCompression::Zstd => zstd::encode_all(bytes.as_slice(), zstd::DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+
+    pub fn decompress(self, bytes: Vec<u8>) -> io::Result<Vec<u8>> {
+        match self {
+            DevCompression::None => Ok(bytes),
+            DevCompression::Zstd => zstd::decode_all(bytes.as_slice()),
+        }
+    }
+}
+
+impl Display for DevCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DevCompression::None => write!(f, "none"),
+            DevCompression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+impl FromStr for DevCompression {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(DevCompression::None),
+            "zstd" => Ok(DevCompression::Zstd),
+            _ => Err(format!("Invalid dev compression '{s}'. Expected 'none' or 'zstd'")),
+        }
+    }
+}
+
+/// Marker written next to an archive packed with `--arc-dev-compression`, saying how it
+/// was stored and what it should be compressed into for a release
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DevMarker {
+    pub compression: DevCompression,
+    /// 'szs' for Yaz0 or 'szp' for Yay0
+    pub format: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecompressedFile {
+    pub path: PathBuf,
+    /// Size as it was stored before
+    pub original_size: usize,
+    pub size: usize,
+}
+
+/// How an archive packed into `format` is stored instead, if it is
+pub fn dev_compression(format: &str, options: &PackOptions) -> Option<DevCompression> {
+    options
+        .arc_dev_compression
+        .filter(|_| options.arc_yaz0_compress && matches!(format, "szs" | "szp"))
+}
+
+/// Writes the marker for an archive that was just packed into `format`, or removes one
+/// left over from packing it with dev compression before
+pub fn update_dev_marker(archive: &Path, format: &str, options: &PackOptions) -> io::Result<()> {
+    let marker_path = dev_marker_path(archive);
+    match dev_compression(format, options) {
+        Some(compression) => {
+            let marker = DevMarker {
+                compression,
+                format: format.to_owned(),
+            };
+            write(marker_path, serde_json::to_vec_pretty(&marker)?)
+        }
+        None => match remove_file(&marker_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        },
+    }
+}
+
+/// Compresses every archive packed with `--arc-dev-compression` in `files` into Yaz0 or
+/// Yay0, as it would have been packed without it. Folders are searched all the way
+/// through, and archives can be given by their own path or their marker's. Archives that
+/// can't be recompressed are reported together once the rest have been.
+pub fn try_recompress(files: &[PathBuf], level: CompressionLevel, json: bool) -> Result<(), Box<dyn Error>> {
+    let mut archives = Vec::new();
+    for file in files {
+        find_archives(file, &mut archives)?;
+    }
+    // One archive failing doesn't stop the rest, since the ones before it are already rewritten
+    let mut recompressed = Vec::with_capacity(archives.len());
+    let mut failed = 0;
+    for archive in archives.iter() {
+        match recompress(archive, level) {
+            Ok(file) => recompressed.push(file),
+            Err(e) => {
+                error!("Couldn't recompress {archive:?}: {e}");
+                failed += 1;
+            }
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&recompressed)?);
+    } else {
+        println!("Recompressed {} archives", recompressed.len());
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{failed} of {} archives failed to recompress", archives.len()).into()),
+    }
+}
+
+fn find_archives(path: &Path, archives: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+    if path.is_dir() {
+        let mut entries = read_dir(path)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() {
+                find_archives(&entry, archives)?;
+            } else if let Some(archive) = marked_archive(&entry) {
+                archives.push(archive);
+            }
+        }
+    } else if let Some(archive) = marked_archive(path) {
+        archives.push(archive);
+    } else if dev_marker_path(path).is_file() {
+        archives.push(path.to_owned());
+    } else {
+        return Err(format!(
+            "{path:?} has no {DEV_MARKER_EXTENSION} marker next to it, so it wasn't packed with dev compression"
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// The archive a marker belongs to, if `path` is one
+fn marked_archive(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let archive = name.strip_suffix(DEV_MARKER_EXTENSION)?;
+    Some(path.with_file_name(archive))
+}
+
+fn recompress(archive: &Path, level: CompressionLevel) -> Result<RecompressedFile, Box<dyn Error>> {
+    let marker_path = dev_marker_path(archive);
+    let marker: DevMarker = serde_json::from_slice(&read(&marker_path)?)?;
+    let stored = read(archive)?;
+    let original_size = stored.len();
+    let data = marker.compression.decompress(stored)?;
+    let bytes = match marker.format.as_str() {
+        "szs" => {
+            let mut bytes = yaz0_compress(&data, level);
+            // Packing leaves the Yaz0 header alone when it's dev compressed
+            let info_path = yaz0_info_path(archive);
+            if info_path.is_file() {
+                let info: Yaz0Info = serde_json::from_slice(&read(&info_path)?)?;
+                info.apply(&mut bytes);
+            }
+            bytes
+        }
+        "szp" => yay0::compress(&data, level),
+        format => return Err(format!("Unknown format {format:?} in {marker_path:?}").into()),
+    };
+    write(archive, &bytes)?;
+    remove_file(&marker_path)?;
+    info!(
+        "Recompressed {archive:?} from {} ({original_size} bytes) to {} bytes",
+        marker.compression,
+        bytes.len()
+    );
+    Ok(RecompressedFile {
+        path: archive.to_owned(),
+        original_size,
+        size: bytes.len(),
+    })
+}
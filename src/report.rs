@@ -0,0 +1,127 @@
+use crate::stats::ExtractStats;
+use cube_rs::{
+    error::{CubeError, ErrorCode},
+    rarc::LoadLocation,
+};
+use log::{Level, Log, Metadata, Record};
+use serde::Serialize;
+use std::{
+    error::Error,
+    path::PathBuf,
+    sync::Mutex,
+    thread::{self, ThreadId},
+    time::Duration,
+};
+
+/// A file written by extract or pack, or that would be written in a dry run
+#[derive(Debug, Serialize)]
+pub struct OutputFile {
+    pub source: PathBuf,
+    pub path: PathBuf,
+    pub size: usize,
+    /// The format the file was extracted from, if it was recognized
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<&'static str>,
+    /// The outermost archive inside the source that the file came out of
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive: Option<PathBuf>,
+    /// Where the game loads the file from, for files in RARC archives
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_location: Option<LoadLocation>,
+}
+
+/// Summary of an extract or pack run, printed to stdout in place of log lines with `--json`
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub command: String,
+    pub inputs: Vec<PathBuf>,
+    pub outputs: Vec<OutputFile>,
+    pub duration_ms: u64,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>,
+    /// Totals by format and archive, with extract's `--stats`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<ExtractStats>,
+}
+
+impl Report {
+    /// Collects everything this thread logged through [`ReportLogger`] so far along with
+    /// the command's result
+    pub fn new(
+        command: impl Into<String>,
+        inputs: Vec<PathBuf>,
+        result: Result<Vec<OutputFile>, Box<dyn Error>>,
+        duration: Duration,
+    ) -> Self {
+        let mut warnings = Vec::new();
+        let mut errors = Vec::new();
+        MESSAGES.lock().unwrap().retain(|(thread, level, message)| {
+            if *thread != thread::current().id() {
+                return true;
+            }
+            if *level == Level::Error {
+                errors.push(message.clone());
+            } else {
+                warnings.push(message.clone());
+            }
+            false
+        });
+        let outputs = result.unwrap_or_else(|e| {
+            errors.push(e.to_string());
+            Vec::new()
+        });
+        Report {
+            command: command.into(),
+            inputs,
+            outputs,
+            duration_ms: duration.as_millis() as u64,
+            warnings,
+            errors,
+            stats: None,
+        }
+    }
+}
+
+/// Wraps an error with its code and room for where it happened. JSON that pack reads
+/// back is the one kind of error cube_rs can't recognize itself.
+pub fn cube_error(error: Box<dyn Error>) -> CubeError {
+    match error.is::<serde_json::Error>() {
+        true => CubeError::with_code(ErrorCode::Json, error),
+        false => CubeError::new(error),
+    }
+}
+
+/// Moves everything another thread logged over to this one, for work split across
+/// threads that still belongs in this thread's report
+pub fn adopt_messages(from: ThreadId) {
+    let current = thread::current().id();
+    for (thread, _, _) in MESSAGES.lock().unwrap().iter_mut() {
+        if *thread == from {
+            *thread = current;
+        }
+    }
+}
+
+/// Messages are tagged with the thread that logged them so batch jobs running in
+/// parallel each get their own
+static MESSAGES: Mutex<Vec<(ThreadId, Level, String)>> = Mutex::new(Vec::new());
+
+/// Logger that keeps warnings and errors for the report instead of printing them
+pub struct ReportLogger;
+
+impl Log for ReportLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            MESSAGES
+                .lock()
+                .unwrap()
+                .push((thread::current().id(), record.level(), record.args().to_string()));
+        }
+    }
+
+    fn flush(&self) {}
+}
@@ -0,0 +1,95 @@
+use crate::report::OutputFile;
+use serde::Serialize;
+use std::{cmp::Reverse, collections::BTreeMap};
+
+/// Label for files that weren't in any archive, like loose files on a disc
+const NO_ARCHIVE: &str = "(not in an archive)";
+
+/// How many files were extracted and how big they are, by format and by the archive they
+/// came out of
+#[derive(Debug, Serialize)]
+pub struct ExtractStats {
+    pub formats: Vec<StatsRow>,
+    pub archives: Vec<StatsRow>,
+    pub total: StatsRow,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsRow {
+    pub name: String,
+    pub files: usize,
+    pub bytes: usize,
+}
+
+impl ExtractStats {
+    pub fn new(outputs: &[OutputFile]) -> Self {
+        let mut formats = BTreeMap::new();
+        let mut archives = BTreeMap::new();
+        for output in outputs {
+            let format = output.format.unwrap_or("other").to_owned();
+            let archive = match &output.archive {
+                Some(archive) => archive.to_string_lossy().into_owned(),
+                None => NO_ARCHIVE.to_owned(),
+            };
+            add(formats.entry(format).or_default(), output.size);
+            add(archives.entry(archive).or_default(), output.size);
+        }
+        ExtractStats {
+            formats: rows(formats),
+            archives: rows(archives),
+            total: StatsRow {
+                name: String::from("total"),
+                files: outputs.len(),
+                bytes: outputs.iter().map(|output| output.size).sum(),
+            },
+        }
+    }
+
+    pub fn print(&self) {
+        print_table("FORMAT", &self.formats, &self.total);
+        println!();
+        print_table("ARCHIVE", &self.archives, &self.total);
+    }
+}
+
+fn add((files, bytes): &mut (usize, usize), size: usize) {
+    *files += 1;
+    *bytes += size;
+}
+
+/// Largest first, since that's usually what's being looked for
+fn rows(totals: BTreeMap<String, (usize, usize)>) -> Vec<StatsRow> {
+    let mut rows: Vec<StatsRow> = totals
+        .into_iter()
+        .map(|(name, (files, bytes))| StatsRow { name, files, bytes })
+        .collect();
+    rows.sort_by_key(|row| Reverse(row.bytes));
+    rows
+}
+
+fn print_table(heading: &str, rows: &[StatsRow], total: &StatsRow) {
+    let cells: Vec<[String; 3]> = rows
+        .iter()
+        .chain(std::iter::once(total))
+        .map(|row| [row.name.clone(), row.files.to_string(), row.bytes.to_string()])
+        .collect();
+
+    let header = [heading, "FILES", "BYTES"].map(String::from);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &cells {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(&cells) {
+        println!(
+            "{:<w0$}  {:>w1$}  {:>w2$}",
+            row[0],
+            row[1],
+            row[2],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2]
+        );
+    }
+}
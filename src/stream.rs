@@ -0,0 +1,165 @@
+use cube_rs::{
+    detect::{detect, FileFormat},
+    virtual_fs::VirtualFile,
+};
+use std::{
+    borrow::Cow,
+    error::Error,
+    fs::{create_dir_all, read, write, File},
+    io::{self, stdin, stdout, Read, Write},
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+/// `-` in place of a path stands for stdin when reading and stdout when writing
+pub fn is_std_stream(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+pub fn read_input(path: &Path) -> io::Result<Vec<u8>> {
+    if is_std_stream(path) {
+        let mut bytes = Vec::new();
+        stdin().lock().read_to_end(&mut bytes)?;
+        Ok(bytes)
+    } else {
+        read(path)
+    }
+}
+
+pub fn write_output(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    if is_std_stream(path) {
+        let mut out = stdout().lock();
+        out.write_all(bytes)?;
+        out.flush()
+    } else {
+        write(long_path(path), bytes)
+    }
+}
+
+/// Sets when an output file was last modified, e.g. to match the file it came from
+pub fn set_modified(path: &Path, time: SystemTime) -> io::Result<()> {
+    File::options().write(true).open(long_path(path))?.set_modified(time)
+}
+
+/// Creates the folder an output file goes in, along with any missing parents
+pub fn create_output_dir(path: &Path) -> io::Result<()> {
+    create_dir_all(long_path(path))
+}
+
+/// Longest path Windows accepts before it has to be given in its verbatim `\\?\` form.
+/// MAX_PATH is 260, but folders have to leave room for an 8.3 file name inside them.
+#[cfg(windows)]
+const MAX_PATH: usize = 248;
+
+/// The path to hand to the OS when writing. Deeply nested archives can produce paths
+/// longer than Windows allows, so there they're made absolute and verbatim instead.
+#[cfg(windows)]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    use std::{ffi::OsString, path::Prefix};
+
+    let Ok(absolute) = std::path::absolute(path) else {
+        return Cow::Borrowed(path);
+    };
+    let Some(Component::Prefix(prefix)) = absolute.components().next() else {
+        return Cow::Borrowed(path);
+    };
+    if absolute.as_os_str().len() < MAX_PATH {
+        return Cow::Borrowed(path);
+    }
+    let mut verbatim = match prefix.kind() {
+        Prefix::Disk(_) => OsString::from(r"\\?\"),
+        Prefix::UNC(..) => OsString::from(r"\\?\UNC\"),
+        // Already verbatim, or a device path
+        _ => return Cow::Borrowed(path),
+    };
+    verbatim.push(prefix.as_os_str().to_string_lossy().trim_start_matches('\\'));
+    let mut verbatim = PathBuf::from(verbatim);
+    // Verbatim paths aren't normalized, but `absolute` has already resolved any `..`
+    for component in absolute.components() {
+        if let Component::Normal(name) = component {
+            verbatim.push(name);
+        }
+    }
+    Cow::Owned(verbatim)
+}
+
+#[cfg(not(windows))]
+fn long_path(path: &Path) -> Cow<'_, Path> {
+    Cow::Borrowed(path)
+}
+
+/// Reads a file to extract from stdin. It has no name to go by, so it's named "stdin"
+/// with the usual extension for whatever format its contents look like.
+pub fn read_stdin_file() -> Result<VirtualFile, Box<dyn Error>> {
+    let bytes = read_input(Path::new("-"))?;
+    let format = detect(&bytes).map(|detection| detection.format);
+    if format == Some(FileFormat::Gcm) {
+        return Err("Disc images can't be read from stdin".into());
+    }
+    let extension = format.and_then(|format| format.info().extensions.first());
+    Ok(VirtualFile {
+        path: PathBuf::from("stdin").with_extension(extension.copied().unwrap_or_default()),
+        bytes,
+    })
+}
+
+/// Writes files as an uncompressed tar archive, for passing a whole extracted tree down
+/// a pipeline. Only the parts of each path below the current directory are kept.
+pub fn write_tar<'a>(out: &mut impl Write, files: impl IntoIterator<Item = &'a VirtualFile>) -> io::Result<()> {
+    for file in files {
+        let path: Vec<String> = file
+            .path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        out.write_all(&tar_header(&path.join("/"), file.bytes.len())?)?;
+        out.write_all(&file.bytes)?;
+        out.write_all(&vec![0; file.bytes.len().next_multiple_of(512) - file.bytes.len()])?;
+    }
+    // Archives end with two empty blocks
+    out.write_all(&[0; 1024])
+}
+
+/// A ustar header for a regular file. Paths longer than 100 bytes are split into a
+/// prefix and a name at a `/`.
+fn tar_header(path: &str, size: usize) -> io::Result<[u8; 512]> {
+    let (prefix, name) = match path.len() {
+        ..=100 => ("", path),
+        _ => path
+            .char_indices()
+            .filter(|&(idx, c)| c == '/' && idx <= 155 && path.len() - idx - 1 <= 100)
+            .map(|(idx, _)| (&path[..idx], &path[idx + 1..]))
+            .next()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{path} is too long for a tar archive"),
+                )
+            })?,
+    };
+    if size >= 8usize.pow(11) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{path} is too large for a tar archive"),
+        ));
+    }
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{size:011o}\0").as_bytes());
+    // Modification times are left at 0 so the same files always make the same archive
+    header[136..148].copy_from_slice(b"00000000000\0");
+    header[156] = b'0';
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    // The checksum is calculated with its own field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    Ok(header)
+}
@@ -0,0 +1,97 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+/// Names that can be used in a template, e.g. `{stem}/{format}/{name}`
+const VARIABLES: &[(&str, &str)] = &[
+    ("stem", "input file name without its extension"),
+    ("format", "format each output was extracted from, e.g. bti"),
+    ("dir", "folder the output would normally be in"),
+    ("name", "output file name"),
+    ("ext", "output file extension"),
+    ("path", "same as {dir}/{name}"),
+];
+
+/// A path with `{variable}` placeholders, filled in for each extracted file to decide
+/// where it's written
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Variable(String),
+}
+
+/// What a template's variables are filled in with for one output file
+pub struct TemplateValues<'a> {
+    pub stem: &'a str,
+    pub format: &'a str,
+    /// The output's path relative to the folder it would normally be extracted to
+    pub relative_path: &'a Path,
+}
+
+impl PathTemplate {
+    pub fn render(&self, values: &TemplateValues) -> PathBuf {
+        let path = values.relative_path;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let dir = path.parent().unwrap_or(Path::new("")).to_string_lossy();
+        let mut rendered = String::new();
+        for part in self.parts.iter() {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Variable(variable) => rendered.push_str(&match variable.as_str() {
+                    "stem" => values.stem.into(),
+                    "format" => values.format.into(),
+                    "dir" => dir.clone(),
+                    "name" => name.clone(),
+                    "ext" => path.extension().unwrap_or_default().to_string_lossy(),
+                    "path" => path.to_string_lossy(),
+                    _ => unreachable!("Variables are checked when parsing"),
+                }),
+            }
+        }
+        // Empty variables like {dir} for files at the top level leave empty components
+        // behind, and files must never end up outside the output folder
+        Path::new(&rendered)
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect()
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("Unclosed '{{' in template '{s}'"))?;
+            let variable = &rest[start + 1..start + end];
+            if !VARIABLES.iter().any(|(name, _)| *name == variable) {
+                let names: Vec<String> = VARIABLES
+                    .iter()
+                    .map(|(name, description)| format!("{{{name}}} ({description})"))
+                    .collect();
+                return Err(format!(
+                    "Unknown variable '{{{variable}}}'. Expected one of {}",
+                    names.join(", ")
+                ));
+            }
+            parts.push(Part::Variable(variable.to_owned()));
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_owned()));
+        }
+        Ok(PathTemplate { parts })
+    }
+}
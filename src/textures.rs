@@ -0,0 +1,254 @@
+use crate::pack::{bti_from_png, BtiMetadata};
+use cube_rs::{
+    bti::{BtiHeader, BtiQuality},
+    rarc::Rarc,
+    szs::{decompress_szs, yaz0_compress, CompressionLevel, SzsCompression},
+    u8_archive::{self, U8Archive},
+    virtual_fs::VirtualFile,
+    yay0,
+};
+use log::{info, warn};
+use serde::Serialize;
+use std::{
+    error::Error,
+    fs::{read, read_dir, write},
+    io,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReplaceReport {
+    pub replaced: Vec<ReplacedTexture>,
+    /// PNGs that didn't match any texture in the archive
+    pub unused: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplacedTexture {
+    /// Where the texture is in the archive, through any archives it's nested in
+    pub path: PathBuf,
+    pub png: PathBuf,
+}
+
+/// A PNG to replace textures with, and the path it's matched against
+struct Replacement {
+    png: PathBuf,
+    key: Vec<String>,
+    used: bool,
+}
+
+struct Replacer {
+    replacements: Vec<Replacement>,
+    compression_level: CompressionLevel,
+    quality: BtiQuality,
+    report: ReplaceReport,
+}
+
+pub fn try_replace_textures(
+    archive: &Path,
+    pngs: &Path,
+    out: &Path,
+    compression_level: CompressionLevel,
+    quality: BtiQuality,
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut replacements = Vec::new();
+    find_pngs(pngs, &mut replacements)?;
+    let mut replacer = Replacer {
+        replacements,
+        compression_level,
+        quality,
+        report: ReplaceReport::default(),
+    };
+    let data = read(archive)?;
+    if !is_archive(&data) {
+        return Err(format!("{archive:?} isn't a RARC or U8 archive").into());
+    }
+    let packed = replacer
+        .replace_in_archive(&data, Path::new(""), &[])
+        .map_err(|e| format!("Couldn't replace textures in {archive:?}: {e}"))?;
+    let Some(packed) = packed else {
+        return Err(format!("None of the PNGs in {pngs:?} match a texture in {archive:?}").into());
+    };
+    write(out, packed)?;
+
+    let mut report = replacer.report;
+    report.unused = replacer
+        .replacements
+        .into_iter()
+        .filter(|replacement| !replacement.used)
+        .map(|replacement| replacement.png)
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for png in report.unused.iter() {
+            warn!("{png:?} doesn't match any texture");
+        }
+        info!("Replaced {} textures in {out:?}", report.replaced.len());
+    }
+    Ok(())
+}
+
+/// Finds every PNG in `dir` and the folders inside it
+fn find_pngs(dir: &Path, replacements: &mut Vec<Replacement>) -> Result<(), Box<dyn Error>> {
+    let mut entries: Vec<PathBuf> = read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<io::Result<_>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            find_pngs(&path, replacements)?;
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")) {
+            replacements.push(Replacement {
+                key: match_key(path.strip_prefix(dir).unwrap_or(&path)),
+                png: path,
+                used: false,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Paths are matched without extensions or case, so `tex/grad.bti.png` matches the
+/// texture `tex.szs/timg/grad.bti` along with any other `grad.bti` in a folder named
+/// `tex`. Nested archives are matched by their name, since they're extracted to folders.
+fn match_key(path: &Path) -> Vec<String> {
+    path.components()
+        .map(|component| {
+            let name = component.as_os_str().to_string_lossy().to_ascii_lowercase();
+            name.split('.').next().unwrap_or_default().to_owned()
+        })
+        .collect()
+}
+
+impl Replacer {
+    /// Replaces every texture that has a PNG in an archive and the archives inside it,
+    /// returning the archive compressed the way it was if anything changed
+    fn replace_in_archive(
+        &mut self,
+        data: &[u8],
+        path: &Path,
+        key: &[String],
+    ) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        let compression = SzsCompression::detect(data);
+        let data = decompress_szs(data.to_vec())?;
+        let archive = match data.get(..4) {
+            Some(b"RARC" | b"CRAR") => {
+                let mut rarc = Rarc::parse(&data)?;
+                let changes = self.replace_files(rarc.files(), path, key)?;
+                if changes.is_empty() {
+                    return Ok(None);
+                }
+                for file in changes {
+                    rarc.add_file(file.path, file.bytes);
+                }
+                rarc.write()?
+            }
+            Some(magic) if *magic == u8_archive::MAGIC => {
+                let u8_archive = U8Archive::parse(&data)?;
+                let changes = self.replace_files(u8_archive.files(), path, key)?;
+                if changes.is_empty() {
+                    return Ok(None);
+                }
+                let files = u8_archive.files().map(|(file_path, bytes)| {
+                    match changes.iter().find(|changed| changed.path == file_path) {
+                        Some(changed) => (file_path, changed.bytes.as_slice()),
+                        None => (file_path, bytes),
+                    }
+                });
+                U8Archive::build(files)?
+            }
+            // Compressed files that aren't archives, like lone Yaz0 models
+            _ => return Ok(None),
+        };
+        Ok(Some(match compression {
+            SzsCompression::None => archive,
+            SzsCompression::Yaz0(info) => {
+                let mut compressed = yaz0_compress(&archive, self.compression_level);
+                info.apply(&mut compressed);
+                compressed
+            }
+            SzsCompression::Yay0 => yay0::compress(&archive, self.compression_level),
+        }))
+    }
+
+    /// New contents for every file in an archive that has a PNG or is an archive with
+    /// textures that have one
+    fn replace_files<'a>(
+        &mut self,
+        files: impl Iterator<Item = (PathBuf, &'a [u8])>,
+        parent: &Path,
+        parent_key: &[String],
+    ) -> Result<Vec<VirtualFile>, Box<dyn Error>> {
+        let mut changes = Vec::new();
+        for (file_path, bytes) in files {
+            let path = parent.join(&file_path);
+            let mut key = parent_key.to_vec();
+            key.extend(match_key(&file_path));
+            let is_bti = file_path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bti"));
+            if is_bti {
+                if let Some(bytes) = self.replace_texture(bytes, &path, &key)? {
+                    changes.push(VirtualFile { path: file_path, bytes });
+                }
+            } else if is_archive(bytes) {
+                let replaced = self
+                    .replace_in_archive(bytes, &path, &key)
+                    .map_err(|e| format!("{path:?}: {e}"))?;
+                if let Some(bytes) = replaced {
+                    changes.push(VirtualFile { path: file_path, bytes });
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Encodes the PNG that best matches a texture, if there is one, with the header from
+    /// the `.bti.json` next to the PNG or else the texture's own
+    fn replace_texture(&mut self, bti: &[u8], path: &Path, key: &[String]) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+        // Either path can have folders in front the other doesn't, like the folder the
+        // archive was extracted to, and the PNG that matches the most of it wins
+        let Some(replacement) = self
+            .replacements
+            .iter_mut()
+            .filter(|replacement| key.ends_with(&replacement.key) || replacement.key.ends_with(key))
+            .max_by_key(|replacement| replacement.key.len().min(key.len()))
+        else {
+            return Ok(None);
+        };
+        replacement.used = true;
+
+        let header_path = header_path(&replacement.png);
+        let metadata: BtiMetadata = match header_path.is_file() {
+            true => serde_json::from_slice(&read(header_path)?)?,
+            false => BtiHeader::read(bti)?.into(),
+        };
+        let bytes = bti_from_png(&read(&replacement.png)?, Some(metadata), false, self.quality)
+            .map_err(|e| format!("{:?}: {e}", replacement.png))?;
+        info!("Replacing {path:?} with {:?}", replacement.png);
+        self.report.replaced.push(ReplacedTexture {
+            path: path.to_owned(),
+            png: replacement.png.clone(),
+        });
+        Ok(Some(bytes))
+    }
+}
+
+/// The texture header extracted next to a PNG, `name.bti.json` for `name.bti.png` or `name.png`
+fn header_path(png: &Path) -> PathBuf {
+    let stem = png.with_extension("");
+    match stem.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bti")) {
+        true => stem.with_extension("bti.json"),
+        false => {
+            let mut path = stem.into_os_string();
+            path.push(".bti.json");
+            path.into()
+        }
+    }
+}
+
+/// Whether data starts like an archive, or a compressed file that might be one
+fn is_archive(data: &[u8]) -> bool {
+    matches!(data.get(..4), Some(b"RARC" | b"CRAR" | b"Yaz0" | b"Yaz1" | b"Yay0"))
+        || data.starts_with(&u8_archive::MAGIC)
+}
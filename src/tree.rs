@@ -0,0 +1,165 @@
+use cube_rs::{
+    detect::detect,
+    iso::IsoMetadata,
+    szs::SzsCompression,
+    tree::{extract_disc_tree, extract_tree, ArchiveKind, ArchiveTree, Folder, TreeEntry},
+};
+use serde::Serialize;
+use std::{error::Error, fs::read, path::PathBuf};
+
+/// An entry in the printed tree. Folders' sizes are the total of everything in them.
+#[derive(Debug, Serialize)]
+struct Node {
+    name: String,
+    kind: NodeKind,
+    /// The format of an archive, or of a file if it's recognized
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<&'static str>,
+    size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decompressed_size: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<Node>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum NodeKind {
+    File,
+    Folder,
+    Archive,
+}
+
+pub fn try_tree(files: &[PathBuf], dot: bool, json: bool) -> Result<(), Box<dyn Error>> {
+    let mut roots = Vec::with_capacity(files.len());
+    for path in files {
+        // Discs are opened from their path, since extract_tree only knows about archives
+        let archive = match IsoMetadata::open(path) {
+            Ok(_) => extract_disc_tree(path)?,
+            Err(_) => extract_tree(read(path)?).map_err(|e| format!("Couldn't open {path:?}: {e}"))?,
+        };
+        roots.push(archive_node(path.to_string_lossy().into_owned(), &archive));
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&roots)?);
+    } else if dot {
+        print_dot(&roots);
+    } else {
+        for (i, root) in roots.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            println!("{}", label(root));
+            print_children(&root.children, "");
+        }
+    }
+    Ok(())
+}
+
+fn archive_node(name: String, archive: &ArchiveTree) -> Node {
+    let compression = match archive.compression {
+        SzsCompression::None => None,
+        SzsCompression::Yaz0(_) => Some("Yaz0"),
+        SzsCompression::Yay0 => Some("Yay0"),
+    };
+    Node {
+        name,
+        kind: NodeKind::Archive,
+        format: Some(match archive.kind {
+            ArchiveKind::Rarc => "RARC",
+            ArchiveKind::U8 => "U8",
+            ArchiveKind::Afs => "AFS",
+            ArchiveKind::Iso => "ISO",
+        }),
+        compression,
+        size: archive.size,
+        decompressed_size: compression.map(|_| archive.decompressed_size),
+        children: folder_children(&archive.root),
+    }
+}
+
+fn folder_children(folder: &Folder) -> Vec<Node> {
+    folder
+        .entries
+        .iter()
+        .map(|(name, entry)| match entry {
+            TreeEntry::File(bytes) => Node {
+                name: name.clone(),
+                kind: NodeKind::File,
+                format: detect(bytes).map(|detection| detection.format.name()),
+                compression: None,
+                size: bytes.len(),
+                decompressed_size: None,
+                children: Vec::new(),
+            },
+            TreeEntry::Folder(folder) => {
+                let children = folder_children(folder);
+                Node {
+                    name: name.clone(),
+                    kind: NodeKind::Folder,
+                    format: None,
+                    compression: None,
+                    size: children.iter().map(|child| child.size).sum(),
+                    decompressed_size: None,
+                    children,
+                }
+            }
+            TreeEntry::Archive(archive) => archive_node(name.clone(), archive),
+        })
+        .collect()
+}
+
+/// The name followed by what's known about it, e.g. `map.szs (RARC, Yaz0, 1200 bytes)`
+fn label(node: &Node) -> String {
+    let mut details: Vec<String> = node.format.into_iter().map(String::from).collect();
+    details.extend(node.compression.map(String::from));
+    details.push(format!("{} bytes", node.size));
+    details.extend(node.decompressed_size.map(|size| format!("{size} decompressed")));
+    match node.kind {
+        NodeKind::Folder => format!("{}/ ({})", node.name, details.join(", ")),
+        _ => format!("{} ({})", node.name, details.join(", ")),
+    }
+}
+
+fn print_children(children: &[Node], indent: &str) {
+    for (i, child) in children.iter().enumerate() {
+        let last = i == children.len() - 1;
+        println!("{indent}{}{}", if last { "└── " } else { "├── " }, label(child));
+        print_children(
+            &child.children,
+            &format!("{indent}{}", if last { "    " } else { "│   " }),
+        );
+    }
+}
+
+/// Prints every tree as one Graphviz graph, for rendering with e.g. `dot -Tsvg`
+fn print_dot(roots: &[Node]) {
+    println!("digraph tree {{");
+    println!("    rankdir=LR;");
+    let mut next_id = 0;
+    for root in roots {
+        print_dot_node(root, None, &mut next_id);
+    }
+    println!("}}");
+}
+
+fn print_dot_node(node: &Node, parent: Option<usize>, next_id: &mut usize) {
+    let id = *next_id;
+    *next_id += 1;
+    let shape = match node.kind {
+        NodeKind::File => "note",
+        NodeKind::Folder => "folder",
+        NodeKind::Archive => "box3d",
+    };
+    let label = label(node).replace('\\', "\\\\").replace('"', "\\\"");
+    println!("    n{id} [label=\"{label}\", shape={shape}];");
+    if let Some(parent) = parent {
+        println!("    n{parent} -> n{id};");
+    }
+    for child in &node.children {
+        print_dot_node(child, Some(id), next_id);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{cache::CACHE_DIR, commands::PackOptions, pack::try_pack, stream::is_std_stream};
+use log::{error, warn};
+use notify::{
+    event::{EventKind, ModifyKind},
+    Event, RecursiveMode, Watcher,
+};
+use std::{
+    error::Error,
+    path::{Component, Path},
+    sync::mpsc::{channel, Receiver},
+    time::Duration,
+};
+
+/// How long to wait after a change for any more before packing, since editors and tools
+/// often save a file in several steps
+const SETTLE_TIME: Duration = Duration::from_millis(300);
+
+/// Packs `file`, then packs it again whenever anything in it changes until the program is
+/// stopped. Only the archives with changes in them are packed again, as with
+/// `--incremental`, and a disc is rebuilt after the archives on it.
+pub fn try_watch(file: &Path, out: Option<&Path>, mut options: PackOptions) -> Result<(), Box<dyn Error>> {
+    if is_std_stream(file) {
+        return Err("Only files and folders can be watched, not stdin".into());
+    }
+    options.incremental = true;
+
+    let (sender, events) = channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    // Editors often save by replacing a file, which would end a watch on the file itself
+    match file.is_dir() {
+        true => watcher.watch(file, RecursiveMode::Recursive)?,
+        false => {
+            let parent = file.parent().filter(|parent| !parent.as_os_str().is_empty());
+            watcher.watch(parent.unwrap_or(Path::new(".")), RecursiveMode::NonRecursive)?;
+        }
+    }
+
+    pack_changes(file, out, &options);
+    println!("Watching {file:?} for changes. Press Ctrl+C to stop.");
+    loop {
+        if wait_for_changes(file, &events)? {
+            pack_changes(file, out, &options);
+        }
+    }
+}
+
+/// Packs whatever's out of date, reporting what was written. Failures are only reported,
+/// so watching carries on and they're retried on the next change.
+fn pack_changes(file: &Path, out: Option<&Path>, options: &PackOptions) {
+    match try_pack(file.to_owned(), out, options) {
+        Ok(outputs) => {
+            for output in outputs {
+                println!("Packed {:?}", output.path);
+            }
+        }
+        Err(e) => error!("{e}"),
+    }
+}
+
+/// Blocks until something happens to `file` and then settles down, returning whether any
+/// of it changed what would be packed. Packing writes archives for the folders inside
+/// `file` too, which shows up here but finds nothing out of date the next time.
+fn wait_for_changes(file: &Path, events: &Receiver<notify::Result<Event>>) -> Result<bool, Box<dyn Error>> {
+    let mut changed = is_change(file, events.recv()?);
+    while let Ok(event) = events.recv_timeout(SETTLE_TIME) {
+        changed |= is_change(file, event);
+    }
+    Ok(changed)
+}
+
+fn is_change(file: &Path, event: notify::Result<Event>) -> bool {
+    let event = match event {
+        Ok(event) => event,
+        Err(e) => {
+            warn!("Error while watching {file:?}: {e}");
+            return false;
+        }
+    };
+    // Packing reads files and touches the ones that came out the same, neither of which
+    // changes anything
+    let relevant_kind = matches!(
+        event.kind,
+        EventKind::Any
+            | EventKind::Create(_)
+            | EventKind::Remove(_)
+            | EventKind::Modify(ModifyKind::Any | ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Other)
+    );
+    let relevant_path = |path: &Path| {
+        let cached = path.components().any(|c| c == Component::Normal(CACHE_DIR.as_ref()));
+        // A file is watched through its folder, where other files can change too
+        !cached && (file.is_dir() || path.file_name() == file.file_name())
+    };
+    relevant_kind && event.paths.iter().any(|path| relevant_path(path))
+}